@@ -0,0 +1,247 @@
+use crate::os_detector::{initial_ttl, OSInfo};
+use crate::scanner::ScanType;
+use crate::service_detector::{guess_service, DetectedService};
+use crate::target::{parse_ports, TOP_TCP_PORTS, TOP_UDP_PORTS};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 开放端口数至少达到这个值、且占扫描端口一半以上时视为疑似 tarpit
+const TARPIT_MIN_OPEN: usize = 50;
+
+/// 值得关注的异常发现，由各项探测结果汇总得出（`--anomalies`），
+/// 开放代理和开放中继来自主动检查（`--check-proxies`），证书和弱 TLS 配置来自 `--tls-audit`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// 不在常见端口表（top 100 TCP / top 20 UDP）中的开放端口
+    RarePort { port: u16, protocol: String },
+    /// 常见端口上识别出了属于其他端口的服务，如 443 上的 SSH
+    UnexpectedService { port: u16, service: String, expected: String },
+    /// ICMP 应答的初始 TTL 与识别出的操作系统不符
+    TtlMismatch { ttl: u8, os: String, expected_ttl: u8 },
+    /// 大部分端口都显示开放，可能是 tarpit 或 SYN 代理
+    Tarpit { open_ports: usize, scanned_ports: usize },
+    /// 同一端口多次探测返回不同的 banner
+    LoadBalancer { port: u16, backends: usize },
+    /// HTTP 代理允许 `CONNECT` 到外部主机
+    OpenProxy { port: u16 },
+    /// SMTP 服务接受外部发件人发往外部域的邮件
+    OpenRelay { port: u16 },
+    /// 证书已过期，`not_after` 为 RFC 3339 格式的到期时间
+    CertExpired { port: u16, not_after: String },
+    /// 证书将在 `days` 天内到期
+    CertExpiring { port: u16, not_after: String, days: u64 },
+    /// 自签名证书
+    SelfSignedCert { port: u16, subject: String },
+    /// 服务端接受已弃用的协议版本（SSL 3.0、TLS 1.0、TLS 1.1）
+    WeakTlsVersion { port: u16, version: String },
+    /// 服务端接受弱密码套件（RC4、DES/3DES、出口级、NULL、匿名）
+    WeakCipher { port: u16, cipher: String },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::RarePort { port, protocol } => write!(f, "不常见的开放端口 {}/{}", port, protocol),
+            Anomaly::UnexpectedService { port, service, expected } => {
+                write!(f, "端口 {} 上运行的是 {}，而不是通常的 {}", port, service, expected)
+            }
+            Anomaly::TtlMismatch { ttl, os, expected_ttl } => {
+                write!(f, "TTL {} 与识别出的操作系统 {} 不符（通常初始 TTL 为 {}）", ttl, os, expected_ttl)
+            }
+            Anomaly::Tarpit { open_ports, scanned_ports } => {
+                write!(f, "扫描的 {} 个端口中 {} 个显示开放，疑似 tarpit 或 SYN 代理", scanned_ports, open_ports)
+            }
+            Anomaly::LoadBalancer { port, backends } => {
+                write!(f, "端口 {} 返回了 {} 种不同的 banner，疑似负载均衡", port, backends)
+            }
+            Anomaly::OpenProxy { port } => write!(f, "端口 {} 是开放的 HTTP 代理，允许 CONNECT 到外部主机", port),
+            Anomaly::OpenRelay { port } => write!(f, "端口 {} 的 SMTP 服务是开放中继，接受发往外部域的邮件", port),
+            Anomaly::CertExpired { port, not_after } => write!(f, "端口 {} 的证书已于 {} 过期", port, not_after),
+            Anomaly::CertExpiring { port, not_after, days } => {
+                write!(f, "端口 {} 的证书将在 {} 天内（{}）过期", port, days, not_after)
+            }
+            Anomaly::SelfSignedCert { port, subject } => write!(f, "端口 {} 使用自签名证书（{}）", port, subject),
+            Anomaly::WeakTlsVersion { port, version } => write!(f, "端口 {} 接受已弃用的 {}", port, version),
+            Anomaly::WeakCipher { port, cipher } => write!(f, "端口 {} 接受弱密码套件 {}", port, cipher),
+        }
+    }
+}
+
+/// 一个主机的各项探测结果
+pub struct HostSignals<'a> {
+    pub services: &'a [(u16, ScanType, DetectedService)],
+    /// 扫描的 TCP 端口数
+    pub scanned_ports: usize,
+    pub os: Option<&'a OSInfo>,
+    /// ICMP 应答的 TTL
+    pub ttl: Option<u8>,
+    /// `--detect-lb` 得到的各端口后端数
+    pub backends: &'a [(u16, usize)],
+}
+
+/// 汇总主机的异常发现
+///
+/// 疑似 tarpit 时几乎所有端口都"开放"，不再逐个报告不常见端口和服务。
+pub fn find_anomalies(signals: &HostSignals) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    let open_tcp = signals.services.iter().filter(|(_, scan_type, _)| *scan_type == ScanType::Tcp).count();
+    let tarpit = open_tcp >= TARPIT_MIN_OPEN && open_tcp * 2 >= signals.scanned_ports;
+    if tarpit {
+        anomalies.push(Anomaly::Tarpit {
+            open_ports: open_tcp,
+            scanned_ports: signals.scanned_ports,
+        });
+    } else {
+        let top_tcp = parse_ports(TOP_TCP_PORTS).unwrap_or_default();
+        let top_udp = parse_ports(TOP_UDP_PORTS).unwrap_or_default();
+        for (port, scan_type, service) in signals.services {
+            let common = match scan_type {
+                ScanType::Tcp => &top_tcp,
+                ScanType::Udp => &top_udp,
+            };
+            if common.binary_search(port).is_err() {
+                anomalies.push(Anomaly::RarePort {
+                    port: *port,
+                    protocol: scan_type.as_str().to_string(),
+                });
+            }
+            if let Some(anomaly) = unexpected_service(*port, service) {
+                anomalies.push(anomaly);
+            }
+        }
+    }
+
+    if let (Some(ttl), Some(os)) = (signals.ttl, signals.os) {
+        let expected_ttl = match os.name.as_str() {
+            "Windows" => Some(128),
+            "Linux/Unix" => Some(64),
+            _ => None,
+        };
+        if let Some(expected_ttl) = expected_ttl.filter(|expected| initial_ttl(ttl) != *expected) {
+            anomalies.push(Anomaly::TtlMismatch {
+                ttl,
+                os: os.name.clone(),
+                expected_ttl,
+            });
+        }
+    }
+
+    for &(port, backends) in signals.backends.iter().filter(|(_, backends)| *backends > 1) {
+        anomalies.push(Anomaly::LoadBalancer { port, backends });
+    }
+    anomalies
+}
+
+/// 匹配到的指纹属于另一种常见服务的端口时，服务不在预期的端口上
+///
+/// 以端口的常见服务比较，80 端口的 Nginx 指纹出现在 443 上都属于 HTTP，不算异常。
+fn unexpected_service(port: u16, service: &DetectedService) -> Option<Anomaly> {
+    let expected = guess_service(port)?;
+    let fingerprint_port = service.fingerprint.as_ref()?.port;
+    let actual = guess_service(fingerprint_port)?;
+    (actual != expected).then(|| Anomaly::UnexpectedService {
+        port,
+        service: service.name.clone(),
+        expected: expected.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service_detector::ServiceMethod;
+    use crate::service_fingerprints::ServiceFingerprint;
+
+    fn service(name: &str, fingerprint_port: Option<u16>) -> DetectedService {
+        DetectedService {
+            name: name.to_string(),
+            version: None,
+            confidence: 0.9,
+            method: ServiceMethod::Banner,
+            fingerprint: fingerprint_port.map(|port| {
+                Box::new(ServiceFingerprint {
+                    name: name.to_string(),
+                    protocol: "TCP".to_string(),
+                    port,
+                    banner_pattern: None,
+                    response_pattern: None,
+                    weight: 1.0,
+                    description: None,
+                    version_pattern: None,
+                    vendor: None,
+                    cpe: None,
+                    binary: false,
+                })
+            }),
+            ..DetectedService::default()
+        }
+    }
+
+    #[test]
+    fn test_find_anomalies() {
+        let services = vec![
+            (22, ScanType::Tcp, service("SSH", Some(22))),
+            (443, ScanType::Tcp, service("SSH", Some(22))),
+            (443, ScanType::Tcp, service("Nginx", Some(80))),
+            (31337, ScanType::Tcp, service("unknown", None)),
+            (53, ScanType::Udp, service("DNS", None)),
+        ];
+        let os = OSInfo {
+            name: "Windows".to_string(),
+            version: None,
+            confidence: 0.9,
+            features: Vec::new(),
+        };
+        let signals = HostSignals {
+            services: &services,
+            scanned_ports: 1000,
+            os: Some(&os),
+            ttl: Some(61),
+            backends: &[(80, 1), (443, 3)],
+        };
+        let anomalies = find_anomalies(&signals);
+        assert_eq!(
+            anomalies,
+            vec![
+                Anomaly::UnexpectedService {
+                    port: 443,
+                    service: "SSH".to_string(),
+                    expected: "HTTP".to_string()
+                },
+                Anomaly::RarePort {
+                    port: 31337,
+                    protocol: "TCP".to_string()
+                },
+                Anomaly::TtlMismatch {
+                    ttl: 61,
+                    os: "Windows".to_string(),
+                    expected_ttl: 128
+                },
+                Anomaly::LoadBalancer { port: 443, backends: 3 },
+            ]
+        );
+        assert_eq!(anomalies[0].to_string(), "端口 443 上运行的是 SSH，而不是通常的 HTTP");
+        assert_eq!(
+            serde_json::to_value(&anomalies[1]).unwrap(),
+            serde_json::json!({ "kind": "rare_port", "port": 31337, "protocol": "TCP" })
+        );
+
+        // 几乎全部端口开放：只报告 tarpit
+        let services: Vec<_> = (1..=100).map(|port| (port + 40000, ScanType::Tcp, service("unknown", None))).collect();
+        let signals = HostSignals {
+            services: &services,
+            scanned_ports: 100,
+            os: None,
+            ttl: None,
+            backends: &[],
+        };
+        assert_eq!(
+            find_anomalies(&signals),
+            vec![Anomaly::Tarpit {
+                open_ports: 100,
+                scanned_ports: 100
+            }]
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::Result;
+use pcap_file::pcap::{PcapHeader, PcapPacket, PcapWriter};
+use pcap_file::DataLink;
+use std::fs::File;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 原始套接字收发报文的 pcap 记录器
+///
+/// 使用 LINKTYPE_RAW（无链路层），每条记录都是完整的 IP 报文，可直接用 Wireshark 打开。
+pub struct PacketCapture {
+    writer: Mutex<PcapWriter<File>>,
+}
+
+impl PacketCapture {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::create(path)?;
+        let header = PcapHeader {
+            datalink: DataLink::RAW,
+            ..Default::default()
+        };
+        let writer = PcapWriter::with_header(file, header)?;
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// 记录一个完整的 IP 报文
+    pub fn record(&self, packet: &[u8]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let packet = PcapPacket::new(timestamp, packet.len() as u32, packet);
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.write_packet(&packet);
+        }
+    }
+
+    /// 记录由内核补全 IP 头的发送报文，这里按相同字段重建 IPv4 头
+    pub fn record_ipv4(&self, src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, ttl: u8, payload: &[u8]) {
+        self.record(&ipv4_packet(src, dst, protocol, ttl, payload));
+    }
+}
+
+/// 构造不带选项的 IPv4 报文
+fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, ttl: u8, payload: &[u8]) -> Vec<u8> {
+    let total_len = (20 + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.push(0x45); // 版本 4，头长度 5 个 32 位字
+    packet.push(0);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0x40, 0]); // 标识 0，DF
+    packet.push(ttl);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+
+    let checksum = internet_checksum(&packet[..20]);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// RFC 1071 校验和
+fn internet_checksum(bytes: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in bytes.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += u32::from(word);
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
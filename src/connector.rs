@@ -0,0 +1,112 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpStream;
+
+/// 可读写的连接，端口探测和服务识别只依赖这两种能力
+pub trait NetStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> NetStream for T {}
+
+pub type BoxStream = Box<dyn NetStream>;
+
+/// 建立连接的方式：真实网络、代理、继承的套接字或测试替身
+pub trait Connector: Send + Sync {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<BoxStream>>;
+}
+
+/// 直接发起 TCP 连接
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpConnector;
+
+impl Connector for TcpConnector {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<BoxStream>> {
+        Box::pin(async move { Ok(Box::new(TcpStream::connect(addr).await?) as BoxStream) })
+    }
+}
+
+/// 返回 `TcpStream` 的闭包，如 `|addr| TcpStream::connect(addr)`
+impl<F, Fut> Connector for F
+where
+    F: Fn(SocketAddr) -> Fut + Send + Sync,
+    Fut: Future<Output = io::Result<TcpStream>> + Send + 'static,
+{
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<BoxStream>> {
+        let connect = self(addr);
+        Box::pin(async move { Ok(Box::new(connect.await?) as BoxStream) })
+    }
+}
+
+/// 模拟服务在连接建立后的行为
+#[derive(Debug, Clone)]
+pub enum MockService {
+    /// 连接后立即发送 banner（SSH、FTP 等）
+    Banner(Vec<u8>),
+    /// 收到请求后发送应答（HTTP 等）
+    Reply(Vec<u8>),
+    /// 接受连接但从不发送数据
+    Silent,
+    /// 按请求的开头选择应答，如同一端口既完成 TLS 握手又应答明文请求
+    Replies(Vec<(Vec<u8>, Vec<u8>)>),
+}
+
+/// 不经过网络的连接替身：按端口返回预设应答，未登记的端口拒绝连接
+#[derive(Debug, Clone, Default)]
+pub struct MockConnector {
+    services: HashMap<u16, MockService>,
+}
+
+impl MockConnector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记端口上的模拟服务，所有目标地址共用
+    pub fn service(mut self, port: u16, service: MockService) -> Self {
+        self.services.insert(port, service);
+        self
+    }
+}
+
+impl Connector for MockConnector {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<BoxStream>> {
+        let service = self.services.get(&addr.port()).cloned();
+        Box::pin(async move {
+            let service = service.ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(serve(server, service));
+            Ok(Box::new(client) as BoxStream)
+        })
+    }
+}
+
+/// 模拟服务端，客户端断开后结束
+async fn serve(mut server: DuplexStream, service: MockService) {
+    match service {
+        MockService::Banner(banner) => {
+            let _ = server.write_all(&banner).await;
+        }
+        MockService::Reply(reply) => {
+            let mut request = [0u8; 1024];
+            if matches!(server.read(&mut request).await, Ok(len) if len > 0) {
+                let _ = server.write_all(&reply).await;
+            }
+        }
+        MockService::Silent => {}
+        MockService::Replies(replies) => {
+            let mut request = [0u8; 1024];
+            if let Ok(len) = server.read(&mut request).await {
+                let reply = replies.iter().find(|(prefix, _)| len > 0 && request[..len].starts_with(prefix));
+                if let Some((_, reply)) = reply {
+                    let _ = server.write_all(reply).await;
+                }
+            }
+        }
+    }
+    // 保持连接直到客户端关闭
+    let mut rest = [0u8; 1024];
+    while matches!(server.read(&mut rest).await, Ok(len) if len > 0) {}
+}
@@ -0,0 +1,121 @@
+use crate::ping::source_addr_for;
+use anyhow::Result;
+use colored::*;
+use if_addrs::IfAddr;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// 本机网卡及其地址
+#[derive(Debug, Default)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub index: Option<u32>,
+    pub up: bool,
+    /// `地址/前缀长度`
+    pub addrs: Vec<String>,
+}
+
+/// 扫描前的本机环境诊断结果
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub interfaces: Vec<InterfaceInfo>,
+    /// 访问公网时使用的源地址（即默认路由出口）
+    pub default_source_v4: Option<IpAddr>,
+    pub default_source_v6: Option<IpAddr>,
+    pub raw_icmp_v4: Result<(), String>,
+    pub raw_icmp_v6: Result<(), String>,
+    pub privileged: bool,
+}
+
+impl Diagnostics {
+    pub fn collect() -> Result<Self> {
+        let mut interfaces: BTreeMap<String, InterfaceInfo> = BTreeMap::new();
+        for iface in if_addrs::get_if_addrs()? {
+            let prefix = match &iface.addr {
+                IfAddr::V4(addr) => addr.prefixlen,
+                IfAddr::V6(addr) => addr.prefixlen,
+            };
+            let info = interfaces.entry(iface.name.clone()).or_insert_with(|| InterfaceInfo {
+                name: iface.name.clone(),
+                index: iface.index,
+                ..Default::default()
+            });
+            info.up |= iface.is_oper_up();
+            info.addrs.push(format!("{}/{}", iface.ip(), prefix));
+        }
+
+        Ok(Self {
+            interfaces: interfaces.into_values().collect(),
+            default_source_v4: source_addr_for(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))),
+            default_source_v6: source_addr_for(IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888))),
+            raw_icmp_v4: raw_socket_available(Domain::IPV4, Protocol::ICMPV4),
+            raw_icmp_v6: raw_socket_available(Domain::IPV6, Protocol::ICMPV6),
+            privileged: is_privileged(),
+        })
+    }
+
+    pub fn print_console(&self) {
+        println!("{} 本机环境诊断:", "[*]".blue());
+
+        println!("\n网络接口:");
+        for iface in &self.interfaces {
+            let status = if iface.up { "up".green() } else { "down".red() };
+            match iface.index {
+                Some(index) => println!("  - {} (索引 {}, {})", iface.name, index, status),
+                None => println!("  - {} ({})", iface.name, status),
+            }
+            for addr in &iface.addrs {
+                println!("      {}", addr);
+            }
+        }
+
+        println!("\n默认出口源地址:");
+        println!("  IPv4: {}", describe_addr(self.default_source_v4));
+        println!("  IPv6: {}", describe_addr(self.default_source_v6));
+
+        println!("\n权限:");
+        if self.privileged {
+            println!("  {} 以 root 身份运行", "[+]".green());
+        } else {
+            println!("  {} 非 root 用户", "[!]".yellow());
+        }
+        print_raw_socket("ICMPv4", &self.raw_icmp_v4);
+        print_raw_socket("ICMPv6", &self.raw_icmp_v6);
+        if self.raw_icmp_v4.is_err() {
+            println!(
+                "\n{} 无法使用原始套接字：存活检测只会尝试 TCP 连接，--pcap-out 不会记录任何报文。\
+                 可以使用 sudo 运行，或执行 setcap cap_net_raw+ep <rustscan 路径>。",
+                "[!]".yellow()
+            );
+        }
+    }
+}
+
+fn describe_addr(addr: Option<IpAddr>) -> String {
+    addr.map(|addr| addr.to_string()).unwrap_or_else(|| "无（没有可用路由）".to_string())
+}
+
+fn print_raw_socket(name: &str, result: &Result<(), String>) {
+    match result {
+        Ok(()) => println!("  {} {} 原始套接字可用", "[+]".green(), name),
+        Err(e) => println!("  {} {} 原始套接字不可用: {}", "[-]".red(), name, e),
+    }
+}
+
+/// 尝试创建原始套接字，判断是否具备 CAP_NET_RAW 等权限
+fn raw_socket_available(domain: Domain, protocol: Protocol) -> Result<(), String> {
+    Socket::new(domain, Type::RAW, Some(protocol))
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(unix)]
+fn is_privileged() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_privileged() -> bool {
+    false
+}
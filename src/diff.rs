@@ -0,0 +1,296 @@
+use crate::output::Output;
+use anyhow::{anyhow, Result};
+use colored::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+/// `--since` 判断端口是否有变化的粒度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChangeLevel {
+    /// 只看端口新开放或关闭
+    State,
+    /// 另外比较服务名
+    Service,
+    /// 另外比较服务名和版本
+    #[default]
+    Version,
+}
+
+impl FromStr for ChangeLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "state" => Ok(ChangeLevel::State),
+            "service" => Ok(ChangeLevel::Service),
+            "version" => Ok(ChangeLevel::Version),
+            _ => Err(anyhow!("无效的变化判定方式: {}（可选 state/service/version）", s)),
+        }
+    }
+}
+
+impl ChangeLevel {
+    /// 同一端口前后两次的识别结果在该粒度下是否不同
+    fn differs(&self, old: &PortEntry, new: &PortEntry) -> bool {
+        match self {
+            ChangeLevel::State => false,
+            ChangeLevel::Service => old.service != new.service,
+            ChangeLevel::Version => old.service != new.service || old.version != new.version,
+        }
+    }
+}
+
+/// 端口在某次扫描中的状态快照
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortEntry {
+    pub port: u16,
+    pub protocol: String,
+    pub service: String,
+    pub version: Option<String>,
+}
+
+/// 同一端口前后两次识别结果不同
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceChange {
+    pub old: PortEntry,
+    pub new: PortEntry,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct HostDiff {
+    pub target: String,
+    pub opened: Vec<PortEntry>,
+    pub closed: Vec<PortEntry>,
+    pub changed: Vec<ServiceChange>,
+}
+
+impl HostDiff {
+    pub fn is_empty(&self) -> bool {
+        self.opened.is_empty() && self.closed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DiffReport {
+    /// 仅出现在新结果中的主机
+    pub new_hosts: Vec<String>,
+    /// 仅出现在旧结果中的主机
+    pub missing_hosts: Vec<String>,
+    pub hosts: Vec<HostDiff>,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.new_hosts.is_empty() && self.missing_hosts.is_empty() && self.hosts.is_empty()
+    }
+
+    pub fn print_console(&self) {
+        println!("{} 扫描结果对比:", "[*]".blue());
+        if self.is_empty() {
+            println!("无变化。");
+            return;
+        }
+        for host in &self.new_hosts {
+            println!("{} 新增主机: {}", "[+]".green(), host);
+        }
+        for host in &self.missing_hosts {
+            println!("{} 消失主机: {}", "[-]".red(), host);
+        }
+        for host in &self.hosts {
+            println!("\n目标: {}", host.target);
+            for entry in &host.opened {
+                println!("  {} {} ({}) - {}", "+".green(), entry.port, entry.protocol, describe(entry));
+            }
+            for entry in &host.closed {
+                println!("  {} {} ({}) - {}", "-".red(), entry.port, entry.protocol, describe(entry));
+            }
+            for change in &host.changed {
+                println!(
+                    "  {} {} ({}) - {} -> {}",
+                    "~".yellow(),
+                    change.new.port,
+                    change.new.protocol,
+                    describe(&change.old),
+                    describe(&change.new)
+                );
+            }
+        }
+    }
+}
+
+fn describe(entry: &PortEntry) -> String {
+    match &entry.version {
+        Some(version) => format!("{} {}", entry.service, version),
+        None => entry.service.clone(),
+    }
+}
+
+fn port_entries(output: &Output) -> BTreeMap<(u16, String), PortEntry> {
+    output
+        .ports()
+        .iter()
+        .map(|info| {
+            let entry = PortEntry {
+                port: info.port(),
+                protocol: info.protocol().to_string(),
+                service: info.service().to_string(),
+                version: info.version().map(str::to_string),
+            };
+            ((entry.port, entry.protocol.clone()), entry)
+        })
+        .collect()
+}
+
+/// 对比两组扫描结果：新开放端口、已关闭端口以及服务/版本变化
+pub fn diff_outputs(old: &[Output], new: &[Output]) -> DiffReport {
+    let old_hosts: BTreeMap<&str, &Output> = old.iter().map(|o| (o.target(), o)).collect();
+    let new_hosts: BTreeMap<&str, &Output> = new.iter().map(|o| (o.target(), o)).collect();
+    let all_hosts: BTreeSet<&str> = old_hosts.keys().chain(new_hosts.keys()).copied().collect();
+
+    let mut report = DiffReport::default();
+    for host in all_hosts {
+        let old_ports = old_hosts.get(host).map(|o| port_entries(o)).unwrap_or_default();
+        let new_ports = new_hosts.get(host).map(|o| port_entries(o)).unwrap_or_default();
+
+        match (old_hosts.contains_key(host), new_hosts.contains_key(host)) {
+            (false, true) => report.new_hosts.push(host.to_string()),
+            (true, false) => report.missing_hosts.push(host.to_string()),
+            _ => {}
+        }
+
+        let mut diff = HostDiff {
+            target: host.to_string(),
+            ..Default::default()
+        };
+        for (key, entry) in &new_ports {
+            match old_ports.get(key) {
+                None => diff.opened.push(entry.clone()),
+                Some(old_entry) if old_entry != entry => diff.changed.push(ServiceChange {
+                    old: old_entry.clone(),
+                    new: entry.clone(),
+                }),
+                _ => {}
+            }
+        }
+        for (key, entry) in &old_ports {
+            if !new_ports.contains_key(key) {
+                diff.closed.push(entry.clone());
+            }
+        }
+
+        if !diff.is_empty() {
+            report.hosts.push(diff);
+        }
+    }
+    report
+}
+
+/// 只保留本次扫描相对基线的变化：新开放和服务变化的端口，已关闭的端口和变化前后的服务记入备注
+///
+/// `baseline` 为基线中同一主机的结果，基线中没有该主机时视为之前没有开放端口。
+/// 没有任何变化时返回 `None`。
+pub fn changes_since(baseline: Option<&Output>, current: &Output, level: ChangeLevel) -> Option<Output> {
+    let old_ports = baseline.map(port_entries).unwrap_or_default();
+    let new_ports = port_entries(current);
+
+    let mut notes = Vec::new();
+    for (key, entry) in &new_ports {
+        if let Some(old_entry) = old_ports.get(key).filter(|old_entry| level.differs(old_entry, entry)) {
+            notes.push(format!(
+                "端口 {}/{} 有变化: {} -> {}",
+                entry.port,
+                entry.protocol,
+                describe(old_entry),
+                describe(entry)
+            ));
+        }
+    }
+    for (key, entry) in &old_ports {
+        if !new_ports.contains_key(key) {
+            notes.push(format!("端口 {}/{} 已关闭（基线中为 {}）", entry.port, entry.protocol, describe(entry)));
+        }
+    }
+
+    let mut delta = current.clone();
+    delta.retain_ports(|info| {
+        let key = (info.port(), info.protocol().to_string());
+        old_ports.get(&key).is_none_or(|old_entry| level.differs(old_entry, &new_ports[&key]))
+    });
+    if delta.is_empty() && notes.is_empty() {
+        return None;
+    }
+    for note in notes {
+        delta.add_note(note);
+    }
+    Some(delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service_detector::DetectedService;
+
+    fn service(name: &str, version: Option<&str>) -> DetectedService {
+        DetectedService {
+            name: name.to_string(),
+            version: version.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_outputs() {
+        let mut old = Output::new("10.0.0.1".to_string());
+        old.add_port(22, &service("SSH", Some("2.0")), "TCP".to_string());
+        old.add_port(80, &service("HTTP", None), "TCP".to_string());
+        let gone = Output::new("10.0.0.2".to_string());
+
+        let mut new = Output::new("10.0.0.1".to_string());
+        new.add_port(22, &service("SSH", Some("2.1")), "TCP".to_string());
+        new.add_port(443, &service("HTTPS", None), "TCP".to_string());
+        let added = Output::new("10.0.0.3".to_string());
+
+        let report = diff_outputs(&[old, gone], &[new, added]);
+        assert_eq!(report.new_hosts, vec!["10.0.0.3"]);
+        assert_eq!(report.missing_hosts, vec!["10.0.0.2"]);
+        assert_eq!(report.hosts.len(), 1);
+
+        let host = &report.hosts[0];
+        assert_eq!(host.opened.iter().map(|e| e.port).collect::<Vec<_>>(), vec![443]);
+        assert_eq!(host.closed.iter().map(|e| e.port).collect::<Vec<_>>(), vec![80]);
+        assert_eq!(host.changed.len(), 1);
+        assert_eq!(host.changed[0].new.version.as_deref(), Some("2.1"));
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let mut baseline = Output::new("10.0.0.1".to_string());
+        baseline.add_port(22, &service("SSH", Some("2.0")), "TCP".to_string());
+        baseline.add_port(80, &service("HTTP", None), "TCP".to_string());
+        baseline.add_port(3306, &service("MySQL", None), "TCP".to_string());
+
+        let mut current = Output::new("10.0.0.1".to_string());
+        current.add_port(22, &service("SSH", Some("2.1")), "TCP".to_string());
+        current.add_port(80, &service("HTTP", None), "TCP".to_string());
+        current.add_port(443, &service("HTTPS", None), "TCP".to_string());
+
+        let ports = |delta: &Output| delta.ports().iter().map(|p| p.port()).collect::<Vec<_>>();
+        let delta = changes_since(Some(&baseline), &current, ChangeLevel::Version).unwrap();
+        assert_eq!(ports(&delta), vec![22, 443]);
+        assert_eq!(
+            delta.notes(),
+            ["端口 22/TCP 有变化: SSH 2.0 -> SSH 2.1", "端口 3306/TCP 已关闭（基线中为 MySQL）"]
+        );
+
+        // 只看开放状态时版本变化不算
+        let delta = changes_since(Some(&baseline), &current, ChangeLevel::State).unwrap();
+        assert_eq!(ports(&delta), vec![443]);
+        assert_eq!(delta.notes().len(), 1);
+
+        assert!(changes_since(Some(&current), &current, ChangeLevel::Version).is_none());
+        assert_eq!(ports(&changes_since(None, &current, ChangeLevel::Service).unwrap()), vec![22, 80, 443]);
+        assert!(changes_since(None, &Output::new("10.0.0.2".to_string()), ChangeLevel::Version).is_none());
+        assert!("ports".parse::<ChangeLevel>().is_err());
+    }
+}
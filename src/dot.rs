@@ -0,0 +1,140 @@
+use crate::output::{write_file, Output};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// 按网段分组时 IPv4 和 IPv6 使用的前缀长度
+const IPV4_CLUSTER_PREFIX: u8 = 24;
+const IPV6_CLUSTER_PREFIX: u8 = 64;
+
+/// 把扫描结果转为 Graphviz DOT 图（`--dot-out`）
+///
+/// 每个主机一个节点，标签依次列出地址、标签、操作系统和开放端口上的服务；
+/// 主机按所在网段（IPv4 /24、IPv6 /64）归入子图，无法解析为地址的目标不分组。
+/// 主机名、banner 中的版本和标题都可能来自扫描目标，写入标签前全部转义。
+pub fn to_dot(outputs: &[Output]) -> String {
+    let mut clusters: BTreeMap<(IpAddr, u8), Vec<usize>> = BTreeMap::new();
+    let mut ungrouped = Vec::new();
+    for (index, output) in outputs.iter().enumerate() {
+        match subnet(output.target()) {
+            Some(subnet) => clusters.entry(subnet).or_default().push(index),
+            None => ungrouped.push(index),
+        }
+    }
+
+    let mut dot = String::new();
+    dot.push_str("digraph rustscan {\n");
+    dot.push_str("  graph [rankdir=LR, fontname=\"sans-serif\"];\n");
+    dot.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+    for (cluster, ((network, prefix), hosts)) in clusters.iter().enumerate() {
+        let _ = writeln!(dot, "  subgraph cluster_{} {{", cluster);
+        let _ = writeln!(dot, "    label=\"{}/{}\";", network, prefix);
+        for &index in hosts {
+            let _ = writeln!(dot, "    {}", host_node(index, &outputs[index]));
+        }
+        dot.push_str("  }\n");
+    }
+    for index in ungrouped {
+        let _ = writeln!(dot, "  {}", host_node(index, &outputs[index]));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// 写入 DOT 文件，`-` 表示标准输出
+pub fn save_dot(outputs: &[Output], path: &Path) -> Result<()> {
+    write_file(path, to_dot(outputs).as_bytes())
+}
+
+/// 主机节点；节点名只用序号，不含任何来自目标的内容
+fn host_node(index: usize, output: &Output) -> String {
+    let mut lines = vec![match output.label() {
+        Some(label) => format!("{} [{}]", output.target(), label),
+        None => output.target().to_string(),
+    }];
+    if let Some(os) = output.os_info() {
+        lines.push(match &os.version {
+            Some(version) => format!("{} {}", os.name, version),
+            None => os.name.clone(),
+        });
+    }
+    for port in output.ports() {
+        let mut line = format!("{}/{} {}", port.port(), port.protocol().to_lowercase(), port.service());
+        if let Some(version) = port.version() {
+            let _ = write!(line, " {}", version);
+        }
+        if let Some(title) = port.title() {
+            let _ = write!(line, " [{}]", title);
+        }
+        lines.push(line);
+    }
+    // 每行以 \l 结尾，在节点中左对齐
+    let label: String = lines.iter().map(|line| format!("{}\\l", escape(line))).collect();
+    format!("host{} [label=\"{}\"];", index, label)
+}
+
+/// 目标所在的网段，IPv6 的区域标识不参与分组
+fn subnet(target: &str) -> Option<(IpAddr, u8)> {
+    let addr = target.split('%').next()?;
+    match addr.parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX << (32 - IPV4_CLUSTER_PREFIX);
+            Some((IpAddr::from((u32::from(ip) & mask).to_be_bytes()), IPV4_CLUSTER_PREFIX))
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX << (128 - IPV6_CLUSTER_PREFIX);
+            Some((IpAddr::from((u128::from(ip) & mask).to_be_bytes()), IPV6_CLUSTER_PREFIX))
+        }
+    }
+}
+
+/// 转义双引号字符串中的内容：反斜杠和双引号加反斜杠（否则 `\l`、`\N` 等会被
+/// Graphviz 当作转义序列），换行等控制字符替换为空格，防止提前结束字符串或注入属性
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service_detector::DetectedService;
+
+    #[test]
+    fn test_dot_graph() {
+        let mut web = Output::new("10.0.0.5".into());
+        let mut service = DetectedService::new("HTTP");
+        service.version = Some("nginx\"];evil[label=\"x".into());
+        service.title = Some("a\\lb\nc".into());
+        web.add_port(80, &service, "TCP".into());
+        let mut db = Output::new("10.0.0.9".into());
+        db.add_port(5432, &DetectedService::new("PostgreSQL"), "TCP".into());
+        let other = Output::new("10.0.1.1".into());
+        let v6 = Output::new("fe80::1%2".into());
+        let named = Output::new("host\"name".into());
+
+        let dot = to_dot(&[web, db, other, v6, named]);
+        assert!(dot.starts_with("digraph rustscan {\n"));
+        assert_eq!(dot.matches("subgraph cluster_").count(), 3);
+        assert!(dot.contains("label=\"10.0.0.0/24\";"));
+        assert!(dot.contains("label=\"10.0.1.0/24\";"));
+        assert!(dot.contains("label=\"fe80::/64\";"));
+        assert!(dot.contains(r#"host0 [label="10.0.0.5\l80/tcp HTTP nginx\"];evil[label=\"x [a\\lb c]\l"];"#));
+        assert!(dot.contains(r#"host1 [label="10.0.0.9\l5432/tcp PostgreSQL\l"];"#));
+        // 不是地址的目标不分组，名称同样转义
+        assert!(dot.contains("\n  host4 [label=\"host\\\"name\\l\"];\n"));
+        // 同一网段的两个主机在同一个子图中
+        let cluster = &dot[dot.find("cluster_0").unwrap()..dot.find("cluster_1").unwrap()];
+        assert!(cluster.contains("host0") && cluster.contains("host1"));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::os_detector::OSInfo;
+use crate::service_detector::DetectedService;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// 扫描过程中实时产生的事件
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ScanEvent {
+    HostStarted {
+        target: String,
+    },
+    PortOpen {
+        target: String,
+        port: u16,
+        protocol: String,
+    },
+    ServiceDetected {
+        target: String,
+        port: u16,
+        protocol: String,
+        service: DetectedService,
+    },
+    OsDetected {
+        target: String,
+        os: OSInfo,
+    },
+    HostFinished {
+        target: String,
+        open_ports: usize,
+    },
+}
+
+impl ScanEvent {
+    /// 事件类型名，与序列化后的 `event` 字段一致
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ScanEvent::HostStarted { .. } => "host_started",
+            ScanEvent::PortOpen { .. } => "port_open",
+            ScanEvent::ServiceDetected { .. } => "service_detected",
+            ScanEvent::OsDetected { .. } => "os_detected",
+            ScanEvent::HostFinished { .. } => "host_finished",
+        }
+    }
+
+    pub fn target(&self) -> &str {
+        match self {
+            ScanEvent::HostStarted { target }
+            | ScanEvent::PortOpen { target, .. }
+            | ScanEvent::ServiceDetected { target, .. }
+            | ScanEvent::OsDetected { target, .. }
+            | ScanEvent::HostFinished { target, .. } => target,
+        }
+    }
+}
+
+/// 扫描事件的接收方，在扫描任务中同步调用，实现中不应长时间阻塞
+pub trait ScanObserver: Send + Sync {
+    fn on_event(&self, event: &ScanEvent);
+}
+
+/// 将事件分发给所有已注册的观察者
+#[derive(Clone, Default)]
+pub struct EventBus {
+    observers: Vec<Arc<dyn ScanObserver>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&mut self, observer: Arc<dyn ScanObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub fn emit(&self, event: ScanEvent) {
+        for observer in &self.observers {
+            observer.on_event(&event);
+        }
+    }
+}
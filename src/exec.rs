@@ -0,0 +1,295 @@
+use crate::scanner::ScanType;
+use crate::service_detector::DetectedService;
+use crate::target::ScanTarget;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::process::Stdio;
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+
+/// 模板中可用的占位符
+const PLACEHOLDERS: [&str; 5] = ["ip", "ports", "ports_nmap", "services", "hostname"];
+
+/// 扫描完成后执行的命令模板，如 `nmap -sV -p {{ports_nmap}} {{ip}}`
+///
+/// 模板本身由用户给出，视为可信，交给系统 shell 执行。替换进模板的值不做转义，
+/// 只允许字母、数字和 `.:,-_%`：服务名中的其他字符替换为 `_`，地址、端口和主机名
+/// 含有其他字符时拒绝执行。批量模式下多个主机的地址和主机名以空格分隔。
+#[derive(Debug, Clone)]
+pub struct ExecHook {
+    template: String,
+}
+
+/// 替换进模板的单个主机扫描结果
+#[derive(Debug, Clone)]
+pub struct ExecHost {
+    pub target: ScanTarget,
+    /// 反向解析得到的主机名，为空时 `{{hostname}}` 使用地址
+    pub hostname: Option<String>,
+    pub ports: Vec<(u16, ScanType)>,
+    pub services: Vec<String>,
+}
+
+/// 命令的执行结果，写入扫描报告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecStatus {
+    pub command: String,
+    /// 被信号终止时为空
+    pub exit_code: Option<i32>,
+    pub success: bool,
+}
+
+impl ExecHost {
+    pub fn new(target: ScanTarget, results: &[(u16, ScanType, DetectedService)]) -> Self {
+        Self {
+            target,
+            hostname: None,
+            ports: results.iter().map(|(port, scan_type, _)| (*port, *scan_type)).collect(),
+            services: results.iter().map(|(_, _, service)| service.name.clone()).collect(),
+        }
+    }
+
+    pub fn with_hostname(mut self, hostname: Option<String>) -> Self {
+        self.hostname = hostname;
+        self
+    }
+}
+
+impl FromStr for ExecHook {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        if template.trim().is_empty() {
+            return Err(anyhow!("--exec 命令模板为空"));
+        }
+        for name in placeholders(template)? {
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(anyhow!(
+                    "--exec 模板中有未知的占位符 {{{{{}}}}}（可用 {}）",
+                    name,
+                    PLACEHOLDERS.map(|name| format!("{{{{{}}}}}", name)).join("、")
+                ));
+            }
+        }
+        Ok(Self {
+            template: template.to_string(),
+        })
+    }
+}
+
+impl ExecHook {
+    /// 模板是否用到 `{{hostname}}`，用不到时无需反向解析
+    pub fn needs_hostname(&self) -> bool {
+        placeholders(&self.template).is_ok_and(|names| names.contains(&"hostname"))
+    }
+
+    /// 用主机的扫描结果替换模板中的占位符；多个主机时端口和服务取并集
+    pub fn render(&self, hosts: &[ExecHost]) -> Result<String> {
+        let ports: BTreeSet<(u16, ScanType)> = hosts.iter().flat_map(|host| host.ports.iter().copied()).collect();
+        if ports.is_empty() {
+            return Err(anyhow!("没有开放端口"));
+        }
+        if ports.iter().any(|(port, _)| *port == 0) {
+            return Err(anyhow!("无效的端口: 0"));
+        }
+
+        let mut values = Vec::new();
+        values.push(("ip", join_checked(hosts.iter().map(|host| host.target.to_string()), " ")?));
+        let numbers: BTreeSet<u16> = ports.iter().map(|(port, _)| *port).collect();
+        values.push(("ports", join_checked(numbers.iter().map(ToString::to_string), ",")?));
+        values.push(("ports_nmap", nmap_ports(&ports)));
+        let services: BTreeSet<String> = hosts
+            .iter()
+            .flat_map(|host| host.services.iter().map(|name| sanitize_service(name)))
+            .collect();
+        values.push(("services", services.into_iter().collect::<Vec<_>>().join(",")));
+        values.push((
+            "hostname",
+            join_checked(
+                hosts.iter().map(|host| host.hostname.clone().unwrap_or_else(|| host.target.to_string())),
+                " ",
+            )?,
+        ));
+
+        let mut command = self.template.clone();
+        for (name, value) in values {
+            command = command.replace(&format!("{{{{{}}}}}", name), &value);
+        }
+        Ok(command)
+    }
+
+    /// 执行命令，标准输出和标准错误逐行交给 `on_line`，等待其退出
+    pub async fn run(&self, hosts: &[ExecHost], on_line: impl Fn(&str)) -> Result<ExecStatus> {
+        let command = self.render(hosts)?;
+        let mut child = shell(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("无法执行命令 {}: {}", command, e))?;
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        tokio::join!(forward_lines(stdout, &on_line), forward_lines(stderr, &on_line));
+        let status = child.wait().await?;
+
+        Ok(ExecStatus {
+            command,
+            exit_code: status.code(),
+            success: status.success(),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+async fn forward_lines<R: AsyncRead + Unpin>(reader: Option<R>, on_line: &impl Fn(&str)) {
+    let Some(reader) = reader else {
+        return;
+    };
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        on_line(&line);
+    }
+}
+
+/// 模板中出现的所有 `{{名称}}`
+fn placeholders(template: &str) -> Result<Vec<&str>> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| anyhow!("--exec 模板中的 {{{{ 没有闭合"))?;
+        names.push(rest[start + 2..start + end].trim());
+        rest = &rest[start + end + 2..];
+    }
+    Ok(names)
+}
+
+/// nmap `-p` 的写法：连续端口合并为区间，同时有 UDP 端口时加上 `T:`/`U:` 前缀
+fn nmap_ports(ports: &BTreeSet<(u16, ScanType)>) -> String {
+    let list = |scan_type: ScanType| {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for &(port, _) in ports.iter().filter(|(_, t)| *t == scan_type) {
+            match ranges.last_mut() {
+                Some((_, end)) if end.checked_add(1) == Some(port) => *end = port,
+                _ => ranges.push((port, port)),
+            }
+        }
+        ranges
+            .iter()
+            .map(|&(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let (tcp, udp) = (list(ScanType::Tcp), list(ScanType::Udp));
+    match (tcp.is_empty(), udp.is_empty()) {
+        (_, true) => tcp,
+        (true, false) => format!("U:{}", udp),
+        (false, false) => format!("T:{},U:{}", tcp, udp),
+    }
+}
+
+fn join_checked(values: impl Iterator<Item = String>, separator: &str) -> Result<String> {
+    let values: Vec<String> = values.collect();
+    if let Some(value) = values.iter().find(|value| !is_shell_safe(value)) {
+        return Err(anyhow!("拒绝替换含特殊字符的值: {}", value));
+    }
+    Ok(values.join(separator))
+}
+
+fn sanitize_service(name: &str) -> String {
+    name.chars()
+        .map(|c| if is_shell_safe_char(c) { c } else { '_' })
+        .collect()
+}
+
+fn is_shell_safe(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(is_shell_safe_char)
+}
+
+fn is_shell_safe_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | ',' | '-' | '_' | '%')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn host(ip: &str, ports: &[(u16, ScanType, &str)]) -> ExecHost {
+        let results: Vec<_> = ports
+            .iter()
+            .map(|&(port, scan_type, name)| (port, scan_type, DetectedService::new(name)))
+            .collect();
+        ExecHost::new(ip.parse().unwrap(), &results)
+    }
+
+    #[test]
+    fn test_render() {
+        let web = host(
+            "10.0.0.1",
+            &[
+                (22, ScanType::Tcp, "SSH"),
+                (80, ScanType::Tcp, "HTTP"),
+                (81, ScanType::Tcp, "HTTP"),
+                (53, ScanType::Udp, "DNS"),
+            ],
+        )
+        .with_hostname(Some("web.lan".to_string()));
+        let ftp = host("10.0.0.2", &[(21, ScanType::Tcp, "FileZilla Server")]);
+
+        let hook: ExecHook = "nmap -sV -p {{ports_nmap}} {{ip}} # {{ports}} {{services}} {{hostname}}".parse().unwrap();
+        assert_eq!(
+            hook.render(std::slice::from_ref(&web)).unwrap(),
+            "nmap -sV -p T:22,80-81,U:53 10.0.0.1 # 22,53,80,81 DNS,HTTP,SSH web.lan"
+        );
+        assert_eq!(
+            hook.render(&[web, ftp]).unwrap(),
+            "nmap -sV -p T:21-22,80-81,U:53 10.0.0.1 10.0.0.2 # 21,22,53,80,81 DNS,FileZilla_Server,HTTP,SSH web.lan 10.0.0.2"
+        );
+        assert!(hook.needs_hostname());
+        assert!(hook.render(&[host("10.0.0.3", &[])]).is_err());
+        assert!(hook.render(&[host("10.0.0.3", &[(0, ScanType::Tcp, "")])]).is_err());
+        let evil = host("10.0.0.4", &[(22, ScanType::Tcp, "SSH")]).with_hostname(Some("x;rm -rf".to_string()));
+        assert!(hook.render(&[evil]).is_err());
+
+        assert!("nmap {{host}}".parse::<ExecHook>().is_err());
+        assert!("nmap {{ip".parse::<ExecHook>().is_err());
+        assert!("  ".parse::<ExecHook>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_hook() {
+        let lines = Mutex::new(Vec::new());
+        let hook: ExecHook = "echo {{ip}} {{ports}}; echo err >&2; exit 3".parse().unwrap();
+        let hosts = [host("127.0.0.1", &[(22, ScanType::Tcp, "SSH"), (80, ScanType::Tcp, "HTTP")])];
+        let status = hook
+            .run(&hosts, |line| lines.lock().unwrap().push(line.to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(status.exit_code, Some(3));
+        assert!(!status.success);
+        let mut lines = lines.into_inner().unwrap();
+        lines.sort();
+        assert_eq!(lines, vec!["127.0.0.1 22,80", "err"]);
+    }
+}
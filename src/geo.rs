@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// 主机的网络归属：自治系统号、所属组织和国家/地区
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeoInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    /// ISO 3166-1 国家/地区代码，如 `US`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+impl GeoInfo {
+    pub fn is_empty(&self) -> bool {
+        self.asn.is_none() && self.org.is_none() && self.country.is_none()
+    }
+
+    /// 用另一份记录补全缺少的字段
+    fn fill_from(&mut self, other: GeoInfo) {
+        self.asn = self.asn.or(other.asn);
+        self.org = self.org.take().or(other.org);
+        self.country = self.country.take().or(other.country);
+    }
+}
+
+/// 如 `AS15169 GOOGLE, US`
+impl fmt::Display for GeoInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let owner = [self.asn.map(|asn| format!("AS{}", asn)), self.org.clone()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let parts: Vec<&str> = [Some(owner.as_str()).filter(|owner| !owner.is_empty()), self.country.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// 查询地址归属的函数，查不到时返回 `None`
+///
+/// 程序不附带数据库，默认实现读取用户提供的 MaxMind 数据库（见 [`maxmind_lookup`]），
+/// 也可以换成任何其他数据来源。
+pub type GeoLookup = Arc<dyn Fn(IpAddr) -> Option<GeoInfo> + Send + Sync>;
+
+/// 读取一个或多个 MaxMind 格式的数据库（`.mmdb`），如 GeoLite2-ASN 和 GeoLite2-Country
+///
+/// 依次查询每个数据库，ASN 和组织取自 ASN/ISP 库，国家取自 Country/City 库，
+/// 前面的数据库已有的字段不会被后面的覆盖。
+pub fn maxmind_lookup(paths: &[PathBuf]) -> Result<GeoLookup> {
+    let readers = paths
+        .iter()
+        .map(|path| Reader::open_readfile(path).map_err(|e| anyhow!("无法读取归属数据库 {}: {}", path.display(), e)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(move |ip| {
+        let mut info = GeoInfo::default();
+        for reader in &readers {
+            info.fill_from(maxmind_record(reader, ip));
+        }
+        (!info.is_empty()).then_some(info)
+    }))
+}
+
+/// 同一个数据库按 ASN 和国家两种结构解码，不含的字段为空
+fn maxmind_record(reader: &Reader<Vec<u8>>, ip: IpAddr) -> GeoInfo {
+    let asn = reader.lookup::<geoip2::Asn>(ip).ok();
+    let country = reader
+        .lookup::<geoip2::Country>(ip)
+        .ok()
+        .and_then(|record| record.country)
+        .and_then(|country| country.iso_code);
+    GeoInfo {
+        asn: asn.as_ref().and_then(|asn| asn.autonomous_system_number),
+        org: asn
+            .as_ref()
+            .and_then(|asn| asn.autonomous_system_organization)
+            .map(str::to_string),
+        country: country.map(str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geo_lookup() {
+        let mut info = GeoInfo {
+            asn: Some(15169),
+            org: Some("GOOGLE".to_string()),
+            country: None,
+        };
+        info.fill_from(GeoInfo {
+            asn: Some(1),
+            org: None,
+            country: Some("US".to_string()),
+        });
+        assert_eq!(info.asn, Some(15169));
+        assert_eq!(info.country.as_deref(), Some("US"));
+        assert_eq!(info.to_string(), "AS15169 GOOGLE, US");
+        assert!(GeoInfo::default().is_empty());
+        assert_eq!(
+            serde_json::to_value(GeoInfo { asn: Some(13335), ..GeoInfo::default() }).unwrap(),
+            serde_json::json!({ "asn": 13335 })
+        );
+
+        // 自定义查询函数
+        let lookup: GeoLookup = Arc::new(|ip: IpAddr| {
+            ip.is_loopback().then(|| GeoInfo {
+                org: Some("localhost".to_string()),
+                ..GeoInfo::default()
+            })
+        });
+        assert!(lookup("127.0.0.1".parse().unwrap()).is_some());
+        assert!(lookup("192.0.2.1".parse().unwrap()).is_none());
+
+        // 在结果中与 target 同级，未设置时不输出
+        let mut output = crate::output::Output::new("127.0.0.1".to_string());
+        assert!(!serde_json::to_value(&output).unwrap().as_object().unwrap().contains_key("org"));
+        output.set_geo(lookup("127.0.0.1".parse().unwrap()));
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["org"], "localhost");
+        let loaded: crate::output::Output = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.geo().and_then(|geo| geo.org.as_deref()), Some("localhost"));
+
+        assert!(maxmind_lookup(&[PathBuf::from("/nonexistent/GeoLite2-ASN.mmdb")]).is_err());
+    }
+}
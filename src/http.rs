@@ -0,0 +1,300 @@
+use anyhow::{anyhow, Result};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// HTTP 探测读取的最大长度（响应头加正文开头）
+pub const MAX_HTTP_LEN: usize = 8192;
+
+/// 解压后正文的最大长度，防止压缩炸弹
+const MAX_DECODED_LEN: u64 = 64 * 1024;
+
+/// 页面标题的最大保留长度
+const MAX_TITLE_LEN: usize = 200;
+
+/// 从 HTTP 响应中提取的信息
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HttpInfo {
+    pub status: u16,
+    pub title: Option<String>,
+    /// 3xx 响应的 `Location`
+    pub location: Option<String>,
+}
+
+/// 以明文 HTTP 探测的常见 Web 端口
+pub fn is_http_port(port: u16) -> bool {
+    matches!(port, 80 | 3000 | 8000 | 8008 | 8080 | 8888)
+}
+
+/// 通常运行 HTTPS 的端口
+pub fn is_https_port(port: u16) -> bool {
+    matches!(port, 443 | 8443)
+}
+
+/// 用户指定的请求头，格式为 `名称: 值`，如 `User-Agent: curl/8.5.0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for HttpHeader {
+    type Err = anyhow::Error;
+
+    fn from_str(header: &str) -> Result<Self> {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| anyhow!("请求头 {:?} 缺少冒号，格式应为 \"名称: 值\"", header))?;
+        let name = name.trim();
+        let value = value.trim();
+        // 名称只能是 RFC 9110 的 token 字符，值不能含换行等控制字符，防止拼出额外的请求头
+        let is_token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        if name.is_empty() || !name.chars().all(is_token) {
+            return Err(anyhow!("无效的请求头名称: {:?}", name));
+        }
+        if value.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(anyhow!("请求头 {} 的值含有控制字符", name));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// 构造探测请求；要求不压缩、短连接，方便直接读取正文
+///
+/// `headers` 追加在默认请求头之后，与默认请求头同名（不区分大小写）时替换默认值。
+pub fn build_request(addr: SocketAddr, headers: &[HttpHeader]) -> String {
+    let host = match (addr, addr.port()) {
+        (SocketAddr::V4(v4), 80) => v4.ip().to_string(),
+        (SocketAddr::V6(v6), 80) => format!("[{}]", v6.ip()),
+        _ => addr.to_string(),
+    };
+    let defaults = [
+        ("Host", host.as_str()),
+        ("User-Agent", "rustscan"),
+        ("Accept", "*/*"),
+        ("Accept-Encoding", "identity"),
+        ("Connection", "close"),
+    ];
+    let mut request = "GET / HTTP/1.1\r\n".to_string();
+    for (name, value) in defaults {
+        if !headers.iter().any(|header| header.name.eq_ignore_ascii_case(name)) {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    for header in headers {
+        request.push_str(&format!("{}: {}\r\n", header.name, header.value));
+    }
+    request.push_str("\r\n");
+    request
+}
+
+/// 响应头已完整读到
+pub fn has_header_end(data: &[u8]) -> bool {
+    data.windows(4).any(|w| w == b"\r\n\r\n")
+}
+
+/// 已读到 `</title>` 即可停止，避免等待大页面
+pub fn has_title_end(data: &[u8]) -> bool {
+    data.windows(8).any(|w| w.eq_ignore_ascii_case(b"</title>"))
+}
+
+/// 解析 HTTP 响应的状态码、标题和重定向地址
+///
+/// 正文可能被截断；分块编码会先拼接各块，服务器无视 `identity` 仍返回的
+/// gzip/deflate 正文会先解压，其它压缩格式不解析标题。
+pub fn parse_response(response: &[u8]) -> Option<HttpInfo> {
+    let header_end = response.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let body = &response[header_end + 4..];
+
+    let mut lines = head.lines();
+    let status_line = lines.next()?;
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status: u16 = status_line.split_whitespace().nth(1)?.parse().ok()?;
+
+    let mut info = HttpInfo {
+        status,
+        ..Default::default()
+    };
+    let mut chunked = false;
+    let mut encoding = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("location") && (300..400).contains(&status) {
+            info.location = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        } else if name.eq_ignore_ascii_case("content-encoding") {
+            encoding = Some(value.to_ascii_lowercase()).filter(|value| value != "identity");
+        }
+    }
+
+    let body = if chunked { dechunk(body) } else { body.to_vec() };
+    let body = match encoding.as_deref() {
+        None => Some(body),
+        Some(encoding) => decode(&body, encoding),
+    };
+    if let Some(body) = body {
+        info.title = extract_title(&String::from_utf8_lossy(&body));
+    }
+    Some(info)
+}
+
+/// 解压 gzip/deflate 正文，最多输出 `MAX_DECODED_LEN` 字节
+///
+/// 正文常被截断，因此解压出错时保留已解出的部分。不支持的编码返回 None。
+fn decode(body: &[u8], encoding: &str) -> Option<Vec<u8>> {
+    let reader: Box<dyn Read + '_> = match encoding {
+        "gzip" | "x-gzip" => Box::new(GzDecoder::new(body)),
+        // 规范要求 zlib 封装，但不少服务器直接发送裸 deflate 流
+        "deflate" if has_zlib_header(body) => Box::new(ZlibDecoder::new(body)),
+        "deflate" => Box::new(DeflateDecoder::new(body)),
+        _ => return None,
+    };
+    let mut reader = reader.take(MAX_DECODED_LEN);
+    let mut decoded = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => decoded.extend_from_slice(&buf[..n]),
+        }
+    }
+    Some(decoded)
+}
+
+/// 拼接分块编码的各块，遇到截断时返回已读到的部分
+fn dechunk(mut data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    while let Some(line_end) = data.windows(2).position(|w| w == b"\r\n") {
+        let size_line = String::from_utf8_lossy(&data[..line_end]);
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let Ok(size) = usize::from_str_radix(size_hex, 16) else {
+            break;
+        };
+        if size == 0 {
+            break;
+        }
+        data = &data[line_end + 2..];
+        let take = size.min(data.len());
+        body.extend_from_slice(&data[..take]);
+        if take < size || data.len() < size + 2 {
+            break;
+        }
+        data = &data[size + 2..];
+    }
+    body
+}
+
+/// zlib 头：压缩方法为 deflate，且前两字节按大端组成的数能被 31 整除
+fn has_zlib_header(body: &[u8]) -> bool {
+    match body {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+fn extract_title(body: &str) -> Option<String> {
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    let re = TITLE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+    let raw = re.captures(body)?.get(1)?.as_str();
+    let title: String = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        return None;
+    }
+    Some(title.chars().take(MAX_TITLE_LEN).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let response = b"HTTP/1.1 200 OK\r\nServer: nginx\r\nContent-Type: text/html\r\n\r\n<html><head><TITLE>\n  Welcome to  nginx!\n</TITLE></head>";
+        let info = parse_response(response).unwrap();
+        assert_eq!(info.status, 200);
+        assert_eq!(info.title.as_deref(), Some("Welcome to nginx!"));
+        assert_eq!(info.location, None);
+
+        let redirect = b"HTTP/1.1 301 Moved Permanently\r\nLocation: https://example.com/\r\nContent-Length: 0\r\n\r\n";
+        let info = parse_response(redirect).unwrap();
+        assert_eq!(info.location.as_deref(), Some("https://example.com/"));
+        assert_eq!(info.title, None);
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n7\r\n<title>\r\n9\r\nDashboard\r\n8\r\n</title>\r\n0\r\n\r\n";
+        assert_eq!(parse_response(chunked).unwrap().title.as_deref(), Some("Dashboard"));
+
+        let brotli = b"HTTP/1.1 200 OK\r\nContent-Encoding: br\r\n\r\n<title>x</title>";
+        assert_eq!(parse_response(brotli).unwrap().title, None);
+
+        assert!(parse_response(b"SSH-2.0-OpenSSH_9.6\r\n").is_none());
+    }
+
+    #[test]
+    fn test_decode_compressed() {
+        use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        let page = b"<html><head><title>Compressed</title></head></html>";
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(page).unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(page).unwrap();
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(page).unwrap();
+
+        for (encoding, body) in [
+            ("gzip", gzip.finish().unwrap()),
+            ("deflate", zlib.finish().unwrap()),
+            ("deflate", raw.finish().unwrap()),
+        ] {
+            let mut response = format!("HTTP/1.1 200 OK\r\nContent-Encoding: {}\r\n\r\n", encoding).into_bytes();
+            response.extend_from_slice(&body);
+            assert_eq!(parse_response(&response).unwrap().title.as_deref(), Some("Compressed"));
+        }
+
+        // 解压结果不超过上限
+        let mut bomb = GzEncoder::new(Vec::new(), Compression::best());
+        bomb.write_all(&vec![b'a'; 1024 * 1024]).unwrap();
+        let decoded = decode(&bomb.finish().unwrap(), "gzip").unwrap();
+        assert_eq!(decoded.len() as u64, MAX_DECODED_LEN);
+    }
+
+    #[test]
+    fn test_build_request() {
+        let request = build_request("[2001:db8::1]:8080".parse().unwrap(), &[]);
+        assert!(request.contains("Host: [2001:db8::1]:8080\r\n"));
+        let request = build_request("10.0.0.1:80".parse().unwrap(), &[]);
+        assert!(request.contains("Host: 10.0.0.1\r\n"));
+        assert!(request.ends_with("Connection: close\r\n\r\n"));
+
+        // 自定义请求头替换同名的默认值，其余追加在后面
+        let headers: Vec<HttpHeader> = ["user-agent: Mozilla/5.0", "Authorization:Basic YWRtaW46YWRtaW4="]
+            .iter()
+            .map(|header| header.parse().unwrap())
+            .collect();
+        let request = build_request("10.0.0.1:80".parse().unwrap(), &headers);
+        assert!(!request.contains("User-Agent: rustscan"));
+        assert!(request.contains("user-agent: Mozilla/5.0\r\n"));
+        assert!(request.ends_with("Authorization: Basic YWRtaW46YWRtaW4=\r\n\r\n"));
+
+        assert!("User-Agent".parse::<HttpHeader>().is_err());
+        assert!(": value".parse::<HttpHeader>().is_err());
+        assert!("X Bad: 1".parse::<HttpHeader>().is_err());
+        assert!("X-Test: a\r\nHost: evil".parse::<HttpHeader>().is_err());
+    }
+}
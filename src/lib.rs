@@ -3,6 +3,34 @@ pub mod service_detector;
 pub mod os_detector;
 pub mod output;
 pub mod service_fingerprints;
+pub mod http;
 pub mod rate_controller;
 pub mod progress;
-pub mod ping; 
\ No newline at end of file
+pub mod progress_socket;
+pub mod ping;
+pub mod capture;
+pub mod diff;
+pub mod target;
+pub mod diagnostics;
+pub mod source_port;
+pub mod events;
+pub mod syslog;
+pub mod limits;
+pub mod udp_probes;
+pub mod exec;
+pub mod resolver;
+pub mod connector;
+pub mod payload;
+pub mod geo;
+pub mod tls;
+pub mod anomaly;
+pub mod relay;
+pub mod tls_audit;
+pub mod metrics;
+pub mod dot;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "ssh")]
+pub mod ssh_jump;
+#[cfg(feature = "tui")]
+pub mod tui;
@@ -0,0 +1,42 @@
+/// 为标准输入输出、输出文件、服务识别连接等保留的最少文件描述符
+const MIN_FD_HEADROOM: u64 = 32;
+
+/// 预留的文件描述符：上限的 10%，至少 `MIN_FD_HEADROOM` 个
+fn fd_headroom(fd_limit: u64) -> u64 {
+    (fd_limit / 10).max(MIN_FD_HEADROOM)
+}
+
+/// 按文件描述符上限限制并发连接数，避免 EMFILE 被误判为端口关闭
+pub fn cap_concurrency(requested: usize, fd_limit: u64) -> usize {
+    let usable = fd_limit.saturating_sub(fd_headroom(fd_limit)).max(1);
+    requested.min(usize::try_from(usable).unwrap_or(usize::MAX)).max(1)
+}
+
+/// 启动时校准并发数：先尝试把 NOFILE 软上限提高到硬上限以内的所需值，
+/// 仍不足时降低并发。返回实际并发数和当前软上限（无法查询时为 `None`）。
+pub fn calibrate_concurrency(requested: usize) -> (usize, Option<u64>) {
+    let wanted = (requested as u64 + MIN_FD_HEADROOM) * 10 / 9 + 1;
+    match rlimit::increase_nofile_limit(wanted) {
+        Ok(fd_limit) => (cap_concurrency(requested, fd_limit), Some(fd_limit)),
+        Err(_) => (requested, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cap_concurrency() {
+        // macOS 默认软上限 256
+        assert_eq!(cap_concurrency(1000, 256), 224);
+        assert_eq!(cap_concurrency(100, 256), 100);
+        assert_eq!(cap_concurrency(5000, 4096), 3687);
+        assert_eq!(cap_concurrency(1000, 1 << 20), 1000);
+        assert_eq!(cap_concurrency(1000, 16), 1);
+
+        // 申请的上限应足以容纳请求的并发数
+        let wanted = (1000 + MIN_FD_HEADROOM) * 10 / 9 + 1;
+        assert_eq!(cap_concurrency(1000, wanted), 1000);
+    }
+}
@@ -1,34 +1,141 @@
-mod scanner;
-mod service_detector;
-mod os_detector;
-mod output;
-mod service_fingerprints;
-mod rate_controller;
-mod progress;
-
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 use colored::*;
-use std::net::{IpAddr, Ipv4Addr};
 use std::time::Duration;
 use anyhow::Result;
 use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::process::ExitCode;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore};
 
 use rustscan::scanner::{Scanner, ScanType};
-use rustscan::service_detector::ServiceDetector;
-use rustscan::os_detector::OSDetector;
-use rustscan::output::Output;
+use rustscan::service_detector::{
+    BannerCache, DetectedService, ServiceDetector, BACKEND_SAMPLES, DEFAULT_DETECT_CONCURRENCY, DEFAULT_HOST_DETECT_CONCURRENCY,
+};
+use rustscan::service_fingerprints::{ListFormat, ServiceFingerprintDB, DEFAULT_PROBE_THRESHOLD};
+use rustscan::os_detector::{hop_count, OSDetector};
+use rustscan::http::HttpHeader;
+use rustscan::output::{collapse_host_ranges, is_stdio, write_file, FileFormat, FileSink, Invocation, Output, OutputSinks, PerHostSink, StreamWriter};
+use rustscan::diff::{changes_since, diff_outputs, ChangeLevel};
+use rustscan::resolver::{expand_targets, AddressFamily, TargetResolver};
+use rustscan::target::{parse_endpoints, parse_ports, port_range, reverse_lookup, shuffle_targets, start_jitter, subnet_broadcast, ExcludeList, ScanTarget, TOP_TCP_PORTS, TOP_UDP_PORTS};
 use rustscan::progress::ScanProgress;
-use rustscan::ping::ping;
-use rustscan::rate_controller::RateController;
+use rustscan::progress_socket::{ProgressSocket, PROGRESS_INTERVAL};
+use rustscan::ping::{DiscoveryMethod, Pinger, ALL_HOSTS_MULTICAST};
+use rustscan::anomaly::{find_anomalies, HostSignals};
+use rustscan::tls_audit::is_tls_service;
+use rustscan::metrics::ScanMetrics;
+use rustscan::dot::save_dot;
+use rustscan::capture::PacketCapture;
+use rustscan::diagnostics::Diagnostics;
+use rustscan::limits::calibrate_concurrency;
+use rustscan::source_port::SourcePorts;
+use rustscan::events::{EventBus, ScanEvent};
+use rustscan::exec::{ExecHook, ExecHost};
+use rustscan::payload::{hexdump, parse_payload, send_payload};
+use rustscan::connector::{Connector, TcpConnector};
+#[cfg(feature = "ssh")]
+use rustscan::ssh_jump::{HostKeyStatus, SshAuth, SshJump, SshJumpConnector};
+use rustscan::geo::maxmind_lookup;
+#[cfg(feature = "tui")]
+use rustscan::tui::{Tui, TuiObserver};
+use rustscan::syslog::{parse_facility, parse_severity, SyslogSink};
+use rustscan::rate_controller::{scale_timeout, RateController};
+
+/// 结果写到标准输出（`-j -`/`-C -`）时，提示信息改写到标准错误，避免混进结果数据
+macro_rules! report {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
+#[command(group(clap::ArgGroup::new("seeded").args(["randomize_hosts", "start_jitter"]).multiple(true)))]
 struct Args {
-    /// 目标IP地址或网段 (例如: 192.168.1.1 或 192.168.1.0/24)
-    #[arg(short = 'i', long)]
-    target: String,
+    #[cfg(feature = "server")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// 目标IP地址、网段或主机名，可用逗号分隔多个 (例如: 192.168.1.1、192.168.1.0/24、fe80::1%eth0 或 example.com)
+    #[arg(short = 'i', long, required_unless_present_any = ["diff", "endpoints", "fingerprint_stats", "list_fingerprints", "diagnostics"], conflicts_with = "endpoints")]
+    target: Option<String>,
+
+    /// 解析主机名使用的 DNS 服务器，默认使用系统配置
+    #[arg(long, value_name = "IP")]
+    dns_server: Option<IpAddr>,
+
+    /// 只扫描 IPv4 地址：主机名只取 A 记录，目标中直接给出 IPv6 地址时报错
+    #[arg(short = '4', long = "ipv4", conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// 只扫描 IPv6 地址：主机名只取 AAAA 记录，目标中直接给出 IPv4 地址时报错
+    #[arg(short = '6', long = "ipv6")]
+    ipv6: bool,
+
+    /// 从文件读取 host:port 端点列表（每行一个，IPv6 写作 [地址]:端口），只扫描这些端点
+    #[arg(long)]
+    endpoints: Option<PathBuf>,
+
+    /// 从目标中排除的地址或网段，逗号分隔 (例如: 10.0.0.0/8,192.168.1.5)，对 --endpoints 同样生效
+    #[arg(long, value_name = "LIST")]
+    exclude: Option<ExcludeList>,
+
+    /// 安全模式：目标中含有公网地址时拒绝扫描（只允许 RFC 1918 私有网段、回环和链路本地地址）
+    #[arg(long, default_value_t = false)]
+    private_only: bool,
+
+    /// 配合 --private-only 使用，确认要扫描公网地址
+    #[arg(long, default_value_t = false)]
+    allow_public: bool,
+
+    /// 展开 IPv4 网段时也扫描网络地址和广播地址（默认跳过），适用于点对点链路等这两个地址也会应答的网络
+    #[arg(long, default_value_t = false)]
+    include_network_broadcast: bool,
+
+    /// 端口列表，如 22,80,8000-8100；`all` 或 `-p-` 表示全部 65535 个端口，指定后忽略 -s/-e
+    #[arg(long, conflicts_with_all = ["start_port", "end_port", "endpoints"])]
+    ports: Option<String>,
+
+    /// UDP 扫描使用的端口列表（格式同 --ports），未指定时与 TCP 相同
+    #[arg(long, conflicts_with = "endpoints")]
+    udp_ports: Option<String>,
+
+    /// 没有任何应答（open|filtered）的 UDP 端口也按开放报告；默认只报告收到应答的端口
+    #[arg(long, default_value_t = false)]
+    udp_lenient: bool,
+
+    /// UDP 探测按这个 MTU 限制专属载荷（如 SNMP、NTP 请求）的大小，默认使用内核记录的到目标的路径 MTU
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u16).range(68..))]
+    mtu: Option<u16>,
+
+    /// 允许 UDP 专属载荷超出 MTU 时分片发送；默认设置 DF 位，放不下的载荷改发空数据报
+    #[arg(long, default_value_t = false)]
+    allow_fragmentation: bool,
+
+    /// 一分钟内的快速筛查预设：常见 100 个 TCP 端口和 20 个 UDP 端口、超时 500ms、服务识别超时 2000ms、
+    /// 并发 500、做服务识别但跳过操作系统识别；命令行显式给出的选项优先
+    #[arg(long, default_value_t = false)]
+    quick: bool,
+
+    /// 时序模板 0-5（如 -T4），从慢到快依次为 paranoid、sneaky、polite、normal、aggressive、insane，
+    /// 同时设定 -o、-c、--scan-delay、--max-rate 和 --retries；命令行显式给出的选项优先
+    #[arg(short = 'T', long = "timing", value_name = "0-5", value_parser = clap::value_parser!(u8).range(0..=5))]
+    timing: Option<u8>,
+
+    /// 同一主机相邻两次探测之间的最小间隔（毫秒），设置后该主机的探测实际上逐个进行
+    #[arg(long, default_value_t = 0)]
+    scan_delay: u64,
+
+    /// 每个主机每秒最多发出的探测数，默认随并发数自适应
+    #[arg(long)]
+    max_rate: Option<u64>,
 
     /// 起始端口
     #[arg(short = 's', long, default_value_t = 1)]
@@ -42,158 +149,1305 @@ struct Args {
     #[arg(short = 'o', long, default_value_t = 200)]
     timeout: u64,
 
+    /// 服务识别超时时间（毫秒）。读取 banner、等待 HTTP 响应通常比建立连接慢得多，应明显大于 -o
+    #[arg(long, default_value_t = 5000)]
+    service_timeout: u64,
+
+    /// 读取单个服务响应的总时限（毫秒），默认与 --service-timeout 相同。连上后不发数据或逐字节慢速发送的服务最多占用这么久
+    #[arg(long)]
+    service_read_timeout: Option<u64>,
+
+    /// 识别没有专属指纹的端口时，按开销从低到高依次探测，命中权重不低于该值（0-1）的指纹即停止
+    #[arg(long, default_value_t = DEFAULT_PROBE_THRESHOLD, value_parser = parse_probe_threshold)]
+    probe_threshold: f32,
+
+    /// 服务识别和系统识别的 HTTP 探测附加的请求头，格式为 '名称: 值'，可重复指定；与默认请求头同名时替换默认值
+    #[arg(long = "http-header", value_name = "HEADER")]
+    http_headers: Vec<HttpHeader>,
+
+    /// 连接超时后的最大重试次数（指数退避并加入随机抖动）
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// 每个主机所有端口共用的重试总次数上限，用完后超时的端口不再重试、按被过滤处理，避免一个反复超时的主机拖慢整个扫描；默认不限制
+    #[arg(long, value_name = "N")]
+    retry_budget: Option<u32>,
+
+    /// 对每个目标重复扫描的轮数；大于 1 时报告各端口开放的轮数，并标出时开时关的端口
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    passes: u32,
+
     /// 并发数
     #[arg(short = 'c', long, default_value_t = 1000)]
     threads: usize,
 
-    /// 扫描类型 (tcp/udp)
+    /// 同时扫描的目标主机数；每个主机各自最多 --threads 个并发连接，总连接数最多为两者之积
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..))]
+    host_concurrency: u64,
+
+    /// 扫描类型 (tcp/udp)，可用逗号同时指定多种，如 tcp,udp
     #[arg(short = 't', long, default_value = "tcp")]
     scan_type: String,
 
-    /// 输出JSON文件路径
+    /// 输出JSON文件路径，`-` 表示写到标准输出
     #[arg(short = 'j', long)]
     json_output: Option<PathBuf>,
 
-    /// 输出CSV文件路径
+    /// 输出CSV文件路径，`-` 表示写到标准输出
     #[arg(short = 'C', long)]
     csv_output: Option<PathBuf>,
 
+    /// JSON 结果中附带每个端口匹配到的完整指纹（名称、匹配模式、权重、CPE 等），用于核对识别依据
+    #[arg(long, default_value_t = false)]
+    debug_fingerprints: bool,
+
+    /// 在 JSON 结果中记录命令行和实际生效的扫描参数（目标、端口、超时、并发、速率、扫描类型、版本等），便于复现和审计；--http-header 的值不会写入
+    #[arg(long, default_value_t = false)]
+    record_invocation: bool,
+
+    /// 记录在每个主机结果中的标签（如 client-a-dmz），合并多次、多个范围的扫描结果时用于区分
+    #[arg(long)]
+    label: Option<String>,
+
+    /// MaxMind 格式的 IP 归属数据库（.mmdb，如 GeoLite2-ASN、GeoLite2-Country），为每个主机标注 ASN、组织和国家；可重复指定以组合多个数据库
+    #[arg(long = "geo-db", value_name = "PATH")]
+    geo_db: Vec<PathBuf>,
+
+    /// 合并进已有的输出文件而不是覆盖：JSON 按主机合并（同一端口以本次结果为准），CSV 在末尾追加行
+    #[arg(long, default_value_t = false)]
+    append: bool,
+
+    /// 每个主机完成后立即写入 -j/-C 的结果文件，扫描中途崩溃也不会丢失已完成的主机；JSON 为每行一个主机的数组（扩展名为 .jsonl 时写 JSON Lines），被截断后仍可用 --diff/--since 读取
+    #[arg(long, default_value_t = false, conflicts_with = "append")]
+    stream_output: bool,
+
+    /// 扫描结束后以 Prometheus 文本格式写入汇总指标（主机数、按服务分组的开放端口数、扫描耗时），供 node_exporter 的 textfile collector 读取
+    #[arg(long, value_name = "FILE")]
+    metrics_out: Option<PathBuf>,
+
+    /// 扫描结束后写入 Graphviz DOT 图：每个有开放端口的主机一个节点，标出端口和服务，按网段分组；可用 `dot -Tsvg` 渲染
+    #[arg(long, value_name = "FILE")]
+    dot_out: Option<PathBuf>,
+
     /// 是否只扫描存活主机
     #[arg(short = 'p', long, default_value_t = false)]
     ping_only: bool,
+
+    /// 存活检测方式 (tcp/icmp/both)：tcp 同时连接 80/443/22/3389，icmp 发送回显请求（需要 root），both 两者同时进行
+    #[arg(long, default_value = "both")]
+    discovery_method: DiscoveryMethod,
+
+    /// 扫描网段前向广播地址和 224.0.0.1 发送 ICMP 回显，应答主机直接视为存活（需要原始套接字，仅本地链路有效）
+    #[arg(long, default_value_t = false)]
+    broadcast_ping: bool,
+
+    /// 跳过操作系统识别（每个目标可节省数秒）
+    #[arg(long, default_value_t = false)]
+    no_os_scan: bool,
+
+    /// 跳过服务识别，开放端口仅按端口号标注服务名
+    #[arg(long, default_value_t = false)]
+    no_service_scan: bool,
+
+    /// 不按端口号猜测服务名：指纹或探测没有确认的端口一律报告为 unknown（如 3306 上的自研服务不会被标成 MySQL）
+    #[arg(long, default_value_t = false)]
+    no_service_guess: bool,
+
+    /// 接受连接后未发送任何数据就关闭或重置的端口报告为 tcpwrapped（识别方式 closed），默认视为识别失败
+    #[arg(long, default_value_t = false)]
+    report_resets: bool,
+
+    /// 输出目录，相对路径的 JSON/CSV 输出文件写入该目录
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// 每个主机单独输出 <输出目录>/<IP>.json 与 .csv
+    #[arg(long, default_value_t = false, requires = "output_dir")]
+    per_host: bool,
+
+    /// 对比两次扫描保存的 JSON 结果（旧 新，其中一个可以是 `-` 表示标准输入），不进行扫描；配合 -j 输出 JSON 格式的差异
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    diff: Option<Vec<PathBuf>>,
+
+    /// 不扫描，直接向 --ports 或 --endpoints 指定的端口发送载荷并打印应答（转义文本和十六进制），用于调试协议和编写指纹；
+    /// 载荷为十六进制（如 0x48454c500d0a）或 @文件路径
+    #[arg(long, value_name = "HEX|@FILE")]
+    send_payload: Option<String>,
+
+    /// 照常扫描全部目标，但只输出和保存相对之前 JSON 结果的变化：新开放、已关闭以及服务有变化的端口，无变化的主机不输出
+    #[arg(long, value_name = "PRIOR_JSON", conflicts_with = "diff")]
+    since: Option<PathBuf>,
+
+    /// --since 判断端口有变化的粒度 (state/service/version)：state 只看开放/关闭，service 另外比较服务名，version 另外比较版本
+    #[arg(long, default_value = "version", requires = "since")]
+    changed_by: ChangeLevel,
+
+    /// 将原始套接字探测（ICMP 存活检测）收发的报文写入 pcap 文件，需要 root 或 CAP_NET_RAW
+    #[arg(long)]
+    pcap_out: Option<PathBuf>,
+
+    /// 统计 fingerprints.json 的覆盖情况（各端口指纹数、缺少指纹的常见端口、无效正则），不进行扫描
+    #[arg(long, default_value_t = false)]
+    fingerprint_stats: bool,
+
+    /// 列出加载的全部指纹（端口、名称、协议、匹配规则、权重、CPE），不进行扫描；
+    /// 格式为 table（默认）或 json（与 fingerprints.json 相同，可作为自定义指纹文件的起点）
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "table")]
+    list_fingerprints: Option<ListFormat>,
+
+    /// fingerprints.json 中存在无法编译的正则时直接退出，而不是仅给出警告
+    #[arg(long, default_value_t = false)]
+    strict_fingerprints: bool,
+
+    /// 列出本机网络接口与地址、默认出口源地址，检查原始套接字与 root 权限，不进行扫描
+    #[arg(long, visible_alias = "interface-list", default_value_t = false)]
+    diagnostics: bool,
+
+    /// 连接扫描的源端口范围（如 40000-40100 或 53），每次探测轮换
+    #[arg(long, value_name = "START-END")]
+    source_port_range: Option<String>,
+
+    /// 端口探测和存活检测发出报文的 TTL（IPv6 为跳数限制），默认使用系统设置；设得较小可只探测到指定跳数为止
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    ttl: Option<u8>,
+
+    /// 端口探测连接成功后立即以 RST 中止（SO_LINGER 为 0），不走正常关闭流程，减少对端的连接记录和状态占用
+    #[arg(long, default_value_t = false)]
+    abort_on_open: bool,
+
+    /// 不根据 ICMP 应答 TTL 估算的跳数放宽远端主机的连接超时（TTL 来自操作系统识别或存活检测，不会为此单独发送探测）
+    #[arg(long, default_value_t = false)]
+    no_timeout_scaling: bool,
+
+    /// 实时将开放端口、服务和操作系统识别结果以 RFC 5424 格式发送到 syslog 服务器 (host:port)
+    #[arg(long, value_name = "HOST:PORT")]
+    syslog: Option<String>,
+
+    /// syslog 传输方式 (udp/tcp)
+    #[arg(long, default_value = "udp", requires = "syslog")]
+    syslog_proto: String,
+
+    /// syslog facility（名称如 local0，或 0-23）
+    #[arg(long, default_value = "local0", requires = "syslog")]
+    syslog_facility: String,
+
+    /// syslog severity（名称如 notice，或 0-7）
+    #[arg(long, default_value = "notice", requires = "syslog")]
+    syslog_severity: String,
+
+    /// 在该路径监听 Unix 域套接字（已存在的命名管道则直接写入），以每行一个 JSON 的形式推送扫描进度和结果，供图形界面等前端使用
+    #[arg(long, value_name = "PATH")]
+    progress_socket: Option<PathBuf>,
+
+    /// 全屏交互界面：实时显示各主机状态、新发现的开放端口和当前探测速率，扫描在后台进行；标准错误不是终端时退回进度条
+    #[cfg(feature = "tui")]
+    #[arg(long, default_value_t = false)]
+    tui: bool,
+
+    /// 每个主机发现这么多开放端口后停止扫描其余端口（仅 TCP），适合快速筛选有服务的主机
+    #[arg(long)]
+    max_open_per_host: Option<usize>,
+
+    /// 每个主机最多对 N 个开放端口做服务识别，优先常见端口，其余只按端口号标注；防止 tarpit 等开放大量端口的主机拖慢识别
+    #[arg(long, value_name = "N")]
+    max_detect: Option<usize>,
+
+    /// 所有主机合计同时进行的服务识别数上限
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_DETECT_CONCURRENCY as u64, value_parser = clap::value_parser!(u64).range(1..))]
+    detect_concurrency: u64,
+
+    /// 服务识别前重新连接复查 TCP 扫描发现的开放端口，连接失败或被重置的端口视为中间设备造成的虚假开放并剔除，剔除数量写入结果备注
+    #[arg(long, default_value_t = false)]
+    verify_open: bool,
+
+    /// 单个主机同时进行的服务识别数上限，开放端口多的主机不会占满 --detect-concurrency，同时扫描的其他主机也能及时识别
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_HOST_DETECT_CONCURRENCY as u64, value_parser = clap::value_parser!(u64).range(1..))]
+    detect_concurrency_per_host: u64,
+
+    /// 逐个输出所有主机的详细结果；默认把无开放端口的主机合并成地址区间汇总
+    #[arg(short = 'v', long, default_value_t = false)]
+    verbose: bool,
+
+    /// 每个主机扫描完成后执行的命令，可用占位符 {{ip}}、{{ports}}、{{ports_nmap}}、{{services}}、{{hostname}}，
+    /// 如 'nmap -sV -p {{ports_nmap}} {{ip}}'；没有开放端口的主机不执行
+    #[arg(long, value_name = "CMD")]
+    exec: Option<String>,
+
+    /// 所有主机扫描完成后只执行一次 --exec 命令，地址以空格分隔，端口和服务取并集
+    #[arg(long, default_value_t = false, requires = "exec")]
+    exec_batch: bool,
+
+    /// 多个主机返回完全相同的 banner 时只做一次指纹匹配，适合同构集群
+    #[arg(long, default_value_t = false)]
+    dedupe_banners: bool,
+
+    /// 对每个开放的 TCP 端口重复建立连接读取 banner，banner 不一致时在结果中提示可能存在负载均衡或多个后端
+    #[arg(long, default_value_t = false)]
+    detect_lb: bool,
+
+    /// 在扫描结束时汇总异常发现：不常见的开放端口、常见端口上的其他服务、与操作系统不符的 TTL、
+    /// 疑似 tarpit 和负载均衡（需 --detect-lb），同时写入 JSON 的 anomalies 字段
+    #[arg(long, default_value_t = false)]
+    anomalies: bool,
+
+    /// 主动检查开放代理和邮件中继：对 HTTP 服务发送 CONNECT example.com:80，对 SMTP 服务以外部域的发件人和收件人走到 RCPT TO（不发送邮件内容）；
+    /// 检查成功时目标会向外部主机发起连接，只应在授权范围内使用。结果写入 anomalies（open_proxy、open_relay）
+    #[arg(long, default_value_t = false)]
+    check_proxies: bool,
+
+    /// 检查 TLS 服务的证书和协议配置：报告已过期或 30 天内到期的证书、自签名证书，以及服务端接受的 SSL 3.0/TLS 1.0/TLS 1.1 和弱密码套件。
+    /// 每个 TLS 端口额外进行五次握手，会增加扫描时间，默认关闭。结果写入 anomalies，证书摘要写入备注
+    #[arg(long, default_value_t = false)]
+    tls_audit: bool,
+
+    /// 对每个开放的 TCP 端口额外进行一次 TLS 握手，记录服务端 ServerHello 的 JA3S 指纹和本程序 ClientHello 的 JA3；无法识别但完成握手的端口报告为 TLS
+    #[arg(long, default_value_t = false)]
+    ja3: bool,
+
+    /// 经 SSH 跳板机扫描：登录跳板机后由它连接目标端口（direct-tcpip 转发），只支持 TCP 连接扫描和服务识别，
+    /// 跳过操作系统识别；密码认证时从环境变量 RUSTSCAN_SSH_PASSWORD 读取密码
+    #[cfg(feature = "ssh")]
+    #[arg(long, value_name = "USER@HOST[:PORT]", conflicts_with_all = ["ping_only", "broadcast_ping", "pcap_out", "source_port_range", "ttl", "abort_on_open"])]
+    ssh_jump: Option<SshJump>,
+
+    /// 登录跳板机使用的私钥，默认依次尝试 ~/.ssh/id_ed25519、id_ecdsa、id_rsa；加密私钥的口令从环境变量 RUSTSCAN_SSH_KEY_PASSPHRASE 读取
+    #[cfg(feature = "ssh")]
+    #[arg(long, value_name = "PATH", requires = "ssh_jump")]
+    ssh_key: Option<PathBuf>,
+
+    /// 跳板机不在 ~/.ssh/known_hosts 中时接受其主机密钥并写入 known_hosts；默认拒绝连接，避免把密码发给未核对的主机
+    #[cfg(feature = "ssh")]
+    #[arg(long, default_value_t = false, requires = "ssh_jump")]
+    ssh_accept_new: bool,
+
+    /// 打乱目标主机的扫描顺序，探测分散到整个网段而不是逐个主机集中进行
+    #[arg(long, default_value_t = false)]
+    randomize_hosts: bool,
+
+    /// 单线程按固定顺序扫描：主机按给定顺序逐个扫描，端口按列表顺序逐个探测，并发数为 1；用于复现问题和对比结果
+    #[arg(long, default_value_t = false, conflicts_with = "randomize_hosts")]
+    sequential: bool,
+
+    /// 开始扫描前随机等待 0 到指定秒数，定时执行的扫描不会每次都在同一时刻发起
+    #[arg(long, value_name = "MAX_SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    start_jitter: Option<u64>,
+
+    /// --randomize-hosts 和 --start-jitter 使用的随机种子，指定后每次得到相同的顺序和等待时长；默认随机生成并在开始时打印
+    #[arg(long, requires = "seeded")]
+    seed: Option<u64>,
 }
 
-fn parse_subnet(subnet: &str) -> Result<Vec<IpAddr>> {
-    if subnet.contains('/') {
-        let (ip_str, mask_str) = subnet.split_once('/').unwrap();
-        let base_ip: Ipv4Addr = ip_str.parse()?;
-        let mask: u8 = mask_str.parse()?;
-        
-        if mask > 32 {
-            return Err(anyhow::anyhow!("无效的子网掩码"));
-        }
-
-        let mut ips = Vec::new();
-        let host_bits = 32 - mask;
-        let num_hosts = 1u32 << host_bits;
-        let base_ip_u32 = u32::from_be_bytes(base_ip.octets());
-        let network_addr = base_ip_u32 & (!0u32 << host_bits);
-        
-        // 跳过网络地址和广播地址
-        for i in 1..num_hosts-1 {
-            let ip_u32 = network_addr | i;
-            let ip = Ipv4Addr::from(ip_u32);
-            ips.push(IpAddr::V4(ip));
-        }
-        
-        Ok(ips)
-    } else {
-        Ok(vec![subnet.parse()?])
+#[cfg(feature = "server")]
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// 以 REST API 服务模式运行：POST /scans 提交扫描，GET /scans/{id} 查询进度和结果
+    Serve {
+        /// 监听地址
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: std::net::SocketAddr,
+    },
+}
+
+/// nmap 用户习惯用 `-p-` 表示全部端口；`-p` 在这里是存活检测开关，clap 会把 `-p-`
+/// 拆成短选项组合而报错，因此在解析前把它改写为 `--ports=all`
+fn expand_all_ports(args: impl Iterator<Item = String>) -> impl Iterator<Item = String> {
+    args.map(|arg| if arg == "-p-" { "--ports=all".to_string() } else { arg })
+}
+
+/// `-T` 时序模板的一组取值
+/// `--probe-threshold` 是指纹权重，只能在 0 到 1 之间
+fn parse_probe_threshold(s: &str) -> Result<f32> {
+    let threshold: f32 = s.parse()?;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(anyhow::anyhow!("{} 不在 0 到 1 之间", threshold));
     }
+    Ok(threshold)
+}
+
+struct TimingTemplate {
+    timeout: u64,
+    threads: usize,
+    scan_delay: u64,
+    max_rate: Option<u64>,
+    retries: u32,
+}
+
+/// `-T0`..`-T5`，`-T3` 与各选项的默认值相同
+const TIMING_TEMPLATES: [TimingTemplate; 6] = [
+    // paranoid：逐个探测，每 5 分钟一次
+    TimingTemplate { timeout: 5000, threads: 1, scan_delay: 300_000, max_rate: Some(1), retries: 3 },
+    // sneaky：逐个探测，每 15 秒一次
+    TimingTemplate { timeout: 5000, threads: 1, scan_delay: 15_000, max_rate: Some(1), retries: 3 },
+    // polite：低并发，探测间隔 400ms
+    TimingTemplate { timeout: 1000, threads: 10, scan_delay: 400, max_rate: Some(10), retries: 2 },
+    // normal
+    TimingTemplate { timeout: 200, threads: 1000, scan_delay: 0, max_rate: None, retries: 0 },
+    // aggressive：假定网络快速可靠
+    TimingTemplate { timeout: 100, threads: 3000, scan_delay: 0, max_rate: None, retries: 0 },
+    // insane：以牺牲准确性换取速度
+    TimingTemplate { timeout: 50, threads: 5000, scan_delay: 0, max_rate: None, retries: 0 },
+];
+
+/// `-T` 时序模板：为命令行未显式给出的选项填入模板的取值，与 `--quick` 同时使用时以模板为准
+fn apply_timing_template(args: &mut Args, matches: &ArgMatches, level: u8) {
+    let explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let template = &TIMING_TEMPLATES[usize::from(level)];
+    if !explicit("timeout") {
+        args.timeout = template.timeout;
+    }
+    if !explicit("threads") {
+        args.threads = template.threads;
+    }
+    if !explicit("scan_delay") {
+        args.scan_delay = template.scan_delay;
+    }
+    if !explicit("max_rate") {
+        args.max_rate = template.max_rate;
+    }
+    if !explicit("retries") {
+        args.retries = template.retries;
+    }
+}
+
+/// `--quick` 预设：在其余参数处理之前，为命令行未显式给出的选项填入快速筛查的取值
+fn apply_quick_profile(args: &mut Args, matches: &ArgMatches) {
+    let explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    // 端点列表自带端口，不替换
+    if args.endpoints.is_none() {
+        if !["ports", "start_port", "end_port"].into_iter().any(explicit) {
+            args.ports = Some(TOP_TCP_PORTS.to_string());
+        }
+        if !explicit("udp_ports") {
+            args.udp_ports = Some(TOP_UDP_PORTS.to_string());
+        }
+    }
+    if !explicit("scan_type") {
+        args.scan_type = "tcp,udp".to_string();
+    }
+    if !explicit("timeout") {
+        args.timeout = 500;
+    }
+    if !explicit("service_timeout") {
+        args.service_timeout = 2000;
+    }
+    if !explicit("threads") {
+        args.threads = 500;
+    }
+    args.no_os_scan = true;
+}
+
+/// 整理交给 `--exec` 命令的主机结果，模板用到 `{{hostname}}` 时做反向解析
+async fn exec_host(hook: &ExecHook, target: ScanTarget, results: &[(u16, ScanType, DetectedService)]) -> ExecHost {
+    let host = ExecHost::new(target, results);
+    if !hook.needs_hostname() {
+        return host;
+    }
+    let hostname = tokio::task::spawn_blocking(move || reverse_lookup(target.ip)).await.ok().flatten();
+    host.with_hostname(hostname)
+}
+
+/// `--since` 时把结果换成相对基线的变化，返回主机是否有变化；未指定基线时原样保留
+fn keep_changes(output: &mut Output, baseline: Option<&HashMap<String, Output>>, level: ChangeLevel) -> bool {
+    let Some(baseline) = baseline else {
+        return true;
+    };
+    match changes_since(baseline.get(output.target()), output, level) {
+        Some(delta) => {
+            *output = delta;
+            true
+        }
+        None => false,
+    }
+}
+
+/// 等待中止扫描的信号；没有信号来源时永远不返回
+async fn cancelled(cancel: &mut Option<tokio::sync::watch::Receiver<bool>>) {
+    if let Some(cancel) = cancel {
+        if cancel.wait_for(|&cancelled| cancelled).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// 全屏界面绘制在标准错误上，需要是支持移动光标的终端
+#[cfg(feature = "tui")]
+fn tui_supported() -> bool {
+    use std::io::IsTerminal;
+    std::io::stderr().is_terminal() && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+}
+
+/// 登录 SSH 跳板机；操作系统识别和按 TTL 放宽超时需要直接访问目标，经跳板机扫描时关闭
+#[cfg(feature = "ssh")]
+async fn connect_ssh_jump(args: &mut Args, jump: &SshJump) -> Result<SshJumpConnector> {
+    let udp = ScanType::parse_list(&args.scan_type).is_ok_and(|scan_types| scan_types.contains(&ScanType::Udp));
+    if udp {
+        return Err(anyhow::anyhow!("经 SSH 跳板机只能进行 TCP 连接扫描，请从 --scan-type 中去掉 udp"));
+    }
+    if !args.no_os_scan {
+        eprintln!("{} 操作系统识别需要直接访问目标，经 SSH 跳板机扫描时已跳过", "[!]".yellow());
+        args.no_os_scan = true;
+    }
+    args.no_timeout_scaling = true;
+
+    let auth = SshAuth::from_env(args.ssh_key.as_deref())?;
+    let connector = SshJumpConnector::connect(jump, auth, args.ssh_accept_new).await?;
+    if let HostKeyStatus::Added { fingerprint } = connector.host_key() {
+        eprintln!(
+            "{} 已将跳板机 {} 的主机密钥记入 known_hosts，指纹为 {}，请核对",
+            "[!]".yellow(),
+            jump.host,
+            fingerprint
+        );
+    }
+    Ok(connector)
+}
+
+/// 安全模式下列出目标中的公网地址并拒绝扫描
+fn check_private_targets<'a>(targets: impl Iterator<Item = &'a ScanTarget>) -> Result<()> {
+    const MAX_LISTED: usize = 10;
+    let public: Vec<String> = targets.filter(|target| !target.is_private()).map(ToString::to_string).collect();
+    if public.is_empty() {
+        return Ok(());
+    }
+    let mut listed = public.iter().take(MAX_LISTED).cloned().collect::<Vec<_>>().join(", ");
+    if public.len() > MAX_LISTED {
+        listed.push_str(&format!(" 等共 {} 个", public.len()));
+    }
+    Err(anyhow::anyhow!(
+        "--private-only 模式下拒绝扫描公网地址: {}。确认无误请加上 --allow-public",
+        listed
+    ))
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+async fn main() -> Result<ExitCode> {
+    let matches = Args::command().get_matches_from(expand_all_ports(std::env::args()));
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if args.quick {
+        apply_quick_profile(&mut args, &matches);
+    }
+    if let Some(level) = args.timing {
+        apply_timing_template(&mut args, &matches, level);
+    }
+
+    // REST API 服务模式
+    #[cfg(feature = "server")]
+    if let Some(Command::Serve { bind }) = args.command {
+        let fingerprint_db = ServiceFingerprintDB::load(args.strict_fingerprints)?;
+        println!("{} REST API 监听于 http://{}", "[*]".blue(), bind);
+        rustscan::server::serve(bind, fingerprint_db).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // 本机环境诊断模式
+    if args.diagnostics {
+        Diagnostics::collect()?.print_console();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // 指纹库统计模式
+    if args.fingerprint_stats {
+        let fingerprint_db = ServiceFingerprintDB::load(false)?;
+        println!("{} 指纹库: {}", "[*]".blue(), fingerprint_db.source());
+        fingerprint_db.stats().print_console();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // 指纹列表模式
+    if let Some(format) = args.list_fingerprints {
+        let fingerprint_db = ServiceFingerprintDB::load(false)?;
+        match format {
+            ListFormat::Table => fingerprint_db.print_list(),
+            ListFormat::Json => println!("{}", serde_json::to_string_pretty(&fingerprint_db.to_config())?),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // 结果对比模式
+    if let Some(paths) = &args.diff {
+        if paths.iter().all(|path| is_stdio(path)) {
+            return Err(anyhow::anyhow!("--diff 的两个结果不能都从标准输入读取"));
+        }
+        let old = Output::load_json(&paths[0])?;
+        let new = Output::load_json(&paths[1])?;
+        let report = diff_outputs(&old, &new);
+        match &args.json_output {
+            Some(path) => {
+                if !is_stdio(path) {
+                    report.print_console();
+                }
+                write_file(path, serde_json::to_string_pretty(&report)?.as_bytes())?;
+            }
+            None => report.print_console(),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
     
-    // 解析目标地址或网段
-    let targets = parse_subnet(&args.target)?;
+    // 解析目标：端点列表按主机聚合，网段中的每个主机共享同一份端口列表
+    let family = match (args.ipv4, args.ipv6) {
+        (true, _) => AddressFamily::V4,
+        (_, true) => AddressFamily::V6,
+        _ => AddressFamily::Any,
+    };
+    let mut targets: Vec<(ScanTarget, Arc<[u16]>)> = if let Some(path) = &args.endpoints {
+        parse_endpoints(&std::fs::read_to_string(path)?)?
+            .into_iter()
+            .map(|(ip, ports)| family.check(&ip).map(|()| (ip, Arc::from(ports))))
+            .collect::<Result<_>>()?
+    } else {
+        let ports: Arc<[u16]> = match &args.ports {
+            Some(spec) => parse_ports(spec)?.into(),
+            None => port_range(args.start_port, args.end_port)?.into(),
+        };
+        // 主机名解析失败只跳过该目标
+        let resolver = TargetResolver::new(args.dns_server)?
+            .with_family(family)
+            .with_network_broadcast(args.include_network_broadcast);
+        let (targets, warnings) = expand_targets(args.target.as_deref().unwrap_or_default(), &resolver).await?;
+        for warning in warnings {
+            eprintln!("{} {}，跳过该目标", "[!]".yellow(), warning);
+        }
+        if targets.is_empty() {
+            return Err(anyhow::anyhow!("没有可扫描的目标"));
+        }
+        targets.into_iter().map(|ip| (ip, ports.clone())).collect()
+    };
+    let udp_ports: Option<Arc<[u16]>> = match &args.udp_ports {
+        Some(spec) => Some(parse_ports(spec)?.into()),
+        None => None,
+    };
+    if let Some(exclude) = &args.exclude {
+        targets.retain(|(target, _)| !exclude.contains(target.ip));
+        if targets.is_empty() {
+            return Err(anyhow::anyhow!("排除 --exclude 中的地址后没有可扫描的目标"));
+        }
+    }
+    if args.private_only && !args.allow_public {
+        check_private_targets(targets.iter().map(|(target, _)| target))?;
+    }
+
+    // 经 SSH 跳板机转发连接
+    #[cfg(feature = "ssh")]
+    let ssh_connector = match args.ssh_jump.clone() {
+        Some(jump) => Some(connect_ssh_jump(&mut args, &jump).await?),
+        None => None,
+    };
+    #[cfg(feature = "ssh")]
+    let payload_connector: &dyn Connector = match &ssh_connector {
+        Some(connector) => connector,
+        None => &TcpConnector,
+    };
+    #[cfg(not(feature = "ssh"))]
+    let payload_connector: &dyn Connector = &TcpConnector;
+
+    // 发送自定义载荷：不做端口扫描和指纹识别
+    if let Some(spec) = &args.send_payload {
+        if args.ports.is_none() && args.endpoints.is_none() {
+            return Err(anyhow::anyhow!("--send-payload 需要用 --ports 或 --endpoints 指定端口"));
+        }
+        let payload = parse_payload(spec)?;
+        let connect_timeout = Duration::from_millis(args.service_timeout);
+        let read_timeout = Duration::from_millis(args.service_read_timeout.unwrap_or(args.service_timeout));
+        for (target, ports) in &targets {
+            for &port in ports.iter() {
+                let addr = target.socket_addr(port);
+                match send_payload(payload_connector, addr, &payload, connect_timeout, read_timeout).await {
+                    Ok(response) if response.is_empty() => {
+                        println!("{} {} 发送 {} 字节，未收到应答", "[*]".blue(), addr, payload.len());
+                    }
+                    Ok(response) => {
+                        println!("{} {} 发送 {} 字节，收到 {} 字节:", "[*]".blue(), addr, payload.len(), response.len());
+                        println!("{}", response.escape_ascii());
+                        print!("{}", hexdump(&response));
+                    }
+                    Err(e) => eprintln!("{} {} 发送失败: {}", "[!]".yellow(), addr, e),
+                }
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
     let timeout = Duration::from_millis(args.timeout);
-    let total_ports = (args.end_port - args.start_port + 1) as u64;
-    let total_targets = targets.len() as u64;
+    if args.sequential {
+        args.threads = 1;
+        args.host_concurrency = 1;
+    }
 
+    // 按文件描述符上限校准并发数：同时扫描的主机共用文件描述符，不足时降低每个主机的并发数
+    let host_concurrency = usize::try_from(args.host_concurrency).unwrap_or(usize::MAX).min(targets.len().max(1));
+    let (total_threads, fd_limit) = calibrate_concurrency(args.threads.saturating_mul(host_concurrency));
+    let threads = (total_threads / host_concurrency).clamp(1, args.threads.max(1));
+    if let Some(fd_limit) = fd_limit.filter(|_| threads < args.threads) {
+        eprintln!(
+            "{} 并发数 {}（同时扫描 {} 个主机）超过可用文件描述符（ulimit -n 为 {}），每个主机已降为 {}；可调高 ulimit -n 或减小 --host-concurrency 后重试",
+            "[!]".yellow(),
+            args.threads,
+            host_concurrency,
+            fd_limit,
+            threads
+        );
+    }
     // 解析扫描类型
-    let scan_type = match args.scan_type.to_lowercase().as_str() {
-        "tcp" => ScanType::Tcp,
-        "udp" => ScanType::Udp,
-        _ => {
-            eprintln!("无效的扫描类型，使用默认值 TCP");
-            ScanType::Tcp
-        }
-    };
+    let scan_types = ScanType::parse_list(&args.scan_type).unwrap_or_else(|e| {
+        eprintln!("{}，使用默认值 TCP", e);
+        vec![ScanType::Tcp]
+    });
 
-    println!("{} 开始{}扫描 {} 个目标...", 
-        "[*]".blue(), 
-        if matches!(scan_type, ScanType::Tcp) { "TCP" } else { "UDP" },
+    // 每种协议各扫描一遍端口，多轮扫描时乘以轮数
+    let total_ports: u64 = u64::from(args.passes) * targets
+        .iter()
+        .flat_map(|(_, ports)| {
+            scan_types.iter().map(|scan_type| match (scan_type, &udp_ports) {
+                (ScanType::Udp, Some(udp_ports)) => udp_ports.len() as u64,
+                _ => ports.len() as u64,
+            })
+        })
+        .sum::<u64>();
+    let total_targets = targets.len() as u64;
+
+    // 标准输出留给 JSON/CSV/DOT 结果
+    let stdout_reserved = [&args.json_output, &args.csv_output, &args.dot_out]
+        .into_iter()
+        .flatten()
+        .any(|path| is_stdio(path));
+
+    report!(stdout_reserved, "{} 开始{}扫描 {} 个目标...",
+        "[*]".blue(),
+        scan_types.iter().map(ScanType::as_str).collect::<Vec<_>>().join("/"),
         total_targets
     );
+    let seed = (args.randomize_hosts || args.start_jitter.is_some()).then(|| args.seed.unwrap_or_else(rand::random));
+    if let Some(seed) = seed.filter(|_| args.randomize_hosts) {
+        shuffle_targets(&mut targets, seed);
+        report!(stdout_reserved, "{} 已打乱目标顺序（种子 {}，可用 --seed {} 复现）", "[*]".blue(), seed, seed);
+    }
+    // 随机推迟开始时间，在发出任何探测之前
+    if let (Some(max), Some(seed)) = (args.start_jitter, seed) {
+        let delay = start_jitter(Duration::from_secs(max), seed);
+        report!(stdout_reserved, "{} 随机等待 {:.1} 秒后开始探测（上限 {} 秒，种子 {}）", "[*]".blue(), delay.as_secs_f64(), max, seed);
+        tokio::time::sleep(delay).await;
+    }
+    // 源端口轮换
+    let source_ports = match args.source_port_range.as_deref() {
+        Some(range) => {
+            let source_ports: SourcePorts = range.parse()?;
+            if source_ports.len() < threads {
+                eprintln!(
+                    "{} 源端口范围只有 {} 个端口，小于并发数 {}，端口会被复用（SO_REUSEADDR），被占用时自动换下一个",
+                    "[!]".yellow(),
+                    source_ports.len(),
+                    threads
+                );
+            }
+            Some(Arc::new(source_ports))
+        }
+        None => None,
+    };
+
+    let invocation = args.record_invocation.then(|| Invocation {
+        command_line: Invocation::redact_command_line(std::env::args()),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        targets: match &args.endpoints {
+            Some(path) => path.display().to_string(),
+            None => args.target.clone().unwrap_or_default(),
+        },
+        ports: args.ports.clone().unwrap_or_else(|| format!("{}-{}", args.start_port, args.end_port)),
+        udp_ports: args.udp_ports.clone(),
+        scan_types: scan_types.iter().map(|scan_type| scan_type.as_str().to_string()).collect(),
+        timeout_ms: args.timeout,
+        service_timeout_ms: args.service_timeout,
+        threads,
+        host_concurrency,
+        max_rate: args.max_rate,
+        scan_delay_ms: args.scan_delay,
+        retries: args.retries,
+        retry_budget: args.retry_budget,
+        timing: args.timing,
+        passes: args.passes,
+        seed,
+        source_ports: source_ports.as_ref().map(|source_ports| source_ports.to_string()),
+        ttl: args.ttl,
+    });
+
+    let geo_lookup = match args.geo_db.as_slice() {
+        [] => None,
+        paths => Some(maxmind_lookup(paths)?),
+    };
+
+    // 准备输出目录
+    let mut json_output = args.json_output.clone();
+    let mut csv_output = args.csv_output.clone();
+    if let Some(dir) = &args.output_dir {
+        std::fs::create_dir_all(dir)?;
+        json_output = json_output.map(|path| if is_stdio(&path) { path } else { dir.join(path) });
+        csv_output = csv_output.map(|path| if is_stdio(&path) { path } else { dir.join(path) });
+    }
+    // 每个主机完成后依次写入所有结果文件；--stream-output 的文件在扫描开始时创建
+    let mut sinks = OutputSinks::new();
+    if let Some(path) = json_output {
+        match args.stream_output {
+            true => sinks.push(StreamWriter::json(&path)?),
+            false => sinks.push(FileSink::new(path, FileFormat::Json, args.append)),
+        }
+    }
+    if let Some(path) = csv_output {
+        match args.stream_output {
+            true => sinks.push(StreamWriter::csv(&path)?),
+            false => sinks.push(FileSink::new(path, FileFormat::Csv, args.append)),
+        }
+    }
+    if let Some(dir) = args.output_dir.clone().filter(|_| args.per_host) {
+        sinks.push(PerHostSink::new(dir, args.append));
+    }
+    let sinks = Arc::new(sinks);
+    let baseline: Option<Arc<HashMap<String, Output>>> = match &args.since {
+        Some(path) => Some(Arc::new(
+            Output::load_json(path)?
+                .into_iter()
+                .map(|output| (output.target().to_string(), output))
+                .collect(),
+        )),
+        None => None,
+    };
+
+    // 加载指纹库，报告无效正则
+    let fingerprint_db = ServiceFingerprintDB::load(args.strict_fingerprints)?;
+    if args.verbose {
+        report!(stdout_reserved, "{} 指纹库: {}", "[*]".blue(), fingerprint_db.source());
+    }
+    for err in fingerprint_db.pattern_errors() {
+        eprintln!("{} {}", "[!]".yellow(), err);
+    }
+
+    // 扫描事件订阅
+    let mut events = EventBus::new();
+    if let Some(endpoint) = &args.syslog {
+        let sink = SyslogSink::connect(
+            endpoint,
+            args.syslog_proto.parse()?,
+            parse_facility(&args.syslog_facility)?,
+            parse_severity(&args.syslog_severity)?,
+        )?;
+        events.subscribe(Arc::new(sink));
+    }
+    let progress_socket = match &args.progress_socket {
+        Some(path) => {
+            let socket = Arc::new(ProgressSocket::open(path)?);
+            events.subscribe(socket.clone());
+            Some(socket)
+        }
+        None => None,
+    };
+    #[cfg(feature = "tui")]
+    let tui_observer = match args.tui {
+        true if tui_supported() => {
+            let observer = Arc::new(TuiObserver::new());
+            events.subscribe(observer.clone());
+            Some(observer)
+        }
+        true => {
+            eprintln!("{} 标准错误不是终端，--tui 改为显示进度条", "[!]".yellow());
+            None
+        }
+        false => None,
+    };
+
+    // 扫描完成后执行的命令
+    let exec_hook = match &args.exec {
+        Some(template) => Some(Arc::new(template.parse::<ExecHook>()?)),
+        None => None,
+    };
+
+    // 跨主机共享的 banner 识别结果
+    let banner_cache = args.dedupe_banners.then(|| Arc::new(BannerCache::default()));
+    let http_headers: Arc<[HttpHeader]> = Arc::from(std::mem::take(&mut args.http_headers));
+    // 每个主机各建一个检测器，全局识别并发数通过共用的信号量限制
+    let detect_slots = Arc::new(Semaphore::new(args.detect_concurrency as usize));
+
+    // 原始报文记录
+    let mut pinger = Pinger::new(timeout).with_method(args.discovery_method);
+    if let Some(ttl) = args.ttl {
+        pinger = pinger.with_probe_ttl(ttl);
+    }
+    if let Some(path) = &args.pcap_out {
+        pinger = pinger.with_capture(Arc::new(PacketCapture::create(path)?));
+    }
+    let pinger = Arc::new(pinger);
+
+    // 广播/组播发现，补充单播探测漏掉的主机
+    let mut broadcast_alive = HashSet::new();
+    if args.broadcast_ping {
+        match args.target.as_deref().and_then(subnet_broadcast) {
+            Some(broadcast) => {
+                // 原始套接字的收发是阻塞的，放到阻塞线程池中，避免占住异步工作线程
+                let pinger = pinger.clone();
+                let discovered = tokio::task::spawn_blocking(move || pinger.broadcast_discover(&[broadcast, ALL_HOSTS_MULTICAST])).await?;
+                match discovered {
+                    Ok(responders) => {
+                        report!(
+                            stdout_reserved,
+                            "{} 广播/组播发现 {} 个应答主机{}",
+                            "[*]".blue(),
+                            responders.len(),
+                            if responders.is_empty() { "（很多系统默认忽略广播 ping）" } else { "" }
+                        );
+                        let responders: Vec<String> = responders.iter().map(ToString::to_string).collect();
+                        for range in collapse_host_ranges(responders.iter().map(String::as_str)) {
+                            report!(stdout_reserved, "  - {}", range);
+                        }
+                        broadcast_alive.extend(responders.iter().filter_map(|ip| ip.parse::<IpAddr>().ok()));
+                    }
+                    Err(e) => eprintln!("{} 广播发现失败（需要 root 或 CAP_NET_RAW）: {}", "[!]".yellow(), e),
+                }
+            }
+            None => eprintln!("{} --broadcast-ping 只适用于 IPv4 网段目标", "[!]".yellow()),
+        }
+    }
 
     // 创建进度显示器
-    let progress = Arc::new(ScanProgress::new(total_ports * total_targets, total_targets));
+    #[cfg(feature = "tui")]
+    let draw_bars = tui_observer.is_none();
+    #[cfg(not(feature = "tui"))]
+    let draw_bars = true;
+    let progress = Arc::new(if draw_bars {
+        ScanProgress::new(total_ports, total_targets)
+    } else {
+        ScanProgress::hidden(total_ports, total_targets)
+    });
+    if args.no_os_scan {
+        progress.disable_os_detect();
+    }
+    if args.no_service_scan {
+        progress.disable_service_detect();
+    }
+    for (target, _) in &targets {
+        if broadcast_alive.contains(&target.ip) {
+            progress.add_alive_ip(target.ip);
+        }
+    }
+    let progress_ticker = progress_socket.clone().map(|socket| {
+        let progress = progress.clone();
+        tokio::spawn(async move {
+            loop {
+                socket.progress(progress.scanned_ports(), progress.total_ports(), progress.probes_sent());
+                tokio::time::sleep(PROGRESS_INTERVAL).await;
+            }
+        })
+    });
 
-    // 并行扫描所有目标
+    #[cfg(feature = "tui")]
+    let tui = match tui_observer {
+        Some(observer) => Some(Tui::start(observer, progress.clone())?),
+        None => None,
+    };
+    // 全屏界面按 q 中止扫描；没有界面时不会中止
+    #[cfg(feature = "tui")]
+    let mut cancel = tui.as_ref().map(Tui::cancel_signal);
+    #[cfg(not(feature = "tui"))]
+    let mut cancel = None;
+
+    // 并行扫描所有目标，同时进行的主机数不超过 --host-concurrency
+    let scan_started = std::time::Instant::now();
+    let host_slots = Arc::new(Semaphore::new(host_concurrency));
     let mut tasks = Vec::new();
-    for target in targets {
+    for (target, ports) in targets {
+        let host_slot = tokio::select! {
+            slot = host_slots.clone().acquire_owned() => slot?,
+            _ = cancelled(&mut cancel) => break,
+        };
         let progress = progress.clone();
-        let scan_type = scan_type.clone();
+        let pinger = pinger.clone();
+        let scan_types = scan_types.clone();
+        let udp_ports = udp_ports.clone();
         let ping_only = args.ping_only;
-        let start_port = args.start_port;
-        let end_port = args.end_port;
-        let threads = args.threads;
-        let json_output = args.json_output.clone();
-        let csv_output = args.csv_output.clone();
+        let sinks = sinks.clone();
+        let os_scan = !args.no_os_scan;
+        let service_scan = !args.no_service_scan;
+        let port_guess = !args.no_service_guess;
+        let report_resets = args.report_resets;
+        let fingerprint_db = fingerprint_db.clone();
+        let source_ports = source_ports.clone();
+        let timeout_scaling = !args.no_timeout_scaling;
+        let events = events.clone();
+        let banner_cache = banner_cache.clone();
+        let exec_hook = exec_hook.clone().filter(|_| !args.exec_batch);
+        let max_open_per_host = args.max_open_per_host;
+        let max_detect = args.max_detect;
+        let verify_open = args.verify_open;
+        let detect_slots = detect_slots.clone();
+        let detect_concurrency_per_host = args.detect_concurrency_per_host as usize;
+        #[cfg(feature = "ssh")]
+        let ssh_connector = ssh_connector.clone();
+        let retry_budget = args.retry_budget;
+        let service_timeout = Duration::from_millis(args.service_timeout);
+        let service_read_timeout = args.service_read_timeout.map(Duration::from_millis);
+        let probe_threshold = args.probe_threshold;
+        let http_headers = http_headers.clone();
+        let (max_rate, min_rate) = match args.max_rate {
+            Some(max_rate) => (max_rate.max(1), (max_rate / 10).max(1)),
+            None => (threads as u64 * 1000, (threads / 10).max(1) as u64),
+        };
+        let scan_delay = Duration::from_millis(args.scan_delay);
+        let detect_lb = args.detect_lb;
+        let ja3 = args.ja3;
+        let anomalies = args.anomalies;
+        let check_proxies = args.check_proxies;
+        let tls_audit = args.tls_audit;
+        let udp_lenient = args.udp_lenient;
+        let udp_mtu = args.mtu;
+        let udp_fragment = args.allow_fragmentation;
+        let probe_ttl = args.ttl;
+        let abort_on_open = args.abort_on_open;
+        let sequential = args.sequential;
+        let label = args.label.clone();
+        let invocation = invocation.clone();
+        let geo_lookup = geo_lookup.clone();
+        let passes = args.passes;
+        let debug_fingerprints = args.debug_fingerprints;
+        let baseline = baseline.clone();
+        let changed_by = args.changed_by;
 
         let task = tokio::spawn(async move {
-            if ping_only {
-                if !ping(target, timeout).await {
-                    return Ok::<(Vec<(u16, String)>, Output), anyhow::Error>((Vec::new(), Output::new(target.to_string())));
-                }
+            let _host_slot = host_slot;
+            // 广播发现的应答主机已记录在存活缓存中，不再单独探测
+            if ping_only && !pinger.ping(target).await {
+                let mut output = Output::new(target.to_string());
+                output.set_label(label);
+                output.set_invocation(invocation);
+                output.set_geo(geo_lookup.and_then(|lookup| lookup(target.ip)));
+                let changed = keep_changes(&mut output, baseline.as_deref(), changed_by);
+                return Ok::<(Vec<(u16, ScanType, DetectedService)>, Output, bool), anyhow::Error>((Vec::new(), output, changed));
             }
+            events.emit(ScanEvent::HostStarted { target: target.to_string() });
 
-            let scanner = Scanner::new(
-                target,
-                start_port,
-                end_port,
-                timeout,
-                threads,
-                progress.clone(),
-                Arc::new(Mutex::new(RateController::new(threads as u64 * 1000, (threads / 10).max(1) as u64))),
-                scan_type.clone(),
-                Arc::new(ServiceDetector::new()),
-            );
+            // 按 ICMP 应答 TTL 估算网络距离，远端主机自动放宽超时；回显请求只为操作系统识别发送，
+            // 不识别操作系统时只复用存活检测已得到的 TTL
+            let ttl = match os_scan {
+                true => pinger.ttl(target).await,
+                false => pinger.liveness(target.ip).and_then(|liveness| liveness.ttl).flatten(),
+            };
+            let timeout = match ttl {
+                Some(ttl) if timeout_scaling => scale_timeout(timeout, hop_count(ttl)),
+                _ => timeout,
+            };
+
+            let service_detector = {
+                let detector = match banner_cache {
+                    Some(banner_cache) => ServiceDetector::with_fingerprint_db(fingerprint_db).with_banner_cache(banner_cache),
+                    None => ServiceDetector::with_fingerprint_db(fingerprint_db),
+                }
+                .with_timeout(service_timeout)
+                .with_probe_threshold(probe_threshold)
+                .with_http_headers(http_headers.clone())
+                .with_tls_fingerprint(ja3)
+                .with_port_guess(port_guess)
+                .with_report_resets(report_resets)
+                .with_shared_semaphore(detect_slots)
+                .with_host_concurrency(detect_concurrency_per_host);
+                // --detect-lb 直接使用这个检测器，同样经跳板机连接
+                #[cfg(feature = "ssh")]
+                let detector = match ssh_connector.clone() {
+                    Some(connector) => detector.with_connector(Arc::new(connector)),
+                    None => detector,
+                };
+                Arc::new(match service_read_timeout {
+                    Some(read_timeout) => detector.with_read_timeout(read_timeout),
+                    None => detector,
+                })
+            };
+            let scanned_ports = ports.len();
+            let scanner = Scanner::builder(target)
+                .ports(ports)
+                .timeout(timeout)
+                .retries(args.retries)
+                .threads(threads)
+                .progress(progress.clone())
+                .rate_controller(Arc::new(Mutex::new(RateController::new(max_rate, min_rate).with_scan_delay(scan_delay))))
+                .service_detector(service_detector.clone())
+                .service_scan(service_scan)
+                .service_timeout(service_timeout)
+                .scan_types(scan_types)
+                .udp_lenient(udp_lenient)
+                .udp_fragment(udp_fragment)
+                .abort_on_open(abort_on_open)
+                .sequential(sequential)
+                .verify_open(verify_open)
+                .events(events.clone());
+            let scanner = match source_ports {
+                Some(source_ports) => scanner.source_ports(source_ports),
+                None => scanner,
+            };
+            let scanner = match udp_ports {
+                Some(udp_ports) => scanner.udp_ports(udp_ports),
+                None => scanner,
+            };
+            let scanner = match udp_mtu {
+                Some(mtu) => scanner.udp_mtu(mtu),
+                None => scanner,
+            };
+            let scanner = match retry_budget {
+                Some(retry_budget) => scanner.retry_budget(retry_budget),
+                None => scanner,
+            };
+            let scanner = match max_open_per_host {
+                Some(max_open) => scanner.max_open(max_open),
+                None => scanner,
+            };
+            let scanner = match max_detect {
+                Some(max_detect) => scanner.max_detect(max_detect),
+                None => scanner,
+            };
+            #[cfg(feature = "ssh")]
+            let scanner = match ssh_connector {
+                Some(connector) => scanner.connector(connector),
+                None => scanner,
+            };
+            let scanner = match probe_ttl {
+                Some(ttl) => scanner.ttl(ttl),
+                None => scanner,
+            }
+            .build();
 
             // 只返回服务识别结果
-            let service_results = scanner.run().await?;
+            let passes_results = scanner.run_passes(passes).await?;
+            let service_results: Vec<(u16, ScanType, DetectedService)> = passes_results
+                .iter()
+                .map(|(port, scan_type, service, _)| (*port, *scan_type, service.clone()))
+                .collect();
 
             // 操作系统识别
             let mut output = Output::new(target.to_string());
-            let os_detector = OSDetector::new(target);
-            if let Ok(os_info) = os_detector.detect().await {
-                output.set_os_info(os_info);
-                progress.set_os_detected();
+            output.set_label(label);
+            output.set_invocation(invocation);
+            output.set_geo(geo_lookup.and_then(|lookup| lookup(target.ip)));
+            if os_scan {
+                let os_detector = OSDetector::new(target).with_ttl(ttl).with_http_headers(http_headers);
+                if let Ok(os_info) = os_detector.detect().await {
+                    events.emit(ScanEvent::OsDetected {
+                        target: target.to_string(),
+                        os: os_info.clone(),
+                    });
+                    output.set_os_info(os_info);
+                    progress.set_os_detected();
+                }
             }
 
             // 填充端口和服务
-            for (port, service) in &service_results {
-                output.add_port(*port, service.clone(),
-                    if matches!(scan_type, ScanType::Tcp) { "TCP" } else { "UDP" }.to_string()
-                );
+            if debug_fingerprints {
+                output.include_fingerprints();
+            }
+            for (port, scan_type, service) in &service_results {
+                output.add_port(*port, service, scan_type.as_str().to_string());
+            }
+
+            // 多轮扫描中并非每轮都开放的端口
+            if passes > 1 {
+                output.set_passes(passes);
+                for (port, scan_type, _, seen_open) in &passes_results {
+                    output.set_seen_open(*port, scan_type.as_str(), *seen_open);
+                    if *seen_open < passes {
+                        output.add_note(format!(
+                            "端口 {}/{} 在 {} 轮扫描中只有 {} 轮开放，状态不稳定（可能是负载均衡、限速或丢包）",
+                            port,
+                            scan_type.as_str(),
+                            passes,
+                            seen_open
+                        ));
+                    }
+                }
+            }
+
+            if scanner.verify_dropped() > 0 {
+                output.add_note(format!(
+                    "{} 个开放端口复查时连接失败或被重置，可能是中间设备代答造成的虚假开放，已剔除",
+                    scanner.verify_dropped()
+                ));
+            }
+
+            if scanner.detect_skipped() > 0 {
+                output.add_note(format!(
+                    "开放端口超过 --max-detect 上限（{} 个），其余 {} 个端口未做服务识别，只按端口号标注",
+                    max_detect.unwrap_or_default(),
+                    scanner.detect_skipped()
+                ));
+            }
+
+            if scanner.retry_budget_exhausted() {
+                output.add_note(format!(
+                    "重试预算（{} 次）已用完，之后仍超时的端口不再重试，按被过滤处理",
+                    retry_budget.unwrap_or_default()
+                ));
+            }
+
+            // 快速扫描中关闭端口的 RST 被限速，无响应不等于被过滤
+            if let Some(limit) = scanner.rst_rate_limit() {
+                output.add_note(format!(
+                    "疑似 RST 限速：{} 个端口无响应，慢速复查其中 {} 个时有 {} 个返回了响应，这些端口多为关闭而非被过滤，可用 --max-rate 或 --scan-delay 降速后重新扫描",
+                    limit.silent, limit.sampled, limit.answered
+                ));
+            }
+
+            // 同一端口多次探测得到不同 banner 时提示可能存在负载均衡
+            let mut backends = Vec::new();
+            if detect_lb {
+                let tcp_ports = service_results.iter().filter(|(_, scan_type, _)| *scan_type == ScanType::Tcp);
+                backends = futures::future::join_all(tcp_ports.map(|&(port, _, _)| {
+                    let service_detector = service_detector.clone();
+                    async move { (port, service_detector.count_backends(target, port, BACKEND_SAMPLES).await) }
+                }))
+                .await;
+                for &(port, count) in backends.iter().filter(|(_, count)| *count > 1) {
+                    output.add_note(format!(
+                        "端口 {} 的 {} 次探测返回了 {} 种不同的 banner，可能存在负载均衡或多个后端",
+                        port, BACKEND_SAMPLES, count
+                    ));
+                }
+            }
+
+            // 开放代理和邮件中继的检查会让目标向外连接，只在 --check-proxies 时进行
+            let mut relays = Vec::new();
+            if check_proxies {
+                let tcp_services = service_results.iter().filter(|(_, scan_type, _)| *scan_type == ScanType::Tcp);
+                relays = futures::future::join_all(
+                    tcp_services.map(|(port, _, service)| service_detector.check_relay(target, *port, service)),
+                )
+                .await;
+            }
+
+            // 旧版本和弱密码套件的检查需要额外握手，只在 --tls-audit 时进行
+            let mut tls_findings = Vec::new();
+            if tls_audit {
+                let tls_ports: Vec<u16> = service_results
+                    .iter()
+                    .filter(|(_, scan_type, service)| *scan_type == ScanType::Tcp && is_tls_service(service))
+                    .map(|(port, _, _)| *port)
+                    .collect();
+                let audits = futures::future::join_all(tls_ports.iter().map(|&port| service_detector.audit_tls(target, port))).await;
+                let now = std::time::SystemTime::now();
+                for (port, audit) in tls_ports.into_iter().zip(audits) {
+                    output.add_note(audit.summary(port));
+                    tls_findings.extend(audit.findings(port, now));
+                }
             }
 
-            // 保存结果
-            if let Some(path) = &json_output {
-                output.save_json(path)?;
+            if anomalies || check_proxies || tls_audit {
+                let mut found = match anomalies {
+                    true => find_anomalies(&HostSignals {
+                        services: &service_results,
+                        scanned_ports,
+                        os: output.os_info(),
+                        ttl,
+                        backends: &backends,
+                    }),
+                    false => Vec::new(),
+                };
+                found.extend(relays.into_iter().flatten());
+                found.extend(tls_findings);
+                output.set_anomalies(found);
             }
-            if let Some(path) = &csv_output {
-                output.save_csv(path)?;
+
+            // 把开放端口交给后续命令
+            if let Some(exec_hook) = exec_hook.filter(|_| !service_results.is_empty()) {
+                let host = exec_host(&exec_hook, target, &service_results).await;
+                let prefix = format!("[{}]", target).cyan().to_string();
+                match exec_hook.run(&[host], |line| progress.println(&format!("{} {}", prefix, line))).await {
+                    Ok(status) => output.set_exec_status(status),
+                    Err(e) => progress.println(&format!("{} {} 执行命令失败: {}", "[!]".yellow(), target, e)),
+                }
             }
 
-            Ok((service_results, output))
+            events.emit(ScanEvent::HostFinished {
+                target: target.to_string(),
+                open_ports: service_results.len(),
+            });
+
+            // 保存结果；--since 时只保存有变化的主机
+            let changed = keep_changes(&mut output, baseline.as_deref(), changed_by);
+            if changed {
+                sinks.write(&output)?;
+            }
+
+            Ok((service_results, output, changed))
         });
 
         tasks.push(task);
     }
 
+    // 等待所有主机扫描完成；中止时取消尚未完成的主机，已完成的结果照常输出
+    let mut results = Vec::with_capacity(tasks.len());
+    for mut task in tasks {
+        let result = tokio::select! {
+            result = &mut task => result,
+            _ = cancelled(&mut cancel) => {
+                task.abort();
+                task.await
+            }
+        };
+        results.push(result);
+    }
+    let finished = results.iter().filter(|result| !result.as_ref().is_err_and(|e| e.is_cancelled())).count();
+    let aborted_hosts = total_targets as usize - finished;
+
+    // 全屏界面在所有主机结束后退出，之后照常输出结果
+    #[cfg(feature = "tui")]
+    if let Some(tui) = tui {
+        tui.finish()?;
+    }
+    if aborted_hosts > 0 {
+        eprintln!("{} 扫描已中止，{} 个主机未完成", "[!]".yellow(), aborted_hosts);
+    }
+
     // 等待所有扫描任务完成，统一 finish 进度条和输出
-    for task in tasks {
-        match task.await? {
-            Ok((service_results, output)) => {
+    let mut empty_hosts = Vec::new();
+    let mut unchanged_hosts = Vec::new();
+    let mut batch_hosts = Vec::new();
+    let mut anomaly_hosts = Vec::new();
+    let mut metrics = ScanMetrics::new();
+    let mut dot_hosts = Vec::new();
+    let mut open_ports = 0;
+    for result in results {
+        let result = match result {
+            Ok(result) => result,
+            // 被中止的主机已在上面计数
+            Err(e) if e.is_cancelled() => continue,
+            Err(e) => return Err(e.into()),
+        };
+        match result {
+            Ok((service_results, output, changed)) => {
                 progress.finish();
-                // 先输出服务识别结果
-                if !service_results.is_empty() {
+                open_ports += service_results.len();
+                metrics.add_host(&service_results);
+                if args.dot_out.is_some() && !output.is_empty() {
+                    dot_hosts.push(output.clone());
+                }
+                if let Some(exec_hook) = exec_hook.as_ref().filter(|_| args.exec_batch && !service_results.is_empty()) {
+                    let target: ScanTarget = output.target().parse()?;
+                    batch_hosts.push(exec_host(exec_hook, target, &service_results).await);
+                }
+                if !output.anomalies().is_empty() {
+                    anomaly_hosts.push((output.target().to_string(), output.anomalies().to_vec()));
+                }
+                if !changed {
+                    unchanged_hosts.push(output);
+                    continue;
+                }
+                // --since 时端口为空的主机也可能有已关闭的端口
+                if output.is_empty() && !args.verbose && baseline.is_none() {
+                    empty_hosts.push(output);
+                    continue;
+                }
+                // 结果已以 JSON/CSV 写到标准输出
+                if stdout_reserved {
+                    continue;
+                }
+                // 先输出服务识别结果；--since 时只输出变化
+                if baseline.is_some() {
+                    println!("\n与基线相比的变化：");
+                } else if !service_results.is_empty() {
                     println!("\n开放端口与服务：");
-                    for (port, service) in service_results {
-                        println!("  - 端口 {}: {}", port, service);
+                    for (port, scan_type, service) in service_results {
+                        println!("  - 端口 {}/{}: {}", port, scan_type.as_str(), service);
                     }
                 } else {
                     println!("\n未发现开放端口。");
@@ -208,8 +1462,59 @@ async fn main() -> Result<()> {
         }
     }
 
+    sinks.finish()?;
+    if let Some(path) = &args.dot_out {
+        save_dot(&dot_hosts, path)?;
+    }
+    if let Some(path) = &args.metrics_out {
+        metrics.save(path, scan_started.elapsed())?;
+    }
+
     // 完成进度显示
     progress.finish();
+    if let Some(socket) = &progress_socket {
+        if let Some(ticker) = progress_ticker {
+            ticker.abort();
+        }
+        socket.progress(progress.scanned_ports(), progress.total_ports(), progress.probes_sent());
+        socket.finish(total_targets as usize, open_ports);
+    }
+
+    if !empty_hosts.is_empty() {
+        report!(stdout_reserved, "\n{} 以下 {} 个主机未发现开放端口（或未响应）:", "[*]".blue(), empty_hosts.len());
+        for range in collapse_host_ranges(empty_hosts.iter().map(Output::target)) {
+            report!(stdout_reserved, "  - {}", range);
+        }
+    }
+    if !unchanged_hosts.is_empty() {
+        report!(stdout_reserved, "\n{} 以下 {} 个主机与基线相比无变化:", "[*]".blue(), unchanged_hosts.len());
+        for range in collapse_host_ranges(unchanged_hosts.iter().map(Output::target)) {
+            report!(stdout_reserved, "  - {}", range);
+        }
+    }
+
+    if !anomaly_hosts.is_empty() {
+        report!(stdout_reserved, "\n{} 异常发现（{} 个主机）:", "[!]".yellow(), anomaly_hosts.len());
+        for (target, found) in &anomaly_hosts {
+            for anomaly in found {
+                report!(stdout_reserved, "  - {}: {}", target, anomaly);
+            }
+        }
+    }
+
+    // 批量模式：所有主机汇总后执行一次
+    if let Some(exec_hook) = exec_hook.filter(|_| !batch_hosts.is_empty()) {
+        report!(stdout_reserved, "\n{} 对 {} 个主机执行命令", "[*]".blue(), batch_hosts.len());
+        let status = exec_hook.run(&batch_hosts, |line| report!(stdout_reserved, "{} {}", "[exec]".cyan(), line)).await?;
+        match status.exit_code {
+            Some(code) => report!(stdout_reserved, "执行命令: {} (退出码: {})", status.command, code),
+            None => report!(stdout_reserved, "执行命令: {} (被信号终止)", status.command),
+        }
+    }
 
-    Ok(())
+    // 中止时各输出已正常收尾，按被 SIGINT 中断的惯例返回 130
+    Ok(match aborted_hosts {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::from(130),
+    })
 }
\ No newline at end of file
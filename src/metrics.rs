@@ -0,0 +1,128 @@
+use crate::output::{is_stdio, write_file};
+use crate::scanner::ScanType;
+use crate::service_detector::DetectedService;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 整次扫描的汇总指标，以 Prometheus 文本格式输出（`--metrics-out`）
+///
+/// 开放端口只按服务名和协议分组，不带主机地址，标签组合的数量不随主机数增长。
+#[derive(Debug, Default)]
+pub struct ScanMetrics {
+    hosts_scanned: usize,
+    hosts_up: usize,
+    /// (服务名, 协议) -> 开放端口数
+    open_ports: BTreeMap<(String, String), usize>,
+}
+
+impl ScanMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 计入一个主机的扫描结果
+    pub fn add_host(&mut self, services: &[(u16, ScanType, DetectedService)]) {
+        self.hosts_scanned += 1;
+        if !services.is_empty() {
+            self.hosts_up += 1;
+        }
+        for (_, scan_type, service) in services {
+            let key = (service.name.to_lowercase(), scan_type.as_str().to_lowercase());
+            *self.open_ports.entry(key).or_default() += 1;
+        }
+    }
+
+    pub fn to_prometheus(&self, duration: Duration) -> String {
+        let finished = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut text = String::new();
+        let mut metric = |name: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} gauge", name);
+            for (labels, value) in samples {
+                let _ = writeln!(text, "{}{} {}", name, labels, value);
+            }
+        };
+        metric("rustscan_hosts_scanned", "扫描的主机数", &[(String::new(), self.hosts_scanned.to_string())]);
+        metric("rustscan_hosts_up", "发现开放端口的主机数", &[(String::new(), self.hosts_up.to_string())]);
+        let open_ports: Vec<_> = self
+            .open_ports
+            .iter()
+            .map(|((service, protocol), count)| {
+                let labels = format!("{{service=\"{}\",protocol=\"{}\"}}", escape_label(service), protocol);
+                (labels, count.to_string())
+            })
+            .collect();
+        metric("rustscan_open_ports", "各服务的开放端口数（所有主机合计）", &open_ports);
+        metric(
+            "rustscan_scan_duration_seconds",
+            "扫描耗时（秒）",
+            &[(String::new(), format!("{:.3}", duration.as_secs_f64()))],
+        );
+        metric(
+            "rustscan_last_run_timestamp_seconds",
+            "扫描完成时间（Unix 时间戳）",
+            &[(String::new(), finished.as_secs().to_string())],
+        );
+        text
+    }
+
+    /// 写入指标文件；先写临时文件再改名，textfile collector 不会读到写了一半的文件
+    pub fn save(&self, path: &Path, duration: Duration) -> Result<()> {
+        let text = self.to_prometheus(duration);
+        if is_stdio(path) {
+            return write_file(path, text.as_bytes());
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, text).map_err(|e| anyhow!("无法写入指标文件 {}: {}", path.display(), e))?;
+        std::fs::rename(&tmp, path).map_err(|e| anyhow!("无法写入指标文件 {}: {}", path.display(), e))?;
+        Ok(())
+    }
+}
+
+/// 标签值中的反斜杠、双引号和换行需要转义
+fn escape_label(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_metrics() {
+        let mut metrics = ScanMetrics::new();
+        metrics.add_host(&[
+            (22, ScanType::Tcp, DetectedService::new("SSH")),
+            (2222, ScanType::Tcp, DetectedService::new("SSH")),
+            (53, ScanType::Udp, DetectedService::new("DNS")),
+        ]);
+        metrics.add_host(&[(22, ScanType::Tcp, DetectedService::new("SSH"))]);
+        metrics.add_host(&[(80, ScanType::Tcp, DetectedService::new("My \"Web\""))]);
+        metrics.add_host(&[]);
+
+        let text = metrics.to_prometheus(Duration::from_millis(12_345));
+        let samples: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            &samples[..6],
+            [
+                "rustscan_hosts_scanned 4",
+                "rustscan_hosts_up 3",
+                r#"rustscan_open_ports{service="dns",protocol="udp"} 1"#,
+                r#"rustscan_open_ports{service="my \"web\"",protocol="tcp"} 1"#,
+                r#"rustscan_open_ports{service="ssh",protocol="tcp"} 3"#,
+                "rustscan_scan_duration_seconds 12.345",
+            ]
+        );
+        assert!(samples[6].starts_with("rustscan_last_run_timestamp_seconds "));
+        assert!(text.contains("# TYPE rustscan_open_ports gauge\n"));
+
+        let path = std::env::temp_dir().join(format!("rustscan-metrics-{}.prom", std::process::id()));
+        metrics.save(&path, Duration::from_secs(1)).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().contains("rustscan_hosts_up 3\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,13 +1,24 @@
-use std::net::{IpAddr, SocketAddr};
+use std::collections::HashMap;
+use crate::http::{self, HttpHeader, MAX_HTTP_LEN};
+use crate::scanner::retry_delay;
+use crate::service_fingerprints::read_until;
+use crate::target::ScanTarget;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpStream as TokioTcpStream;
 use tokio::time;
 use anyhow::Result;
 use regex::Regex;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 use std::str;
 use serde::{Serialize, Deserialize};
 
+/// 合并后置信度的上限，避免多源证据叠加到"绝对确定"
+const MAX_CONFIDENCE: f32 = 0.99;
+/// 冲突证据对最终置信度的扣减系数
+const CONFLICT_PENALTY: f32 = 0.5;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OSInfo {
     pub name: String,
@@ -16,19 +27,73 @@ pub struct OSInfo {
     pub features: Vec<String>,
 }
 
+/// HTTP 与服务探测的超时和重试设置
+#[derive(Debug, Clone, Copy)]
+pub struct OSDetectorConfig {
+    /// 建立 TCP 连接的超时
+    pub connect_timeout: Duration,
+    /// 等待 HTTP 响应的超时
+    pub read_timeout: Duration,
+    /// 连接超时或连上后没有任何响应时的重试次数
+    pub retries: u32,
+}
+
+impl Default for OSDetectorConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(2),
+            read_timeout: Duration::from_secs(2),
+            retries: 1,
+        }
+    }
+}
+
 pub struct OSDetector {
-    target: IpAddr,
-    timeout: Duration,
+    target: ScanTarget,
+    config: OSDetectorConfig,
+    /// 存活检测时观察到的应答 TTL
+    observed_ttl: Option<u8>,
+    /// HTTP 探测最多读取的字节数
+    http_read_limit: usize,
+    /// HTTP 探测附加的请求头
+    http_headers: Arc<[HttpHeader]>,
 }
 
 impl OSDetector {
-    pub fn new(target: IpAddr) -> Self {
+    pub fn new(target: impl Into<ScanTarget>) -> Self {
         Self {
-            target,
-            timeout: Duration::from_secs(2),
+            target: target.into(),
+            config: OSDetectorConfig::default(),
+            observed_ttl: None,
+            http_read_limit: MAX_HTTP_LEN,
+            http_headers: Arc::from([]),
         }
     }
 
+    /// 设置 HTTP 与服务探测的超时和重试次数
+    pub fn with_config(mut self, config: OSDetectorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// 设置 HTTP 探测读取响应的上限，响应头很多的服务器可能需要调大
+    pub fn with_http_read_limit(mut self, limit: usize) -> Self {
+        self.http_read_limit = limit;
+        self
+    }
+
+    /// HTTP 探测附加的请求头，同名时替换默认的 `User-Agent` 等
+    pub fn with_http_headers(mut self, headers: Arc<[HttpHeader]>) -> Self {
+        self.http_headers = headers;
+        self
+    }
+
+    /// 使用 ICMP 应答中的 TTL 推断系统类型
+    pub fn with_ttl(mut self, ttl: Option<u8>) -> Self {
+        self.observed_ttl = ttl;
+        self
+    }
+
     pub async fn detect(&self) -> Result<OSInfo> {
         // 并行执行所有检测方法
         let (http_result, tcp_result, services_result) = tokio::join!(
@@ -38,49 +103,45 @@ impl OSDetector {
         );
 
         // 合并结果
-        let mut all_features = Vec::new();
-        let mut max_confidence = 0.0;
-        let mut best_name = "Unknown".to_string();
-        let mut best_version = None;
-
-        for info in [http_result, tcp_result, services_result].iter().filter_map(|r| r.as_ref().ok()) {
-            if info.confidence > max_confidence {
-                max_confidence = info.confidence;
-                best_name = info.name.clone();
-                best_version = info.version.clone();
-            }
-            for feat in &info.features {
-                if !all_features.contains(feat) {
-                    all_features.push(feat.clone());
-                }
-            }
-        }
+        let results: Vec<OSInfo> = [http_result, tcp_result, services_result]
+            .into_iter()
+            .filter_map(|r| r.ok())
+            .collect();
 
-        Ok(OSInfo {
-            name: best_name,
-            version: best_version,
-            confidence: max_confidence,
-            features: all_features,
-        })
+        Ok(combine_evidence(&results))
     }
 
     async fn detect_via_http(&self) -> Result<OSInfo> {
-        let addr = SocketAddr::new(self.target, 80);
-        if let Ok(stream) = time::timeout(self.timeout, TokioTcpStream::connect(&addr)).await {
-            if let Ok(mut stream) = stream {
-                let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
-                stream.write_all(request.as_bytes()).await?;
-                
-                let mut buffer = [0u8; 1024];
-                if let Ok(len) = stream.read(&mut buffer).await {
-                    let response = String::from_utf8_lossy(&buffer[..len]);
-                    
-                    // 分析 HTTP 响应头
-                    if let Some(os_info) = self.analyze_http_headers(&response) {
-                        return Ok(os_info);
-                    }
-                }
+        self.probe_http(self.target.socket_addr(80)).await
+    }
+
+    async fn probe_http(&self, addr: SocketAddr) -> Result<OSInfo> {
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                time::sleep(retry_delay(attempt - 1)).await;
+            }
+            let mut stream = match time::timeout(self.config.connect_timeout, TokioTcpStream::connect(&addr)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(_)) => break,
+                Err(_) => continue,
+            };
+            stream.write_all(http::build_request(addr, &self.http_headers).as_bytes()).await?;
+
+            // 分多次读取，直到响应头结束或达到上限，避免 Server 头排在大量其他头之后时被截断
+            let mut buffer = Vec::new();
+            let deadline = time::Instant::now() + self.config.read_timeout;
+            read_until(&mut stream, &mut buffer, 0, self.http_read_limit, deadline, http::has_header_end).await;
+            // 繁忙的主机可能接受连接后不作应答
+            if buffer.is_empty() {
+                continue;
             }
+            let response = String::from_utf8_lossy(&buffer);
+
+            // 分析 HTTP 响应头
+            if let Some(os_info) = self.analyze_http_headers(&response) {
+                return Ok(os_info);
+            }
+            break;
         }
         
         Ok(OSInfo {
@@ -152,55 +213,33 @@ impl OSDetector {
         }
     }
 
+    /// 根据目标应答的 TTL 推断系统类型
+    ///
+    /// TCP 连接套接字拿不到对端报文的 TTL，这里使用存活检测时 ICMP 回显应答中的值。
     async fn detect_via_tcp(&self) -> Result<OSInfo> {
-        let mut features = Vec::new();
+        let ttl = self
+            .observed_ttl
+            .ok_or_else(|| anyhow::anyhow!("没有可用的 TTL 观测值"))?;
+        let mut features = vec![format!("TTL: {} (约 {} 跳)", ttl, hop_count(ttl))];
         let mut confidence = 0.0;
         let mut name = "Unknown".to_string();
         let version = None;
 
-        // 并行测试常见端口
-        let test_ports = vec![22, 23, 80, 443, 445, 3389];
-        let mut tasks = Vec::new();
-
-        for port in test_ports {
-            let addr = SocketAddr::new(self.target, port);
-            let timeout = self.timeout;
-            tasks.push(tokio::spawn(async move {
-                if let Ok(stream) = time::timeout(timeout, TokioTcpStream::connect(&addr)).await {
-                    if let Ok(_stream) = stream {
-                        let ttl = _stream.ttl().ok()?;
-                        Some((port, ttl))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }));
-        }
-
-        // 收集结果
-        for task in tasks {
-            if let Ok(Some((port, ttl))) = task.await {
-                features.push(format!("TTL: {} (port {})", ttl, port));
-                
-                // 根据 TTL 猜测操作系统
-                match ttl {
-                    64 => {
-                        name = "Linux/Unix".to_string();
-                        confidence = 0.7;
-                    }
-                    128 => {
-                        name = "Windows".to_string();
-                        confidence = 0.7;
-                    }
-                    255 => {
-                        name = "Solaris/AIX".to_string();
-                        confidence = 0.7;
-                    }
-                    _ => {}
-                }
+        // 根据初始 TTL 猜测操作系统
+        match initial_ttl(ttl) {
+            64 => {
+                name = "Linux/Unix".to_string();
+                confidence = 0.7;
+            }
+            128 => {
+                name = "Windows".to_string();
+                confidence = 0.7;
             }
+            255 => {
+                name = "Solaris/AIX".to_string();
+                confidence = 0.7;
+            }
+            _ => features.clear(),
         }
 
         Ok(OSInfo {
@@ -226,18 +265,10 @@ impl OSDetector {
 
         let mut tasks = Vec::new();
         for (port, service) in test_services {
-            let addr = SocketAddr::new(self.target, port);
-            let timeout = self.timeout;
+            let addr = self.target.socket_addr(port);
+            let config = self.config;
             tasks.push(tokio::spawn(async move {
-                if let Ok(stream) = time::timeout(timeout, TokioTcpStream::connect(&addr)).await {
-                    if let Ok(_stream) = stream {
-                        Some((port, service))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
+                connect_with_retry(addr, &config).await.then_some((port, service))
             }));
         }
 
@@ -274,14 +305,224 @@ impl OSDetector {
     }
 }
 
+/// 端口是否可连接；连接超时按配置重试，连接被拒绝等明确结果直接返回
+async fn connect_with_retry(addr: SocketAddr, config: &OSDetectorConfig) -> bool {
+    for attempt in 0..=config.retries {
+        if attempt > 0 {
+            time::sleep(retry_delay(attempt - 1)).await;
+        }
+        match time::timeout(config.connect_timeout, TokioTcpStream::connect(&addr)).await {
+            Ok(result) => return result.is_ok(),
+            Err(_) => continue,
+        }
+    }
+    false
+}
+
+/// 合并多个检测来源的证据
+///
+/// 同一操作系统的多条证据按 noisy-OR 叠加（`1 - Π(1 - c)`），相互印证时置信度上升；
+/// 指向其他系统的证据同样叠加后作为冲突度，按比例扣减最终置信度。
+pub fn combine_evidence(results: &[OSInfo]) -> OSInfo {
+    let mut features = Vec::new();
+    // 每个系统名称对应的"未命中概率"连乘
+    let mut miss: HashMap<&str, f32> = HashMap::new();
+
+    for info in results {
+        for feat in &info.features {
+            if !features.contains(feat) {
+                features.push(feat.clone());
+            }
+        }
+        if info.name == "Unknown" || info.confidence <= 0.0 {
+            continue;
+        }
+        let c = info.confidence.clamp(0.0, MAX_CONFIDENCE);
+        *miss.entry(info.name.as_str()).or_insert(1.0) *= 1.0 - c;
+    }
+
+    let support: HashMap<&str, f32> = miss.iter().map(|(name, m)| (*name, 1.0 - m)).collect();
+    let best = support
+        .iter()
+        .max_by(|a, b| a.1.total_cmp(b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(name, s)| (*name, *s));
+
+    let Some((best_name, best_support)) = best else {
+        return OSInfo {
+            name: "Unknown".to_string(),
+            version: None,
+            confidence: 0.0,
+            features,
+        };
+    };
+
+    // 其余系统的证据合并为冲突度
+    let conflict = 1.0
+        - support
+            .iter()
+            .filter(|(name, _)| **name != best_name)
+            .map(|(_, s)| 1.0 - s)
+            .product::<f32>();
+    let confidence = (best_support * (1.0 - conflict * CONFLICT_PENALTY)).min(MAX_CONFIDENCE);
+
+    // 版本取自支持该系统且置信度最高的单项结果
+    let version = results
+        .iter()
+        .filter(|info| info.name == best_name && info.version.is_some())
+        .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+        .and_then(|info| info.version.clone());
+
+    OSInfo {
+        name: best_name.to_string(),
+        version,
+        confidence,
+        features,
+    }
+}
+
+/// 常见系统的初始 TTL：32（旧 Windows）、64（Linux/Unix）、128（Windows）、255（网络设备/Solaris）
+pub fn initial_ttl(observed: u8) -> u8 {
+    [32, 64, 128, 255]
+        .into_iter()
+        .find(|&initial| observed <= initial)
+        .unwrap_or(255)
+}
+
+/// 由观察到的 TTL 估算与目标之间的跳数
+pub fn hop_count(observed: u8) -> u8 {
+    initial_ttl(observed) - observed
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[tokio::test]
     async fn test_os_detection() {
-        let detector = OSDetector::new("127.0.0.1".parse().unwrap());
+        let detector = OSDetector::new("127.0.0.1".parse::<ScanTarget>().unwrap());
         let result = detector.detect().await;
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_http_headers_beyond_first_read() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let mut response = "HTTP/1.1 200 OK\r\n".to_string();
+            for i in 0..100 {
+                response.push_str(&format!("X-Padding-{}: {}\r\n", i, "a".repeat(40)));
+            }
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            stream.write_all(b"Server: nginx/1.24.0\r\n\r\n").await.unwrap();
+        });
+
+        let detector = OSDetector::new(addr.ip());
+        let os_info = detector.probe_http(addr).await.unwrap();
+        assert_eq!(os_info.name, "Linux/Unix");
+        assert_eq!(os_info.version.as_deref(), Some("1.24.0"));
+    }
+
+    #[tokio::test]
+    async fn test_http_probe_sends_custom_headers() {
+        use tokio::io::AsyncReadExt;
+
+        // 只对带指定 User-Agent 的请求返回真实的 Server 头，模拟挑剔的 WAF
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let len = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..len]);
+                let response: &[u8] = if request.contains("User-Agent: Mozilla/5.0\r\n") && !request.contains("rustscan") {
+                    b"HTTP/1.1 200 OK\r\nServer: Microsoft-IIS/10.0\r\n\r\n"
+                } else {
+                    b"HTTP/1.1 403 Forbidden\r\n\r\n"
+                };
+                let _ = stream.write_all(response).await;
+            }
+        });
+
+        let headers: Arc<[HttpHeader]> = Arc::from(["User-Agent: Mozilla/5.0".parse::<HttpHeader>().unwrap()]);
+        let blocked = OSDetector::new(addr.ip()).probe_http(addr).await.unwrap();
+        assert_eq!(blocked.name, "Unknown");
+        let detector = OSDetector::new(addr.ip()).with_http_headers(headers);
+        assert_eq!(detector.probe_http(addr).await.unwrap().name, "Windows");
+    }
+
+    #[tokio::test]
+    async fn test_http_probe_retries_silent_connection() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // 第一次连接直接关闭，模拟繁忙的主机
+            drop(listener.accept().await.unwrap());
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            stream.write_all(b"HTTP/1.1 200 OK\r\nServer: Microsoft-IIS/10.0\r\n\r\n").await.unwrap();
+        });
+
+        let config = OSDetectorConfig {
+            connect_timeout: Duration::from_millis(500),
+            read_timeout: Duration::from_millis(500),
+            retries: 1,
+        };
+        let detector = OSDetector::new(addr.ip()).with_config(config);
+        let os_info = detector.probe_http(addr).await.unwrap();
+        assert_eq!(os_info.name, "Windows");
+    }
+
+    #[test]
+    fn test_hop_count() {
+        assert_eq!((initial_ttl(64), hop_count(64)), (64, 0));
+        assert_eq!((initial_ttl(52), hop_count(52)), (64, 12));
+        assert_eq!((initial_ttl(113), hop_count(113)), (128, 15));
+        assert_eq!((initial_ttl(240), hop_count(240)), (255, 15));
+    }
+
+    fn evidence(name: &str, confidence: f32) -> OSInfo {
+        OSInfo {
+            name: name.to_string(),
+            version: None,
+            confidence,
+            features: vec![format!("{} {}", name, confidence)],
+        }
+    }
+
+    #[test]
+    fn test_combine_evidence() {
+        // 相互印证的证据应高于任一单项
+        let agreeing = combine_evidence(&[
+            evidence("Linux/Unix", 0.85),
+            evidence("Linux/Unix", 0.7),
+            evidence("Linux/Unix", 0.8),
+        ]);
+        assert_eq!(agreeing.name, "Linux/Unix");
+        assert!(agreeing.confidence > 0.85);
+        assert!(agreeing.confidence <= MAX_CONFIDENCE);
+        assert_eq!(agreeing.features.len(), 3);
+
+        // 冲突证据应降低置信度
+        let conflicting = combine_evidence(&[
+            evidence("Linux/Unix", 0.85),
+            evidence("Windows", 0.7),
+        ]);
+        assert_eq!(conflicting.name, "Linux/Unix");
+        assert!(conflicting.confidence < 0.85);
+
+        let unknown = combine_evidence(&[evidence("Unknown", 0.0)]);
+        assert_eq!(unknown.name, "Unknown");
+        assert_eq!(unknown.confidence, 0.0);
+    }
 }
\ No newline at end of file
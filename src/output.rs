@@ -1,46 +1,322 @@
+use crate::anomaly::Anomaly;
+use crate::exec::ExecStatus;
+use crate::geo::GeoInfo;
+use crate::tls::TlsFingerprint;
 use crate::os_detector::OSInfo;
+use crate::service_detector::{DetectedService, ServiceMethod};
+use crate::service_fingerprints::ServiceFingerprint;
 use colored::*;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::cell::OnceCell;
+use std::net::IpAddr;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// `--append` 时串行化对结果文件的读取、合并和写入，多个主机并发保存也不会互相覆盖
+static APPEND_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Output {
     target: String,
+    /// 用户指定的标签（`--label`），区分不同范围或不同批次的扫描结果
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    /// 目标地址的 ASN、组织和国家（`--geo-db`），与 `target` 同级输出
+    #[serde(flatten)]
+    geo: GeoInfo,
     os_info: Option<OSInfo>,
     ports: Vec<PortInfo>,
+    /// `--exec` 命令的执行结果
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exec: Option<ExecStatus>,
+    /// 主机级别的附加发现，如可能存在负载均衡
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<String>,
+    /// 汇总出的异常发现（`--anomalies`）和开放代理、邮件中继（`--check-proxies`）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    anomalies: Vec<Anomaly>,
+    /// 多轮扫描（`--passes`）的轮数，单轮扫描时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    passes: Option<u32>,
+    /// 产生这份结果的命令行和扫描参数（`--record-invocation`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    invocation: Option<Invocation>,
+    /// 之后添加的端口是否带上匹配到的指纹
+    #[serde(skip)]
+    include_fingerprints: bool,
+}
+
+/// 扫描的调用方式，用于复现和审计
+///
+/// 记录的是模板（`-T`、`--quick`）和文件描述符校准之后实际生效的值。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invocation {
+    /// 完整命令行，`--http-header` 的值已隐去
+    pub command_line: Vec<String>,
+    /// rustscan 版本
+    pub version: String,
+    /// 目标（`-i`）或端点列表文件（`--endpoints`）
+    pub targets: String,
+    /// 端口列表，未指定 `--ports` 时为 `起始-结束`
+    pub ports: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_ports: Option<String>,
+    pub scan_types: Vec<String>,
+    pub timeout_ms: u64,
+    pub service_timeout_ms: u64,
+    /// 每个主机的并发数
+    pub threads: usize,
+    pub host_concurrency: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rate: Option<u64>,
+    pub scan_delay_ms: u64,
+    pub retries: u32,
+    /// 每个主机的重试预算（`--retry-budget`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_budget: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<u8>,
+    pub passes: u32,
+    /// `--randomize-hosts` 和 `--start-jitter` 使用的随机种子
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// 连接扫描轮换的源端口范围（`--source-port-range`），便于在防火墙日志中找出扫描流量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_ports: Option<String>,
+    /// 探测报文使用的 TTL（`--ttl`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+}
+
+impl Invocation {
+    /// 去掉命令行中 `--http-header` 的值，避免把 `Authorization` 等凭据写进报告
+    pub fn redact_command_line(args: impl IntoIterator<Item = String>) -> Vec<String> {
+        let mut redact_next = false;
+        args.into_iter()
+            .map(|arg| {
+                if std::mem::take(&mut redact_next) {
+                    return redact_header(&arg);
+                }
+                match arg.strip_prefix("--http-header") {
+                    Some("") => {
+                        redact_next = true;
+                        arg
+                    }
+                    Some(value) if value.starts_with('=') => format!("--http-header={}", redact_header(&value[1..])),
+                    _ => arg,
+                }
+            })
+            .collect()
+    }
+}
+
+/// 只保留请求头名称
+fn redact_header(header: &str) -> String {
+    match header.split_once(':') {
+        Some((name, _)) => format!("{}: ***", name.trim()),
+        None => "***".to_string(),
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortInfo {
     port: u16,
     service: String,
     protocol: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    /// 协议版本，如 `TLS 1.2`、`SSH-2.0`、`HTTP/1.1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    protocol_version: Option<String>,
+    /// TLS 握手的 JA3/JA3S 指纹（`--ja3`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls: Option<TlsFingerprint>,
+    /// 识别结果的置信度（0-1）
+    #[serde(default)]
+    confidence: f32,
+    /// 识别方式：port-guess/banner/probe/tls
+    #[serde(default)]
+    method: ServiceMethod,
+    /// 多轮扫描（`--passes`）中该端口开放的轮数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seen_open: Option<u32>,
+    /// 匹配到的完整指纹，仅 `--debug-fingerprints` 时输出
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<ServiceFingerprint>,
+}
+
+/// 结果文件既可能是单个 `Output`，也可能是多个主机的数组
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OutputFile {
+    Many(Vec<Output>),
+    One(Box<Output>),
 }
 
 impl Output {
     pub fn new(target: String) -> Self {
         Self {
             target,
+            label: None,
+            geo: GeoInfo::default(),
             os_info: None,
             ports: Vec::new(),
+            exec: None,
+            notes: Vec::new(),
+            anomalies: Vec::new(),
+            passes: None,
+            invocation: None,
+            include_fingerprints: false,
+        }
+    }
+
+    /// 读取之前保存的 JSON 结果，`-` 表示从标准输入读取
+    ///
+    /// 内容可以是多个依次排列的 JSON 值（`-j -` 逐个主机输出的格式）。
+    pub fn load_json(path: &Path) -> anyhow::Result<Vec<Output>> {
+        let content = if is_stdio(path) {
+            let mut content = String::new();
+            std::io::stdin().read_to_string(&mut content)?;
+            content
+        } else {
+            std::fs::read_to_string(path)?
+        };
+        let mut outputs = Vec::new();
+        for file in serde_json::Deserializer::from_str(&content).into_iter::<OutputFile>() {
+            match file {
+                Ok(OutputFile::Many(many)) => outputs.extend(many),
+                Ok(OutputFile::One(output)) => outputs.push(*output),
+                // 扫描中断时 --stream-output 写到一半的文件
+                Err(e) => {
+                    let recovered = recover_stream(&content);
+                    return if recovered.is_empty() { Err(e.into()) } else { Ok(recovered) };
+                }
+            }
         }
+        Ok(outputs)
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+    }
+
+    /// 记录目标的网络归属，查询不到时为 `None`
+    pub fn set_geo(&mut self, geo: Option<GeoInfo>) {
+        self.geo = geo.unwrap_or_default();
+    }
+
+    pub fn geo(&self) -> Option<&GeoInfo> {
+        (!self.geo.is_empty()).then_some(&self.geo)
+    }
+
+    pub fn ports(&self) -> &[PortInfo] {
+        &self.ports
+    }
+
+    /// 没有发现任何开放端口（主机未响应或端口全部关闭）
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
     }
 
     pub fn set_os_info(&mut self, os_info: OSInfo) {
         self.os_info = Some(os_info);
     }
 
-    pub fn add_port(&mut self, port: u16, service: String, protocol: String) {
+    pub fn os_info(&self) -> Option<&OSInfo> {
+        self.os_info.as_ref()
+    }
+
+    pub fn set_exec_status(&mut self, status: ExecStatus) {
+        self.exec = Some(status);
+    }
+
+    pub fn add_note(&mut self, note: String) {
+        self.notes.push(note);
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub fn set_anomalies(&mut self, anomalies: Vec<Anomaly>) {
+        self.anomalies = anomalies;
+    }
+
+    pub fn anomalies(&self) -> &[Anomaly] {
+        &self.anomalies
+    }
+
+    /// 只保留满足条件的端口
+    pub fn retain_ports(&mut self, keep: impl FnMut(&PortInfo) -> bool) {
+        self.ports.retain(keep);
+    }
+
+    pub fn add_port(&mut self, port: u16, service: &DetectedService, protocol: String) {
         self.ports.push(PortInfo {
             port,
-            service,
+            service: service.name.clone(),
             protocol,
+            version: service.version.clone(),
+            title: service.title.clone(),
+            location: service.location.clone(),
+            protocol_version: service.protocol_version.clone(),
+            tls: service.tls.clone(),
+            confidence: service.confidence,
+            method: service.method,
+            seen_open: None,
+            fingerprint: service.fingerprint.as_deref().filter(|_| self.include_fingerprints).cloned(),
         });
     }
 
+    /// 之后通过 `add_port` 添加的端口在 JSON 中附带匹配到的完整指纹（名称、匹配模式、权重、CPE 等），
+    /// 用于核对识别依据；默认不输出以免结果过大
+    pub fn include_fingerprints(&mut self) {
+        self.include_fingerprints = true;
+    }
+
+    /// 记录产生这份结果的命令行和扫描参数
+    pub fn set_invocation(&mut self, invocation: Option<Invocation>) {
+        self.invocation = invocation;
+    }
+
+    pub fn invocation(&self) -> Option<&Invocation> {
+        self.invocation.as_ref()
+    }
+
+    /// 记录多轮扫描的轮数
+    pub fn set_passes(&mut self, passes: u32) {
+        self.passes = Some(passes);
+    }
+
+    /// 记录端口在多轮扫描中开放的轮数
+    pub fn set_seen_open(&mut self, port: u16, protocol: &str, seen_open: u32) {
+        for port_info in self.ports.iter_mut().filter(|p| p.port == port && p.protocol == protocol) {
+            port_info.seen_open = Some(seen_open);
+        }
+    }
+
     pub fn print_console(&self) {
         println!("{} 扫描结果:", "[*]".blue());
         println!("目标: {}", self.target);
+        if let Some(label) = &self.label {
+            println!("标签: {}", label);
+        }
+        if let Some(geo) = self.geo() {
+            println!("归属: {}", geo);
+        }
 
         if let Some(os_info) = &self.os_info {
             println!(
@@ -59,43 +335,788 @@ impl Output {
 
         println!("\n开放端口:");
         for port_info in &self.ports {
-            println!(
-                "  - {} ({}) - {}",
-                port_info.port, port_info.protocol, port_info.service
-            );
+            let basis = match (port_info.seen_open, self.passes) {
+                (Some(seen_open), Some(passes)) => format!(
+                    "[{} {:.0}% 开放 {}/{}]",
+                    port_info.method.as_str(),
+                    port_info.confidence * 100.0,
+                    seen_open,
+                    passes
+                ),
+                _ => format!("[{} {:.0}%]", port_info.method.as_str(), port_info.confidence * 100.0),
+            };
+            match &port_info.version {
+                Some(version) => println!(
+                    "  - {} ({}) - {} {} {}",
+                    port_info.port, port_info.protocol, port_info.service, version, basis.dimmed()
+                ),
+                None => println!(
+                    "  - {} ({}) - {} {}",
+                    port_info.port, port_info.protocol, port_info.service, basis.dimmed()
+                ),
+            }
+            if let Some(title) = &port_info.title {
+                println!("      标题: {}", title);
+            }
+            if let Some(location) = &port_info.location {
+                println!("      重定向: {}", location);
+            }
+            if let Some(protocol_version) = &port_info.protocol_version {
+                println!("      协议版本: {}", protocol_version);
+            }
+            if let Some(tls) = &port_info.tls {
+                println!("      JA3S: {} ({})", tls.ja3s, tls.ja3s_string);
+            }
+        }
+
+        if !self.notes.is_empty() {
+            println!("\n备注:");
+            for note in &self.notes {
+                println!("  - {}", note);
+            }
         }
+
+        if let Some(exec) = &self.exec {
+            match exec.exit_code {
+                Some(code) => println!("\n执行命令: {} (退出码: {})", exec.command, code),
+                None => println!("\n执行命令: {} (被信号终止)", exec.command),
+            }
+        }
+    }
+
+    /// 按主机生成文件名，例如 `<dir>/192.168.1.1.json`
+    pub fn host_file_path(&self, dir: &Path, extension: &str) -> PathBuf {
+        dir.join(format!("{}.{}", host_file_stem(&self.target), extension))
     }
 
-    pub fn save_json(&self, path: &PathBuf) -> anyhow::Result<()> {
+    /// 写入 JSON 文件，`-` 表示写到标准输出
+    pub fn save_json(&self, path: &Path) -> anyhow::Result<()> {
+        write_file(path, &self.json_document()?)
+    }
+
+    fn json_document(&self) -> anyhow::Result<Vec<u8>> {
         let json = serde_json::to_string_pretty(&self)?;
-        std::fs::write(path, json)?;
-        Ok(())
+        Ok(format!("{}\n", json).into_bytes())
     }
 
-    pub fn save_csv(&self, path: &PathBuf) -> anyhow::Result<()> {
-        let mut wtr = csv::Writer::from_path(path)?;
+    /// 合并进已有的 JSON 结果文件后整体重写，文件不存在时直接创建；`-` 时与 `save_json` 相同
+    ///
+    /// 已有文件中同一主机的结果与本次合并：同一端口和协议以本次扫描为准，本次没有扫到的端口保留，
+    /// 操作系统和 `--exec` 结果本次有则替换；其他主机原样保留。
+    pub fn append_json(&self, path: &Path) -> anyhow::Result<()> {
+        if is_stdio(path) {
+            return self.save_json(path);
+        }
+        let _guard = APPEND_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut outputs = if path.exists() { Output::load_json(path)? } else { Vec::new() };
+        match outputs.iter_mut().find(|output| output.target == self.target) {
+            Some(existing) => existing.merge(self),
+            None => outputs.push(self.clone()),
+        }
+        let json = serde_json::to_string_pretty(&outputs)?;
+        write_file(path, format!("{}\n", json).as_bytes())
+    }
+
+    /// 把较新的扫描结果合并进来，同一端口和协议以 `newer` 为准
+    fn merge(&mut self, newer: &Output) {
+        self.ports
+            .retain(|old| !newer.ports.iter().any(|new| new.port == old.port && new.protocol == old.protocol));
+        self.ports.extend(newer.ports.iter().cloned());
+        self.ports.sort_by(|a, b| (a.port, &a.protocol).cmp(&(b.port, &b.protocol)));
+        if newer.label.is_some() {
+            self.label = newer.label.clone();
+        }
+        self.passes = newer.passes;
+        if newer.invocation.is_some() {
+            self.invocation = newer.invocation.clone();
+        }
+        if !newer.geo.is_empty() {
+            self.geo = newer.geo.clone();
+        }
+        if newer.os_info.is_some() {
+            self.os_info = newer.os_info.clone();
+        }
+        if newer.exec.is_some() {
+            self.exec = newer.exec.clone();
+        }
+        for note in &newer.notes {
+            if !self.notes.contains(note) {
+                self.notes.push(note.clone());
+            }
+        }
+        if !newer.anomalies.is_empty() {
+            self.anomalies = newer.anomalies.clone();
+        }
+    }
+
+    /// 在已有的 CSV 文件末尾追加本次的行（结果文件没有表头），`-` 时与 `save_csv` 相同
+    pub fn append_csv(&self, path: &Path) -> anyhow::Result<()> {
+        append_csv_rows(path, &self.csv_rows()?)
+    }
+
+    /// 写入 CSV 文件，`-` 表示写到标准输出
+    pub fn save_csv(&self, path: &Path) -> anyhow::Result<()> {
+        write_file(path, &self.csv_rows()?)
+    }
+
+    fn csv_rows(&self) -> anyhow::Result<Vec<u8>> {
+        // 操作系统行与端口行的列数不同
+        let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(Vec::new());
+
+        if let Some(label) = &self.label {
+            wtr.write_record(["Label", label])?;
+        }
+
+        if let Some(geo) = self.geo() {
+            wtr.write_record([
+                "Geo",
+                &geo.asn.map(|asn| asn.to_string()).unwrap_or_default(),
+                geo.org.as_deref().unwrap_or_default(),
+                geo.country.as_deref().unwrap_or_default(),
+            ])?;
+        }
 
         // 写入操作系统信息
         if let Some(os_info) = &self.os_info {
-            wtr.write_record(&[
+            wtr.write_record([
                 "OS",
                 &os_info.name,
-                &os_info.version.as_deref().unwrap_or("Unknown"),
+                os_info.version.as_deref().unwrap_or("Unknown"),
                 &format!("{:.2}", os_info.confidence * 100.0),
             ])?;
         }
 
-        // 写入端口信息
+        // 写入端口信息；协议版本列在识别方式之后，多轮扫描时最后一列为开放轮数，如 2/3
         for port_info in &self.ports {
-            wtr.write_record(&[
-                "Port",
-                &port_info.port.to_string(),
-                &port_info.protocol,
-                &port_info.service,
-            ])?;
+            let mut record = vec![
+                "Port".to_string(),
+                port_info.port.to_string(),
+                port_info.protocol.clone(),
+                port_info.service.clone(),
+                port_info.version.clone().unwrap_or_default(),
+                port_info.title.clone().unwrap_or_default(),
+                port_info.location.clone().unwrap_or_default(),
+                format!("{:.2}", port_info.confidence),
+                port_info.method.as_str().to_string(),
+                port_info.protocol_version.clone().unwrap_or_default(),
+            ];
+            if let (Some(seen_open), Some(passes)) = (port_info.seen_open, self.passes) {
+                record.push(format!("{}/{}", seen_open, passes));
+            }
+            wtr.write_record(&record)?;
         }
 
-        wtr.flush()?;
+        for note in &self.notes {
+            wtr.write_record(["Note", note])?;
+        }
+        for anomaly in &self.anomalies {
+            wtr.write_record(["Anomaly", &anomaly.to_string()])?;
+        }
+
+        Ok(wtr.into_inner()?)
+    }
+}
+
+/// 在 CSV 文件末尾追加已编码的行，`-` 时直接写到标准输出
+fn append_csv_rows(path: &Path, rows: &[u8]) -> anyhow::Result<()> {
+    if is_stdio(path) {
+        return write_file(path, rows);
+    }
+    let _guard = APPEND_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(rows)?;
+    Ok(())
+}
+
+/// 交给写入目标的一个主机结果：各格式的编码在第一次用到时生成，
+/// 同一主机写到多个目标（如 `-j` 和 `--per-host` 的 JSON）时每种格式只序列化一次
+pub struct OutputRecord<'a> {
+    output: &'a Output,
+    json: OnceCell<Vec<u8>>,
+    json_line: OnceCell<Vec<u8>>,
+    csv: OnceCell<Vec<u8>>,
+}
+
+impl<'a> OutputRecord<'a> {
+    pub fn new(output: &'a Output) -> Self {
+        Self {
+            output,
+            json: OnceCell::new(),
+            json_line: OnceCell::new(),
+            csv: OnceCell::new(),
+        }
+    }
+
+    pub fn output(&self) -> &'a Output {
+        self.output
+    }
+
+    /// 格式化的 JSON 文档，与 [`Output::save_json`] 写入的内容相同
+    pub fn json(&self) -> anyhow::Result<&[u8]> {
+        encode_once(&self.json, || self.output.json_document())
+    }
+
+    /// 单行 JSON，不含换行
+    pub fn json_line(&self) -> anyhow::Result<&[u8]> {
+        encode_once(&self.json_line, || Ok(serde_json::to_vec(self.output)?))
+    }
+
+    /// CSV 行，与 [`Output::save_csv`] 写入的内容相同
+    pub fn csv(&self) -> anyhow::Result<&[u8]> {
+        encode_once(&self.csv, || self.output.csv_rows())
+    }
+}
+
+fn encode_once(cell: &OnceCell<Vec<u8>>, encode: impl FnOnce() -> anyhow::Result<Vec<u8>>) -> anyhow::Result<&[u8]> {
+    if let Some(encoded) = cell.get() {
+        return Ok(encoded);
+    }
+    let encoded = encode()?;
+    Ok(cell.get_or_init(|| encoded))
+}
+
+/// 结果的写入目标：每个主机完成后调用一次 `write`，所有主机结束后调用一次 `finish`
+pub trait OutputSink: Send + Sync {
+    fn write(&self, record: &OutputRecord<'_>) -> anyhow::Result<()>;
+
+    fn finish(&self) -> anyhow::Result<()> {
         Ok(())
     }
 }
+
+/// 把每个主机的结果依次交给所有写入目标，结果只遍历一次，各格式的编码由目标共用
+#[derive(Default)]
+pub struct OutputSinks {
+    sinks: Vec<Box<dyn OutputSink>>,
+}
+
+impl OutputSinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, sink: impl OutputSink + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// 按添加顺序写入，遇到第一个错误即返回
+    pub fn write(&self, output: &Output) -> anyhow::Result<()> {
+        let record = OutputRecord::new(output);
+        self.sinks.iter().try_for_each(|sink| sink.write(&record))
+    }
+
+    pub fn finish(&self) -> anyhow::Result<()> {
+        self.sinks.iter().try_for_each(|sink| sink.finish())
+    }
+}
+
+/// 结果文件的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Csv,
+}
+
+impl FileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Json => "json",
+            FileFormat::Csv => "csv",
+        }
+    }
+
+    /// 覆盖写入，`append` 时 JSON 按主机合并、CSV 在末尾追加
+    fn save(&self, record: &OutputRecord<'_>, path: &Path, append: bool) -> anyhow::Result<()> {
+        match (self, append) {
+            (FileFormat::Json, false) => write_file(path, record.json()?),
+            // 合并后整体重写，无法复用单个主机的编码
+            (FileFormat::Json, true) => record.output().append_json(path),
+            (FileFormat::Csv, false) => write_file(path, record.csv()?),
+            (FileFormat::Csv, true) => append_csv_rows(path, record.csv()?),
+        }
+    }
+}
+
+/// `-j`/`-C`：每个主机完成后写入结果文件
+pub struct FileSink {
+    path: PathBuf,
+    format: FileFormat,
+    append: bool,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, format: FileFormat, append: bool) -> Self {
+        Self { path, format, append }
+    }
+}
+
+impl OutputSink for FileSink {
+    fn write(&self, record: &OutputRecord<'_>) -> anyhow::Result<()> {
+        self.format.save(record, &self.path, self.append)
+    }
+}
+
+/// `--per-host`：每个主机在目录中单独写一个 JSON 和一个 CSV 文件
+pub struct PerHostSink {
+    dir: PathBuf,
+    append: bool,
+}
+
+impl PerHostSink {
+    pub fn new(dir: PathBuf, append: bool) -> Self {
+        Self { dir, append }
+    }
+}
+
+impl OutputSink for PerHostSink {
+    fn write(&self, record: &OutputRecord<'_>) -> anyhow::Result<()> {
+        for format in [FileFormat::Json, FileFormat::Csv] {
+            let path = record.output().host_file_path(&self.dir, format.extension());
+            format.save(record, &path, self.append)?;
+        }
+        Ok(())
+    }
+}
+
+/// 从 `--stream-output` 写到一半的文件中取出完整的主机结果：每行一个主机，
+/// 数组形式的行尾带逗号，最后一行可能不完整
+fn recover_stream(content: &str) -> Vec<Output> {
+    content
+        .lines()
+        .map(|line| line.trim().trim_end_matches(','))
+        .filter(|line| !matches!(*line, "" | "[" | "]"))
+        .map_while(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// 流式结果文件的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamFormat {
+    /// JSON 数组，每行一个主机，结束时补上 `]`
+    JsonArray,
+    /// JSON Lines（扩展名为 `.jsonl`），每行一个主机
+    JsonLines,
+    Csv,
+}
+
+/// `--stream-output`：每个主机完成后立即把结果写入文件，扫描中途崩溃也不会丢失已完成的主机
+///
+/// JSON 数组每个元素占一行，被截断时 [`Output::load_json`] 仍能读出完整的行；
+/// CSV 与 JSON Lines 本身就可以逐行追加。
+pub struct StreamWriter {
+    format: StreamFormat,
+    /// 输出目标和已写入的主机数
+    state: Mutex<(Box<dyn Write + Send>, usize)>,
+}
+
+impl StreamWriter {
+    /// 创建（覆盖）JSON 结果文件，扩展名为 `.jsonl` 时写 JSON Lines，否则写 JSON 数组；`-` 表示标准输出
+    pub fn json(path: &Path) -> anyhow::Result<Self> {
+        let format = match path.extension() {
+            Some(extension) if extension == "jsonl" => StreamFormat::JsonLines,
+            _ => StreamFormat::JsonArray,
+        };
+        let writer = Self::open(path, format)?;
+        if format == StreamFormat::JsonArray {
+            writer.write_flushed(b"[\n")?;
+        }
+        Ok(writer)
+    }
+
+    /// 创建（覆盖）CSV 结果文件，`-` 表示标准输出
+    pub fn csv(path: &Path) -> anyhow::Result<Self> {
+        Self::open(path, StreamFormat::Csv)
+    }
+
+    fn open(path: &Path, format: StreamFormat) -> anyhow::Result<Self> {
+        let out: Box<dyn Write + Send> = if is_stdio(path) {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(std::fs::File::create(path)?)
+        };
+        Ok(Self {
+            format,
+            state: Mutex::new((out, 0)),
+        })
+    }
+
+    fn write_flushed(&self, content: &[u8]) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.0.write_all(content)?;
+        state.0.flush()?;
+        Ok(())
+    }
+}
+
+impl OutputSink for StreamWriter {
+    /// 写入一个主机的结果并立即刷新
+    fn write(&self, record: &OutputRecord<'_>) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (prefix, chunk, suffix): (&[u8], _, &[u8]) = match self.format {
+            StreamFormat::JsonArray if state.1 > 0 => (b",\n", record.json_line()?, b""),
+            StreamFormat::JsonArray => (b"", record.json_line()?, b""),
+            StreamFormat::JsonLines => (b"", record.json_line()?, b"\n"),
+            StreamFormat::Csv => (b"", record.csv()?, b""),
+        };
+        state.0.write_all(&[prefix, chunk, suffix].concat())?;
+        state.0.flush()?;
+        state.1 += 1;
+        Ok(())
+    }
+
+    /// 所有主机写完后结束文件：JSON 数组补上 `]`
+    fn finish(&self) -> anyhow::Result<()> {
+        if self.format == StreamFormat::JsonArray {
+            let empty = self.state.lock().unwrap_or_else(PoisonError::into_inner).1 == 0;
+            self.write_flushed(if empty { b"]\n" } else { b"\n]\n" })?;
+        }
+        Ok(())
+    }
+}
+
+/// 路径是否为表示标准输入/输出的 `-`
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// 写入文件；写到标准输出时整块写入，多个主机并发输出也不会交错
+pub fn write_file(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    if is_stdio(path) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(content)?;
+        stdout.flush()?;
+    } else {
+        std::fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+impl PortInfo {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    pub fn method(&self) -> ServiceMethod {
+        self.method
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    pub fn protocol_version(&self) -> Option<&str> {
+        self.protocol_version.as_deref()
+    }
+
+    pub fn tls(&self) -> Option<&TlsFingerprint> {
+        self.tls.as_ref()
+    }
+}
+
+/// 将目标地址转换为可用作文件名的形式（IPv6 的 `:` 与区域标识的 `%` 替换为 `_`）
+pub fn host_file_stem(target: &str) -> String {
+    target
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// 把地址连续的主机合并成 `起始-结束` 区间，用于汇总大量无开放端口的主机
+///
+/// 无法解析为 IP 的目标（如带区域标识的 IPv6 地址）单独列出。
+pub fn collapse_host_ranges<'a>(targets: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut ips = Vec::new();
+    let mut others = Vec::new();
+    for target in targets {
+        match target.parse::<IpAddr>() {
+            Ok(ip) => ips.push(ip),
+            Err(_) => others.push(target.to_string()),
+        }
+    }
+    ips.sort();
+    ips.dedup();
+
+    let mut ranges: Vec<(IpAddr, IpAddr)> = Vec::new();
+    for ip in ips {
+        match ranges.last_mut() {
+            Some((_, end)) if is_next_addr(*end, ip) => *end = ip,
+            _ => ranges.push((ip, ip)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .chain(others)
+        .collect()
+}
+
+fn is_next_addr(prev: IpAddr, next: IpAddr) -> bool {
+    match (prev, next) {
+        (IpAddr::V4(prev), IpAddr::V4(next)) => u32::from(prev).checked_add(1) == Some(u32::from(next)),
+        (IpAddr::V6(prev), IpAddr::V6(next)) => u128::from(prev).checked_add(1) == Some(u128::from(next)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_file_stem() {
+        assert_eq!(host_file_stem("192.168.1.1"), "192.168.1.1");
+        assert_eq!(host_file_stem("2001:db8::1"), "2001_db8__1");
+        assert_eq!(host_file_stem("fe80::1%eth0"), "fe80__1_eth0");
+        assert_eq!(host_file_stem("../etc/passwd"), ".._etc_passwd");
+    }
+
+    #[test]
+    fn test_invocation() {
+        let args = ["rustscan", "-i", "10.0.0.0/24", "--http-header", "Authorization: Basic YWRtaW46YWRtaW4=", "--http-header=X-Token:abc"];
+        let command_line = Invocation::redact_command_line(args.map(String::from));
+        assert_eq!(
+            command_line,
+            vec!["rustscan", "-i", "10.0.0.0/24", "--http-header", "Authorization: ***", "--http-header=X-Token: ***"]
+        );
+
+        let invocation = Invocation {
+            command_line,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            targets: "10.0.0.0/24".to_string(),
+            ports: "1-1000".to_string(),
+            udp_ports: None,
+            scan_types: vec!["TCP".to_string()],
+            timeout_ms: 200,
+            service_timeout_ms: 5000,
+            threads: 1000,
+            host_concurrency: 64,
+            max_rate: None,
+            scan_delay_ms: 0,
+            retries: 0,
+            retry_budget: None,
+            timing: Some(4),
+            passes: 1,
+            seed: None,
+            source_ports: Some("40000-40100".to_string()),
+            ttl: None,
+        };
+        let mut output = Output::new("10.0.0.1".to_string());
+        assert!(!serde_json::to_string(&output).unwrap().contains("invocation"));
+        output.set_invocation(Some(invocation.clone()));
+        let json: serde_json::Value = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["invocation"]["timing"], 4);
+        assert!(json["invocation"].get("max_rate").is_none());
+        assert_eq!(json["invocation"]["source_ports"], "40000-40100");
+        assert!(json["invocation"].get("ttl").is_none());
+        let loaded: Output = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.invocation(), Some(&invocation));
+    }
+
+    #[test]
+    fn test_load_concatenated_json() {
+        let path = std::env::temp_dir().join(format!("rustscan-load-{}.json", std::process::id()));
+        let mut first = Output::new("10.0.0.1".to_string());
+        first.set_label(Some("client-a-dmz".to_string()));
+        first.add_port(22, &DetectedService::new("SSH"), "TCP".to_string());
+        first.save_json(&path).unwrap();
+        let mut content = std::fs::read(&path).unwrap();
+        content.extend_from_slice(br#"[{"target": "10.0.0.2", "os_info": null, "ports": []}]"#);
+        std::fs::write(&path, content).unwrap();
+
+        let outputs = Output::load_json(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let targets: Vec<&str> = outputs.iter().map(Output::target).collect();
+        assert_eq!(targets, vec!["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(outputs[0].ports()[0].service(), "SSH");
+        assert_eq!(outputs[0].label(), Some("client-a-dmz"));
+        assert_eq!(outputs[1].label(), None);
+        assert!(is_stdio(Path::new("-")));
+    }
+
+    #[test]
+    fn test_append_merges_by_host_and_port() {
+        let dir = std::env::temp_dir().join(format!("rustscan-append-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (json, csv) = (dir.join("scan.json"), dir.join("scan.csv"));
+
+        let mut old = Output::new("10.0.0.1".to_string());
+        old.add_port(22, &DetectedService::new("SSH"), "TCP".to_string());
+        old.add_port(80, &DetectedService::new("HTTP"), "TCP".to_string());
+        let other = Output::new("10.0.0.2".to_string());
+        let mut new = Output::new("10.0.0.1".to_string());
+        new.add_port(80, &DetectedService::new("nginx"), "TCP".to_string());
+        new.add_port(53, &DetectedService::new("DNS"), "UDP".to_string());
+        for output in [&old, &other, &new] {
+            output.append_json(&json).unwrap();
+            output.append_csv(&csv).unwrap();
+        }
+
+        let outputs = Output::load_json(&json).unwrap();
+        let csv = std::fs::read_to_string(&csv).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(outputs.len(), 2);
+        let ports: Vec<(u16, &str)> = outputs[0].ports().iter().map(|p| (p.port(), p.service())).collect();
+        assert_eq!(ports, vec![(22, "SSH"), (53, "DNS"), (80, "nginx")]);
+        assert_eq!(outputs[1].target(), "10.0.0.2");
+        assert_eq!(csv.lines().count(), 4);
+    }
+
+    #[test]
+    fn test_stream_writer() {
+        let dir = std::env::temp_dir().join(format!("rustscan-stream-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut first = Output::new("10.0.0.1".to_string());
+        first.add_port(22, &DetectedService::new("SSH"), "TCP".to_string());
+        let second = Output::new("10.0.0.2".to_string());
+
+        // 写完第一个主机后中断：文件不完整，但已写入的主机可以读出
+        let json = dir.join("scan.json");
+        let writer = StreamWriter::json(&json).unwrap();
+        writer.write(&OutputRecord::new(&first)).unwrap();
+        assert_eq!(Output::load_json(&json).unwrap().len(), 1);
+        writer.write(&OutputRecord::new(&second)).unwrap();
+        let mut truncated = std::fs::read_to_string(&json).unwrap();
+        truncated.truncate(truncated.len() - 5);
+        std::fs::write(dir.join("truncated.json"), truncated).unwrap();
+        let recovered = Output::load_json(&dir.join("truncated.json")).unwrap();
+        assert_eq!(recovered.iter().map(Output::target).collect::<Vec<_>>(), vec!["10.0.0.1"]);
+        // 正常结束后是合法的 JSON 数组
+        writer.finish().unwrap();
+        let array: Vec<Output> = serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(array.len(), 2);
+        assert_eq!(array[0].ports()[0].service(), "SSH");
+
+        let jsonl = dir.join("scan.jsonl");
+        let writer = StreamWriter::json(&jsonl).unwrap();
+        writer.write(&OutputRecord::new(&first)).unwrap();
+        writer.write(&OutputRecord::new(&second)).unwrap();
+        writer.finish().unwrap();
+        let content = std::fs::read_to_string(&jsonl).unwrap();
+        assert!(content.lines().all(|line| serde_json::from_str::<Output>(line).is_ok()));
+        assert_eq!(Output::load_json(&jsonl).unwrap().len(), 2);
+
+        let csv = dir.join("scan.csv");
+        let writer = StreamWriter::csv(&csv).unwrap();
+        writer.write(&OutputRecord::new(&first)).unwrap();
+        assert_eq!(std::fs::read_to_string(&csv).unwrap().lines().count(), 1);
+
+        let empty = dir.join("empty.json");
+        StreamWriter::json(&empty).unwrap().finish().unwrap();
+        assert!(Output::load_json(&empty).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_sinks() {
+        let dir = std::env::temp_dir().join(format!("rustscan-sinks-{}", std::process::id()));
+        let single = dir.join("single");
+        std::fs::create_dir_all(&single).unwrap();
+        let mut output = Output::new("10.0.0.1".to_string());
+        output.add_port(22, &DetectedService::new("SSH"), "TCP".to_string());
+        output.add_note("备注".to_string());
+
+        // 同一格式的编码只生成一次
+        let record = OutputRecord::new(&output);
+        assert!(std::ptr::eq(record.json().unwrap(), record.json().unwrap()));
+        assert!(std::ptr::eq(record.csv().unwrap(), record.csv().unwrap()));
+
+        let mut sinks = OutputSinks::new();
+        assert!(sinks.is_empty());
+        sinks.push(FileSink::new(dir.join("scan.json"), FileFormat::Json, false));
+        sinks.push(FileSink::new(dir.join("scan.csv"), FileFormat::Csv, false));
+        sinks.push(StreamWriter::json(&dir.join("stream.jsonl")).unwrap());
+        sinks.push(PerHostSink::new(dir.clone(), false));
+        sinks.write(&output).unwrap();
+        sinks.finish().unwrap();
+
+        // 与单独写入每种格式的结果完全相同
+        output.save_json(&single.join("scan.json")).unwrap();
+        output.save_csv(&single.join("scan.csv")).unwrap();
+        let read = |path: PathBuf| std::fs::read(path).unwrap();
+        assert_eq!(read(dir.join("scan.json")), read(single.join("scan.json")));
+        assert_eq!(read(dir.join("scan.csv")), read(single.join("scan.csv")));
+        assert_eq!(read(dir.join("10.0.0.1.json")), read(single.join("scan.json")));
+        assert_eq!(read(dir.join("10.0.0.1.csv")), read(single.join("scan.csv")));
+        let line = String::from_utf8(read(dir.join("stream.jsonl"))).unwrap();
+        assert_eq!(line, format!("{}\n", serde_json::to_string(&output).unwrap()));
+
+        // --append：CSV 追加，JSON 按主机合并
+        let mut appending = OutputSinks::new();
+        appending.push(FileSink::new(dir.join("scan.json"), FileFormat::Json, true));
+        appending.push(FileSink::new(dir.join("scan.csv"), FileFormat::Csv, true));
+        appending.write(&Output::new("10.0.0.2".to_string())).unwrap();
+        assert_eq!(Output::load_json(&dir.join("scan.json")).unwrap().len(), 2);
+        assert_eq!(read(dir.join("scan.csv")), read(single.join("scan.csv")));
+        appending.write(&output).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("scan.csv")).unwrap().lines().count(), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_fingerprints() {
+        let fingerprint = ServiceFingerprint {
+            name: "SSH".to_string(),
+            protocol: "TCP".to_string(),
+            port: 22,
+            banner_pattern: Some(r"SSH-\d\.\d".to_string()),
+            response_pattern: None,
+            weight: 0.95,
+            description: None,
+            version_pattern: None,
+            vendor: None,
+            cpe: Some("cpe:/a:openssh:openssh".to_string()),
+            binary: false,
+        };
+        let service = DetectedService {
+            fingerprint: Some(Box::new(fingerprint.clone())),
+            ..DetectedService::new("SSH")
+        };
+
+        let mut plain = Output::new("10.0.0.1".to_string());
+        plain.add_port(22, &service, "TCP".to_string());
+        assert!(!serde_json::to_string(&plain).unwrap().contains("fingerprint"));
+
+        let mut debug = Output::new("10.0.0.1".to_string());
+        debug.include_fingerprints();
+        debug.add_port(22, &service, "TCP".to_string());
+        let json: serde_json::Value = serde_json::to_value(&debug).unwrap();
+        assert_eq!(json["ports"][0]["fingerprint"]["cpe"], "cpe:/a:openssh:openssh");
+        assert_eq!(json["ports"][0]["fingerprint"]["banner_pattern"], r"SSH-\d\.\d");
+        let loaded: Output = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.ports()[0].fingerprint, Some(fingerprint));
+    }
+
+    #[test]
+    fn test_collapse_host_ranges() {
+        let targets = ["10.0.0.3", "10.0.0.1", "10.0.0.2", "10.0.0.5", "10.0.0.255", "10.0.1.0", "fe80::1%eth0", "::1"];
+        assert_eq!(
+            collapse_host_ranges(targets),
+            vec!["10.0.0.1-10.0.0.3", "10.0.0.5", "10.0.0.255-10.0.1.0", "::1", "fe80::1%eth0"]
+        );
+        assert!(collapse_host_ranges([]).is_empty());
+    }
+}
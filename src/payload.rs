@@ -0,0 +1,102 @@
+use crate::connector::Connector;
+use crate::service_fingerprints::read_until;
+use anyhow::{anyhow, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{timeout, Instant};
+
+/// 读取应答的最大长度
+pub const MAX_RESPONSE_LEN: usize = 64 * 1024;
+
+/// 解析 `--send-payload` 的载荷：十六进制字符串（可带 `0x` 前缀，允许空格和 `:` 分隔），
+/// 或 `@文件路径` 读取文件的原始字节
+pub fn parse_payload(spec: &str) -> Result<Vec<u8>> {
+    if let Some(path) = spec.strip_prefix('@') {
+        return std::fs::read(path).map_err(|e| anyhow!("无法读取载荷文件 {}: {}", path, e));
+    }
+    let digits: String = spec
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect();
+    if digits.is_empty() {
+        return Err(anyhow!("载荷为空"));
+    }
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("无效的十六进制载荷: {}", spec));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(anyhow!("十六进制载荷的位数必须为偶数: {}", spec));
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).expect("已检查为十六进制数字"))
+        .collect())
+}
+
+/// 连接目标端口发送载荷，读取应答直到对方关闭连接、到达 `read_timeout` 或读满上限
+pub async fn send_payload(
+    connector: &dyn Connector,
+    addr: SocketAddr,
+    payload: &[u8],
+    connect_timeout: Duration,
+    read_timeout: Duration,
+) -> Result<Vec<u8>> {
+    let mut stream = timeout(connect_timeout, connector.connect(addr))
+        .await
+        .map_err(|_| anyhow!("连接超时"))??;
+    stream.write_all(payload).await?;
+    let mut response = Vec::new();
+    read_until(&mut stream, &mut response, 0, MAX_RESPONSE_LEN, Instant::now() + read_timeout, |_| false).await;
+    Ok(response)
+}
+
+/// 按 `xxd` 的格式输出：偏移、16 个字节的十六进制和可打印字符
+pub fn hexdump(data: &[u8]) -> String {
+    data.chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            format!("{:08x}  {:<47}  |{}|\n", line * 16, hex.join(" "), text)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::{MockConnector, MockService};
+
+    #[tokio::test]
+    async fn test_send_payload() {
+        assert_eq!(parse_payload("0x48 45:4c 50").unwrap(), b"HELP");
+        assert_eq!(parse_payload("0d0A").unwrap(), b"\r\n");
+        assert!(parse_payload("abc").is_err());
+        assert!(parse_payload("zz").is_err());
+        assert!(parse_payload("éé").is_err());
+        assert!(parse_payload(" ").is_err());
+        assert!(parse_payload("@/nonexistent/payload.bin").is_err());
+
+        let connector = MockConnector::new().service(6379, MockService::Reply(b"-ERR unknown command\r\n".to_vec()));
+        let addr = "192.0.2.1:6379".parse().unwrap();
+        let response = send_payload(&connector, addr, b"HELP\r\n", Duration::from_secs(1), Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert_eq!(response, b"-ERR unknown command\r\n");
+        assert_eq!(response.escape_ascii().to_string(), r"-ERR unknown command\r\n");
+        assert_eq!(
+            hexdump(&response),
+            "00000000  2d 45 52 52 20 75 6e 6b 6e 6f 77 6e 20 63 6f 6d  |-ERR unknown com|\n\
+             00000010  6d 61 6e 64 0d 0a                                |mand..|\n"
+        );
+
+        let refused = "192.0.2.1:6380".parse().unwrap();
+        assert!(send_payload(&connector, refused, b"x", Duration::from_secs(1), Duration::from_secs(1)).await.is_err());
+    }
+}
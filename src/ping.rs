@@ -1,13 +1,95 @@
+use std::collections::{BTreeSet, HashMap};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::time::Duration;
-use tokio::net::TcpStream;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use futures::stream::{FuturesUnordered, StreamExt};
 use tokio::time::timeout;
 use socket2::{Domain, Protocol, Socket, Type, SockAddr};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::mem::MaybeUninit;
+use std::net::UdpSocket;
+use std::sync::{Arc, RwLock};
+use crate::capture::PacketCapture;
+use crate::source_port::tcp_socket;
+use crate::target::ScanTarget;
 
 const ICMP_ECHO_REQUEST: u8 = 8;
 const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+
+/// 广播/组播探测使用的 ICMP 标识符，用来区分其他程序的回显应答
+const DISCOVERY_IDENTIFIER: u16 = 0x5253;
+/// 广播发现至少等待的时间，大量主机的应答会陆续到达
+const MIN_DISCOVERY_WAIT: Duration = Duration::from_secs(1);
+/// 本地链路上的全部主机组播地址
+pub const ALL_HOSTS_MULTICAST: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+
+/// 未指定 `--ttl` 时 pcap 中记录的发出报文 TTL（Linux 默认值）
+const DEFAULT_TTL: u8 = 64;
+
+/// TCP 存活探测尝试连接的常见端口
+const DISCOVERY_PORTS: [u16; 4] = [80, 443, 22, 3389];
+
+/// 存活检测方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiscoveryMethod {
+    /// 同时连接常见 TCP 端口
+    Tcp,
+    /// ICMP 回显请求，需要原始套接字
+    Icmp,
+    /// TCP 与 ICMP 同时进行，任一成功即视为存活
+    #[default]
+    Both,
+}
+
+impl FromStr for DiscoveryMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(DiscoveryMethod::Tcp),
+            "icmp" => Ok(DiscoveryMethod::Icmp),
+            "both" => Ok(DiscoveryMethod::Both),
+            _ => Err(anyhow!("无效的存活检测方式: {}（可选 tcp/icmp/both）", s)),
+        }
+    }
+}
+
+/// 原始套接字收到的 ICMP 报文分类
+#[derive(Debug, PartialEq, Eq)]
+enum IcmpReply {
+    EchoReply,
+    /// 目标不可达；code 9/10/13 表示被管理策略禁止
+    Unreachable { code: u8 },
+    Other,
+}
+
+impl IcmpReply {
+    /// 解析包含 IPv4 头的原始报文
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let header_len = usize::from(packet.first()? & 0x0F) * 4;
+        let icmp = packet.get(header_len..)?;
+        if icmp.len() < 8 {
+            return None;
+        }
+        Some(match icmp[0] {
+            ICMP_ECHO_REPLY => IcmpReply::EchoReply,
+            ICMP_DEST_UNREACHABLE => IcmpReply::Unreachable { code: icmp[1] },
+            _ => IcmpReply::Other,
+        })
+    }
+
+    fn is_admin_prohibited(&self) -> bool {
+        matches!(self, IcmpReply::Unreachable { code: 9 | 10 | 13 })
+    }
+}
+
+/// 回显应答中的 ICMP 标识符
+fn echo_identifier(packet: &[u8]) -> Option<u16> {
+    let header_len = usize::from(packet.first()? & 0x0F) * 4;
+    let id = packet.get(header_len + 4..header_len + 6)?;
+    Some(u16::from_be_bytes([id[0], id[1]]))
+}
 
 struct IcmpHeader {
     type_: u8,
@@ -58,32 +140,231 @@ impl IcmpHeader {
     }
 }
 
-pub async fn ping(target: IpAddr, timeout_duration: Duration) -> bool {
-    // 尝试连接常见端口
-    let test_ports = [80, 443, 22, 3389];
-    
-    for port in test_ports {
-        let addr = SocketAddr::new(target, port);
-        if let Ok(Ok(_)) = timeout(timeout_duration, TcpStream::connect(addr)).await {
-            return true;
+/// 单次运行内已得到的存活检测结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Liveness {
+    /// `ping` 的结论，还没有进行完整检测时为 `None`
+    pub up: Option<bool>,
+    /// ICMP 探测结果：外层为 `None` 表示还没有探测过，内层为回显应答的 TTL
+    pub ttl: Option<Option<u8>>,
+    /// 最近一次更新的时间
+    pub checked_at: Instant,
+}
+
+/// 存活检测器
+pub struct Pinger {
+    timeout: Duration,
+    capture: Option<Arc<PacketCapture>>,
+    method: DiscoveryMethod,
+    /// 探测报文的 TTL，未设置时使用系统默认值
+    probe_ttl: Option<u8>,
+    /// 已检测过的主机，同一次运行中每个主机只探测一次
+    liveness: Arc<RwLock<HashMap<IpAddr, Liveness>>>,
+}
+
+impl Pinger {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            capture: None,
+            method: DiscoveryMethod::default(),
+            probe_ttl: None,
+            liveness: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// TCP 和 ICMP 探测报文使用指定的 TTL（仅影响发出的报文，不影响 `ttl` 返回的应答 TTL）
+    pub fn with_probe_ttl(mut self, ttl: u8) -> Self {
+        self.probe_ttl = Some(ttl);
+        self
+    }
+
+    /// 只使用指定的存活检测方式
+    pub fn with_method(mut self, method: DiscoveryMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// 将 ICMP 探测的收发报文写入 pcap（仅原始套接字路径，需要 root 或 CAP_NET_RAW）
+    pub fn with_capture(mut self, capture: Arc<PacketCapture>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// 已缓存的检测结果
+    pub fn liveness(&self, ip: IpAddr) -> Option<Liveness> {
+        self.liveness.read().ok()?.get(&ip).copied()
+    }
+
+    fn record(&self, ip: IpAddr, update: impl FnOnce(&mut Liveness)) {
+        if let Ok(mut entries) = self.liveness.write() {
+            let entry = entries.entry(ip).or_insert(Liveness {
+                up: None,
+                ttl: None,
+                checked_at: Instant::now(),
+            });
+            update(entry);
+            entry.checked_at = Instant::now();
+        }
+    }
+
+    /// 检测主机是否存活；各项探测同时进行，主机不存活时最多等待一个超时
+    ///
+    /// 结果按 IP 缓存，同一主机再次调用时不再发出探测。
+    pub async fn ping(&self, target: impl Into<ScanTarget>) -> bool {
+        let target = target.into();
+        if let Some(up) = self.liveness(target.ip).and_then(|liveness| liveness.up) {
+            return up;
+        }
+        let up = match self.method {
+            DiscoveryMethod::Tcp => self.tcp_ping(target).await,
+            DiscoveryMethod::Icmp => self.ttl(target).await.is_some(),
+            DiscoveryMethod::Both => {
+                let tcp = self.tcp_ping(target);
+                let icmp = async { self.ttl(target).await.is_some() };
+                tokio::pin!(tcp, icmp);
+                tokio::select! {
+                    alive = &mut tcp => alive || icmp.await,
+                    alive = &mut icmp => alive || tcp.await,
+                }
+            }
+        };
+        self.record(target.ip, |liveness| liveness.up = Some(up));
+        up
+    }
+
+    async fn tcp_ping(&self, target: ScanTarget) -> bool {
+        connect_any(target, &DISCOVERY_PORTS, self.timeout, self.probe_ttl).await
+    }
+
+    /// 发送 ICMP 回显请求，返回目标应答的 IP TTL；需要原始套接字，且目前仅支持 IPv4
+    ///
+    /// 与 `ping` 共用缓存：每个主机只发一次回显请求，收到应答的主机同时记为存活。
+    pub async fn ttl(&self, target: impl Into<ScanTarget>) -> Option<u8> {
+        let ip = target.into().ip;
+        if let Some(ttl) = self.liveness(ip).and_then(|liveness| liveness.ttl) {
+            return ttl;
         }
+        let IpAddr::V4(ipv4) = ip else {
+            return None;
+        };
+        // 原始套接字的收发是阻塞的，放到阻塞线程池中，避免占住异步工作线程
+        let timeout_duration = self.timeout;
+        let capture = self.capture.clone();
+        let probe_ttl = self.probe_ttl;
+        let ttl = tokio::task::spawn_blocking(move || icmp_ping(ipv4, timeout_duration, probe_ttl, capture.as_deref()))
+            .await
+            .ok()?
+            .ok()
+            .flatten();
+        self.record(ip, |liveness| {
+            liveness.ttl = Some(ttl);
+            if ttl.is_some() {
+                liveness.up = Some(true);
+            }
+        });
+        ttl
     }
+}
 
-    // 如果常见端口都不可达，尝试 ICMP ping
-    if let IpAddr::V4(ipv4) = target {
-        if let Ok(result) = icmp_ping(ipv4, timeout_duration).await {
-            return result;
+impl Pinger {
+    /// 向子网广播地址和组播地址发送 ICMP 回显请求，返回所有应答主机，并记为存活
+    ///
+    /// 只在本地链路有效，需要原始套接字。很多系统默认忽略广播 ping
+    /// （Linux 的 `net.ipv4.icmp_echo_ignore_broadcasts`、Windows），结果只能作为单播探测的补充。
+    pub fn broadcast_discover(&self, destinations: &[Ipv4Addr]) -> Result<BTreeSet<Ipv4Addr>> {
+        let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+        socket.set_broadcast(true)?;
+        socket.set_write_timeout(Some(self.timeout))?;
+        if let Some(ttl) = self.probe_ttl {
+            socket.set_ttl(u32::from(ttl))?;
         }
+
+        let mut header = IcmpHeader::new(DISCOVERY_IDENTIFIER, 1);
+        header.calculate_checksum();
+        let packet = header.to_bytes();
+        for &destination in destinations {
+            socket.send_to(&packet, &SockAddr::from(SocketAddr::new(IpAddr::V4(destination), 0)))?;
+            if let Some(capture) = &self.capture {
+                let src = match source_addr_for(IpAddr::V4(destination)) {
+                    Some(IpAddr::V4(src)) => src,
+                    _ => Ipv4Addr::UNSPECIFIED,
+                };
+                capture.record_ipv4(src, destination, 1, self.probe_ttl.unwrap_or(DEFAULT_TTL), &packet);
+            }
+        }
+
+        let mut responders = BTreeSet::new();
+        let deadline = Instant::now() + self.timeout.max(MIN_DISCOVERY_WAIT);
+        let mut buffer = [MaybeUninit::uninit(); 1024];
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+            socket.set_read_timeout(Some(remaining))?;
+            let Ok((len, from)) = socket.recv_from(&mut buffer) else {
+                break;
+            };
+            let received: Vec<u8> = buffer[..len].iter().map(|b| unsafe { b.assume_init() }).collect();
+            if let Some(capture) = &self.capture {
+                capture.record(&received);
+            }
+            let is_reply = IcmpReply::parse(&received) == Some(IcmpReply::EchoReply)
+                && echo_identifier(&received) == Some(DISCOVERY_IDENTIFIER);
+            if let (true, Some(SocketAddr::V4(from))) = (is_reply, from.as_socket()) {
+                responders.insert(*from.ip());
+            }
+        }
+        for &responder in &responders {
+            self.record(IpAddr::V4(responder), |liveness| liveness.up = Some(true));
+        }
+        Ok(responders)
     }
+}
 
+/// 同时连接多个端口，任一连接成功即返回并取消其余连接；全部失败时最多等待一个超时
+async fn connect_any(target: ScanTarget, ports: &[u16], timeout_duration: Duration, ttl: Option<u8>) -> bool {
+    let mut probes: FuturesUnordered<_> = ports
+        .iter()
+        .map(|&port| {
+            let addr = target.socket_addr(port);
+            timeout(timeout_duration, async move { tcp_socket(addr, ttl)?.connect(addr).await })
+        })
+        .collect();
+    while let Some(result) = probes.next().await {
+        if let Ok(Ok(_)) = result {
+            return true;
+        }
+    }
     false
 }
 
-async fn icmp_ping(target: Ipv4Addr, timeout_duration: Duration) -> Result<bool> {
+pub async fn ping(target: IpAddr, timeout_duration: Duration) -> bool {
+    Pinger::new(timeout_duration).ping(target).await
+}
+
+/// 查询发往目标时本机使用的源地址（UDP connect 不会发出任何报文）
+pub fn source_addr_for(target: IpAddr) -> Option<IpAddr> {
+    let bind_addr = match target {
+        IpAddr::V4(_) => "0.0.0.0:0",
+        IpAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(bind_addr).ok()?;
+    socket.connect(SocketAddr::new(target, 9)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// 返回回显应答的 TTL，未收到应答时为 `None`
+fn icmp_ping(
+    target: Ipv4Addr,
+    timeout_duration: Duration,
+    probe_ttl: Option<u8>,
+    capture: Option<&PacketCapture>,
+) -> Result<Option<u8>> {
     // 创建原始套接字
     let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
     socket.set_read_timeout(Some(timeout_duration))?;
     socket.set_write_timeout(Some(timeout_duration))?;
+    if let Some(ttl) = probe_ttl {
+        socket.set_ttl(u32::from(ttl))?;
+    }
 
     // 准备 ICMP 包
     let mut header = IcmpHeader::new(1, 1);
@@ -93,18 +374,118 @@ async fn icmp_ping(target: Ipv4Addr, timeout_duration: Duration) -> Result<bool>
     // 发送 ICMP 包
     let target_addr = SockAddr::from(SocketAddr::new(IpAddr::V4(target), 0));
     socket.send_to(&packet, &target_addr)?;
+    if let Some(capture) = capture {
+        let src = match source_addr_for(IpAddr::V4(target)) {
+            Some(IpAddr::V4(src)) => src,
+            _ => Ipv4Addr::UNSPECIFIED,
+        };
+        capture.record_ipv4(src, target, 1, probe_ttl.unwrap_or(DEFAULT_TTL), &packet);
+    }
 
     // 接收响应
+    // 原始套接字会收到本机所有 ICMP 报文（包括发往回环地址的请求本身），跳过无关报文直到超时
+    let deadline = Instant::now() + timeout_duration;
     let mut buffer = [MaybeUninit::uninit(); 1024];
-    match socket.recv_from(&mut buffer) {
-        Ok((len, _)) => {
-            if len >= 8 {
-                let reply_type = unsafe { buffer[0].assume_init() };
-                return Ok(reply_type == ICMP_ECHO_REPLY);
-            }
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+        socket.set_read_timeout(Some(remaining))?;
+        let Ok((len, from)) = socket.recv_from(&mut buffer) else {
+            break;
+        };
+        // IPv4 原始套接字收到的数据包含 IP 头
+        let received: Vec<u8> = buffer[..len].iter().map(|b| unsafe { b.assume_init() }).collect();
+        if let Some(capture) = capture {
+            capture.record(&received);
+        }
+        let from_target = from.as_socket().map(|addr| addr.ip()) == Some(IpAddr::V4(target));
+        match IcmpReply::parse(&received) {
+            // IP 头第 9 字节为 TTL
+            Some(IcmpReply::EchoReply) if from_target => return Ok(received.get(8).copied()),
+            // 防火墙明确拒绝，不再重试其他探测
+            Some(reply) if reply.is_admin_prohibited() => return Ok(None),
+            _ => {}
         }
-        Err(_) => {}
     }
 
-    Ok(false)
-} 
\ No newline at end of file
+    Ok(None)
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_icmp_reply() {
+        let mut packet = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        packet.extend_from_slice(&[ICMP_DEST_UNREACHABLE, 13, 0, 0, 0, 0, 0, 0]);
+        let reply = IcmpReply::parse(&packet).unwrap();
+        assert_eq!(reply, IcmpReply::Unreachable { code: 13 });
+        assert!(reply.is_admin_prohibited());
+
+        packet[20] = ICMP_ECHO_REPLY;
+        packet[21] = 0;
+        assert_eq!(IcmpReply::parse(&packet), Some(IcmpReply::EchoReply));
+
+        // 端口不可达不属于管理禁止
+        assert!(!IcmpReply::Unreachable { code: 3 }.is_admin_prohibited());
+        assert_eq!(IcmpReply::parse(&packet[..24]), None);
+
+        packet[24..26].copy_from_slice(&DISCOVERY_IDENTIFIER.to_be_bytes());
+        assert_eq!(echo_identifier(&packet), Some(DISCOVERY_IDENTIFIER));
+    }
+
+    /// 监听队列已满、不再应答 SYN 的端口，连接会一直挂起到超时
+    fn unresponsive_port() -> (Socket, Vec<Socket>) {
+        let listener = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        listener.bind(&SockAddr::from(SocketAddr::from(([127, 0, 0, 1], 0)))).unwrap();
+        listener.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fillers = (0..3)
+            .map(|_| {
+                let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+                socket.set_nonblocking(true).unwrap();
+                let _ = socket.connect(&addr);
+                socket
+            })
+            .collect();
+        std::thread::sleep(Duration::from_millis(50));
+        (listener, fillers)
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probes_run_concurrently() {
+        let timeout_duration = Duration::from_millis(300);
+        let target: ScanTarget = "127.0.0.1".parse().unwrap();
+        let hung: Vec<_> = (0..3).map(|_| unresponsive_port()).collect();
+        let mut ports: Vec<u16> = hung
+            .iter()
+            .map(|(listener, _)| listener.local_addr().unwrap().as_socket().unwrap().port())
+            .collect();
+
+        // 全部挂起：只等待一个超时，而不是每个端口各一个
+        let start = Instant::now();
+        assert!(!connect_any(target, &ports, timeout_duration, None).await);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= timeout_duration && elapsed < timeout_duration * 2, "{:?}", elapsed);
+
+        // 只有最后一个端口开放：立即返回，不等前面的端口超时
+        let open = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        ports.push(open.local_addr().unwrap().port());
+        let start = Instant::now();
+        assert!(connect_any(target, &ports, timeout_duration, None).await);
+        assert!(start.elapsed() < timeout_duration);
+
+        // 存活结果按 IP 缓存，再次检测同一主机不发出探测
+        let pinger = Pinger::new(timeout_duration).with_method(DiscoveryMethod::Tcp);
+        let up = pinger.ping(target).await;
+        let cached = pinger.liveness(target.ip).unwrap();
+        assert_eq!((cached.up, cached.ttl), (Some(up), None));
+        // 缓存里改成相反的结论：发出探测会得到原来的结论并更新记录时间
+        pinger.record(target.ip, |liveness| liveness.up = Some(!up));
+        let recorded = pinger.liveness(target.ip).unwrap().checked_at;
+        assert_eq!(pinger.ping(target).await, !up);
+        assert_eq!(pinger.liveness(target.ip).unwrap().checked_at, recorded);
+        pinger.record(target.ip, |liveness| liveness.ttl = Some(Some(64)));
+        assert_eq!(pinger.ttl(target).await, Some(64));
+        assert!(pinger.liveness("127.0.0.2".parse().unwrap()).is_none());
+    }
+}
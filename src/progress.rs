@@ -1,6 +1,6 @@
-use colored::*;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use std::collections::HashSet;
+use std::ffi::OsString;
 use std::net::IpAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
@@ -11,55 +11,45 @@ pub struct ScanProgress {
     service_detect_bar: ProgressBar,
     os_detect_bar: ProgressBar,
     ip_scan_bar: ProgressBar,
-    total_ports: u64,
+    total_ports: AtomicU64,
     scanned_ports: AtomicU64,
+    /// 实际发出的探测数，重试和复查会多于端口数
+    probes_sent: AtomicU64,
     total_services: AtomicU64,
     detected_services: AtomicU64,
     os_detected: AtomicU64,
     alive_ips: Mutex<HashSet<IpAddr>>,
     total_ips: u64,
     scanned_ips: AtomicU64,
+    /// 全屏界面占用终端时暂存 println 的输出，界面退出后再写出
+    held_lines: Mutex<Option<Vec<String>>>,
 }
 
 impl ScanProgress {
+    /// 在标准错误绘制进度条；`TERM=dumb` 的终端不绘制
     pub fn new(total_ports: u64, total_ips: u64) -> Self {
-        let multi_progress = MultiProgress::new();
+        Self::with_draw_target(total_ports, total_ips, draw_target(std::env::var_os("TERM")))
+    }
+
+    /// 不绘制任何进度条，供库调用方使用
+    pub fn hidden(total_ports: u64, total_ips: u64) -> Self {
+        Self::with_draw_target(total_ports, total_ips, ProgressDrawTarget::hidden())
+    }
+
+    fn with_draw_target(total_ports: u64, total_ips: u64, target: ProgressDrawTarget) -> Self {
+        let multi_progress = MultiProgress::with_draw_target(target);
 
         let port_scan_bar = multi_progress.add(ProgressBar::new(total_ports));
-        port_scan_bar.set_style(
-            ProgressStyle::default_bar()
-                .template(
-                    "{spinner:.green} 端口扫描 [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}",
-                )
-                .unwrap()
-                .progress_chars("█▉▊▋▌▍▎▏  "),
-        );
+        port_scan_bar.set_style(bar_style("{spinner:.green} 端口扫描 [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}"));
 
         let service_detect_bar = multi_progress.add(ProgressBar::new(0));
-        service_detect_bar.set_style(
-            ProgressStyle::default_bar()
-                .template(
-                    "{spinner:.yellow} 服务识别 [{bar:40.yellow/blue}] {pos}/{len} ({eta}) {msg}",
-                )
-                .unwrap()
-                .progress_chars("█▉▊▋▌▍▎▏  "),
-        );
+        service_detect_bar.set_style(bar_style("{spinner:.yellow} 服务识别 [{bar:40.yellow/blue}] {pos}/{len} ({eta}) {msg}"));
 
         let os_detect_bar = multi_progress.add(ProgressBar::new(1));
-        os_detect_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.magenta} 操作系统识别 [{bar:40.magenta/blue}] {pos}/{len} ({eta}) {msg}")
-                .unwrap()
-                .progress_chars("█▉▊▋▌▍▎▏  "),
-        );
+        os_detect_bar.set_style(bar_style("{spinner:.magenta} 操作系统识别 [{bar:40.magenta/blue}] {pos}/{len} ({eta}) {msg}"));
 
         let ip_scan_bar = multi_progress.add(ProgressBar::new(total_ips));
-        ip_scan_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} IP扫描 [{bar:40.green/blue}] {pos}/{len} ({eta}) {msg}")
-                .unwrap()
-                .progress_chars("█▉▊▋▌▍▎▏  "),
-        );
+        ip_scan_bar.set_style(bar_style("{spinner:.green} IP扫描 [{bar:40.green/blue}] {pos}/{len} ({eta}) {msg}"));
 
         Self {
             multi_progress,
@@ -67,25 +57,64 @@ impl ScanProgress {
             service_detect_bar,
             os_detect_bar,
             ip_scan_bar,
-            total_ports,
+            total_ports: AtomicU64::new(total_ports),
             scanned_ports: AtomicU64::new(0),
+            probes_sent: AtomicU64::new(0),
             total_services: AtomicU64::new(0),
             detected_services: AtomicU64::new(0),
             os_detected: AtomicU64::new(0),
             alive_ips: Mutex::new(HashSet::new()),
             total_ips,
             scanned_ips: AtomicU64::new(0),
+            held_lines: Mutex::new(None),
         }
     }
 
+    /// 一个端口已有结论（开放、关闭或被过滤），无论探测了几次都只计一次
     pub fn increment_port_scan(&self) {
         let scanned = self.scanned_ports.fetch_add(1, Ordering::Relaxed) + 1;
         self.port_scan_bar.inc(1);
-        if scanned == self.total_ports {
+        let probes = self.probes_sent();
+        if probes > scanned {
+            self.port_scan_bar.set_message(format!("共 {} 次探测", probes));
+        }
+        if scanned == self.total_ports.load(Ordering::Relaxed) {
             self.port_scan_bar.finish_with_message("完成");
         }
     }
 
+    /// 提前结束扫描时，把未探测的端口计为已完成
+    pub fn skip_ports(&self, skipped: u64) {
+        if skipped == 0 {
+            return;
+        }
+        let scanned = self.scanned_ports.fetch_add(skipped, Ordering::Relaxed) + skipped;
+        self.port_scan_bar.inc(skipped);
+        if scanned == self.total_ports.load(Ordering::Relaxed) {
+            self.port_scan_bar.finish_with_message("完成");
+        }
+    }
+
+    /// 记录发出的探测（含重试和 RST 限速复查），不影响端口进度和剩余时间的估算
+    pub fn add_probes(&self, probes: u64) {
+        self.probes_sent.fetch_add(probes, Ordering::Relaxed);
+    }
+
+    /// 已有结论的端口数
+    pub fn scanned_ports(&self) -> u64 {
+        self.scanned_ports.load(Ordering::Relaxed)
+    }
+
+    /// 实际发出的探测总数
+    pub fn probes_sent(&self) -> u64 {
+        self.probes_sent.load(Ordering::Relaxed)
+    }
+
+    /// 需要探测的端口总数，多种协议或多轮扫描时分别计数
+    pub fn total_ports(&self) -> u64 {
+        self.total_ports.load(Ordering::Relaxed)
+    }
+
     pub fn add_alive_ip(&self, ip: IpAddr) {
         let mut alive_ips = self.alive_ips.lock().unwrap();
         if alive_ips.insert(ip) {
@@ -122,11 +151,73 @@ impl ScanProgress {
         self.os_detect_bar.finish_with_message("完成");
     }
 
+    /// 不进行操作系统识别时移除对应进度条
+    pub fn disable_os_detect(&self) {
+        self.multi_progress.remove(&self.os_detect_bar);
+    }
+
+    /// 不进行服务识别时移除对应进度条
+    pub fn disable_service_detect(&self) {
+        self.multi_progress.remove(&self.service_detect_bar);
+    }
+
+    /// 在进度条上方输出一行，不打乱进度条的绘制；不绘制进度条时直接写到标准错误，
+    /// 调用 [`hold_lines`](Self::hold_lines) 后先暂存
+    pub fn println(&self, line: &str) {
+        if let Some(held) = self.held_lines.lock().unwrap().as_mut() {
+            held.push(line.to_string());
+            return;
+        }
+        if self.multi_progress.is_hidden() {
+            eprintln!("{}", line);
+        } else {
+            let _ = self.multi_progress.println(line);
+        }
+    }
+
+    /// 之后 println 的输出先暂存，直到 [`release_lines`](Self::release_lines)
+    pub fn hold_lines(&self) {
+        self.held_lines.lock().unwrap().get_or_insert_with(Vec::new);
+    }
+
+    /// 暂存的最后 `max` 行
+    pub fn held_lines(&self, max: usize) -> Vec<String> {
+        match self.held_lines.lock().unwrap().as_ref() {
+            Some(held) => held[held.len().saturating_sub(max)..].to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    /// 停止暂存，把暂存的输出按顺序写出
+    pub fn release_lines(&self) {
+        let held = self.held_lines.lock().unwrap().take();
+        for line in held.into_iter().flatten() {
+            self.println(&line);
+        }
+    }
+
     pub fn finish(&self) {
         let _ = self.multi_progress.clear();
     }
 }
 
+/// `TERM=dumb` 的终端不支持移动光标，多行进度条会刷成大量重复的行，因此不绘制
+fn draw_target(term: Option<OsString>) -> ProgressDrawTarget {
+    if term.is_some_and(|term| term == "dumb") {
+        ProgressDrawTarget::hidden()
+    } else {
+        ProgressDrawTarget::stderr()
+    }
+}
+
+/// 进度条样式；模板无法解析时退回默认样式，进度条显示不了也不影响扫描
+fn bar_style(template: &str) -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("█▉▊▋▌▍▎▏  ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,7 +225,84 @@ mod tests {
     #[test]
     fn test_progress() {
         let progress = ScanProgress::new(100, 100);
-        assert_eq!(progress.total_ports, 100);
+        assert_eq!(progress.total_ports.load(Ordering::Relaxed), 100);
         assert_eq!(progress.scanned_ports.load(Ordering::Relaxed), 0);
     }
+
+    #[test]
+    fn test_dumb_terminal() {
+        let progress = ScanProgress::with_draw_target(10, 1, draw_target(Some("dumb".into())));
+        assert!(progress.multi_progress.is_hidden());
+        for _ in 0..10 {
+            progress.increment_port_scan();
+        }
+        progress.println("不绘制进度条时仍能输出");
+        assert_eq!(progress.scanned_ports(), 10);
+        progress.finish();
+    }
+
+    /// 记录进度条输出的终端
+    #[derive(Debug, Default, Clone)]
+    struct Screen(std::sync::Arc<Mutex<String>>);
+
+    impl indicatif::TermLike for Screen {
+        fn width(&self) -> u16 {
+            80
+        }
+        fn move_cursor_up(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn move_cursor_down(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn move_cursor_right(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn move_cursor_left(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn write_line(&self, s: &str) -> std::io::Result<()> {
+            self.write_str(s)
+        }
+        fn write_str(&self, s: &str) -> std::io::Result<()> {
+            self.0.lock().unwrap().push_str(s);
+            Ok(())
+        }
+        fn clear_line(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn flush(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 用给定样式绘制一个走到一半的进度条，返回终端上的内容
+    fn render(style: ProgressStyle) -> String {
+        let screen = Screen::default();
+        let bar = ProgressBar::with_draw_target(
+            Some(10),
+            ProgressDrawTarget::term_like(Box::new(screen.clone())),
+        );
+        bar.set_style(style);
+        bar.set_position(5);
+        // 只保留 abandon 强制绘制的那一帧
+        screen.0.lock().unwrap().clear();
+        bar.abandon();
+        let output = screen.0.lock().unwrap().clone();
+        output
+    }
+
+    #[test]
+    fn test_bar_style_fallback() {
+        let default = render(ProgressStyle::default_bar().progress_chars("█▉▊▋▌▍▎▏  "));
+        assert!(default.contains("5/10"), "{}", default);
+
+        // 无法解析的模板退回默认样式而不是 panic
+        for template in ["{bar:40x.cyan/blue}", "{spinner} {pos:4x}"] {
+            assert!(ProgressStyle::default_bar().template(template).is_err());
+            assert_eq!(render(bar_style(template)), default);
+        }
+        // 能解析的模板照常生效
+        assert_eq!(render(bar_style("{pos}")).trim_end(), "5");
+    }
 }
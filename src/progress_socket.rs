@@ -0,0 +1,305 @@
+use crate::events::{ScanEvent, ScanObserver};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 定期发送 `progress` 事件的间隔
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 每个前端未写出数据的上限，超过后跳过新消息，直到前端读走积压的数据
+const MAX_PENDING: usize = 1 << 20;
+
+/// 结束时等待前端读走积压数据的最长时间
+const FINAL_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 扫描事件之外、只发给前端的消息
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressMessage {
+    /// 端口探测进度，扫描期间定期发送
+    /// `probes_sent` 为实际发出的探测数，有重试时多于 `scanned_ports`
+    Progress { scanned_ports: u64, total_ports: u64, probes_sent: u64 },
+    /// 所有主机扫描完成，之后连接关闭
+    ScanFinished { hosts: usize, open_ports: usize },
+}
+
+/// 每行消息：`time` 为 Unix 毫秒时间戳，其余字段来自事件本身
+#[derive(Serialize)]
+struct Line<'a, T: Serialize> {
+    time: u64,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+/// 一个前端连接及其尚未写出的数据
+///
+/// 连接是非阻塞的，一行可能只写出一部分；剩下的部分留在 `pending` 中下次继续写，
+/// 保证前端收到的总是完整的行。
+struct Client {
+    writer: Box<dyn Write + Send>,
+    pending: Vec<u8>,
+}
+
+impl Client {
+    fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            pending: Vec::new(),
+        }
+    }
+
+    /// 排队一行并尽量写出；积压过多时跳过这一行。返回 false 表示连接已失效
+    fn send(&mut self, line: &[u8]) -> bool {
+        if self.pending.len() < MAX_PENDING {
+            self.pending.extend_from_slice(line);
+        }
+        self.flush_pending()
+    }
+
+    /// 写出积压的数据，写满缓冲区时留到下次。返回 false 表示连接已失效
+    fn flush_pending(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.writer.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(written) => {
+                    self.pending.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+type Clients = Arc<Mutex<Vec<Client>>>;
+
+/// 通过 Unix 域套接字或命名管道向图形界面等前端推送扫描进度和结果
+///
+/// 每行一个 JSON 对象，`event` 字段为消息类型：`progress`、`scan_finished`
+/// （见 [`ProgressMessage`]）以及 `host_started`、`port_open` 等扫描事件（见
+/// [`ScanEvent`]）。`PATH` 是已存在的命名管道时写入管道，否则在该路径监听套接字，
+/// 可以有多个前端同时连接，连接前产生的事件不补发。前端断开时只丢弃这个连接，
+/// 读取过慢时消息先在内存中排队，积压过多则跳过新消息，都不影响扫描。
+pub struct ProgressSocket {
+    clients: Clients,
+    /// 由本进程创建的套接字文件，结束时删除
+    socket_path: Option<PathBuf>,
+}
+
+impl ProgressSocket {
+    #[cfg(unix)]
+    pub fn open(path: &Path) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let clients = Clients::default();
+        match std::fs::metadata(path) {
+            Ok(meta) if meta.file_type().is_fifo() => {
+                spawn_pipe_writer(path.to_path_buf(), clients.clone());
+                return Ok(Self {
+                    clients,
+                    socket_path: None,
+                });
+            }
+            Ok(meta) if meta.file_type().is_socket() => {
+                // 上次运行残留的套接字文件；仍能连上说明有其他进程在用
+                if UnixStream::connect(path).is_ok() {
+                    return Err(anyhow!("{} 已被其他进程使用", path.display()));
+                }
+                std::fs::remove_file(path)?;
+            }
+            Ok(_) => return Err(anyhow!("{} 已存在，且不是套接字或命名管道", path.display())),
+            Err(_) => {}
+        }
+
+        let listener = UnixListener::bind(path).map_err(|e| anyhow!("无法监听 {}: {}", path.display(), e))?;
+        let accepted = clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stream.set_nonblocking(true).is_ok() {
+                    accepted.lock().unwrap().push(Client::new(Box::new(stream)));
+                }
+            }
+        });
+        Ok(Self {
+            clients,
+            socket_path: Some(path.to_path_buf()),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(_path: &Path) -> Result<Self> {
+        Err(anyhow!("--progress-socket 只支持 Unix 系统"))
+    }
+
+    /// 当前连接的前端数
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn progress(&self, scanned_ports: u64, total_ports: u64, probes_sent: u64) {
+        self.send(&ProgressMessage::Progress {
+            scanned_ports,
+            total_ports,
+            probes_sent,
+        });
+    }
+
+    pub fn finish(&self, hosts: usize, open_ports: usize) {
+        self.send(&ProgressMessage::ScanFinished { hosts, open_ports });
+    }
+
+    /// 发给所有前端，写入出错的连接直接丢弃
+    fn send<T: Serialize>(&self, body: &T) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+            .unwrap_or_default();
+        let Ok(mut line) = serde_json::to_string(&Line { time, body }) else {
+            return;
+        };
+        line.push('\n');
+        self.clients
+            .lock()
+            .unwrap()
+            .retain_mut(|client| client.send(line.as_bytes()));
+    }
+}
+
+impl ScanObserver for ProgressSocket {
+    fn on_event(&self, event: &ScanEvent) {
+        self.send(event);
+    }
+}
+
+impl Drop for ProgressSocket {
+    fn drop(&mut self) {
+        // 给读取较慢的前端一点时间收完积压的消息，避免最后一行被截断
+        let deadline = Instant::now() + FINAL_FLUSH_TIMEOUT;
+        loop {
+            let mut clients = self.clients.lock().unwrap();
+            clients.retain_mut(|client| client.flush_pending() && !client.pending.is_empty());
+            if clients.is_empty() || Instant::now() >= deadline {
+                break;
+            }
+            drop(clients);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// 命名管道同一时间只有一个读取方：等待前端打开管道，断开后再等下一个
+#[cfg(unix)]
+fn spawn_pipe_writer(path: PathBuf, clients: Clients) {
+    use std::os::unix::io::AsRawFd;
+
+    std::thread::spawn(move || loop {
+        // 没有读取方时 open 会阻塞
+        let Ok(pipe) = std::fs::OpenOptions::new().write(true).open(&path) else {
+            return;
+        };
+        // 打开后改为非阻塞写，前端停止读取时不会卡住扫描
+        unsafe {
+            let flags = libc::fcntl(pipe.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(pipe.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+        clients.lock().unwrap().push(Client::new(Box::new(pipe)));
+        while !clients.lock().unwrap().is_empty() {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    });
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+
+    fn wait_for_clients(socket: &ProgressSocket, count: usize) {
+        for _ in 0..100 {
+            if socket.client_count() == count {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("等待 {} 个连接超时", count);
+    }
+
+    #[test]
+    fn test_progress_socket_survives_disconnect() {
+        let path = std::env::temp_dir().join(format!("rustscan-progress-{}.sock", std::process::id()));
+        let socket = ProgressSocket::open(&path).unwrap();
+
+        let client = UnixStream::connect(&path).unwrap();
+        wait_for_clients(&socket, 1);
+        socket.progress(10, 100, 12);
+        socket.on_event(&ScanEvent::PortOpen {
+            target: "10.0.0.1".to_string(),
+            port: 22,
+            protocol: "TCP".to_string(),
+        });
+
+        let mut lines = BufReader::new(client).lines();
+        let progress: serde_json::Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(progress["event"], "progress");
+        assert_eq!((progress["scanned_ports"].as_u64(), progress["probes_sent"].as_u64()), (Some(10), Some(12)));
+        assert!(progress["time"].as_u64().unwrap() > 0);
+        let open: serde_json::Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!((open["event"].as_str(), open["port"].as_u64()), (Some("port_open"), Some(22)));
+
+        // 前端中途断开：后续事件照常发送，断开的连接被丢弃
+        drop(lines);
+        for _ in 0..3 {
+            socket.progress(20, 100, 20);
+        }
+        assert_eq!(socket.client_count(), 0);
+        socket.finish(1, 1);
+        assert!(ProgressSocket::open(&path).is_err());
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_progress_socket_slow_client() {
+        let path = std::env::temp_dir().join(format!("rustscan-progress-slow-{}.sock", std::process::id()));
+        let socket = ProgressSocket::open(&path).unwrap();
+        let client = UnixStream::connect(&path).unwrap();
+        wait_for_clients(&socket, 1);
+
+        // 前端暂不读取，写出的数据远超套接字缓冲区
+        let target = "x".repeat(1000);
+        for port in 0..500 {
+            socket.on_event(&ScanEvent::PortOpen {
+                target: target.clone(),
+                port,
+                protocol: "TCP".to_string(),
+            });
+        }
+        assert_eq!(socket.client_count(), 1);
+        assert!(!socket.clients.lock().unwrap()[0].pending.is_empty());
+
+        let reader = std::thread::spawn(move || BufReader::new(client).lines().map(Result::unwrap).collect::<Vec<_>>());
+        socket.finish(1, 500);
+        drop(socket);
+
+        // 每一行都是完整的 JSON，没有丢失也没有截断
+        let lines = reader.join().unwrap();
+        assert_eq!(lines.len(), 501);
+        for (port, line) in lines[..500].iter().enumerate() {
+            let open: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(open["port"].as_u64(), Some(port as u64));
+        }
+        let finished: serde_json::Value = serde_json::from_str(&lines[500]).unwrap();
+        assert_eq!(finished["event"], "scan_finished");
+    }
+}
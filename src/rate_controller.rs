@@ -2,6 +2,20 @@ use std::time::{Duration, Instant};
 use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::time;
 
+/// 比较无响应比例的窗口大小（有结论的探测数）
+const SILENCE_WINDOW: u64 = 20;
+/// 无响应比例比上一窗口至少升高这么多才视为突增
+const SILENCE_SPIKE: f64 = 0.5;
+
+/// 最近一个窗口内探测的应答情况
+#[derive(Default)]
+struct SilenceWindow {
+    answered: u64,
+    silent: u64,
+    /// 上一个完整窗口的无响应比例
+    previous: Option<f64>,
+}
+
 pub struct RateController {
     start_time: Instant,
     total_requests: AtomicU64,
@@ -13,24 +27,39 @@ pub struct RateController {
     last_second_requests: AtomicU64,
     last_second_time: AtomicU64,
     last_request_time: AtomicU64,
+    /// 相邻两次探测之间的最小间隔
+    scan_delay: Duration,
+    silence: SilenceWindow,
 }
 
 impl RateController {
     pub fn new(max_rate: u64, min_rate: u64) -> Self {
+        let adjustment_interval = Duration::from_millis(100);
         Self {
             start_time: Instant::now(),
             total_requests: AtomicU64::new(0),
             current_rate: AtomicU64::new(max_rate),
             max_rate,
             min_rate,
-            last_adjustment: Instant::now(),
-            adjustment_interval: Duration::from_millis(100),
+            // 允许首次调整立即生效
+            last_adjustment: Instant::now()
+                .checked_sub(adjustment_interval)
+                .unwrap_or_else(Instant::now),
+            adjustment_interval,
             last_second_requests: AtomicU64::new(0),
             last_second_time: AtomicU64::new(0),
             last_request_time: AtomicU64::new(0),
+            scan_delay: Duration::ZERO,
+            silence: SilenceWindow::default(),
         }
     }
 
+    /// 相邻两次探测至少间隔 `scan_delay`，不受速率自适应影响
+    pub fn with_scan_delay(mut self, scan_delay: Duration) -> Self {
+        self.scan_delay = scan_delay;
+        self
+    }
+
     pub async fn wait(&self) {
         let now = Instant::now();
         let elapsed = now.duration_since(self.start_time).as_secs();
@@ -43,22 +72,24 @@ impl RateController {
 
         // 增加请求计数
         self.last_second_requests.fetch_add(1, Ordering::Relaxed);
-        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        let previous_requests = self.total_requests.fetch_add(1, Ordering::Relaxed);
 
         // 计算请求间隔
         let current_rate = self.current_rate.load(Ordering::Relaxed).max(1);
-        let interval = Duration::from_secs_f64(1.0 / current_rate as f64);
+        let interval = Duration::from_secs_f64(1.0 / current_rate as f64).max(self.scan_delay);
 
-        // 控制速率，避免自旋
+        // 控制速率，避免自旋；记录实际发出的时刻，等待过的请求不会压缩下一次的间隔
         let last_request = self.last_request_time.load(Ordering::Relaxed);
         let now_ms = now.duration_since(self.start_time).as_millis() as u64;
-        if now_ms > last_request {
+        let mut sent_ms = now_ms;
+        if previous_requests > 0 {
             let next_time = last_request + interval.as_millis() as u64;
             if next_time > now_ms {
                 time::sleep(Duration::from_millis(next_time - now_ms)).await;
+                sent_ms = next_time;
             }
         }
-        self.last_request_time.store(now_ms, Ordering::Relaxed);
+        self.last_request_time.store(sent_ms, Ordering::Relaxed);
     }
 
     pub fn increment_requests(&self) {
@@ -84,6 +115,49 @@ impl RateController {
         self.last_adjustment = now;
     }
 
+    /// 目标明确表示在过滤（如 ICMP 管理禁止）时大幅降速
+    pub fn backoff(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_adjustment) < self.adjustment_interval {
+            return;
+        }
+        self.halve_rate(now);
+    }
+
+    fn halve_rate(&mut self, now: Instant) {
+        let current_rate = self.current_rate.load(Ordering::Relaxed);
+        self.current_rate
+            .store((current_rate / 2).clamp(self.min_rate, self.max_rate), Ordering::Relaxed);
+        self.last_adjustment = now;
+    }
+
+    /// 记录一个端口的探测结论：`answered` 为收到了 RST、连接成功或 ICMP 拒绝，否则为无响应
+    ///
+    /// 每 [`SILENCE_WINDOW`] 个端口比较一次无响应比例：比上一窗口升高至少 [`SILENCE_SPIKE`]，
+    /// 说明目标随着扫描开始丢弃应答，多为 RST 限速。此时立即减半速率（不受调整间隔限制）并返回 true；
+    /// 有状态防火墙从一开始就丢弃的端口比例稳定，不会触发。
+    pub fn record_answer(&mut self, answered: bool) -> bool {
+        let window = &mut self.silence;
+        if answered {
+            window.answered += 1;
+        } else {
+            window.silent += 1;
+        }
+        if window.answered + window.silent < SILENCE_WINDOW {
+            return false;
+        }
+        let ratio = window.silent as f64 / SILENCE_WINDOW as f64;
+        let spiked = window.previous.is_some_and(|previous| ratio - previous >= SILENCE_SPIKE);
+        *window = SilenceWindow {
+            previous: Some(ratio),
+            ..SilenceWindow::default()
+        };
+        if spiked {
+            self.halve_rate(Instant::now());
+        }
+        spiked
+    }
+
     pub fn get_current_rate(&self) -> u64 {
         self.current_rate.load(Ordering::Relaxed)
     }
@@ -97,6 +171,17 @@ impl RateController {
     }
 }
 
+/// 每跳增加的超时比例
+const TIMEOUT_PER_HOP: f64 = 0.1;
+/// 按跳数放大后的超时上限（基础超时的倍数）
+const MAX_TIMEOUT_SCALE: f64 = 3.0;
+
+/// 按网络距离放宽连接超时：每跳增加 10%，最多放大到 3 倍
+pub fn scale_timeout(base: Duration, hops: u8) -> Duration {
+    let scale = (1.0 + f64::from(hops) * TIMEOUT_PER_HOP).min(MAX_TIMEOUT_SCALE);
+    base.mul_f64(scale)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,12 +191,56 @@ mod tests {
         let mut controller = RateController::new(1000, 100);
         assert_eq!(controller.get_current_rate(), 1000);
 
-        // 测试速率调整
-        controller.adjust_rate(true, Duration::from_millis(50));
-        assert!(controller.get_current_rate() > 1000);
+        // 测试速率调整（初始即为最大速率，失败时应下调）
+        controller.adjust_rate(false, Duration::from_millis(50));
+        assert!(controller.get_current_rate() < 1000);
+
+        // 退避受调整间隔限制，间隔内不会连续减半
+        let rate = controller.get_current_rate();
+        controller.backoff();
+        assert_eq!(controller.get_current_rate(), rate);
 
         // 测试等待
         controller.increment_requests();
         controller.wait().await;
     }
+
+    #[tokio::test]
+    async fn test_scan_delay() {
+        let controller = RateController::new(1000, 100).with_scan_delay(Duration::from_millis(50));
+        let started = Instant::now();
+        for _ in 0..3 {
+            controller.wait().await;
+        }
+        // 第一次探测不等待，之后每次间隔 50ms
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_silence_spike() {
+        let mut controller = RateController::new(1000, 100);
+        // 一开始就有一半端口无响应（有状态防火墙）：比例稳定，不触发
+        for i in 0..60 {
+            assert!(!controller.record_answer(i % 2 == 0));
+        }
+        assert_eq!(controller.get_current_rate(), 1000);
+
+        // 应答突然消失：窗口结束时触发并立即减半，不受调整间隔限制
+        controller.adjust_rate(true, Duration::ZERO);
+        let spikes = (0..SILENCE_WINDOW).filter(|_| controller.record_answer(false)).count();
+        assert_eq!(spikes, 1);
+        assert_eq!(controller.get_current_rate(), 500);
+        // 持续无响应不再重复触发
+        assert!(!(0..SILENCE_WINDOW).any(|_| controller.record_answer(false)));
+    }
+
+    #[test]
+    fn test_scale_timeout() {
+        let base = Duration::from_millis(200);
+        assert_eq!(scale_timeout(base, 0), base);
+        assert_eq!(scale_timeout(base, 10), Duration::from_millis(400));
+        assert_eq!(scale_timeout(base, 60), Duration::from_millis(600));
+    }
 }
\ No newline at end of file
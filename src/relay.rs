@@ -0,0 +1,195 @@
+use crate::anomaly::Anomaly;
+use crate::connector::Connector;
+use crate::http;
+use crate::service_detector::{guess_service, DetectedService, ServiceMethod};
+use crate::service_fingerprints::{has_final_reply, read_until};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Instant};
+
+/// HTTP 代理 CONNECT 的目标，IANA 保留给文档示例的域名
+pub const PROXY_CHECK_TARGET: &str = "example.com:80";
+
+/// SMTP 中继测试的发件人和收件人，都在 IANA 保留的示例域中；只测试到 `RCPT TO`，不发送邮件内容
+pub const RELAY_CHECK_SENDER: &str = "rustscan@example.org";
+pub const RELAY_CHECK_RECIPIENT: &str = "relay-test@example.com";
+
+/// 读取单个应答的长度上限
+const MAX_REPLY_LEN: usize = 8 * 1024;
+
+/// 对开放端口进行的配置检查（`--check-proxies`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayCheck {
+    /// 发送 `CONNECT`，代理同意连接外部主机即为开放代理
+    HttpProxy,
+    /// 以外部域的发件人向外部域的收件人投递，服务器接受收件人即为开放中继
+    Smtp,
+}
+
+impl RelayCheck {
+    /// 按识别出的服务选择检查方式：明文 HTTP 应答检查代理，SMTP 检查中继；
+    /// 只完成 TLS 握手的服务无法用明文交互，不检查
+    pub fn for_service(service: &DetectedService) -> Option<Self> {
+        if service.method == ServiceMethod::Tls {
+            return None;
+        }
+        let family = service.fingerprint.as_ref().and_then(|fingerprint| guess_service(fingerprint.port));
+        if family == Some("SMTP") || service.name == "SMTP" {
+            return Some(RelayCheck::Smtp);
+        }
+        let http_response = service.protocol_version.as_deref().is_some_and(|version| version.starts_with("HTTP/"));
+        (http_response || service.name == "HTTP").then_some(RelayCheck::HttpProxy)
+    }
+
+    /// 检查成立时的异常发现
+    pub fn anomaly(&self, port: u16) -> Anomaly {
+        match self {
+            RelayCheck::HttpProxy => Anomaly::OpenProxy { port },
+            RelayCheck::Smtp => Anomaly::OpenRelay { port },
+        }
+    }
+
+    /// 建立新连接执行检查，对方明确同意转发时返回 `true`；连接失败、超时或拒绝都视为不成立
+    ///
+    /// 代理成功时会真的连接 [`PROXY_CHECK_TARGET`]，SMTP 服务器可能为验证收件人查询外部域，
+    /// 都会从目标网络产生外发流量。
+    pub async fn run(&self, connector: &dyn Connector, addr: SocketAddr, connect_timeout: Duration, read_timeout: Duration) -> bool {
+        let Ok(Ok(mut stream)) = timeout(connect_timeout, connector.connect(addr)).await else {
+            return false;
+        };
+        let deadline = Instant::now() + read_timeout;
+        match self {
+            RelayCheck::HttpProxy => http_connect(&mut stream, deadline).await,
+            RelayCheck::Smtp => smtp_relay(&mut stream, deadline).await,
+        }
+    }
+}
+
+/// 代理对 `CONNECT` 返回 2xx 表示已与目标建立隧道
+async fn http_connect<S>(stream: &mut S, deadline: Instant) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n", target = PROXY_CHECK_TARGET);
+    if stream.write_all(request.as_bytes()).await.is_err() {
+        return false;
+    }
+    let mut response = Vec::new();
+    read_until(stream, &mut response, 0, MAX_REPLY_LEN, deadline, http::has_header_end).await;
+    let status_line = response.split(|&b| b == b'\n').next().unwrap_or_default();
+    let mut parts = status_line.split(|&b| b == b' ');
+    parts.next().is_some_and(|version| version.starts_with(b"HTTP/")) && parts.next().is_some_and(|code| code.len() == 3 && code[0] == b'2')
+}
+
+/// 依次完成欢迎、`EHLO`、`MAIL FROM` 和 `RCPT TO`，收件人被接受（250/251）即为开放中继；
+/// 结束前发送 `QUIT`，不进入 `DATA`
+async fn smtp_relay<S>(stream: &mut S, deadline: Instant) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let commands = [
+        None,
+        Some("EHLO rustscan".to_string()),
+        Some(format!("MAIL FROM:<{}>", RELAY_CHECK_SENDER)),
+        Some(format!("RCPT TO:<{}>", RELAY_CHECK_RECIPIENT)),
+    ];
+    let mut accepted = false;
+    for command in commands {
+        if let Some(command) = command {
+            if stream.write_all(format!("{}\r\n", command).as_bytes()).await.is_err() {
+                return false;
+            }
+        }
+        match smtp_reply(stream, deadline).await {
+            Some(code) if (200..300).contains(&code) => accepted = true,
+            _ => {
+                accepted = false;
+                break;
+            }
+        }
+    }
+    let _ = stream.write_all(b"QUIT\r\n").await;
+    accepted
+}
+
+/// 读取一个完整的（可能是多行的）应答，返回状态码
+async fn smtp_reply<S>(stream: &mut S, deadline: Instant) -> Option<u16>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut reply = Vec::new();
+    read_until(stream, &mut reply, 0, MAX_REPLY_LEN, deadline, has_final_reply).await;
+    if !has_final_reply(&reply) {
+        return None;
+    }
+    std::str::from_utf8(&reply[..3]).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::TcpConnector;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// 按行应答的假服务：先发送 `greeting`，之后对每行请求调用 `reply`
+    async fn line_server(greeting: &'static str, reply: fn(&str) -> Option<&'static str>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let _ = write.write_all(greeting.as_bytes()).await;
+                    let mut lines = BufReader::new(read).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if let Some(answer) = reply(&line) {
+                            let _ = write.write_all(answer.as_bytes()).await;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_relay_checks() {
+        let open_proxy = line_server("", |line| line.is_empty().then_some("HTTP/1.1 200 Connection established\r\n\r\n")).await;
+        let closed_proxy = line_server("", |line| line.is_empty().then_some("HTTP/1.1 403 Forbidden\r\n\r\n")).await;
+        let open_relay = line_server("220 mail ESMTP\r\n", |line| match line {
+            line if line.starts_with("EHLO") => Some("250-mail\r\n250 PIPELINING\r\n"),
+            line if line.starts_with("MAIL") || line.starts_with("RCPT") => Some("250 OK\r\n"),
+            _ => None,
+        })
+        .await;
+        let closed_relay = line_server("220 mail ESMTP\r\n", |line| match line {
+            line if line.starts_with("EHLO") || line.starts_with("MAIL") => Some("250 OK\r\n"),
+            line if line.starts_with("RCPT") => Some("554 5.7.1 Relay access denied\r\n"),
+            _ => None,
+        })
+        .await;
+
+        let timeout = Duration::from_secs(1);
+        let check = |check: RelayCheck, addr| async move { check.run(&TcpConnector, addr, timeout, timeout).await };
+        assert!(check(RelayCheck::HttpProxy, open_proxy).await);
+        assert!(!check(RelayCheck::HttpProxy, closed_proxy).await);
+        assert!(check(RelayCheck::Smtp, open_relay).await);
+        assert!(!check(RelayCheck::Smtp, closed_relay).await);
+        // 不是 SMTP 服务时在欢迎信息处就结束
+        assert!(!check(RelayCheck::Smtp, closed_proxy).await);
+
+        let mut squid = DetectedService::new("Squid");
+        squid.protocol_version = Some("HTTP/1.1".to_string());
+        assert_eq!(RelayCheck::for_service(&squid), Some(RelayCheck::HttpProxy));
+        assert_eq!(RelayCheck::for_service(&DetectedService::new("SMTP")), Some(RelayCheck::Smtp));
+        assert_eq!(RelayCheck::for_service(&DetectedService::new("SSH")), None);
+        let https = DetectedService {
+            method: ServiceMethod::Tls,
+            ..DetectedService::new("HTTPS")
+        };
+        assert_eq!(RelayCheck::for_service(&https), None);
+        assert_eq!(RelayCheck::Smtp.anomaly(25), Anomaly::OpenRelay { port: 25 });
+    }
+}
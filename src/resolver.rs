@@ -0,0 +1,195 @@
+use crate::scanner::retry_delay;
+use crate::target::{parse_subnet_with, ScanTarget};
+use anyhow::{anyhow, Result};
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// 解析失败（超时、网络错误）后的重试次数；域名不存在等明确结果不重试
+const DNS_RETRIES: u32 = 2;
+/// 单次查询的超时
+const DNS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 扫描的地址族（`-4`/`-6`），默认 IPv4 和 IPv6 都扫描
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        match self {
+            AddressFamily::Any => true,
+            AddressFamily::V4 => ip.is_ipv4(),
+            AddressFamily::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// 直接给出的地址或网段不属于指定的地址族时报错
+    pub fn check(&self, target: &ScanTarget) -> Result<()> {
+        match self {
+            AddressFamily::V4 if !self.matches(target.ip) => Err(anyhow!("指定了 -4，但目标 {} 是 IPv6 地址", target)),
+            AddressFamily::V6 if !self.matches(target.ip) => Err(anyhow!("指定了 -6，但目标 {} 是 IPv4 地址", target)),
+            _ => Ok(()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            AddressFamily::Any => "IP",
+            AddressFamily::V4 => "IPv4",
+            AddressFamily::V6 => "IPv6",
+        }
+    }
+}
+
+/// 将主机名目标解析为地址
+pub struct TargetResolver {
+    resolver: TokioAsyncResolver,
+    family: AddressFamily,
+    /// 展开网段时保留网络地址和广播地址
+    include_network_broadcast: bool,
+}
+
+impl TargetResolver {
+    /// 指定 DNS 服务器时只向它查询，否则使用系统配置（/etc/resolv.conf）
+    pub fn new(dns_server: Option<IpAddr>) -> Result<Self> {
+        let mut options = ResolverOpts::default();
+        options.timeout = DNS_TIMEOUT;
+        options.attempts = 1;
+        // 同时查询 A 和 AAAA 记录
+        options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+
+        let config = match dns_server {
+            Some(server) => ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::from_ips_clear(&[server], 53, true)),
+            None => hickory_resolver::system_conf::read_system_conf()
+                .map(|(config, _)| config)
+                .unwrap_or_default(),
+        };
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio(config, options),
+            family: AddressFamily::Any,
+            include_network_broadcast: false,
+        })
+    }
+
+    /// 只返回指定地址族的解析结果
+    pub fn with_family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    /// 展开 IPv4 网段时保留网络地址和广播地址
+    pub fn with_network_broadcast(mut self, include: bool) -> Self {
+        self.include_network_broadcast = include;
+        self
+    }
+
+    /// 解析主机名，超时或网络错误时按退避间隔重试；没有指定地址族的地址时报错
+    pub async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let mut attempt = 0;
+        loop {
+            match self.resolver.lookup_ip(host).await {
+                Ok(lookup) => {
+                    let mut ips: Vec<IpAddr> = lookup.iter().filter(|&ip| self.family.matches(ip)).collect();
+                    ips.dedup();
+                    if ips.is_empty() {
+                        return Err(anyhow!("{} 没有 {} 地址", host, self.family.name()));
+                    }
+                    return Ok(ips);
+                }
+                Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) || attempt >= DNS_RETRIES => {
+                    return Err(anyhow!("无法解析 {}: {}", host, e));
+                }
+                Err(_) => {
+                    tokio::time::sleep(retry_delay(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// 目标是否为主机名：含字母且不是 IPv6 地址或网段
+pub fn is_hostname(target: &str) -> bool {
+    !target.contains([':', '/', '%'])
+        && target.chars().any(|c| c.is_ascii_alphabetic())
+        && target.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// 展开逗号分隔的目标列表（地址、网段或主机名）
+///
+/// 主机名解析失败只作为警告返回，不影响其他目标；地址或网段格式错误、
+/// 或不属于解析器指定的地址族时直接报错。
+pub async fn expand_targets(spec: &str, resolver: &TargetResolver) -> Result<(Vec<ScanTarget>, Vec<anyhow::Error>)> {
+    let mut targets = Vec::new();
+    let mut warnings = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        if !is_hostname(item) {
+            let subnet = parse_subnet_with(item, resolver.include_network_broadcast)?;
+            if let Some(target) = subnet.first() {
+                resolver.family.check(target)?;
+            }
+            targets.extend(subnet);
+            continue;
+        }
+        match resolver.resolve(item).await {
+            Ok(ips) => targets.extend(ips.into_iter().map(ScanTarget::new)),
+            Err(e) => warnings.push(e),
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    targets.retain(|target| seen.insert(*target));
+    Ok((targets, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_expand_targets() {
+        assert!(is_hostname("scanme.example.com"));
+        assert!(is_hostname("localhost"));
+        assert!(!is_hostname("10.0.0.1"));
+        assert!(!is_hostname("fe80::1%eth0"));
+        assert!(!is_hostname("10.0.0.0/30"));
+
+        // 只有地址和网段时不发出 DNS 查询
+        let resolver = TargetResolver::new(None).unwrap();
+        let (targets, warnings) = expand_targets("10.0.0.0/30, 10.0.0.1", &resolver).await.unwrap();
+        let targets: Vec<String> = targets.iter().map(ToString::to_string).collect();
+        assert_eq!(targets, vec!["10.0.0.1", "10.0.0.2"]);
+        assert!(warnings.is_empty());
+
+        assert!(expand_targets("10.0.0.300", &resolver).await.is_err());
+
+        // -4/-6：字面地址不属于指定地址族时报错
+        let v4_only = TargetResolver::new(None).unwrap().with_family(AddressFamily::V4);
+        assert!(expand_targets("10.0.0.1,::1", &v4_only).await.is_err());
+        let v6_only = TargetResolver::new(None).unwrap().with_family(AddressFamily::V6);
+        assert!(expand_targets("10.0.0.0/30", &v6_only).await.is_err());
+        assert!(AddressFamily::V6.check(&ScanTarget::new("fe80::1".parse().unwrap())).is_ok());
+        assert!(AddressFamily::Any.matches("10.0.0.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    #[ignore = "经系统 DNS 解析主机名，离线时会等待超时和重试"]
+    async fn test_expand_hostnames() {
+        // 解析失败的主机名只作为警告
+        let resolver = TargetResolver::new(None).unwrap();
+        let (targets, warnings) = expand_targets("10.0.0.1,name.invalid", &resolver).await.unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(warnings.len(), 1);
+
+        // 主机名只取指定地址族的结果
+        let v4_only = TargetResolver::new(None).unwrap().with_family(AddressFamily::V4);
+        let (targets, _) = expand_targets("10.0.0.1,localhost", &v4_only).await.unwrap();
+        assert!(targets.iter().all(|target| target.ip.is_ipv4()));
+    }
+}
@@ -1,122 +1,629 @@
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::collections::BTreeMap;
+use std::io;
+use std::str::FromStr;
 use std::time::Duration;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, Interest};
+use tokio::net::UdpSocket;
 use tokio::time;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::sync::Arc;
 use tokio::sync::{Semaphore, Mutex};
 use crate::progress::ScanProgress;
 use crate::rate_controller::RateController;
-use std::sync::atomic::{AtomicU64, Ordering};
-use crate::service_detector::ServiceDetector;
-use std::collections::HashMap;
-use tokio::net::TcpSocket;
-use std::time::Instant;
-use tokio::io::AsyncWriteExt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use crate::service_detector::{DetectedService, ServiceDetector, ServiceMethod};
+use crate::events::{EventBus, ScanEvent};
+use crate::source_port::{set_ttl, tcp_socket, SourcePorts};
+use crate::target::{parse_ports, ScanTarget, TOP_TCP_PORTS};
+use crate::udp_probes;
+use crate::connector::{BoxStream, Connector};
 use futures::stream::{FuturesUnordered, StreamExt};
+use rand::Rng;
+use socket2::SockRef;
 
-// 连接池结构
-struct ConnectionPool {
-    connections: HashMap<u16, TcpStream>,
-    last_used: HashMap<u16, Instant>,
-    max_idle_time: Duration,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScanType {
+    Tcp,
+    Udp,
 }
 
-impl ConnectionPool {
-    fn new(max_idle_time: Duration) -> Self {
-        Self {
-            connections: HashMap::new(),
-            last_used: HashMap::new(),
-            max_idle_time,
+impl ScanType {
+    /// 协议名，与输出中的 `protocol` 字段一致
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanType::Tcp => "TCP",
+            ScanType::Udp => "UDP",
         }
     }
 
-    async fn get_connection(&mut self, addr: SocketAddr) -> Result<Option<TcpStream>> {
-        let port = addr.port();
-        
-        // 清理过期连接
-        self.cleanup_expired();
-        
-        // 检查是否有可用的连接
-        if let Some(stream) = self.connections.remove(&port) {
-            self.last_used.remove(&port);
-            return Ok(Some(stream));
+    /// 解析逗号分隔的扫描类型列表（如 `tcp,udp`），去除重复项
+    pub fn parse_list(s: &str) -> Result<Vec<ScanType>> {
+        let mut scan_types = Vec::new();
+        for item in s.split(',') {
+            let scan_type: ScanType = item.parse()?;
+            if !scan_types.contains(&scan_type) {
+                scan_types.push(scan_type);
+            }
         }
-        
-        Ok(None)
+        Ok(scan_types)
     }
+}
 
-    fn cleanup_expired(&mut self) {
-        let now = Instant::now();
-        let expired_ports: Vec<u16> = self.last_used
-            .iter()
-            .filter(|(_, &last_used)| now.duration_since(last_used) > self.max_idle_time)
-            .map(|(&port, _)| port)
-            .collect();
-            
-        for port in expired_ports {
-            self.connections.remove(&port);
-            self.last_used.remove(&port);
+impl FromStr for ScanType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tcp" => Ok(ScanType::Tcp),
+            "udp" => Ok(ScanType::Udp),
+            _ => Err(anyhow!("无效的扫描类型: {}（可选 tcp/udp）", s)),
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum ScanType {
-    Tcp,
-    Udp,
+/// 单个端口的探测结果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortState {
+    Open,
+    /// 收到 RST 或 ICMP 端口不可达
+    Closed,
+    /// 收到 ICMP 主机/网络不可达或管理禁止，说明有防火墙在过滤
+    Filtered,
+    /// 超时或没有明确响应
+    NoResponse,
+}
+
+impl PortState {
+    /// 按连接错误归类。内核会把收到的 ICMP 不可达转换为 errno：
+    /// 端口不可达为 ECONNREFUSED，管理禁止（type 3 code 9/10/13）与主机不可达为 EHOSTUNREACH，
+    /// 部分系统对管理禁止返回 EACCES。
+    pub fn from_error(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => PortState::Closed,
+            io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::PermissionDenied => PortState::Filtered,
+            _ => PortState::NoResponse,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Scanner {
-    target: IpAddr,
-    start_port: u16,
-    end_port: u16,
+    target: ScanTarget,
+    ports: Arc<[u16]>,
+    udp_ports: Arc<[u16]>,
     timeout: Duration,
     threads: usize,
     progress: Arc<ScanProgress>,
     rate_controller: Arc<Mutex<RateController>>,
     service_detector: Arc<ServiceDetector>,
-    connection_pool: Arc<Mutex<ConnectionPool>>,
-    batch_size: usize,
+    service_scan: bool,
+    source_ports: Option<Arc<SourcePorts>>,
+    connector: Option<Arc<dyn Connector>>,
+    events: EventBus,
+    retries: u32,
+    scan_types: Vec<ScanType>,
+    max_open: Option<usize>,
+    udp_lenient: bool,
+    udp_mtu: Option<u16>,
+    udp_fragment: bool,
+    ttl: Option<u8>,
+    abort_on_open: bool,
+    udp_batch_size: usize,
+    detect_batch_size: usize,
+    sequential: bool,
+    rst_rate_limit: Arc<std::sync::Mutex<Option<RstRateLimit>>>,
+    retry_budget: Option<Arc<RetryBudget>>,
+    max_detect: Option<usize>,
+    /// 最近一次 TCP 扫描中超出 `max_detect`、只按端口号标注的端口数
+    detect_skipped: Arc<AtomicUsize>,
+    verify_open: bool,
+    /// 最近一次 TCP 扫描中复查未通过、被剔除的端口数
+    verify_dropped: Arc<AtomicUsize>,
 }
 
-impl Scanner {
-    pub fn new(
-        target: IpAddr,
-        start_port: u16,
-        end_port: u16,
-        timeout: Duration,
-        threads: usize,
-        progress: Arc<ScanProgress>,
-        rate_controller: Arc<Mutex<RateController>>,
-        _scan_type: ScanType,
-        service_detector: Arc<ServiceDetector>,
-    ) -> Self {
+/// 服务识别的优先顺序：常见端口表中的端口、其余知名端口（1-1023）、高端口，同类按端口号
+fn detect_priority(port: u16, top_ports: &[u16]) -> (u8, u16) {
+    match port {
+        _ if top_ports.binary_search(&port).is_ok() => (0, port),
+        1..=1023 => (1, port),
+        _ => (2, port),
+    }
+}
+
+/// 同一主机所有端口共用的重试次数上限，避免一个反复超时的主机产生大量重试
+#[derive(Debug)]
+struct RetryBudget {
+    remaining: AtomicU32,
+    exhausted: AtomicBool,
+}
+
+impl RetryBudget {
+    fn new(retries: u32) -> Self {
+        Self {
+            remaining: AtomicU32::new(retries),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// 占用一次重试，预算已用完时返回 false 并记录
+    fn take(&self) -> bool {
+        let taken = self
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| remaining.checked_sub(1))
+            .is_ok();
+        if !taken {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        taken
+    }
+}
+
+/// 疑似 RST 限速的复查结果
+///
+/// 部分协议栈对 RST 限速，快速扫描时关闭端口本应返回的 RST 被丢弃，端口看起来像被过滤。
+/// 扫描中无响应比例突然升高时（见 [`RateController::record_answer`]）立即降速，扫描结束后
+/// 慢速复查一部分无响应的端口；多数复查端口有了应答，说明无响应是探测过快所致，而不是防火墙过滤。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RstRateLimit {
+    /// 快速扫描中无响应的端口数
+    pub silent: usize,
+    /// 慢速复查的端口数
+    pub sampled: usize,
+    /// 复查时返回 RST 或连接成功的端口数
+    pub answered: usize,
+}
+
+/// 无响应端口至少达到这个数量才复查
+const RST_RECHECK_MIN_SILENT: usize = 5;
+/// 无响应端口占有响应（RST 或超时）端口的比例至少达到 1/10 才复查
+const RST_RECHECK_SILENT_RATIO: usize = 10;
+/// 最多复查的端口数
+const RST_RECHECK_SAMPLES: usize = 10;
+/// 复查时相邻两次探测的间隔
+const RST_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 从快速扫描中无响应的端口里均匀选取复查样本；没有收到过 RST 或无响应端口不够多时不复查，
+/// 调用方只在扫描中出现过无响应比例突增时才复查
+fn rst_recheck_sample(silent: &[u16], closed: usize) -> Vec<u16> {
+    if closed == 0
+        || silent.len() < RST_RECHECK_MIN_SILENT
+        || silent.len() * RST_RECHECK_SILENT_RATIO < silent.len() + closed
+    {
+        return Vec::new();
+    }
+    let step = silent.len().div_ceil(RST_RECHECK_SAMPLES);
+    silent.iter().step_by(step).copied().collect()
+}
+
+/// UDP 扫描每个任务依次探测的默认端口数
+pub const DEFAULT_UDP_BATCH_SIZE: usize = 100;
+/// 服务识别每个任务负责的默认端口数
+pub const DEFAULT_DETECT_BATCH_SIZE: usize = 20;
+
+/// `Scanner` 构建器，未设置的选项使用与命令行一致的默认值
+pub struct ScannerBuilder {
+    target: ScanTarget,
+    ports: Arc<[u16]>,
+    udp_ports: Option<Arc<[u16]>>,
+    timeout: Duration,
+    threads: usize,
+    progress: Option<Arc<ScanProgress>>,
+    rate_controller: Option<Arc<Mutex<RateController>>>,
+    service_detector: Option<Arc<ServiceDetector>>,
+    service_scan: bool,
+    service_timeout: Option<Duration>,
+    source_ports: Option<Arc<SourcePorts>>,
+    connector: Option<Arc<dyn Connector>>,
+    events: EventBus,
+    retries: u32,
+    scan_types: Vec<ScanType>,
+    max_open: Option<usize>,
+    udp_lenient: bool,
+    udp_mtu: Option<u16>,
+    udp_fragment: bool,
+    ttl: Option<u8>,
+    abort_on_open: bool,
+    udp_batch_size: usize,
+    detect_batch_size: usize,
+    sequential: bool,
+    retry_budget: Option<u32>,
+    max_detect: Option<usize>,
+    verify_open: bool,
+}
+
+impl ScannerBuilder {
+    fn new(target: ScanTarget) -> Self {
         Self {
             target,
-            start_port,
-            end_port,
-            timeout,
+            ports: (1..=65535).collect(),
+            udp_ports: None,
+            timeout: Duration::from_millis(200),
+            threads: 1000,
+            progress: None,
+            rate_controller: None,
+            service_detector: None,
+            service_scan: true,
+            service_timeout: None,
+            source_ports: None,
+            connector: None,
+            events: EventBus::new(),
+            retries: 0,
+            scan_types: vec![ScanType::Tcp],
+            max_open: None,
+            udp_lenient: false,
+            udp_mtu: None,
+            udp_fragment: false,
+            ttl: None,
+            abort_on_open: false,
+            udp_batch_size: DEFAULT_UDP_BATCH_SIZE,
+            detect_batch_size: DEFAULT_DETECT_BATCH_SIZE,
+            sequential: false,
+            retry_budget: None,
+            max_detect: None,
+            verify_open: false,
+        }
+    }
+
+    /// 扫描连续端口范围（含两端）
+    pub fn port_range(mut self, start_port: u16, end_port: u16) -> Self {
+        self.ports = (start_port..=end_port).collect();
+        self
+    }
+
+    /// 扫描指定的端口列表，多个目标可共享同一份列表
+    pub fn ports(mut self, ports: impl Into<Arc<[u16]>>) -> Self {
+        self.ports = ports.into();
+        self
+    }
+
+    /// UDP 扫描使用单独的端口列表，未设置时与 `ports` 相同
+    pub fn udp_ports(mut self, ports: impl Into<Arc<[u16]>>) -> Self {
+        self.udp_ports = Some(ports.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn progress(mut self, progress: Arc<ScanProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn rate_controller(mut self, rate_controller: Arc<Mutex<RateController>>) -> Self {
+        self.rate_controller = Some(rate_controller);
+        self
+    }
+
+    pub fn service_detector(mut self, service_detector: Arc<ServiceDetector>) -> Self {
+        self.service_detector = Some(service_detector);
+        self
+    }
+
+    /// 是否对开放端口进行服务识别，关闭时仅按端口号标注服务名
+    pub fn service_scan(mut self, enabled: bool) -> Self {
+        self.service_scan = enabled;
+        self
+    }
+
+    /// 服务识别的超时，与端口探测的 `timeout` 相互独立，同样作用于通过
+    /// `service_detector` 传入的检测器
+    pub fn service_timeout(mut self, timeout: Duration) -> Self {
+        self.service_timeout = Some(timeout);
+        self
+    }
+
+    /// 连接扫描从指定范围轮换源端口，多个目标共享同一轮换序列
+    pub fn source_ports(mut self, source_ports: Arc<SourcePorts>) -> Self {
+        self.source_ports = Some(source_ports);
+        self
+    }
+
+    /// 端口探测和服务识别改用调用方提供的方式建立连接，如继承的套接字、代理或
+    /// `MockConnector`；也可以直接传入返回 `TcpStream` 的闭包。设置后 `source_ports` 不再生效
+    pub fn connector(mut self, connector: impl Connector + 'static) -> Self {
+        self.connector = Some(Arc::new(connector));
+        self
+    }
+
+    /// 发现开放端口和识别出服务时通知的观察者
+    pub fn events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// 连接超时未响应时的最大重试次数，每次重试前等待带抖动的指数退避间隔
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// 整个主机（所有端口、所有轮次）最多重试的总次数，用完后超时的端口不再重试，按被过滤处理；
+    /// 默认不限制
+    pub fn retry_budget(mut self, retries: u32) -> Self {
+        self.retry_budget = Some(retries);
+        self
+    }
+
+    /// 依次执行的扫描协议，默认只做 TCP 扫描
+    pub fn scan_types(mut self, scan_types: Vec<ScanType>) -> Self {
+        self.scan_types = scan_types;
+        self
+    }
+
+    /// UDP 端口没有任何应答（open|filtered）时也按开放报告。默认只报告收到应答的端口：
+    /// 没有应答既可能是服务不回应空探测包，也可能是防火墙丢弃，无法区分
+    pub fn udp_lenient(mut self, lenient: bool) -> Self {
+        self.udp_lenient = lenient;
+        self
+    }
+
+    /// UDP 探测按这个 MTU 限制专属载荷的大小，未设置时使用内核记录的路径 MTU，
+    /// 取不到时按 IPv4 576、IPv6 1280 字节保守估计
+    pub fn udp_mtu(mut self, mtu: u16) -> Self {
+        self.udp_mtu = Some(mtu);
+        self
+    }
+
+    /// 允许 UDP 专属载荷超出 MTU 时分片发送。默认设置 DF 位，放不下的载荷改发空数据报：
+    /// 分片常被防火墙丢弃，对分片敏感的服务也可能不应答，结果会误判为无响应
+    pub fn udp_fragment(mut self, fragment: bool) -> Self {
+        self.udp_fragment = fragment;
+        self
+    }
+
+    /// 探测报文使用指定的 TTL（IPv6 为跳数限制），未设置时使用系统默认值；
+    /// 通过 `connector` 建立的连接不受影响
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// 连接成功后以 RST 立即中止（SO_LINGER 为 0），而不是正常四次挥手关闭；
+    /// 通过 `connector` 建立的连接不受影响
+    pub fn abort_on_open(mut self, abort: bool) -> Self {
+        self.abort_on_open = abort;
+        self
+    }
+
+    /// UDP 扫描把端口按这个大小分批，每批一个任务、占用一个 `threads` 名额依次探测。
+    /// 同时进行的探测数为 `threads` 与批数中的较小者：批越小并发越高，但任务数和
+    /// 同时打开的套接字也越多。至少为 1，默认 100
+    pub fn udp_batch_size(mut self, batch_size: usize) -> Self {
+        self.udp_batch_size = batch_size.max(1);
+        self
+    }
+
+    /// 服务识别把开放端口按这个大小分批，每批一个任务、批内并发识别。
+    /// 同时进行的识别总数受检测器的并发上限约束，批大小决定任务数量和每个任务缓存的结果数。
+    /// 至少为 1，默认 20
+    pub fn detect_batch_size(mut self, batch_size: usize) -> Self {
+        self.detect_batch_size = batch_size.max(1);
+        self
+    }
+
+    /// 按端口顺序逐个探测和识别，不并发也不派生任务，`threads` 和批大小不再生效。
+    /// 同样的输入得到同样顺序的事件和结果，用于复现问题；速度很慢
+    pub fn sequential(mut self, sequential: bool) -> Self {
+        self.sequential = sequential;
+        self
+    }
+
+    /// TCP 扫描发现这么多开放端口后取消该主机剩余端口的探测
+    pub fn max_open(mut self, max_open: usize) -> Self {
+        self.max_open = Some(max_open);
+        self
+    }
+
+    /// 每次 TCP 扫描最多对这么多开放端口做服务识别，优先常见端口，其余只按端口号标注
+    pub fn max_detect(mut self, max_detect: usize) -> Self {
+        self.max_detect = Some(max_detect);
+        self
+    }
+
+    /// 服务识别前对连接扫描发现的开放端口重新连接复查，剔除中间设备代答等造成的虚假开放，
+    /// 见 [`Scanner::verify_dropped`]
+    pub fn verify_open(mut self, verify: bool) -> Self {
+        self.verify_open = verify;
+        self
+    }
+
+    pub fn build(self) -> Scanner {
+        let udp_ports = self.udp_ports.unwrap_or_else(|| self.ports.clone());
+        let total_ports: u64 = self
+            .scan_types
+            .iter()
+            .map(|scan_type| match scan_type {
+                ScanType::Tcp => self.ports.len() as u64,
+                ScanType::Udp => udp_ports.len() as u64,
+            })
+            .sum();
+        let threads = self.threads;
+        let service_detector = match (self.service_detector, self.service_timeout) {
+            (Some(detector), Some(timeout)) => Arc::new((*detector).clone().with_timeout(timeout)),
+            (Some(detector), None) => detector,
+            (None, Some(timeout)) => Arc::new(ServiceDetector::new().with_timeout(timeout)),
+            (None, None) => Arc::new(ServiceDetector::new()),
+        };
+        // 服务识别与端口探测使用同一种连接方式
+        let service_detector = match &self.connector {
+            Some(connector) => Arc::new((*service_detector).clone().with_connector(connector.clone())),
+            None => service_detector,
+        };
+        Scanner {
+            target: self.target,
+            ports: self.ports,
+            udp_ports,
+            timeout: self.timeout,
             threads,
-            progress,
-            rate_controller,
+            progress: self
+                .progress
+                .unwrap_or_else(|| Arc::new(ScanProgress::hidden(total_ports, 1))),
+            rate_controller: self.rate_controller.unwrap_or_else(|| {
+                Arc::new(Mutex::new(RateController::new(threads as u64 * 1000, (threads / 10).max(1) as u64)))
+            }),
             service_detector,
-            connection_pool: Arc::new(Mutex::new(ConnectionPool::new(Duration::from_secs(30)))),
-            batch_size: 100, // 默认批处理大小
+            service_scan: self.service_scan,
+            source_ports: self.source_ports,
+            connector: self.connector,
+            events: self.events,
+            retries: self.retries,
+            scan_types: self.scan_types,
+            max_open: self.max_open,
+            udp_lenient: self.udp_lenient,
+            udp_mtu: self.udp_mtu,
+            udp_fragment: self.udp_fragment,
+            ttl: self.ttl,
+            abort_on_open: self.abort_on_open,
+            udp_batch_size: self.udp_batch_size,
+            detect_batch_size: self.detect_batch_size,
+            sequential: self.sequential,
+            rst_rate_limit: Arc::default(),
+            retry_budget: self.retry_budget.map(|retries| Arc::new(RetryBudget::new(retries))),
+            max_detect: self.max_detect,
+            detect_skipped: Arc::default(),
+            verify_open: self.verify_open,
+            verify_dropped: Arc::default(),
         }
     }
+}
+
+impl Scanner {
+    pub fn builder(target: impl Into<ScanTarget>) -> ScannerBuilder {
+        ScannerBuilder::new(target.into())
+    }
+
+    /// 任意一次 TCP 扫描中疑似遇到 RST 限速时返回复查结果
+    pub fn rst_rate_limit(&self) -> Option<RstRateLimit> {
+        *self.rst_rate_limit.lock().unwrap()
+    }
+
+    /// 最近一次 TCP 扫描中因 `max_detect` 没有做服务识别、只按端口号标注的端口数
+    pub fn detect_skipped(&self) -> usize {
+        self.detect_skipped.load(Ordering::Relaxed)
+    }
+
+    /// 最近一次 TCP 扫描中开放端口复查（`verify_open`）未通过、被剔除的端口数
+    pub fn verify_dropped(&self) -> usize {
+        self.verify_dropped.load(Ordering::Relaxed)
+    }
+
+    /// 重试预算（`retry_budget`）是否已经用完
+    pub fn retry_budget_exhausted(&self) -> bool {
+        self.retry_budget
+            .as_ref()
+            .is_some_and(|budget| budget.exhausted.load(Ordering::Relaxed))
+    }
+
+    /// 按设置的协议依次扫描，返回开放端口、协议及识别出的服务
+    pub async fn run(&self) -> Result<Vec<(u16, ScanType, DetectedService)>> {
+        let mut results = Vec::new();
+        for &scan_type in &self.scan_types {
+            let services = match scan_type {
+                ScanType::Tcp => self.run_tcp().await?,
+                ScanType::Udp => self.run_udp().await?,
+            };
+            self.emit_services(scan_type, &services);
+            results.extend(services.into_iter().map(|(port, service)| (port, scan_type, service)));
+        }
+        Ok(results)
+    }
+
+    /// 对同一目标重复扫描 `passes` 轮，返回任意一轮中开放过的端口及其开放的轮数
+    ///
+    /// 服务取最后一次开放时的识别结果。开放轮数少于 `passes` 的端口状态不稳定，
+    /// 常见原因是负载均衡、限速或丢包。
+    pub async fn run_passes(&self, passes: u32) -> Result<Vec<(u16, ScanType, DetectedService, u32)>> {
+        let mut seen: BTreeMap<(u16, ScanType), (DetectedService, u32)> = BTreeMap::new();
+        for _ in 0..passes.max(1) {
+            for (port, scan_type, service) in self.run().await? {
+                let entry = seen.entry((port, scan_type)).or_insert_with(|| (service.clone(), 0));
+                entry.0 = service;
+                entry.1 += 1;
+            }
+        }
+        Ok(seen
+            .into_iter()
+            .map(|((port, scan_type), (service, count))| (port, scan_type, service, count))
+            .collect())
+    }
+
+    /// DNS/NTP/SNMP 从探测应答中解析版本，其他端口按端口号标注服务名
+    async fn run_udp(&self) -> Result<Vec<(u16, DetectedService)>> {
+        let open_ports = self.run_udp_scan().await?;
+        Ok(open_ports
+            .into_iter()
+            .map(|(port, response)| {
+                let response = response.as_deref().filter(|_| self.service_scan);
+                let service = udp_probes::identify(port, response)
+                    .filter(|service| self.service_detector.port_guess() || service.method != ServiceMethod::PortGuess)
+                    .unwrap_or_else(|| self.service_detector.unconfirmed(port));
+                (port, service)
+            })
+            .collect())
+    }
+
+    async fn run_tcp(&self) -> Result<Vec<(u16, DetectedService)>> {
+        let mut open_ports: Vec<u16> = self
+            .run_tcp_scan()
+            .await?
+            .into_iter()
+            .filter(|&(_, state)| state == PortState::Open)
+            .map(|(port, _)| port)
+            .collect();
+        if self.verify_open {
+            open_ports = self.verify_open_ports(open_ports).await;
+        }
+
+        // 不做服务识别时直接按端口号表标注
+        if !self.service_scan {
+            let results: Vec<(u16, DetectedService)> = open_ports
+                .into_iter()
+                .map(|port| (port, self.service_detector.unconfirmed(port)))
+                .collect();
+            return Ok(results);
+        }
+
+        // 超出上限的端口不做识别，按端口号表标注
+        let mut labeled = Vec::new();
+        if let Some(max_detect) = self.max_detect.filter(|max_detect| open_ports.len() > *max_detect) {
+            let top_ports = parse_ports(TOP_TCP_PORTS).unwrap_or_default();
+            open_ports.sort_by_key(|&port| detect_priority(port, &top_ports));
+            labeled = open_ports
+                .split_off(max_detect)
+                .into_iter()
+                .map(|port| (port, self.service_detector.unconfirmed(port)))
+                .collect();
+            open_ports.sort_unstable();
+        }
+        self.detect_skipped.store(labeled.len(), Ordering::Relaxed);
 
-    pub async fn run(&self) -> Result<Vec<(u16, String)>> {
-        let open_ports = self.run_tcp_scan().await?;
         self.progress.set_total_services(open_ports.len() as u64);
 
+        if self.sequential {
+            let mut results = labeled;
+            for port in open_ports {
+                if let Ok(Some(service)) = self.service_detector.detect(self.target, port).await {
+                    results.push((port, service));
+                }
+                self.progress.increment_service_detect();
+            }
+            return Ok(results);
+        }
+
         // 批量并发服务识别
-        let batch_size = 20;
-        let mut port_chunks = open_ports.chunks(batch_size);
         let mut tasks = FuturesUnordered::new();
 
-        while let Some(chunk) = port_chunks.next() {
+        for chunk in open_ports.chunks(self.detect_batch_size) {
             let ports = chunk.to_vec();
             let target = self.target;
             let service_detector = self.service_detector.clone();
@@ -142,7 +649,7 @@ impl Scanner {
             }));
         }
 
-        let mut all_results = Vec::new();
+        let mut all_results = labeled;
         while let Some(result) = tasks.next().await {
             if let Ok(services) = result {
                 for (port, service) in services {
@@ -154,175 +661,1020 @@ impl Scanner {
         Ok(all_results)
     }
 
-    pub async fn run_tcp_scan(&self) -> Result<Vec<u16>> {
-        let semaphore = Arc::new(Semaphore::new(self.threads));
-        let total_requests = Arc::new(AtomicU64::new(0));
-        let open_ports_mutex = Arc::new(Mutex::new(Vec::<u16>::new()));
-
-        let total_ports = (self.end_port as u32).saturating_sub(self.start_port as u32).saturating_add(1) as usize;
-        let batch_size = 2000; // 更大批次提升效率
-        let num_batches = (total_ports + batch_size - 1) / batch_size;
+    fn emit_services(&self, scan_type: ScanType, results: &[(u16, DetectedService)]) {
+        for (port, service) in results {
+            self.events.emit(ScanEvent::ServiceDetected {
+                target: self.target.to_string(),
+                port: *port,
+                protocol: scan_type.as_str().to_string(),
+                service: service.clone(),
+            });
+        }
+    }
 
-        let mut tasks = FuturesUnordered::new();
+    fn port_probe(&self) -> PortProbe {
+        PortProbe {
+            target: self.target,
+            timeout: self.timeout,
+            retries: self.retries,
+            retry_budget: self.retry_budget.clone(),
+            rate_controller: self.rate_controller.clone(),
+            total_requests: Arc::new(AtomicU64::new(0)),
+            source_ports: self.source_ports.clone(),
+            connector: self.connector.clone(),
+            ttl: self.ttl,
+            abort_on_open: self.abort_on_open,
+            progress: self.progress.clone(),
+        }
+    }
 
-        for batch in 0..num_batches {
-            let batch_start = self.start_port.saturating_add((batch * batch_size) as u16);
-            let batch_end = std::cmp::min(
-                batch_start.saturating_add(batch_size as u16),
-                self.end_port.saturating_add(1)
-            );
+    /// 逐个复查开放端口，返回复查通过的端口并记录被剔除的数量
+    ///
+    /// 这些端口在扫描中已经报告过 `PortOpen` 事件，复查未通过的不再撤回。
+    async fn verify_open_ports(&self, open_ports: Vec<u16>) -> Vec<u16> {
+        let probe = self.port_probe();
+        let total = open_ports.len();
+        let concurrency = if self.sequential { 1 } else { self.threads.max(1) };
+        let confirmed: Vec<u16> = futures::stream::iter(open_ports)
+            .map(|port| {
+                let probe = &probe;
+                async move { probe.verify(port).await.then_some(port) }
+            })
+            .buffered(concurrency)
+            .filter_map(|port| async move { port })
+            .collect()
+            .await;
+        self.verify_dropped.store(total - confirmed.len(), Ordering::Relaxed);
+        confirmed
+    }
 
-            let target = self.target;
-            let timeout = self.timeout;
-            let semaphore = semaphore.clone();
-            let progress = self.progress.clone();
-            let rate_controller = self.rate_controller.clone();
-            let total_requests = total_requests.clone();
-            let open_ports = open_ports_mutex.clone();
+    /// TCP connect 扫描，返回开放和被防火墙过滤的端口
+    ///
+    /// 固定数量的 worker 从共享队列中取端口探测，`threads` 即同时进行的连接数上限。
+    /// 设置了 `max_open` 时，开放端口达到上限后不再取新端口，未探测的端口直接计入进度。
+    pub async fn run_tcp_scan(&self) -> Result<Vec<(u16, PortState)>> {
+        let probe = self.port_probe();
+        let queue = TcpQueue {
+            ports: self.ports.clone(),
+            next_port: Arc::new(AtomicUsize::new(0)),
+            open_count: Arc::new(AtomicUsize::new(0)),
+            max_open: self.max_open.unwrap_or(usize::MAX),
+            events: self.events.clone(),
+            closed: Arc::new(AtomicUsize::new(0)),
+            silent: Arc::default(),
+            silence_spiked: Arc::default(),
+        };
+        let next_port = queue.next_port.clone();
 
-            tasks.push(tokio::spawn(async move {
-                let _permit = semaphore.acquire().await.unwrap();
-                let mut batch_ports = Vec::with_capacity((batch_end - batch_start) as usize);
+        let mut result = Vec::new();
+        if self.sequential {
+            // 唯一的 worker 在当前任务中按顺序取端口
+            result = queue.work(&probe).await;
+        } else {
+            let mut workers = FuturesUnordered::new();
+            for _ in 0..self.threads.clamp(1, self.ports.len().max(1)) {
+                let queue = queue.clone();
+                let probe = probe.clone();
+                workers.push(tokio::spawn(async move { queue.work(&probe).await }));
+            }
+            while let Some(found) = workers.next().await {
+                result.extend(found?);
+            }
+        }
+        let probed = next_port.load(Ordering::Relaxed).min(self.ports.len());
+        self.progress.skip_ports((self.ports.len() - probed) as u64);
 
-                let mut futs = FuturesUnordered::new();
-                for port in batch_start..batch_end {
-                    let target = target;
-                    let timeout = timeout;
-                    let rate_controller = rate_controller.clone();
-                    let total_requests = total_requests.clone();
-                    futs.push(Self::scan_port(target, port, timeout, rate_controller, total_requests));
-                }
-                let mut idx = 0;
-                while let Some(result) = futs.next().await {
-                    if result.is_some() {
-                        batch_ports.push(batch_start.saturating_add(idx as u16));
+        // 扫描中无响应比例突增（已在当时降速）：慢速复查一部分，区分 RST 限速与真正的过滤
+        let mut silent = std::mem::take(&mut *queue.silent.lock().unwrap());
+        silent.sort_unstable();
+        let sample = match queue.silence_spiked.load(Ordering::Relaxed) {
+            true => rst_recheck_sample(&silent, queue.closed.load(Ordering::Relaxed)),
+            false => Vec::new(),
+        };
+        if !sample.is_empty() {
+            let mut answered = 0;
+            for &port in &sample {
+                time::sleep(RST_RECHECK_INTERVAL).await;
+                match probe.connect_once(port).await {
+                    PortState::Open => {
+                        answered += 1;
+                        if queue.open_count.fetch_add(1, Ordering::Relaxed) < queue.max_open {
+                            result.push((port, PortState::Open));
+                            queue.emit_open(&probe, port);
+                        }
                     }
-                    progress.increment_port_scan();
-                    idx += 1;
+                    PortState::Closed => answered += 1,
+                    _ => {}
                 }
+            }
+            if answered * 2 >= sample.len() {
+                *self.rst_rate_limit.lock().unwrap() = Some(RstRateLimit {
+                    silent: silent.len(),
+                    sampled: sample.len(),
+                    answered,
+                });
+            }
+        }
+        result.sort_by_key(|&(port, _)| port);
+        Ok(result)
+    }
 
-                let mut open_ports = open_ports.lock().await;
-                open_ports.extend(batch_ports);
-            }));
+    /// UDP 扫描，返回收到应答的端口及应答内容
+    ///
+    /// 已知服务的端口发送专属探测包（如 DNS 的 `version.bind` 查询），其余端口发送空数据报。
+    /// 收到 ICMP 端口不可达的端口为关闭；没有任何应答的端口是 open|filtered，
+    /// 只有设置了 `udp_lenient` 时才一并返回（应答为空）。
+    pub async fn run_udp_scan(&self) -> Result<Vec<(u16, Option<Vec<u8>>)>> {
+        let probe = UdpBatch {
+            target: self.target,
+            timeout: self.timeout,
+            ttl: self.ttl,
+            lenient: self.udp_lenient,
+            mtu: self.udp_mtu,
+            fragment: self.udp_fragment,
+            rate_controller: self.rate_controller.clone(),
+            events: self.events.clone(),
+            progress: self.progress.clone(),
+        };
+        let mut open_ports = Vec::new();
+        if self.sequential {
+            open_ports = probe.scan(&self.udp_ports).await;
+        } else {
+            let semaphore = Arc::new(Semaphore::new(self.threads));
+            let mut tasks = Vec::new();
+            for chunk in self.udp_ports.chunks(self.udp_batch_size) {
+                let batch = chunk.to_vec();
+                let semaphore = semaphore.clone();
+                let probe = probe.clone();
+
+                let task = tokio::spawn(async move {
+                    let _permit = semaphore.acquire().await.unwrap();
+                    probe.scan(&batch).await
+                });
+
+                tasks.push(task);
+            }
+
+            for task in tasks {
+                if let Ok(ports) = task.await {
+                    open_ports.extend(ports);
+                }
+            }
         }
 
-        while let Some(_res) = tasks.next().await {}
+        open_ports.sort_by_key(|&(port, _)| port);
+        Ok(open_ports)
+    }
+}
 
-        let open_ports = open_ports_mutex.lock().await;
-        let mut result = open_ports.clone();
-        result.sort();
-        Ok(result)
+/// 禁止内核对发出的报文分片（设置 DF 位），超过已知路径 MTU 的报文发送时直接报错
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &UdpSocket, ipv6: bool) -> io::Result<()> {
+    let (level, name, value) = match ipv6 {
+        true => (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DO),
+        false => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO),
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            std::os::fd::AsRawFd::as_raw_fd(socket),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
     }
+}
 
-    async fn run_udp_scan(&self) -> Result<Vec<u16>> {
-        let semaphore = Arc::new(Semaphore::new(self.threads));
-        let mut open_ports = Vec::new();
-        let mut tasks = Vec::new();
-
-        // UDP扫描使用更小的批次大小
-        const UDP_BATCH_SIZE: usize = 100;
-        let total_ports = (self.end_port - self.start_port + 1) as usize;
-        let num_batches = (total_ports + UDP_BATCH_SIZE - 1) / UDP_BATCH_SIZE;
-
-        for batch in 0..num_batches {
-            let batch_start = self.start_port + (batch * UDP_BATCH_SIZE) as u16;
-            let batch_end = std::cmp::min(batch_start + UDP_BATCH_SIZE as u16, self.end_port + 1);
-            
-            let semaphore = semaphore.clone();
-            let progress = self.progress.clone();
-            let rate_controller = self.rate_controller.clone();
-            let target = self.target;
-            let timeout = self.timeout;
+/// 其他系统按系统默认行为发送，载荷大小仍受 MTU 限制
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_socket: &UdpSocket, _ipv6: bool) -> io::Result<()> {
+    Ok(())
+}
 
-            let task = tokio::spawn(async move {
-                let mut batch_ports = Vec::new();
-                let _permit = semaphore.acquire().await.unwrap();
+/// 已连接套接字到目标的路径 MTU：内核按路由和收到的 ICMP“需要分片”消息记录
+#[cfg(target_os = "linux")]
+fn path_mtu(socket: &UdpSocket, ipv6: bool) -> Option<u16> {
+    let (level, name) = match ipv6 {
+        true => (libc::IPPROTO_IPV6, libc::IPV6_MTU),
+        false => (libc::IPPROTO_IP, libc::IP_MTU),
+    };
+    let mut mtu: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            std::os::fd::AsRawFd::as_raw_fd(socket),
+            level,
+            name,
+            &mut mtu as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    // 回环接口的 MTU 为 65536，超出 u16 时取上限
+    (ret == 0 && mtu > 0).then(|| u16::try_from(mtu).unwrap_or(u16::MAX))
+}
 
-                for port in batch_start..batch_end {
-                    if let Ok(true) = Self::scan_udp_port(target, port, timeout, rate_controller.clone()).await {
-                        batch_ports.push(port);
-                    }
-                    progress.increment_port_scan();
+#[cfg(not(target_os = "linux"))]
+fn path_mtu(_socket: &UdpSocket, _ipv6: bool) -> Option<u16> {
+    None
+}
+
+/// 接收 UDP 响应或 ICMP 不可达错误
+///
+/// `UdpSocket::recv` 只等待可读事件，错误事件（EPOLLERR）不会唤醒它，
+/// 因此要同时等待 ERROR 就绪，再通过 SO_ERROR 取出挂起的错误。
+async fn recv_or_error(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+        if ready.is_error() {
+            if let Some(e) = socket.take_error()? {
+                return Err(e);
+            }
+        }
+        match socket.try_recv(buf) {
+            // ERROR 就绪状态不会被清除：没有挂起的错误时改为只等待可读，以免被反复唤醒空转
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && ready.is_error() => return socket.recv(buf).await,
+            // try_recv 已清除可读就绪状态，重新等待
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
+
+/// 复查开放端口时连接建立后观察的时长
+const VERIFY_WINDOW: Duration = Duration::from_secs(1);
+
+/// 首次重试前的等待时间，之后每次翻倍
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+/// 重试等待时间上限（不含抖动）
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// 第 `attempt` 次重试前的等待时间：指数增长，并加入 ±50% 随机抖动，避免重试同步成突发
+pub(crate) fn retry_delay(attempt: u32) -> Duration {
+    let delay = RETRY_BASE_DELAY
+        .saturating_mul(1u32 << attempt.min(16))
+        .min(RETRY_MAX_DELAY);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+}
+
+/// 单个端口 TCP 连接探测所需的共享状态
+/// 一批 UDP 端口的探测参数
+#[derive(Clone)]
+struct UdpBatch {
+    target: ScanTarget,
+    timeout: Duration,
+    ttl: Option<u8>,
+    lenient: bool,
+    /// 未设置时使用内核记录的路径 MTU
+    mtu: Option<u16>,
+    /// 允许专属载荷分片发送
+    fragment: bool,
+    rate_controller: Arc<Mutex<RateController>>,
+    events: EventBus,
+    progress: Arc<ScanProgress>,
+}
+
+impl UdpBatch {
+    /// 探测单个 UDP 端口
+    ///
+    /// 专属载荷按路径 MTU（或 `--mtu`）限制大小：不允许分片时设置 DF 位，载荷超出
+    /// 不分片的上限、或发送时内核报告超过已知路径 MTU，都改发空数据报，只凭 ICMP 判断端口状态。
+    async fn probe(&self, port: u16) -> Result<(PortState, Option<Vec<u8>>)> {
+        {
+            let rate_controller = self.rate_controller.lock().await;
+            rate_controller.wait().await;
+            rate_controller.increment_requests();
+        }
+        let addr = self.target.socket_addr(port);
+
+        let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+        if let Some(ttl) = self.ttl {
+            set_ttl(SockRef::from(&socket), addr.is_ipv6(), ttl)?;
+        }
+        if !self.fragment {
+            set_dont_fragment(&socket, addr.is_ipv6())?;
+        }
+        // 已连接的 UDP 套接字才会收到内核转换后的 ICMP 不可达错误
+        socket.connect(addr).await?;
+
+        let mtu = self
+            .mtu
+            .or_else(|| path_mtu(&socket, addr.is_ipv6()))
+            .unwrap_or_else(|| udp_probes::fallback_mtu(addr.is_ipv6()));
+        let limit = udp_probes::payload_limit(port, mtu, addr.is_ipv6());
+        let payload = udp_probes::sized_payload(port, limit, self.fragment);
+        if let Err(e) = socket.send(payload).await {
+            if e.raw_os_error() == Some(libc::EMSGSIZE) && !payload.is_empty() {
+                let _ = socket.send(&[]).await;
+            }
+        }
+
+        let mut buf = [0u8; 4096];
+        let received = time::timeout(self.timeout, recv_or_error(&socket, &mut buf)).await;
+        let mut rate_controller = self.rate_controller.lock().await;
+        match received {
+            Ok(Ok(len)) => {
+                rate_controller.adjust_rate(true, Duration::from_millis(0));
+                Ok((PortState::Open, Some(buf[..len].to_vec())))
+            }
+            Err(_) => {
+                rate_controller.adjust_rate(true, Duration::from_millis(0));
+                Ok((PortState::NoResponse, None))
+            }
+            Ok(Err(e)) => {
+                let state = PortState::from_error(&e);
+                if state == PortState::Filtered {
+                    rate_controller.backoff();
+                } else {
+                    rate_controller.adjust_rate(false, Duration::from_millis(0));
                 }
+                Ok((state, None))
+            }
+        }
+    }
 
-                batch_ports
-            });
+    /// 按顺序逐个探测，返回要报告为开放的端口及应答
+    async fn scan(&self, ports: &[u16]) -> Vec<(u16, Option<Vec<u8>>)> {
+        let mut found = Vec::new();
+        for &port in ports {
+            self.progress.add_probes(1);
+            let reported = match self.probe(port).await {
+                Ok((PortState::Open, response)) => Some(response),
+                Ok((PortState::NoResponse, response)) if self.lenient => Some(response),
+                _ => None,
+            };
+            if let Some(response) = reported {
+                found.push((port, response));
+                self.events.emit(ScanEvent::PortOpen {
+                    target: self.target.to_string(),
+                    port,
+                    protocol: ScanType::Udp.as_str().to_string(),
+                });
+            }
+            self.progress.increment_port_scan();
+        }
+        found
+    }
+}
 
-            tasks.push(task);
+/// TCP 扫描 worker 共享的端口队列
+#[derive(Clone)]
+struct TcpQueue {
+    ports: Arc<[u16]>,
+    next_port: Arc<AtomicUsize>,
+    open_count: Arc<AtomicUsize>,
+    max_open: usize,
+    events: EventBus,
+    /// 收到 RST 的端口数
+    closed: Arc<AtomicUsize>,
+    /// 超时无响应的端口
+    silent: Arc<std::sync::Mutex<Vec<u16>>>,
+    /// 扫描中出现过无响应比例突增
+    silence_spiked: Arc<AtomicBool>,
+}
+
+impl TcpQueue {
+    /// 从队列中依次取端口探测，直到取完或开放端口达到上限，返回开放和被过滤的端口
+    async fn work(&self, probe: &PortProbe) -> Vec<(u16, PortState)> {
+        let mut found = Vec::new();
+        while self.open_count.load(Ordering::Relaxed) < self.max_open {
+            let Some(&port) = self.ports.get(self.next_port.fetch_add(1, Ordering::Relaxed)) else {
+                break;
+            };
+            let state = probe.scan_port(port).await;
+            match state {
+                // 达到上限后仍在进行中的探测结果不再计入
+                PortState::Open if self.open_count.fetch_add(1, Ordering::Relaxed) < self.max_open => {
+                    found.push((port, state));
+                    self.emit_open(probe, port);
+                }
+                PortState::Filtered => found.push((port, state)),
+                PortState::Closed => {
+                    self.closed.fetch_add(1, Ordering::Relaxed);
+                }
+                PortState::NoResponse => self.silent.lock().unwrap().push(port),
+                _ => {}
+            }
+            if probe.rate_controller.lock().await.record_answer(state != PortState::NoResponse) {
+                self.silence_spiked.store(true, Ordering::Relaxed);
+            }
+            probe.progress.increment_port_scan();
         }
+        found
+    }
+
+    fn emit_open(&self, probe: &PortProbe, port: u16) {
+        self.events.emit(ScanEvent::PortOpen {
+            target: probe.target.to_string(),
+            port,
+            protocol: ScanType::Tcp.as_str().to_string(),
+        });
+    }
+}
 
-        for task in tasks {
-            if let Ok(ports) = task.await {
-                open_ports.extend(ports);
+#[derive(Clone)]
+struct PortProbe {
+    target: ScanTarget,
+    timeout: Duration,
+    retries: u32,
+    retry_budget: Option<Arc<RetryBudget>>,
+    rate_controller: Arc<Mutex<RateController>>,
+    total_requests: Arc<AtomicU64>,
+    source_ports: Option<Arc<SourcePorts>>,
+    connector: Option<Arc<dyn Connector>>,
+    ttl: Option<u8>,
+    abort_on_open: bool,
+    progress: Arc<ScanProgress>,
+}
+
+impl PortProbe {
+    /// 探测端口，超时未响应时按退避间隔重试；重试只计入探测数，端口进度只在有结论后前进一次
+    ///
+    /// 主机的重试预算用完后不再重试，仍然超时的端口按被过滤处理。
+    async fn scan_port(&self, port: u16) -> PortState {
+        let mut state = self.connect_once(port).await;
+        for attempt in 0..self.retries {
+            if state != PortState::NoResponse {
+                break;
+            }
+            if self.retry_budget.as_ref().is_some_and(|budget| !budget.take()) {
+                return PortState::Filtered;
             }
+            time::sleep(retry_delay(attempt)).await;
+            state = self.connect_once(port).await;
         }
+        state
+    }
 
-        open_ports.sort();
-        Ok(open_ports)
+    /// 重新建立一次连接并观察 [`VERIFY_WINDOW`]：收到数据、对端正常关闭（如 tcpwrapped）
+    /// 或连接一直保持都算确认开放；连接失败或被重置（RST）多为中间设备代答握手后
+    /// 后端并不存在，复查不通过
+    async fn verify(&self, port: u16) -> bool {
+        let addr = self.target.socket_addr(port);
+        self.rate_controller.lock().await.wait().await;
+        self.progress.add_probes(1);
+        let connect = async {
+            match &self.connector {
+                Some(connector) => connector.connect(addr).await,
+                None => Ok(Box::new(tcp_socket(addr, self.ttl)?.connect(addr).await?) as BoxStream),
+            }
+        };
+        let Ok(Ok(mut stream)) = time::timeout(self.timeout, connect).await else {
+            return false;
+        };
+        let mut buf = [0u8; 1];
+        !matches!(time::timeout(VERIFY_WINDOW, stream.read(&mut buf)).await, Ok(Err(_)))
     }
 
-    async fn scan_port(
-        target: IpAddr,
-        port: u16,
-        timeout_duration: Duration,
-        rate_controller: Arc<Mutex<RateController>>,
-        total_requests: Arc<AtomicU64>,
-    ) -> Option<u16> {
-        let addr = SocketAddr::new(target, port);
+    async fn connect_once(&self, port: u16) -> PortState {
+        let addr = self.target.socket_addr(port);
+        self.rate_controller.lock().await.wait().await;
         
         // 在获取锁之前增加请求计数
-        total_requests.fetch_add(1, Ordering::Relaxed);
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.progress.add_probes(1);
         
-        match time::timeout(timeout_duration, TcpStream::connect(&addr)).await {
-            Ok(Ok(_stream)) => {
+        let connect = async {
+            let stream = match (&self.connector, &self.source_ports) {
+                (Some(connector), _) => return connector.connect(addr).await.map(drop),
+                (None, Some(source_ports)) => source_ports.connect(addr, self.ttl).await?,
+                (None, None) => tcp_socket(addr, self.ttl)?.connect(addr).await?,
+            };
+            // 关闭时直接发送 RST，对端不必经过正常关闭流程
+            if self.abort_on_open {
+                SockRef::from(&stream).set_linger(Some(Duration::ZERO))?;
+            }
+            Ok(())
+        };
+        match time::timeout(self.timeout, connect).await {
+            Ok(Ok(())) => {
                 // 连接成功，调整速率
-                let mut controller = rate_controller.lock().await;
+                let mut controller = self.rate_controller.lock().await;
                 controller.adjust_rate(true, Duration::from_millis(0));
-                Some(port)
+                PortState::Open
             }
-            Ok(Err(_)) => {
-                // 连接失败，调整速率
-                let mut controller = rate_controller.lock().await;
-                controller.adjust_rate(false, Duration::from_millis(0));
-                None
+            Ok(Err(e)) => {
+                let state = PortState::from_error(&e);
+                let mut controller = self.rate_controller.lock().await;
+                if state == PortState::Filtered {
+                    // 防火墙已明确拒绝，立即退避，避免继续冲击
+                    controller.backoff();
+                } else {
+                    controller.adjust_rate(false, Duration::from_millis(0));
+                }
+                state
             }
-            Err(_) => None,
+            Err(_) => PortState::NoResponse,
         }
     }
+}
 
-    async fn scan_udp_port(
-        target: IpAddr,
-        port: u16,
-        timeout: Duration,
-        rate_controller: Arc<Mutex<RateController>>,
-    ) -> Result<bool> {
-        let mut rate_controller = rate_controller.lock().await;
-        rate_controller.wait().await;
-        let addr = SocketAddr::new(target, port);
-        
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.set_read_timeout(Some(timeout))?;
-        
-        let _ = socket.send_to(&[], addr);
-        
-        let mut buf = [0u8; 1024];
-        match socket.recv_from(&mut buf) {
-            Ok(_) => {
-                rate_controller.increment_requests();
-                rate_controller.adjust_rate(true, Duration::from_millis(0));
-                Ok(true)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_state_from_error() {
+        let state = |kind| PortState::from_error(&io::Error::from(kind));
+        assert_eq!(state(io::ErrorKind::ConnectionRefused), PortState::Closed);
+        assert_eq!(state(io::ErrorKind::HostUnreachable), PortState::Filtered);
+        assert_eq!(state(io::ErrorKind::NetworkUnreachable), PortState::Filtered);
+        assert_eq!(state(io::ErrorKind::PermissionDenied), PortState::Filtered);
+        assert_eq!(state(io::ErrorKind::TimedOut), PortState::NoResponse);
+    }
+
+    #[test]
+    fn test_parse_scan_types() {
+        assert_eq!(ScanType::parse_list("tcp").unwrap(), vec![ScanType::Tcp]);
+        assert_eq!(ScanType::parse_list("UDP, tcp,udp").unwrap(), vec![ScanType::Udp, ScanType::Tcp]);
+        assert!(ScanType::parse_list("tcp,sctp").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_scan_reports_open_ports() {
+        let listeners: Vec<_> = futures::future::join_all((0..2).map(|_| tokio::net::TcpListener::bind("127.0.0.1:0")))
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let mut open: Vec<u16> = listeners.iter().map(|l| l.local_addr().unwrap().port()).collect();
+        open.sort();
+
+        // 开放端口夹在大量关闭端口之间，worker 数远小于端口数
+        let mut ports: Vec<u16> = (40100..40150).collect();
+        ports.extend(&open);
+        let scanner = Scanner::builder("127.0.0.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(ports)
+            .threads(4)
+            .build();
+        let result = scanner.run_tcp_scan().await.unwrap();
+        let found: Vec<u16> = result.iter().map(|&(port, _)| port).collect();
+        assert_eq!(found, open);
+        assert!(result.iter().all(|&(_, state)| state == PortState::Open));
+    }
+
+    #[tokio::test]
+    async fn test_custom_connector() {
+        // 目标地址不可达，连接全部交给自定义方式：22 和 80 转到本地监听端口，其余拒绝
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let attempts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = attempts.clone();
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(vec![21, 22, 23, 80])
+            .threads(2)
+            .connector(move |addr: std::net::SocketAddr| {
+                seen.lock().unwrap().push(addr);
+                async move {
+                    match addr.port() {
+                        22 | 80 => tokio::net::TcpStream::connect(local).await,
+                        _ => Err(io::ErrorKind::ConnectionRefused.into()),
+                    }
+                }
+            })
+            .build();
+
+        let result = scanner.run_tcp_scan().await.unwrap();
+        assert_eq!(result, vec![(22, PortState::Open), (80, PortState::Open)]);
+        let attempts = attempts.lock().unwrap();
+        assert_eq!(attempts.len(), 4);
+        assert!(attempts.iter().all(|addr| addr.ip().to_string() == "192.0.2.1"));
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_completion() {
+        // 端口号越小连接越慢，探测按相反顺序完成；结果中的端口号必须来自各自的探测而不是完成顺序
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let completed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let order = completed.clone();
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports((1..=8).collect::<Vec<u16>>())
+            .threads(8)
+            .timeout(Duration::from_secs(2))
+            .connector(move |addr: std::net::SocketAddr| {
+                let order = order.clone();
+                async move {
+                    time::sleep(Duration::from_millis(30 * (9 - u64::from(addr.port())))).await;
+                    order.lock().unwrap().push(addr.port());
+                    match addr.port() % 2 {
+                        0 => tokio::net::TcpStream::connect(local).await,
+                        _ => Err(io::ErrorKind::ConnectionRefused.into()),
+                    }
+                }
+            })
+            .build();
+
+        let result = scanner.run_tcp_scan().await.unwrap();
+        assert_eq!(*completed.lock().unwrap(), vec![8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(
+            result,
+            vec![(2, PortState::Open), (4, PortState::Open), (6, PortState::Open), (8, PortState::Open)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_open() {
+        // 22 保持连接；21 接受后立即 RST；23 只有第一次连接成功，复查时被拒绝
+        let hold = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hold_addr = hold.local_addr().unwrap();
+        let reset = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reset_addr = reset.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = reset.accept().await {
+                SockRef::from(&stream).set_linger(Some(Duration::ZERO)).unwrap();
+                // 稍后再重置，先让连接扫描看到握手成功
+                tokio::spawn(async move {
+                    time::sleep(Duration::from_millis(50)).await;
+                    drop(stream);
+                });
             }
-            Err(e) => {
-                rate_controller.increment_requests();
-                if e.kind() == std::io::ErrorKind::WouldBlock || 
-                   e.kind() == std::io::ErrorKind::TimedOut {
-                    rate_controller.adjust_rate(true, Duration::from_millis(0));
-                    Ok(true)
-                } else {
-                    rate_controller.adjust_rate(false, Duration::from_millis(0));
-                    Ok(false)
+        });
+        let spurious = Arc::new(AtomicUsize::new(0));
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(vec![21, 22, 23])
+            .service_scan(false)
+            .verify_open(true)
+            .connector(move |addr: std::net::SocketAddr| {
+                let first = addr.port() != 23 || spurious.fetch_add(1, Ordering::Relaxed) == 0;
+                async move {
+                    match addr.port() {
+                        21 => tokio::net::TcpStream::connect(reset_addr).await,
+                        22 | 23 if first => tokio::net::TcpStream::connect(hold_addr).await,
+                        _ => Err(io::ErrorKind::ConnectionRefused.into()),
+                    }
                 }
+            })
+            .build();
+
+        let results = scanner.run().await.unwrap();
+        let ports: Vec<u16> = results.iter().map(|(port, _, _)| *port).collect();
+        assert_eq!(ports, [22]);
+        assert_eq!(scanner.verify_dropped(), 2);
+        assert_eq!(scanner.progress.probes_sent(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_sequential_scan_order() {
+        use crate::events::ScanObserver;
+
+        struct OpenPorts(std::sync::Mutex<Vec<u16>>);
+        impl ScanObserver for OpenPorts {
+            fn on_event(&self, event: &ScanEvent) {
+                if let ScanEvent::PortOpen { port, .. } = event {
+                    self.0.lock().unwrap().push(*port);
+                }
+            }
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let attempts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = attempts.clone();
+        let observer = Arc::new(OpenPorts(std::sync::Mutex::new(Vec::new())));
+        let mut events = EventBus::new();
+        events.subscribe(observer.clone());
+        let ports = vec![8080, 22, 443, 21, 80];
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(ports.clone())
+            .threads(100)
+            .sequential(true)
+            .service_scan(false)
+            .events(events)
+            .connector(move |addr: std::net::SocketAddr| {
+                seen.lock().unwrap().push(addr.port());
+                async move {
+                    match addr.port() {
+                        21 => Err(io::ErrorKind::ConnectionRefused.into()),
+                        _ => tokio::net::TcpStream::connect(local).await,
+                    }
+                }
+            })
+            .build();
+
+        let results = scanner.run().await.unwrap();
+        // 按给定顺序逐个探测，事件顺序与之一致，结果按端口排序
+        assert_eq!(*attempts.lock().unwrap(), ports);
+        assert_eq!(*observer.0.lock().unwrap(), vec![8080, 22, 443, 80]);
+        assert_eq!(results.iter().map(|(port, _, _)| *port).collect::<Vec<_>>(), vec![22, 80, 443, 8080]);
+    }
+
+    #[tokio::test]
+    async fn test_rst_rate_limit_recheck() {
+        use crate::connector::BoxStream;
+        use futures::future::BoxFuture;
+
+        // 前 20 次连接返回 RST，之后的快速探测全部无响应（RST 被限速），间隔足够长时恢复 RST
+        struct RstLimited {
+            attempts: AtomicUsize,
+            last: std::sync::Mutex<Option<std::time::Instant>>,
+        }
+        impl Connector for RstLimited {
+            fn connect(&self, _addr: std::net::SocketAddr) -> BoxFuture<'_, io::Result<BoxStream>> {
+                let attempt = self.attempts.fetch_add(1, Ordering::Relaxed);
+                let now = std::time::Instant::now();
+                let last = self.last.lock().unwrap().replace(now);
+                let slow = last.is_some_and(|last| now - last >= Duration::from_millis(80));
+                Box::pin(async move {
+                    if attempt >= 20 && !slow {
+                        futures::future::pending::<()>().await;
+                    }
+                    Err(io::ErrorKind::ConnectionRefused.into())
+                })
             }
         }
+
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports((1..=100).collect::<Vec<u16>>())
+            .threads(1)
+            .timeout(Duration::from_millis(20))
+            .service_scan(false)
+            .connector(RstLimited {
+                attempts: AtomicUsize::new(0),
+                last: std::sync::Mutex::new(None),
+            })
+            .build();
+        assert!(scanner.run_tcp_scan().await.unwrap().is_empty());
+        assert_eq!(
+            scanner.rst_rate_limit(),
+            Some(RstRateLimit {
+                silent: 80,
+                sampled: 10,
+                answered: 10
+            })
+        );
+
+        // 有状态防火墙：每 3 个端口有 1 个返回 RST，其余从一开始就无响应，不触发复查
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports((1..=100).collect::<Vec<u16>>())
+            .threads(1)
+            .timeout(Duration::from_millis(20))
+            .service_scan(false)
+            .connector(move |addr: std::net::SocketAddr| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    if !addr.port().is_multiple_of(3) {
+                        futures::future::pending::<()>().await;
+                    }
+                    Err::<tokio::net::TcpStream, _>(io::ErrorKind::ConnectionRefused.into())
+                }
+            })
+            .build();
+        assert!(scanner.run_tcp_scan().await.unwrap().is_empty());
+        assert_eq!(scanner.rst_rate_limit(), None);
+        assert_eq!(attempts.load(Ordering::Relaxed), 100);
+
+        // 从未收到 RST：全部无响应按过滤处理，不复查
+        assert!(rst_recheck_sample(&(1..=100).collect::<Vec<u16>>(), 0).is_empty());
+        assert!(rst_recheck_sample(&[1, 2], 50).is_empty());
+        assert_eq!(rst_recheck_sample(&[1, 2, 3, 4, 5], 1), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget() {
+        // 所有端口都不响应：预算内的 2 次重试用完后，其余超时端口不再重试
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        let progress = Arc::new(ScanProgress::hidden(4, 1));
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(vec![1, 2, 3, 4])
+            .threads(1)
+            .timeout(Duration::from_millis(10))
+            .retries(3)
+            .retry_budget(2)
+            .progress(progress.clone())
+            .connector(move |_addr: std::net::SocketAddr| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                futures::future::pending::<io::Result<tokio::net::TcpStream>>()
+            })
+            .build();
+        assert!(!scanner.retry_budget_exhausted());
+
+        let result = scanner.run_tcp_scan().await.unwrap();
+        assert_eq!(attempts.load(Ordering::Relaxed), 4 + 2);
+        assert!(result.iter().all(|&(_, state)| state == PortState::Filtered));
+        assert_eq!(result.len(), 4);
+        assert!(scanner.retry_budget_exhausted());
+        // 进度按端口计，重试只增加探测数
+        assert_eq!((progress.scanned_ports(), progress.total_ports()), (4, 4));
+        assert_eq!(progress.probes_sent(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_run_passes_counts_flapping_ports() {
+        // 22 始终开放，80 只在奇数次连接时开放
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(vec![22, 80, 443])
+            .service_scan(false)
+            .connector(move |addr: std::net::SocketAddr| {
+                let attempt = (addr.port() == 80).then(|| attempts.fetch_add(1, Ordering::Relaxed));
+                async move {
+                    match (addr.port(), attempt) {
+                        (22, _) => tokio::net::TcpStream::connect(local).await,
+                        (80, Some(attempt)) if attempt % 2 == 0 => tokio::net::TcpStream::connect(local).await,
+                        _ => Err(io::ErrorKind::ConnectionRefused.into()),
+                    }
+                }
+            })
+            .build();
+
+        let seen: Vec<_> = scanner
+            .run_passes(3)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(port, scan_type, _, count)| (port, scan_type, count))
+            .collect();
+        assert_eq!(seen, vec![(22, ScanType::Tcp, 3), (80, ScanType::Tcp, 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_scan_with_mock_services() {
+        use crate::connector::{MockConnector, MockService};
+        use crate::service_detector::ServiceMethod;
+
+        let connector = MockConnector::new()
+            .service(22, MockService::Banner(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec()))
+            .service(
+                80,
+                MockService::Reply(b"HTTP/1.1 200 OK\r\nServer: nginx/1.24.0\r\n\r\n<title>Router</title>".to_vec()),
+            )
+            .service(3306, MockService::Silent);
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(vec![21, 22, 80, 3306])
+            .service_timeout(Duration::from_millis(300))
+            .connector(connector.clone())
+            .build();
+
+        let mut results = scanner.run().await.unwrap();
+        results.sort_by_key(|&(port, _, _)| port);
+        let found: Vec<_> = results
+            .iter()
+            .map(|(port, _, service)| (*port, service.name.as_str(), service.version.as_deref(), service.method))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (22, "SSH", Some("2.0"), ServiceMethod::Banner),
+                (80, "HTTP", Some("1.1"), ServiceMethod::Probe),
+                // 连上但不发数据，只能按端口号猜测
+                (3306, "MySQL", None, ServiceMethod::PortGuess),
+            ]
+        );
+        assert_eq!(results[1].2.title.as_deref(), Some("Router"));
+        let protocols: Vec<_> = results.iter().map(|(_, _, service)| service.protocol_version.as_deref()).collect();
+        assert_eq!(protocols, vec![Some("SSH-2.0"), Some("HTTP/1.1"), None]);
+
+        // 只识别 2 个端口：常见端口优先，其余按端口号标注
+        let scanner = Scanner::builder("192.0.2.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(vec![22, 80, 3306, 31337])
+            .service_timeout(Duration::from_millis(300))
+            .connector(connector.service(31337, MockService::Banner(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec())))
+            .max_detect(2)
+            .build();
+        let mut results = scanner.run().await.unwrap();
+        results.sort_by_key(|&(port, _, _)| port);
+        let found: Vec<_> = results.iter().map(|(port, _, service)| (*port, service.name.as_str(), service.method)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (22, "SSH", ServiceMethod::Banner),
+                (80, "HTTP", ServiceMethod::Probe),
+                (3306, "MySQL", ServiceMethod::PortGuess),
+                (31337, "unknown", ServiceMethod::PortGuess),
+            ]
+        );
+        assert_eq!(scanner.detect_skipped(), 2);
+        assert_eq!(detect_priority(443, &[22, 443]), (0, 443));
+        assert!(detect_priority(1023, &[22]) < detect_priority(1024, &[22]));
+    }
+
+    #[tokio::test]
+    async fn test_udp_open_requires_response() {
+        // 应答任意数据报的端口、收到后不应答的端口，以及没有监听（ICMP 端口不可达）的端口
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let closed = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (echo_port, silent_port) = (echo.local_addr().unwrap().port(), silent.local_addr().unwrap().port());
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((_, peer)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(b"pong", peer).await;
+            }
+        });
+
+        let ports = vec![echo_port, silent_port, closed_port];
+        let scan = |lenient: bool| {
+            Scanner::builder("127.0.0.1".parse::<std::net::IpAddr>().unwrap())
+                .udp_ports(ports.clone())
+                .timeout(Duration::from_millis(300))
+                .udp_lenient(lenient)
+                .build()
+        };
+
+        let strict = scan(false).run_udp_scan().await.unwrap();
+        assert_eq!(strict, vec![(echo_port, Some(b"pong".to_vec()))]);
+
+        let mut lenient = scan(true).run_udp_scan().await.unwrap();
+        lenient.sort();
+        let mut expected = vec![(echo_port, Some(b"pong".to_vec())), (silent_port, None)];
+        expected.sort();
+        assert_eq!(lenient, expected);
+        drop(silent);
+    }
+
+    #[tokio::test]
+    async fn test_udp_batch_size() {
+        // 都不应答，每个端口等满超时
+        let mut sockets = Vec::new();
+        for _ in 0..4 {
+            sockets.push(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        }
+        let ports: Vec<u16> = sockets.iter().map(|socket| socket.local_addr().unwrap().port()).collect();
+        let scan = |batch_size: usize| {
+            Scanner::builder("127.0.0.1".parse::<std::net::IpAddr>().unwrap())
+                .udp_ports(ports.clone())
+                .timeout(Duration::from_millis(200))
+                .threads(4)
+                .udp_batch_size(batch_size)
+                .build()
+        };
+
+        // 默认批大小下 4 个端口在同一批中依次探测
+        let started = std::time::Instant::now();
+        scan(DEFAULT_UDP_BATCH_SIZE).run_udp_scan().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(800), "耗时 {:?}", started.elapsed());
+
+        // 每批一个端口，4 批并发
+        let started = std::time::Instant::now();
+        scan(1).run_udp_scan().await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(600), "耗时 {:?}", started.elapsed());
+        drop(sockets);
+    }
+
+    #[tokio::test]
+    async fn test_abort_on_open_resets_connection() {
+        use tokio::io::AsyncReadExt;
+
+        for abort in [false, true] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let scanner = Scanner::builder("127.0.0.1".parse::<std::net::IpAddr>().unwrap())
+                .ports(vec![port])
+                .abort_on_open(abort)
+                .build();
+            assert_eq!(scanner.run_tcp_scan().await.unwrap(), vec![(port, PortState::Open)]);
+
+            let (mut accepted, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 16];
+            let read = accepted.read(&mut buf).await;
+            if abort {
+                assert_eq!(read.unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+            } else {
+                assert_eq!(read.unwrap(), 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_open_stops_scan() {
+        let listeners: Vec<_> = futures::future::join_all((0..3).map(|_| tokio::net::TcpListener::bind("127.0.0.1:0")))
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let ports: Vec<u16> = listeners.iter().map(|l| l.local_addr().unwrap().port()).collect();
+
+        let progress = Arc::new(ScanProgress::hidden(ports.len() as u64, 1));
+        let scanner = Scanner::builder("127.0.0.1".parse::<std::net::IpAddr>().unwrap())
+            .ports(ports)
+            .threads(1)
+            .max_open(1)
+            .progress(progress.clone())
+            .build();
+        let result = scanner.run_tcp_scan().await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(progress.scanned_ports(), 3);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        for _ in 0..100 {
+            let first = retry_delay(0);
+            assert!(first >= Duration::from_millis(25) && first < Duration::from_millis(75));
+            let capped = retry_delay(30);
+            assert!(capped >= Duration::from_millis(500) && capped < Duration::from_millis(1500));
+        }
     }
 }
\ No newline at end of file
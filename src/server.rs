@@ -0,0 +1,457 @@
+use crate::events::{EventBus, ScanEvent, ScanObserver};
+use crate::limits::calibrate_concurrency;
+use crate::os_detector::OSDetector;
+use crate::output::Output;
+use crate::progress::ScanProgress;
+use crate::scanner::{ScanType, Scanner};
+use crate::service_detector::{ServiceDetector, DEFAULT_SERVICE_TIMEOUT};
+use crate::service_fingerprints::ServiceFingerprintDB;
+use crate::target::{parse_subnet, ScanTarget};
+use anyhow::{anyhow, Result};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 单个任务的并发数上限，超出的请求值按上限处理
+const MAX_THREADS: usize = 5000;
+
+/// 已结束的任务保留这么久，之后查询返回 404
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(3600);
+
+/// 最多保留的已结束任务数，超出时先移除最早结束的
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// `POST /scans` 的请求体，未给出的字段使用与命令行一致的默认值
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanRequest {
+    /// IP 地址或网段
+    pub target: String,
+    #[serde(default = "default_start_port")]
+    pub start_port: u16,
+    #[serde(default = "default_end_port")]
+    pub end_port: u16,
+    /// 超时时间（毫秒）
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+    /// 扫描类型，逗号分隔，如 `tcp,udp`
+    #[serde(default = "default_scan_type")]
+    pub scan_type: String,
+    #[serde(default = "default_true")]
+    pub service_scan: bool,
+    /// 服务识别超时时间（毫秒）
+    #[serde(default = "default_service_timeout")]
+    pub service_timeout: u64,
+    #[serde(default)]
+    pub os_scan: bool,
+    #[serde(default)]
+    pub retries: u32,
+}
+
+fn default_start_port() -> u16 {
+    1
+}
+
+fn default_end_port() -> u16 {
+    65535
+}
+
+fn default_timeout() -> u64 {
+    200
+}
+
+fn default_service_timeout() -> u64 {
+    DEFAULT_SERVICE_TIMEOUT.as_millis() as u64
+}
+
+fn default_threads() -> usize {
+    1000
+}
+
+fn default_scan_type() -> String {
+    "tcp".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// 校验后的扫描任务参数
+struct ScanPlan {
+    targets: Vec<ScanTarget>,
+    ports: Arc<[u16]>,
+    scan_types: Vec<ScanType>,
+    /// 按上限和文件描述符校准后的并发数
+    threads: usize,
+}
+
+impl ScanRequest {
+    fn plan(&self) -> Result<ScanPlan> {
+        let targets = parse_subnet(&self.target).map_err(|e| anyhow!("无效的目标 {}: {}", self.target, e))?;
+        if self.start_port == 0 || self.start_port > self.end_port {
+            return Err(anyhow!("无效的端口范围: {}-{}", self.start_port, self.end_port));
+        }
+        if self.threads == 0 {
+            return Err(anyhow!("并发数必须大于 0"));
+        }
+        Ok(ScanPlan {
+            targets,
+            ports: (self.start_port..=self.end_port).collect(),
+            scan_types: ScanType::parse_list(&self.scan_type)?,
+            threads: calibrate_concurrency(self.threads.min(MAX_THREADS)).0,
+        })
+    }
+}
+
+/// `POST /scans` 的响应
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanCreated {
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanState {
+    Running,
+    Finished,
+    Failed,
+}
+
+/// `GET /scans/{id}` 的响应
+#[derive(Debug, Serialize)]
+pub struct ScanStatus<'a> {
+    pub id: u64,
+    pub state: ScanState,
+    pub scanned_ports: u64,
+    pub total_ports: u64,
+    /// 实际发出的探测数（含重试）
+    pub probes_sent: u64,
+    /// 目前已发现的开放端口数，扫描过程中实时更新
+    pub open_ports: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+    /// 已完成主机的结果
+    pub results: &'a [Output],
+}
+
+/// 错误响应体
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiError {
+    pub error: String,
+}
+
+fn error_response(status: StatusCode, error: impl ToString) -> Response {
+    (status, Json(ApiError { error: error.to_string() })).into_response()
+}
+
+struct ScanJob {
+    state: ScanState,
+    progress: Arc<ScanProgress>,
+    open_ports: Arc<AtomicUsize>,
+    error: Option<String>,
+    results: Vec<Output>,
+    /// 任务结束（完成或失败）的时间
+    finished_at: Option<Instant>,
+}
+
+impl ScanJob {
+    fn finish(&mut self, state: ScanState) {
+        self.state = state;
+        self.finished_at = Some(Instant::now());
+    }
+}
+
+/// 移除超过保留时间的已结束任务，剩余的已结束任务超过 `MAX_FINISHED_JOBS` 时移除最早结束的；
+/// 运行中的任务不受影响
+fn evict_finished(jobs: &mut HashMap<u64, ScanJob>, now: Instant) {
+    jobs.retain(|_, job| job.finished_at.is_none_or(|at| now.duration_since(at) < FINISHED_JOB_TTL));
+    let mut finished: Vec<(Instant, u64)> = jobs.iter().filter_map(|(&id, job)| Some((job.finished_at?, id))).collect();
+    if finished.len() > MAX_FINISHED_JOBS {
+        finished.sort_unstable();
+        for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            jobs.remove(id);
+        }
+    }
+}
+
+/// 统计开放端口事件，供状态查询实时展示
+struct OpenPortCounter(Arc<AtomicUsize>);
+
+impl ScanObserver for OpenPortCounter {
+    fn on_event(&self, event: &ScanEvent) {
+        if let ScanEvent::PortOpen { .. } = event {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    jobs: Arc<Mutex<HashMap<u64, ScanJob>>>,
+    next_id: Arc<AtomicU64>,
+    fingerprint_db: ServiceFingerprintDB,
+}
+
+/// 构建 API 路由
+pub fn router(fingerprint_db: ServiceFingerprintDB) -> Router {
+    let state = AppState {
+        jobs: Arc::new(Mutex::new(HashMap::new())),
+        next_id: Arc::new(AtomicU64::new(1)),
+        fingerprint_db,
+    };
+    Router::new()
+        .route("/scans", post(create_scan))
+        .route("/scans/{id}", get(scan_status))
+        .with_state(state)
+}
+
+/// 在指定地址上提供 REST API，直到进程退出
+pub async fn serve(bind: SocketAddr, fingerprint_db: ServiceFingerprintDB) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    axum::serve(listener, router(fingerprint_db)).await?;
+    Ok(())
+}
+
+async fn create_scan(
+    State(state): State<AppState>,
+    body: Result<Json<ScanRequest>, JsonRejection>,
+) -> Response {
+    let request = match body {
+        Ok(Json(request)) => request,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e.body_text()),
+    };
+    let plan = match request.plan() {
+        Ok(plan) => plan,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed);
+    let total_ports = (plan.targets.len() * plan.ports.len() * plan.scan_types.len()) as u64;
+    let progress = Arc::new(ScanProgress::hidden(total_ports, plan.targets.len() as u64));
+    let open_ports = Arc::new(AtomicUsize::new(0));
+    {
+        let mut jobs = state.jobs.lock().unwrap();
+        evict_finished(&mut jobs, Instant::now());
+        jobs.insert(
+            id,
+            ScanJob {
+                state: ScanState::Running,
+                progress: progress.clone(),
+                open_ports: open_ports.clone(),
+                error: None,
+                results: Vec::new(),
+                finished_at: None,
+            },
+        );
+    }
+
+    let mut events = EventBus::new();
+    events.subscribe(Arc::new(OpenPortCounter(open_ports)));
+    tokio::spawn(async move {
+        let service_detector = Arc::new(ServiceDetector::with_fingerprint_db(state.fingerprint_db.clone()));
+        for &target in &plan.targets {
+            let result = scan_host(target, &request, &plan, &progress, &service_detector, &events).await;
+            let mut jobs = state.jobs.lock().unwrap();
+            let Some(job) = jobs.get_mut(&id) else {
+                return;
+            };
+            match result {
+                Ok(output) => job.results.push(output),
+                Err(e) => {
+                    job.finish(ScanState::Failed);
+                    job.error = Some(e.to_string());
+                    return;
+                }
+            }
+        }
+        if let Some(job) = state.jobs.lock().unwrap().get_mut(&id) {
+            job.finish(ScanState::Finished);
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json(ScanCreated { id })).into_response()
+}
+
+async fn scan_host(
+    target: ScanTarget,
+    request: &ScanRequest,
+    plan: &ScanPlan,
+    progress: &Arc<ScanProgress>,
+    service_detector: &Arc<ServiceDetector>,
+    events: &EventBus,
+) -> Result<Output> {
+    events.emit(ScanEvent::HostStarted { target: target.to_string() });
+    let scanner = Scanner::builder(target)
+        .ports(plan.ports.clone())
+        .timeout(Duration::from_millis(request.timeout))
+        .threads(plan.threads)
+        .retries(request.retries)
+        .progress(progress.clone())
+        .service_detector(service_detector.clone())
+        .service_scan(request.service_scan)
+        .service_timeout(Duration::from_millis(request.service_timeout))
+        .scan_types(plan.scan_types.clone())
+        .events(events.clone())
+        .build();
+    let results = scanner.run().await?;
+
+    let mut output = Output::new(target.to_string());
+    if request.os_scan {
+        if let Ok(os_info) = OSDetector::new(target).detect().await {
+            output.set_os_info(os_info);
+        }
+    }
+    for (port, scan_type, service) in &results {
+        output.add_port(*port, service, scan_type.as_str().to_string());
+    }
+    events.emit(ScanEvent::HostFinished {
+        target: target.to_string(),
+        open_ports: results.len(),
+    });
+    Ok(output)
+}
+
+async fn scan_status(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    let mut jobs = state.jobs.lock().unwrap();
+    evict_finished(&mut jobs, Instant::now());
+    let Some(job) = jobs.get(&id) else {
+        return error_response(StatusCode::NOT_FOUND, format!("扫描任务不存在: {}", id));
+    };
+    Json(ScanStatus {
+        id,
+        state: job.state,
+        scanned_ports: job.progress.scanned_ports(),
+        total_ports: job.progress.total_ports(),
+        probes_sent: job.progress.probes_sent(),
+        open_ports: job.open_ports.load(Ordering::Relaxed),
+        error: job.error.as_deref(),
+        results: &job.results,
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// 发送一个 HTTP/1.1 请求，返回状态码和 JSON 响应体
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: test\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_scan_api() {
+        let open = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open_port = open.local_addr().unwrap().port();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(ServiceFingerprintDB::new())).await });
+
+        let (status, body) = request(addr, "POST", "/scans", r#"{"target": "not-an-ip"}"#).await;
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().contains("not-an-ip"));
+        let (status, _) = request(addr, "POST", "/scans", r#"{"target": "127.0.0.1", "start_port": 10, "end_port": 5}"#).await;
+        assert_eq!(status, 400);
+        let (status, _) = request(addr, "POST", "/scans", "{").await;
+        assert_eq!(status, 400);
+
+        let scan = format!(
+            r#"{{"target": "127.0.0.1", "start_port": {0}, "end_port": {0}, "service_scan": false}}"#,
+            open_port
+        );
+        let (status, body) = request(addr, "POST", "/scans", &scan).await;
+        assert_eq!(status, 202);
+        let id = body["id"].as_u64().unwrap();
+
+        let mut body = Value::Null;
+        for _ in 0..50 {
+            body = request(addr, "GET", &format!("/scans/{}", id), "").await.1;
+            if body["state"] == "finished" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(body["state"], "finished");
+        assert_eq!(body["total_ports"], 1);
+        assert_eq!(body["open_ports"], 1);
+        assert_eq!(body["results"][0]["ports"][0]["port"], open_port);
+
+        let (status, _) = request(addr, "GET", "/scans/999", "").await;
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_job_limits() {
+        let plan = |threads: usize| {
+            ScanRequest {
+                target: "127.0.0.1".to_string(),
+                start_port: 1,
+                end_port: 1,
+                timeout: default_timeout(),
+                threads,
+                scan_type: default_scan_type(),
+                service_scan: false,
+                service_timeout: default_service_timeout(),
+                os_scan: false,
+                retries: 0,
+            }
+            .plan()
+            .unwrap()
+            .threads
+        };
+        assert_eq!(plan(10), 10);
+        assert!(plan(1_000_000) <= MAX_THREADS);
+
+        let job = |finished_at: Option<Instant>| ScanJob {
+            state: ScanState::Running,
+            progress: Arc::new(ScanProgress::hidden(1, 1)),
+            open_ports: Arc::default(),
+            error: None,
+            results: Vec::new(),
+            finished_at,
+        };
+        let start = Instant::now();
+        let mut jobs = HashMap::new();
+        jobs.insert(0, job(None));
+        jobs.insert(1, job(Some(start)));
+        for id in 2..MAX_FINISHED_JOBS as u64 + 3 {
+            jobs.insert(id, job(Some(start + Duration::from_secs(id))));
+        }
+
+        // 已结束的任务超过上限：移除最早结束的两个，运行中的任务保留
+        evict_finished(&mut jobs, start + Duration::from_secs(200));
+        assert_eq!(jobs.len(), MAX_FINISHED_JOBS + 1);
+        assert!(jobs.contains_key(&0) && !jobs.contains_key(&1) && !jobs.contains_key(&2));
+
+        // 超过保留时间后全部移除
+        evict_finished(&mut jobs, start + FINISHED_JOB_TTL + Duration::from_secs(500));
+        assert_eq!(jobs.keys().collect::<Vec<_>>(), vec![&0]);
+    }
+}
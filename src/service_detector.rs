@@ -1,30 +1,481 @@
-use std::net::IpAddr;
+use crate::target::ScanTarget;
 use std::time::Duration;
 use anyhow::Result;
-use crate::service_fingerprints::ServiceFingerprintDB;
-use std::sync::Arc;
-use tokio::sync::Semaphore;
-use std::collections::HashMap;
+use crate::anomaly::Anomaly;
+use crate::connector::{BoxStream, Connector, TcpConnector};
+use crate::relay::RelayCheck;
+use crate::tls_audit::TlsAudit;
+use crate::http::{self, HttpHeader};
+use crate::tls::{self, TlsFingerprint};
+use crate::service_fingerprints::{identify_unknown, read_banner, Probe, ServiceFingerprint, ServiceFingerprintDB, ServiceMatch, DEFAULT_PROBE_THRESHOLD};
+use regex::Regex;
+use futures::future::BoxFuture;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+/// 只按端口号猜测服务名时的置信度
+pub const PORT_GUESS_CONFIDENCE: f32 = 0.3;
+
+/// 只有 TLS 握手成功、无法确定上层协议时的置信度
+pub const TLS_HANDSHAKE_CONFIDENCE: f32 = 0.5;
+
+/// 连接建立后未发送任何数据就被关闭或重置（`tcpwrapped`）时的置信度
+pub const CLOSED_ON_CONNECT_CONFIDENCE: f32 = 0.4;
+
+/// 检测负载均衡时对同一端口重复探测的次数
+pub const BACKEND_SAMPLES: usize = 4;
+
+/// 服务的识别方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceMethod {
+    /// 按端口号猜测
+    #[default]
+    PortGuess,
+    /// 被动读取服务主动发送的 banner 并匹配指纹
+    Banner,
+    /// 主动发送请求（HTTP 请求、UDP 协议探测包）后匹配应答
+    Probe,
+    /// TLS 握手
+    Tls,
+    /// 连接建立后对方未发送任何数据就关闭或重置了连接
+    Closed,
+}
+
+impl ServiceMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceMethod::PortGuess => "port-guess",
+            ServiceMethod::Banner => "banner",
+            ServiceMethod::Probe => "probe",
+            ServiceMethod::Tls => "tls",
+            ServiceMethod::Closed => "closed",
+        }
+    }
+}
+
+/// 识别出的服务
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DetectedService {
+    pub name: String,
+    pub version: Option<String>,
+    /// 识别结果的置信度（0-1），匹配指纹时取指纹权重
+    #[serde(default)]
+    pub confidence: f32,
+    #[serde(default)]
+    pub method: ServiceMethod,
+    /// Web 页面标题
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// HTTP 重定向地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// 协议版本（如 `TLS 1.2`、`SSH-2.0`、`HTTP/1.1`），与产品版本 `version` 分开记录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<String>,
+    /// TLS 握手的 JA3/JA3S 指纹，只在开启 TLS 指纹且对方完成 ServerHello 时存在
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsFingerprint>,
+    /// 匹配到的指纹，按端口号猜测时为空；只用于调试输出，不随事件序列化
+    #[serde(skip)]
+    pub fingerprint: Option<Box<ServiceFingerprint>>,
+}
+
+impl DetectedService {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// 按端口号猜测的服务，端口不在常见服务表中时为 `unknown`
+    pub fn guessed(port: u16) -> Self {
+        match guess_service(port) {
+            Some(name) => Self {
+                confidence: PORT_GUESS_CONFIDENCE,
+                ..Self::new(name)
+            },
+            None => Self::new("unknown"),
+        }
+    }
+
+    /// 接受连接后不发送任何数据就关闭或重置的服务，沿用 nmap 的名称 `tcpwrapped`；
+    /// TCP Wrappers、部分代理和端口敲门守护进程都有这种行为
+    pub fn tcpwrapped() -> Self {
+        Self {
+            confidence: CLOSED_ON_CONNECT_CONFIDENCE,
+            method: ServiceMethod::Closed,
+            ..Self::new("tcpwrapped")
+        }
+    }
+}
+
+impl From<ServiceMatch> for DetectedService {
+    fn from(matched: ServiceMatch) -> Self {
+        let http = matched.http.unwrap_or_default();
+        Self {
+            name: matched.fingerprint.name.clone(),
+            version: matched.version,
+            confidence: matched.fingerprint.weight,
+            method: matched.method,
+            title: http.title,
+            location: http.location,
+            protocol_version: matched.protocol_version,
+            tls: None,
+            fingerprint: Some(Box::new(matched.fingerprint)),
+        }
+    }
+}
+
+/// 把读到的 banner 解释为服务
+///
+/// `ServiceDetector` 负责连接和读取 banner，识别规则由实现此 trait 的类型决定，
+/// 默认为指纹库（[`ServiceFingerprintDB`]），也可以换成自定义规则或模型。
+pub trait ServiceClassifier: Send + Sync {
+    fn classify(&self, port: u16, banner: &[u8]) -> Option<DetectedService>;
+}
+
+impl ServiceClassifier for ServiceFingerprintDB {
+    fn classify(&self, port: u16, banner: &[u8]) -> Option<DetectedService> {
+        ServiceFingerprintDB::classify(self, port, banner).map(DetectedService::from)
+    }
+}
+
+/// 闭包形式的识别规则，如 `|port, banner| ...`
+impl<F> ServiceClassifier for F
+where
+    F: Fn(u16, &[u8]) -> Option<DetectedService> + Send + Sync,
+{
+    fn classify(&self, port: u16, banner: &[u8]) -> Option<DetectedService> {
+        self(port, banner)
+    }
+}
+
+impl fmt::Display for DetectedService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {}", self.name, version)?,
+            None => write!(f, "{}", self.name)?,
+        }
+        if let Some(title) = &self.title {
+            write!(f, " [{}]", title)?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, " -> {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+/// 端口与完整 banner
+type BannerKey = (u16, Vec<u8>);
+
+/// 跨主机共享的 banner 识别结果
+///
+/// 以端口和完整 banner 为键，只有逐字节相同的 banner 才复用结果，
+/// 因此 banner 中带主机名等差异的服务仍会各自识别。条目数有上限，满后不再新增。
+pub struct BannerCache {
+    entries: std::sync::Mutex<HashMap<BannerKey, Option<DetectedService>>>,
+    capacity: usize,
+}
+
+impl BannerCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: std::sync::Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    /// 查找已识别过的相同 banner；`None` 表示未命中
+    fn get(&self, port: u16, banner: &[u8]) -> Option<Option<DetectedService>> {
+        let entries = self.entries.lock().ok()?;
+        entries.get(&(port, banner.to_vec())).cloned()
+    }
+
+    fn insert(&self, port: u16, banner: Vec<u8>, matched: Option<DetectedService>) {
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() < self.capacity {
+                entries.insert((port, banner), matched);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|entries| entries.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for BannerCache {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
 
 #[derive(Clone)]
 pub struct ServiceDetector {
     timeout: Duration,
-    fingerprint_db: ServiceFingerprintDB,
-    cache: Arc<tokio::sync::RwLock<HashMap<(IpAddr, u16), String>>>,
+    /// 读取单次探测响应的总时限，未设置时与 `timeout` 相同
+    read_timeout: Option<Duration>,
+    connector: Arc<dyn Connector>,
+    /// 决定各端口读取 banner 的方式：有专属指纹的端口读取一次，其余端口依次发送各种探测
+    fingerprint_db: Arc<ServiceFingerprintDB>,
+    /// 解释读到的 banner，默认与 `fingerprint_db` 是同一个指纹库
+    classifier: Arc<dyn ServiceClassifier>,
+    cache: Arc<tokio::sync::RwLock<HashMap<(ScanTarget, u16), DetectedService>>>,
+    banner_cache: Option<Arc<BannerCache>>,
+    /// 未知端口探测的提前结束阈值
+    probe_threshold: f32,
+    /// 全局并发上限，克隆出的检测器和 `with_shared_semaphore` 传入同一信号量的检测器共用
     semaphore: Arc<Semaphore>,
+    /// 单个主机同时识别的端口数上限
+    host_concurrency: usize,
+    /// 有识别在进行或排队的主机各自的并发名额
+    host_slots: Arc<HostSlots>,
+    /// `detect_batch` 同时进行的识别数
+    batch_concurrency: usize,
+    /// `detect_batch` 整批的时限，到期未完成的端口不再等待
+    batch_timeout: Option<Duration>,
+    /// HTTP 探测附加的请求头
+    http_headers: Arc<[HttpHeader]>,
+    /// 是否额外进行一次 TLS 握手计算 JA3S
+    tls_fingerprint: bool,
+    /// 识别失败时是否按端口号猜测服务名
+    port_guess: bool,
+    /// 连接后未收到数据就被关闭或重置的端口是否报告为 `tcpwrapped`
+    report_resets: bool,
 }
 
+type HostSlots = std::sync::Mutex<HashMap<ScanTarget, Arc<Semaphore>>>;
+
+/// 服务识别默认的全局并发上限
+pub const DEFAULT_DETECT_CONCURRENCY: usize = 100;
+
+/// 单个主机默认同时识别的端口数，开放端口多的主机不会占满全局名额
+pub const DEFAULT_HOST_DETECT_CONCURRENCY: usize = 32;
+
+/// 一次识别占用的并发名额，释放时移除不再使用的主机信号量
+struct DetectPermit<'a> {
+    host: Option<OwnedSemaphorePermit>,
+    _global: Option<SemaphorePermit<'a>>,
+    addr: ScanTarget,
+    host_slots: &'a HostSlots,
+}
+
+impl Drop for DetectPermit<'_> {
+    fn drop(&mut self) {
+        drop(self.host.take());
+        let mut host_slots = self.host_slots.lock().unwrap();
+        // 只剩表中的引用时，该主机没有进行中或排队的识别
+        if host_slots.get(&self.addr).is_some_and(|slots| Arc::strong_count(slots) == 1) {
+            host_slots.remove(&self.addr);
+        }
+    }
+}
+
+/// `detect_batch` 默认同时识别的端口数
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 32;
+
+/// 服务识别默认超时：需要等待 banner 或 HTTP 响应，远长于端口探测的连接超时
+pub const DEFAULT_SERVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl ServiceDetector {
     pub fn new() -> Self {
+        Self::with_fingerprint_db(ServiceFingerprintDB::new())
+    }
+
+    /// 使用已加载的指纹库，避免每个目标重复读取 fingerprints.json
+    pub fn with_fingerprint_db(fingerprint_db: ServiceFingerprintDB) -> Self {
+        let fingerprint_db = Arc::new(fingerprint_db);
         Self {
-            timeout: Duration::from_secs(5),
-            fingerprint_db: ServiceFingerprintDB::new(),
+            timeout: DEFAULT_SERVICE_TIMEOUT,
+            read_timeout: None,
+            connector: Arc::new(TcpConnector),
+            classifier: fingerprint_db.clone(),
+            fingerprint_db,
             cache: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
-            semaphore: Arc::new(Semaphore::new(100)), // 限制并发数
+            banner_cache: None,
+            probe_threshold: DEFAULT_PROBE_THRESHOLD,
+            semaphore: Arc::new(Semaphore::new(DEFAULT_DETECT_CONCURRENCY)),
+            host_concurrency: DEFAULT_HOST_DETECT_CONCURRENCY,
+            host_slots: Arc::new(HostSlots::default()),
+            batch_concurrency: DEFAULT_BATCH_CONCURRENCY,
+            batch_timeout: None,
+            http_headers: Arc::from([]),
+            tls_fingerprint: false,
+            port_guess: true,
+            report_resets: false,
         }
     }
 
-    pub async fn detect(&self, addr: IpAddr, port: u16) -> Result<Option<String>> {
+    /// 建立连接的超时，未调用 `with_read_timeout` 时同时作为读取响应的时限
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 读取 banner 或探测响应的总时限，与连接超时分开设置；
+    /// 连上后不发数据或逐字节慢速发送的服务最多占用这么久
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = Some(read_timeout);
+        self
+    }
+
+    /// 改用其他方式建立连接，如代理或测试用的 `MockConnector`
+    pub fn with_connector(mut self, connector: Arc<dyn Connector>) -> Self {
+        self.connector = connector;
+        self
+    }
+
+    /// 未知端口按开销依次探测时，匹配到权重不低于 `threshold` 的指纹即停止
+    pub fn with_probe_threshold(mut self, threshold: f32) -> Self {
+        self.probe_threshold = threshold;
+        self
+    }
+
+    /// 所有主机合计同时识别的端口数上限，至少为 1
+    pub fn with_concurrency(mut self, limit: usize) -> Self {
+        self.semaphore = Arc::new(Semaphore::new(limit.max(1)));
+        self
+    }
+
+    /// 与其他检测器共用全局并发上限，每个主机各建一个检测器时使用
+    pub fn with_shared_semaphore(mut self, semaphore: Arc<Semaphore>) -> Self {
+        self.semaphore = semaphore;
+        self
+    }
+
+    /// 单个主机同时识别的端口数上限，至少为 1
+    pub fn with_host_concurrency(mut self, limit: usize) -> Self {
+        self.host_concurrency = limit.max(1);
+        self
+    }
+
+    /// `detect_batch` 同时识别的端口数上限，至少为 1
+    pub fn with_batch_concurrency(mut self, concurrency: usize) -> Self {
+        self.batch_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// `detect_batch` 整批的时限；到期时仍在识别的端口结果为 `None`
+    pub fn with_batch_timeout(mut self, timeout: Duration) -> Self {
+        self.batch_timeout = Some(timeout);
+        self
+    }
+
+    /// HTTP 探测附加的请求头，同名时替换默认的 `User-Agent` 等
+    pub fn with_http_headers(mut self, headers: Arc<[HttpHeader]>) -> Self {
+        self.http_headers = headers;
+        self
+    }
+
+    /// 用自定义规则代替指纹库解释 banner
+    ///
+    /// 读取 banner 的方式不变：有专属指纹的端口读取一次（HTTP 端口发送请求），其余端口依次发送各种探测包，
+    /// Web 端口无法识别时再发送明文 HTTP 请求；每次读到的数据都交给 `classifier`。
+    pub fn with_classifier(mut self, classifier: Arc<dyn ServiceClassifier>) -> Self {
+        self.classifier = classifier;
+        self
+    }
+
+    /// 识别时另建一个连接发送 ClientHello，记录 ServerHello 的 JA3S 和本程序 ClientHello 的 JA3；
+    /// 端口无法识别但完成了 TLS 握手时按 `TLS` 报告
+    pub fn with_tls_fingerprint(mut self, enabled: bool) -> Self {
+        self.tls_fingerprint = enabled;
+        self
+    }
+
+    /// 关闭后识别失败的端口不再按端口号猜测服务名，一律报告为 `unknown`，
+    /// 结果中只保留指纹或探测实际确认的服务
+    pub fn with_port_guess(mut self, enabled: bool) -> Self {
+        self.port_guess = enabled;
+        self
+    }
+
+    /// 开启后，识别时建立的连接在收到任何数据前被对方关闭或重置、且没有匹配到服务时，
+    /// 报告为 `tcpwrapped`（[`DetectedService::tcpwrapped`]），不再按端口号猜测或丢弃；
+    /// 只是一直不发数据的服务不受影响
+    pub fn with_report_resets(mut self, enabled: bool) -> Self {
+        self.report_resets = enabled;
+        self
+    }
+
+    /// 是否按端口号猜测服务名
+    pub fn port_guess(&self) -> bool {
+        self.port_guess
+    }
+
+    /// 未经识别的端口的服务：按端口号猜测，关闭猜测时为 `unknown`
+    pub fn unconfirmed(&self, port: u16) -> DetectedService {
+        match self.port_guess {
+            true => DetectedService::guessed(port),
+            false => DetectedService::new("unknown"),
+        }
+    }
+
+    /// 与其他主机的检测器共享 banner 识别结果，同构集群中相同 banner 只匹配一次
+    pub fn with_banner_cache(mut self, banner_cache: Arc<BannerCache>) -> Self {
+        self.banner_cache = Some(banner_cache);
+        self
+    }
+
+    async fn identify(&self, addr: ScanTarget, port: u16) -> Option<DetectedService> {
+        if !self.report_resets {
+            return self.identify_with(self.connector.as_ref(), addr, port).await;
+        }
+        let watch = CloseWatch::new(self.connector.as_ref());
+        match self.identify_with(&watch, addr, port).await {
+            None if watch.closed() => Some(DetectedService::tcpwrapped()),
+            identified => identified,
+        }
+    }
+
+    async fn identify_with(&self, connector: &dyn Connector, addr: ScanTarget, port: u16) -> Option<DetectedService> {
+        let read_timeout = self.read_timeout.unwrap_or(self.timeout);
+        let socket_addr = addr.socket_addr(port);
+        if !self.fingerprint_db.has_fingerprints(port) {
+            return identify_unknown(
+                self.classifier.as_ref(),
+                connector,
+                socket_addr,
+                self.timeout,
+                read_timeout,
+                self.probe_threshold,
+                &self.http_headers,
+            )
+            .await;
+        }
+        let banner = self
+            .fingerprint_db
+            .grab_banner(connector, socket_addr, self.timeout, read_timeout, &self.http_headers)
+            .await?;
+        let Some(banner_cache) = &self.banner_cache else {
+            return self.classifier.classify(port, &banner);
+        };
+        if let Some(matched) = banner_cache.get(port, &banner) {
+            return matched;
+        }
+        let matched = self.classifier.classify(port, &banner);
+        banner_cache.insert(port, banner, matched.clone());
+        matched
+    }
+
+    pub async fn detect(&self, addr: ScanTarget, port: u16) -> Result<Option<DetectedService>> {
         // 检查缓存
         {
             let cache = self.cache.read().await;
@@ -33,67 +484,548 @@ impl ServiceDetector {
             }
         }
 
-        // 获取信号量许可
-        let _permit = self.semaphore.acquire().await.unwrap();
-
-        // 使用指纹数据库进行服务识别
-        if let Ok(Some(fingerprint)) = self.fingerprint_db.identify_service(&addr.to_string(), port, self.timeout).await {
-            let service = fingerprint.name.clone();
-            // 更新缓存
-            let mut cache = self.cache.write().await;
-            cache.insert((addr, port), service.clone());
-            return Ok(Some(service));
-        }
-
-        // 如果指纹识别失败，根据端口号进行基本服务识别
-        let service = match port {
-            80 | 443 => Some("HTTP"),
-            22 => Some("SSH"),
-            25 | 587 => Some("SMTP"),
-            110 => Some("POP3"),
-            143 => Some("IMAP"),
-            3306 => Some("MySQL"),
-            5432 => Some("PostgreSQL"),
-            27017 => Some("MongoDB"),
-            6379 => Some("Redis"),
-            _ => None,
+        // 获取主机和全局的并发名额
+        let _permit = self.acquire(addr).await;
+
+        // Web 端口同时尝试 TLS 握手和明文识别，握手成功即为 HTTPS，不论端口号
+        let web_port = http::is_http_port(port) || http::is_https_port(port);
+        let (mut tls, identified) = match self.tls_fingerprint || web_port {
+            true => futures::join!(self.tls_handshake(addr, port), self.identify(addr, port)),
+            false => (None, self.identify(addr, port).await),
+        };
+        // 其他端口对明文请求返回了 HTTP 应答（如 nginx 的 400），再确认是否为 HTTPS
+        if tls.is_none() && !self.tls_fingerprint && identified.as_ref().is_some_and(is_http_response) {
+            tls = self.tls_handshake(addr, port).await;
+        }
+
+        // 使用指纹数据库（或自定义规则）进行服务识别，失败时根据端口号进行基本服务识别
+        let service = match (&tls, identified) {
+            // 明文连接被关闭，但另一个连接完成了 TLS 握手
+            (Some(tls), Some(service)) if service.method == ServiceMethod::Closed => {
+                Some(tls_service(if web_port { "HTTPS" } else { "TLS" }, tls))
+            }
+            // 保留明文识别出的产品、版本和标题，只改为 HTTPS
+            (Some(tls), Some(service)) if web_port || is_http_response(&service) => Some(DetectedService {
+                name: "HTTPS".to_string(),
+                method: ServiceMethod::Tls,
+                protocol_version: tls.version.clone(),
+                confidence: service.confidence.max(TLS_HANDSHAKE_CONFIDENCE),
+                ..service
+            }),
+            (Some(tls), None) if web_port => Some(tls_service("HTTPS", tls)),
+            // 明文应答匹配到了 443 端口的 HTTPS 指纹，但握手没有成功
+            (None, Some(service)) if service.name == "HTTPS" && is_http_response(&service) => Some(DetectedService {
+                name: "HTTP".to_string(),
+                ..service
+            }),
+            (_, Some(service)) => Some(service),
+            (None, None) if web_port => match self.probe_http(addr, port).await {
+                Some(service) => Some(service),
+                None => (guess_service(port).is_some() || !self.port_guess).then(|| self.unconfirmed(port)),
+            },
+            (_, None) if guess_service(port).is_some() => Some(self.unconfirmed(port)),
+            (tls, None) => tls.as_ref().map(|tls| tls_service("TLS", tls)),
+        };
+        let Some(mut service) = service else {
+            return Ok(None);
+        };
+        service.tls = tls.filter(|_| self.tls_fingerprint);
+        // 更新缓存
+        let mut cache = self.cache.write().await;
+        cache.insert((addr, port), service.clone());
+        Ok(Some(service))
+    }
+
+    async fn tls_handshake(&self, addr: ScanTarget, port: u16) -> Option<TlsFingerprint> {
+        let read_timeout = self.read_timeout.unwrap_or(self.timeout);
+        tls::fingerprint(self.connector.as_ref(), addr.socket_addr(port), self.timeout, read_timeout).await
+    }
+
+    /// Web 端口上既没有完成 TLS 握手、也没有识别出服务时，发送明文 HTTP 请求，
+    /// 按 80 端口解释应答（指纹库使用 80 端口的指纹）
+    async fn probe_http(&self, addr: ScanTarget, port: u16) -> Option<DetectedService> {
+        let read_timeout = self.read_timeout.unwrap_or(self.timeout);
+        let socket_addr = addr.socket_addr(port);
+        let banner = Probe::Http
+            .run(self.connector.as_ref(), socket_addr, self.timeout, read_timeout, &self.http_headers)
+            .await?;
+        http::parse_response(&banner)?;
+        self.classifier.classify(80, &banner)
+    }
+
+    /// 先取得主机名额再取全局名额：排队中的端口不占全局名额，
+    /// 开放端口多的主机最多占用 `host_concurrency` 个，其余名额留给其他主机
+    async fn acquire(&self, addr: ScanTarget) -> DetectPermit<'_> {
+        // 先建好名额守卫，识别被取消（如整批超时）时同样清理主机信号量
+        let mut permit = DetectPermit {
+            host: None,
+            _global: None,
+            addr,
+            host_slots: &self.host_slots,
+        };
+        let host = {
+            let mut host_slots = self.host_slots.lock().unwrap();
+            host_slots
+                .entry(addr)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.host_concurrency)))
+                .clone()
         };
+        permit.host = Some(host.acquire_owned().await.unwrap());
+        permit._global = Some(self.semaphore.acquire().await.unwrap());
+        permit
+    }
+
+    /// 识别多个端口，结果按 `ports` 的顺序返回
+    ///
+    /// 同时进行的识别数不超过 `with_batch_concurrency` 的设置；设置了整批时限时，
+    /// 到期后放弃未完成的识别，这些端口的结果为 `None`。
+    pub async fn detect_batch(&self, addr: ScanTarget, ports: &[u16]) -> Result<Vec<(u16, Option<DetectedService>)>> {
+        let mut pending = futures::stream::iter(ports.iter().copied())
+            .map(|port| async move { (port, self.detect(addr, port).await.ok().flatten()) })
+            .buffer_unordered(self.batch_concurrency);
+        let deadline = self.batch_timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+        let mut detected = HashMap::new();
+        loop {
+            let next = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, pending.next()).await {
+                    Ok(next) => next,
+                    Err(_) => break,
+                },
+                None => pending.next().await,
+            };
+            let Some((port, service)) = next else {
+                break;
+            };
+            detected.insert(port, service);
+        }
+
+        Ok(ports
+            .iter()
+            .map(|port| (*port, detected.get(port).cloned().flatten()))
+            .collect())
+    }
 
-        if let Some(service) = service {
-            let service = service.to_string();
-            // 更新缓存
-            let mut cache = self.cache.write().await;
-            cache.insert((addr, port), service.clone());
-            Ok(Some(service))
-        } else {
-            Ok(None)
-        }
-    }
-
-    pub async fn detect_batch(&self, addr: IpAddr, ports: &[u16]) -> Result<Vec<(u16, Option<String>)>> {
-        let mut tasks = Vec::new();
-        
-        for &port in ports {
-            let detector = self.clone();
-            let addr = addr;
-            let task = tokio::spawn(async move {
-                match detector.detect(addr, port).await {
-                    Ok(service) => (port, service),
-                    Err(_) => (port, None),
+    /// `--check-proxies`：按识别出的服务检查开放代理或开放中继（见 [`RelayCheck`]），成立时返回异常发现
+    pub async fn check_relay(&self, addr: ScanTarget, port: u16, service: &DetectedService) -> Option<Anomaly> {
+        let check = RelayCheck::for_service(service)?;
+        let read_timeout = self.read_timeout.unwrap_or(self.timeout);
+        let open = check.run(self.connector.as_ref(), addr.socket_addr(port), self.timeout, read_timeout).await;
+        open.then(|| check.anomaly(port))
+    }
+
+    /// `--tls-audit`：检查 TLS 端口的证书、已弃用的协议版本和弱密码套件（见 [`TlsAudit`]）
+    pub async fn audit_tls(&self, addr: ScanTarget, port: u16) -> TlsAudit {
+        let read_timeout = self.read_timeout.unwrap_or(self.timeout);
+        TlsAudit::run(self.connector.as_ref(), addr.socket_addr(port), self.timeout, read_timeout).await
+    }
+
+    /// 对同一端口建立 `samples` 次新连接读取 banner，返回不同 banner 的数量
+    ///
+    /// 比较前去掉时间、Cookie 等每次都会变化的内容；多于一种说明请求可能落到了
+    /// 负载均衡后面的不同后端。读不到任何 banner 时返回 0。
+    pub async fn count_backends(&self, addr: ScanTarget, port: u16, samples: usize) -> usize {
+        let socket_addr = addr.socket_addr(port);
+        let read_timeout = self.read_timeout.unwrap_or(self.timeout);
+        let mut variants = HashSet::new();
+        for _ in 0..samples {
+            let Ok(Ok(mut stream)) = tokio::time::timeout(self.timeout, self.connector.connect(socket_addr)).await else {
+                continue;
+            };
+            let banner = read_banner(&mut stream, socket_addr, read_timeout, &self.http_headers).await;
+            if let Some(signature) = backend_signature(&banner) {
+                variants.insert(signature);
+            }
+        }
+        variants.len()
+    }
+}
+
+/// 包装识别使用的连接，记录是否有连接在收到任何数据前被对方关闭或重置
+struct CloseWatch<'a> {
+    inner: &'a dyn Connector,
+    closed: Arc<AtomicBool>,
+}
+
+impl<'a> CloseWatch<'a> {
+    fn new(inner: &'a dyn Connector) -> Self {
+        Self {
+            inner,
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl Connector for CloseWatch<'_> {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<BoxStream>> {
+        Box::pin(async move {
+            let inner = self.inner.connect(addr).await?;
+            Ok(Box::new(WatchedStream {
+                inner,
+                received: false,
+                closed: self.closed.clone(),
+            }) as BoxStream)
+        })
+    }
+}
+
+struct WatchedStream {
+    inner: BoxStream,
+    received: bool,
+    closed: Arc<AtomicBool>,
+}
+
+impl AsyncRead for WatchedStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let (before, remaining) = (buf.filled().len(), buf.remaining());
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        match &poll {
+            Poll::Ready(Ok(())) if buf.filled().len() > before => self.received = true,
+            // 读到 EOF（FIN）
+            Poll::Ready(Ok(())) if remaining > 0 && !self.received => self.closed.store(true, Ordering::Relaxed),
+            Poll::Ready(Err(e))
+                if !self.received
+                    && matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted) =>
+            {
+                self.closed.store(true, Ordering::Relaxed)
+            }
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for WatchedStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        // 发送探测请求前连接已被重置
+        if let Poll::Ready(Err(e)) = &poll {
+            if !self.received && matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe) {
+                self.closed.store(true, Ordering::Relaxed);
+            }
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// 用于区分后端的 banner 特征：HTTP 响应取状态行、`Server` 等标识头和页面标题，
+/// 其他协议取第一行并去掉其中的时间
+fn backend_signature(banner: &[u8]) -> Option<String> {
+    static TIME: OnceLock<Regex> = OnceLock::new();
+    let text = String::from_utf8_lossy(banner);
+    let mut lines = text.lines();
+    let first = lines.next()?.trim();
+    if first.is_empty() {
+        return None;
+    }
+    if !first.starts_with("HTTP/") {
+        let re = TIME.get_or_init(|| Regex::new(r"\d{1,2}:\d{2}:\d{2}").unwrap());
+        return Some(re.replace_all(first, "").into_owned());
+    }
+
+    let mut signature = vec![first.to_string()];
+    for line in lines.take_while(|line| !line.trim().is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if ["server", "x-powered-by", "via"].contains(&name.trim().to_ascii_lowercase().as_str()) {
+            signature.push(format!("{}: {}", name.trim().to_ascii_lowercase(), value.trim()));
+        }
+    }
+    if let Some(title) = http::parse_response(banner).and_then(|info| info.title) {
+        signature.push(title);
+    }
+    Some(signature.join("\n"))
+}
+
+/// 识别结果来自明文 HTTP 应答
+fn is_http_response(service: &DetectedService) -> bool {
+    service.protocol_version.as_deref().is_some_and(|version| version.starts_with("HTTP/"))
+}
+
+/// 只完成了 TLS 握手、无法读取上层协议的服务
+fn tls_service(name: &str, tls: &TlsFingerprint) -> DetectedService {
+    DetectedService {
+        confidence: TLS_HANDSHAKE_CONFIDENCE,
+        method: ServiceMethod::Tls,
+        protocol_version: tls.version.clone(),
+        ..DetectedService::new(name)
+    }
+}
+
+/// 根据端口号猜测常见服务名
+pub fn guess_service(port: u16) -> Option<&'static str> {
+    match port {
+        80 | 443 => Some("HTTP"),
+        22 => Some("SSH"),
+        25 | 587 => Some("SMTP"),
+        110 => Some("POP3"),
+        143 => Some("IMAP"),
+        3306 => Some("MySQL"),
+        5432 => Some("PostgreSQL"),
+        27017 => Some("MongoDB"),
+        6379 => Some("Redis"),
+        _ => None,
+    }
+}
+
+impl Default for ServiceDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banner_cache() {
+        let db = ServiceFingerprintDB::new();
+        let cache = BannerCache::new(2);
+        let banner = b"SSH-2.0-OpenSSH_9.6\r\n".to_vec();
+
+        assert!(cache.get(22, &banner).is_none());
+        cache.insert(22, banner.clone(), ServiceClassifier::classify(&db, 22, &banner));
+        let hit = cache.get(22, &banner).unwrap().unwrap();
+        assert_eq!(hit.name, "SSH");
+
+        // 端口或 banner 不同都不能复用
+        assert!(cache.get(2222, &banner).is_none());
+        assert!(cache.get(22, b"SSH-2.0-dropbear\r\n").is_none());
+
+        // 未匹配的结果同样缓存，达到上限后不再新增
+        cache.insert(21, b"garbage".to_vec(), None);
+        assert!(matches!(cache.get(21, b"garbage"), Some(None)));
+        cache.insert(25, b"220 mail".to_vec(), None);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout_bounds_silent_service() {
+        use tokio::io::AsyncWriteExt;
+
+        // 接受连接后一直不发数据
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_port = silent.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = silent.accept().await {
+                connections.push(stream);
+            }
+        });
+        // 每 20ms 发送一个字节，每次读取都不会单独超时
+        let dribble = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dribble_port = dribble.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = dribble.accept().await {
+                tokio::spawn(async move {
+                    while stream.write_all(b"x").await.is_ok() {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                    }
+                });
+            }
+        });
+
+        let detector = ServiceDetector::new()
+            .with_timeout(Duration::from_secs(5))
+            .with_read_timeout(Duration::from_millis(200));
+        let target: ScanTarget = "127.0.0.1".parse().unwrap();
+        for port in [silent_port, dribble_port] {
+            let started = std::time::Instant::now();
+            detector.detect(target, port).await.unwrap();
+            // 被动探测和 HTTP 探测各最多等待 200ms
+            assert!(started.elapsed() < Duration::from_secs(1), "端口 {} 耗时 {:?}", port, started.elapsed());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_resets() {
+        // 接受连接后立即关闭（FIN）或重置（SO_LINGER 为 0 时关闭发送 RST）
+        let mut ports = Vec::new();
+        for linger in [None, Some(Duration::ZERO)] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            ports.push(listener.local_addr().unwrap().port());
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    stream.set_linger(linger).unwrap();
                 }
             });
-            tasks.push(task);
         }
-
-        let results = futures::future::join_all(tasks).await;
-        let mut detected_services = Vec::new();
-        
-        for result in results {
-            if let Ok((port, service)) = result {
-                detected_services.push((port, service));
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_port = silent.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = silent.accept().await {
+                connections.push(stream);
             }
+        });
+
+        let target: ScanTarget = "127.0.0.1".parse().unwrap();
+        let detector = ServiceDetector::new().with_read_timeout(Duration::from_millis(200));
+        let reporting = ServiceDetector::new()
+            .with_read_timeout(Duration::from_millis(200))
+            .with_report_resets(true);
+        for &port in &ports {
+            let service = reporting.detect(target, port).await.unwrap().unwrap();
+            assert_eq!((service.name.as_str(), service.method), ("tcpwrapped", ServiceMethod::Closed), "端口 {}", port);
+            // 默认不区分，与识别失败相同
+            assert!(detector.detect(target, port).await.unwrap().is_none());
         }
+        // 一直不发数据的服务不是 tcpwrapped
+        assert!(reporting.detect(target, silent_port).await.unwrap().is_none());
+    }
 
-        Ok(detected_services)
+    #[tokio::test]
+    async fn test_detect_batch_bounds() {
+        use crate::connector::{MockConnector, MockService};
+
+        let silent_ports = [21, 25, 110, 143];
+        let connector = silent_ports
+            .iter()
+            .fold(MockConnector::new(), |connector, &port| connector.service(port, MockService::Silent))
+            .service(22, MockService::Banner(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec()));
+        let detector = ServiceDetector::new()
+            .with_connector(Arc::new(connector))
+            .with_read_timeout(Duration::from_millis(100))
+            .with_batch_concurrency(1);
+        let target: ScanTarget = "127.0.0.1".parse().unwrap();
+        let mut ports = vec![22];
+        ports.extend(silent_ports);
+
+        // 逐个识别：每个不发数据的端口至少要等一次读取时限
+        let started = std::time::Instant::now();
+        let results = detector.detect_batch(target, &ports).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(400), "耗时 {:?}", started.elapsed());
+        assert_eq!(results.iter().map(|(port, _)| *port).collect::<Vec<_>>(), ports);
+        assert_eq!(results[0].1.as_ref().unwrap().name, "SSH");
+
+        // 整批时限到期后直接返回，未完成的端口没有结果
+        let detector = detector.with_batch_timeout(Duration::from_millis(150));
+        let target: ScanTarget = "127.0.0.2".parse().unwrap();
+        let started = std::time::Instant::now();
+        let results = detector.detect_batch(target, &ports).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(300), "耗时 {:?}", started.elapsed());
+        assert_eq!(results.len(), ports.len());
+        assert_eq!(results[0].1.as_ref().unwrap().name, "SSH");
+        assert!(results[1..].iter().any(|(_, service)| service.is_none()));
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_detect_concurrency_limits() {
+        use futures::FutureExt;
+
+        let detector = ServiceDetector::new().with_concurrency(3).with_host_concurrency(2);
+        let busy: ScanTarget = "10.0.0.1".parse().unwrap();
+        let other: ScanTarget = "10.0.0.2".parse().unwrap();
+
+        // 单个主机占满自己的名额后，其他主机仍能取得全局名额
+        let first = detector.acquire(busy).await;
+        let second = detector.acquire(busy).await;
+        assert!(detector.acquire(busy).now_or_never().is_none());
+        let third = detector.acquire(other).await;
+        // 全局名额用完，所有主机都要等待
+        assert!(detector.acquire(other).now_or_never().is_none());
+        assert_eq!(detector.host_slots.lock().unwrap().len(), 2);
+
+        drop(first);
+        let fourth = detector.acquire(other).now_or_never().expect("释放后应取得名额");
+        drop((second, third, fourth));
+        assert!(detector.host_slots.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_count_backends() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::AsyncWriteExt;
+
+        // 轮流由两个版本不同的后端应答
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let banner: &[u8] = match counter.fetch_add(1, Ordering::Relaxed) % 2 {
+                    0 => b"SSH-2.0-OpenSSH_9.6\r\n",
+                    _ => b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3\r\n",
+                };
+                let _ = stream.write_all(banner).await;
+            }
+        });
+
+        let detector = ServiceDetector::new().with_timeout(Duration::from_millis(500));
+        let target: ScanTarget = "127.0.0.1".parse().unwrap();
+        assert_eq!(detector.count_backends(target, port, BACKEND_SAMPLES).await, 2);
+        assert_eq!(accepted.load(Ordering::Relaxed), BACKEND_SAMPLES);
+
+        // 只有日期和 Cookie 不同的 HTTP 响应视为同一后端
+        let first = b"HTTP/1.1 200 OK\r\nDate: Mon, 01 Jan 2024 10:00:00 GMT\r\nServer: nginx\r\nSet-Cookie: id=1\r\n\r\n<title>Home</title>";
+        let second = b"HTTP/1.1 200 OK\r\nDate: Mon, 01 Jan 2024 10:00:01 GMT\r\nServer: nginx\r\nSet-Cookie: id=2\r\n\r\n<title>Home</title>";
+        let other = b"HTTP/1.1 200 OK\r\nServer: Apache\r\n\r\n<title>Home</title>";
+        assert_eq!(backend_signature(first), backend_signature(second));
+        assert_ne!(backend_signature(first), backend_signature(other));
+        assert_eq!(
+            backend_signature(b"220 mx ESMTP Exim 4.96 Mon, 01 Jan 2024 10:00:00 +0000\r\n"),
+            backend_signature(b"220 mx ESMTP Exim 4.96 Mon, 01 Jan 2024 10:00:03 +0000\r\n")
+        );
+        assert_eq!(backend_signature(b""), None);
+    }
+
+    #[tokio::test]
+    async fn test_custom_classifier() {
+        use crate::connector::{MockConnector, MockService};
+
+        let connector: Arc<dyn Connector> = Arc::new(
+            MockConnector::new()
+                .service(22, MockService::Banner(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec()))
+                .service(9000, MockService::Banner(b"MYPROTO v3 ready\r\n".to_vec()))
+                .service(9999, MockService::Reply(b"MYPROTO v3 ready\r\n".to_vec())),
+        );
+        let classifier = |_port: u16, banner: &[u8]| {
+            banner.starts_with(b"MYPROTO").then(|| DetectedService {
+                version: Some("3".to_string()),
+                confidence: 0.8,
+                method: ServiceMethod::Banner,
+                ..DetectedService::new("MyProto")
+            })
+        };
+        let detector = ServiceDetector::new()
+            .with_timeout(Duration::from_millis(200))
+            .with_connector(connector.clone())
+            .with_classifier(Arc::new(classifier));
+        let target: ScanTarget = "192.0.2.1".parse().unwrap();
+
+        let service = detector.detect(target, 9000).await.unwrap().unwrap();
+        assert_eq!((service.name.as_str(), service.version.as_deref()), ("MyProto", Some("3")));
+        // 只应答请求的服务：没有专属指纹的端口照常依次探测，HTTP 探测的应答同样交给自定义规则
+        assert_eq!(detector.detect(target, 9999).await.unwrap().unwrap().name, "MyProto");
+        // 自定义规则不认识的 banner 只按端口号猜测，不再使用指纹库
+        let fallback = detector.detect(target, 22).await.unwrap().unwrap();
+        assert_eq!((fallback.name.as_str(), fallback.method), ("SSH", ServiceMethod::PortGuess));
+        // 关闭端口号猜测后只报告确认的服务
+        let confirmed_only = ServiceDetector::new()
+            .with_timeout(Duration::from_millis(200))
+            .with_connector(connector.clone())
+            .with_classifier(Arc::new(classifier))
+            .with_port_guess(false);
+        assert_eq!(confirmed_only.detect(target, 22).await.unwrap().unwrap().name, "unknown");
+        assert_eq!(confirmed_only.unconfirmed(3306).name, "unknown");
+
+        // 默认使用指纹库
+        let default = ServiceDetector::new().with_timeout(Duration::from_millis(200)).with_connector(connector);
+        assert_eq!(default.detect(target, 22).await.unwrap().unwrap().method, ServiceMethod::Banner);
+    }
+}
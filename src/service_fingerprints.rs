@@ -1,15 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
 use std::time::Duration;
-use tokio::net::TcpStream;
-use tokio::time::timeout;
-use anyhow::Result;
+use tokio::time::{timeout, timeout_at, Instant};
+use anyhow::{anyhow, Result};
 use regex::Regex;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use serde::{Serialize, Deserialize};
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use crate::connector::{Connector, TcpConnector};
+use crate::http::{self, HttpHeader, HttpInfo, MAX_HTTP_LEN};
+use crate::service_detector::{guess_service, DetectedService, ServiceClassifier, ServiceMethod};
+use crate::target::ScanTarget;
+use crate::tls;
+use colored::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ServiceFingerprint {
     pub name: String,
     pub protocol: String,
@@ -21,6 +29,219 @@ pub struct ServiceFingerprint {
     pub version_pattern: Option<String>,
     pub vendor: Option<String>,
     pub cpe: Option<String>, // Common Platform Enumeration
+    /// 正则按原始字节匹配（`regex::bytes`，不启用 Unicode，`\xNN` 表示单个字节），
+    /// 用于 RDP、SMB、TLS 等二进制协议；默认匹配 UTF-8 解码后的文本
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub binary: bool,
+}
+
+/// 一次指纹匹配的结果
+#[derive(Debug, Clone)]
+pub struct ServiceMatch {
+    pub fingerprint: ServiceFingerprint,
+    pub version: Option<String>,
+    /// Web 端口的页面标题和重定向地址
+    pub http: Option<HttpInfo>,
+    /// 被动读取 banner 还是主动发送请求后匹配
+    pub method: ServiceMethod,
+    /// 应答中的协议版本，见 [`protocol_version`]
+    pub protocol_version: Option<String>,
+}
+
+/// 单个服务 banner 的最大读取长度
+const MAX_BANNER_LEN: usize = 4096;
+
+/// 会主动发送多行欢迎信息的文本协议，以及需要先发请求的 HTTP 和 RDP
+enum GreetingProtocol {
+    Ftp,
+    Smtp,
+    Pop3,
+    Http,
+    /// 发送 X.224 连接请求，读取连接确认
+    Rdp,
+}
+
+/// RDP 连接请求：TPKT 头、X.224 Connection Request 和 RDP_NEG_REQ（请求 TLS 和 CredSSP）
+const RDP_NEGOTIATION_REQUEST: [u8; 19] = [
+    0x03, 0x00, 0x00, 0x13, 0x0e, 0xe0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08, 0x00, 0x03, 0x00, 0x00, 0x00,
+];
+
+fn greeting_protocol(port: u16) -> Option<GreetingProtocol> {
+    match port {
+        21 => Some(GreetingProtocol::Ftp),
+        25 | 587 => Some(GreetingProtocol::Smtp),
+        110 => Some(GreetingProtocol::Pop3),
+        3389 => Some(GreetingProtocol::Rdp),
+        port if http::is_http_port(port) => Some(GreetingProtocol::Http),
+        _ => None,
+    }
+}
+
+/// 默认的提前结束阈值：匹配到权重不低于该值的指纹即视为确定，不再尝试更昂贵的探测
+pub const DEFAULT_PROBE_THRESHOLD: f32 = 0.9;
+
+/// 识别未知端口时使用的探测方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// 连接后等待服务主动发送欢迎信息（SSH、FTP、SMTP 等）
+    Passive,
+    /// 发送 HTTP `GET /` 请求
+    Http,
+}
+
+/// 所有探测方式，`identify_unknown` 会按 `rarity` 从低到高依次尝试
+const PROBES: [Probe; 2] = [Probe::Http, Probe::Passive];
+
+impl Probe {
+    /// 探测开销：数值越小越便宜、命中率越高，越先尝试
+    pub fn rarity(&self) -> u8 {
+        match self {
+            Probe::Passive => 1,
+            Probe::Http => 2,
+        }
+    }
+
+    /// 建立新连接执行探测，返回 `read_timeout` 内读到的数据
+    pub(crate) async fn run(
+        &self,
+        connector: &dyn Connector,
+        addr: SocketAddr,
+        timeout_duration: Duration,
+        read_timeout: Duration,
+        http_headers: &[HttpHeader],
+    ) -> Option<Vec<u8>> {
+        let mut stream = timeout(timeout_duration, connector.connect(addr)).await.ok()?.ok()?;
+        let mut banner = Vec::new();
+        let deadline = Instant::now() + read_timeout;
+        match self {
+            Probe::Passive => {
+                read_until(&mut stream, &mut banner, 0, MAX_BANNER_LEN, deadline, |_| true).await;
+            }
+            Probe::Http => {
+                stream.write_all(http::build_request(addr, http_headers).as_bytes()).await.ok()?;
+                read_until(&mut stream, &mut banner, 0, MAX_HTTP_LEN, deadline, http::has_title_end).await;
+            }
+        }
+        Some(banner)
+    }
+}
+
+/// 识别没有专属指纹的端口：按开销从低到高依次探测，每次的响应交给 `classifier` 解释
+///
+/// 识别结果的置信度不低于 `threshold` 时立即返回，否则尝试完所有探测后返回置信度最高的结果；
+/// 某次探测连接失败时不再尝试后面的探测，返回已有的结果。每次探测连接超时为 `timeout_duration`，
+/// 读取响应最多等待 `read_timeout`；HTTP 探测附带 `http_headers`。
+pub async fn identify_unknown(
+    classifier: &dyn ServiceClassifier,
+    connector: &dyn Connector,
+    addr: SocketAddr,
+    timeout_duration: Duration,
+    read_timeout: Duration,
+    threshold: f32,
+    http_headers: &[HttpHeader],
+) -> Option<DetectedService> {
+    let mut probes = PROBES;
+    probes.sort_by_key(Probe::rarity);
+
+    let mut best: Option<DetectedService> = None;
+    for probe in probes {
+        // 连接失败说明端口已不可用（或限制了连接数），后面的探测也不必再试，保留已有的结果
+        let Some(banner) = probe.run(connector, addr, timeout_duration, read_timeout, http_headers).await else {
+            break;
+        };
+        let Some(service) = classifier.classify(addr.port(), &banner) else {
+            continue;
+        };
+        let confidence = service.confidence;
+        if best.as_ref().is_none_or(|b| confidence > b.confidence) {
+            best = Some(service);
+        }
+        if confidence >= threshold {
+            break;
+        }
+    }
+    best
+}
+
+/// 无法编译的指纹正则
+#[derive(Debug, Clone)]
+pub struct PatternError {
+    pub fingerprint: String,
+    pub pattern: String,
+    pub error: String,
+}
+
+impl std::fmt::Display for PatternError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "指纹 {} 的正则 {} 无效: {}", self.fingerprint, self.pattern, self.error)
+    }
+}
+
+/// 指纹库覆盖情况统计
+#[derive(Debug, Default)]
+pub struct FingerprintStats {
+    pub total: usize,
+    /// 每个端口的指纹数量
+    pub per_port: BTreeMap<u16, usize>,
+    /// 有常见服务名但没有任何指纹的端口
+    pub uncovered_ports: Vec<u16>,
+    pub with_version: usize,
+    pub without_version: usize,
+    pub invalid_patterns: Vec<PatternError>,
+}
+
+impl FingerprintStats {
+    pub fn print_console(&self) {
+        println!("{} 指纹库统计:", "[*]".blue());
+        println!(
+            "指纹总数: {}（含版本提取 {}，不含 {}）",
+            self.total, self.with_version, self.without_version
+        );
+
+        println!("\n各端口指纹数量:");
+        for (port, count) in &self.per_port {
+            match guess_service(*port) {
+                Some(service) => println!("  - {} ({}): {}", port, service, count),
+                None => println!("  - {}: {}", port, count),
+            }
+        }
+
+        if !self.uncovered_ports.is_empty() {
+            println!("\n{} 以下常见端口没有指纹:", "[!]".yellow());
+            for port in &self.uncovered_ports {
+                println!("  - {} ({})", port, guess_service(*port).unwrap_or("unknown"));
+            }
+        }
+
+        if !self.invalid_patterns.is_empty() {
+            println!("\n{} 无法编译的正则:", "[-]".red());
+            for err in &self.invalid_patterns {
+                println!("  - {}: {}\n    {}", err.fingerprint, err.pattern, err.error);
+            }
+        }
+    }
+}
+
+/// `--list-fingerprints` 的输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListFormat {
+    /// 每条指纹一行，匹配规则缩进列在下方
+    #[default]
+    Table,
+    /// 与 fingerprints.json 相同的格式，可直接作为自定义指纹文件
+    Json,
+}
+
+impl std::str::FromStr for ListFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" => Ok(ListFormat::Table),
+            "json" => Ok(ListFormat::Json),
+            _ => Err(anyhow!("无效的输出格式: {}（可选 table/json）", s)),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,51 +249,125 @@ pub struct FingerprintConfig {
     pub fingerprints: Vec<ServiceFingerprint>,
 }
 
+/// 编译进程序的默认指纹库，与仓库中的 `fingerprints.json` 相同
+const EMBEDDED_FINGERPRINTS: &str = include_str!("../fingerprints.json");
+
+/// 指定指纹文件路径的环境变量
+pub const FINGERPRINTS_ENV: &str = "RUSTSCAN_FINGERPRINTS";
+
+/// 指纹库的来源
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FingerprintSource {
+    /// 内置指纹叠加该文件中的指纹
+    File(PathBuf),
+    /// 没有找到指纹文件，只有内置指纹
+    Builtin,
+}
+
+impl fmt::Display for FingerprintSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FingerprintSource::File(path) => write!(f, "内置指纹 + {}", path.display()),
+            FingerprintSource::Builtin => write!(f, "内置指纹"),
+        }
+    }
+}
+
+/// 按顺序查找指纹文件：`$RUSTSCAN_FINGERPRINTS`、`$XDG_CONFIG_HOME/rustscan/fingerprints.json`
+/// （未设置时为 `~/.config/rustscan/fingerprints.json`）、当前目录的 `fingerprints.json`
+///
+/// `env` 读取环境变量。环境变量指定的文件不存在时报错，其余位置不存在时跳过；都没有时返回 `None`。
+fn locate_fingerprints(env: impl Fn(&str) -> Option<OsString>) -> Result<Option<PathBuf>> {
+    let env = |name: &str| env(name).filter(|value| !value.is_empty());
+    if let Some(path) = env(FINGERPRINTS_ENV) {
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            return Err(anyhow!("{} 指定的指纹文件不存在: {}", FINGERPRINTS_ENV, path.display()));
+        }
+        return Ok(Some(path));
+    }
+    let config_dir = env("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let candidates = config_dir
+        .map(|dir| dir.join("rustscan").join("fingerprints.json"))
+        .into_iter()
+        .chain([PathBuf::from("fingerprints.json")]);
+    Ok(candidates.into_iter().find(|path| path.is_file()))
+}
+
 #[derive(Clone)]
 pub struct ServiceFingerprintDB {
     fingerprints: HashMap<u16, Vec<ServiceFingerprint>>,
     compiled_patterns: HashMap<String, Regex>,
+    /// 二进制指纹的正则，与文本正则分开编译
+    compiled_bytes: HashMap<String, regex::bytes::Regex>,
+    /// 加载时编译失败的正则，对应的匹配条件不会生效
+    pattern_errors: Vec<PatternError>,
+    source: FingerprintSource,
 }
 
 impl ServiceFingerprintDB {
+    /// 内置指纹叠加按查找顺序找到的指纹文件，文件无法解析时只使用内置指纹
     pub fn new() -> Self {
-        let mut db = Self {
+        Self::discover(|name| std::env::var_os(name)).unwrap_or_else(|_| Self::builtin())
+    }
+
+    fn empty(source: FingerprintSource) -> Self {
+        Self {
             fingerprints: HashMap::new(),
             compiled_patterns: HashMap::new(),
-        };
-        
-        // 尝试从配置文件加载指纹
-        if let Ok(config) = db.load_config("fingerprints.json") {
-            db.initialize_from_config(config);
-        } else {
-            // 如果配置文件不存在，使用默认指纹
-            db.initialize_default_fingerprints();
+            compiled_bytes: HashMap::new(),
+            pattern_errors: Vec::new(),
+            source,
         }
-        
-        // 预编译所有正则表达式
-        for fps in db.fingerprints.values() {
-            for fp in fps {
-                if let Some(pattern) = &fp.banner_pattern {
-                    if !db.compiled_patterns.contains_key(pattern) {
-                        if let Ok(re) = Regex::new(pattern) {
-                            db.compiled_patterns.insert(pattern.clone(), re);
-                        }
-                    }
-                }
-                if let Some(pattern) = &fp.response_pattern {
-                    if !db.compiled_patterns.contains_key(pattern) {
-                        if let Ok(re) = Regex::new(pattern) {
-                            db.compiled_patterns.insert(pattern.clone(), re);
-                        }
-                    }
-                }
-            }
-        }
-        
+    }
+
+    /// 只有内置指纹
+    pub fn builtin() -> Self {
+        let config: FingerprintConfig = serde_json::from_str(EMBEDDED_FINGERPRINTS).expect("内置指纹库格式错误");
+        let mut db = Self::empty(FingerprintSource::Builtin);
+        db.initialize_from_config(config);
         db
     }
 
-    fn load_config<P: AsRef<Path>>(&self, path: P) -> Result<FingerprintConfig> {
+    /// 内置指纹叠加查找到的第一个指纹文件：文件中与内置指纹同端口同名的替换内置的，其余追加；
+    /// 找到的文件无法解析时报错
+    fn discover(env: impl Fn(&str) -> Option<OsString>) -> Result<Self> {
+        let mut db = Self::builtin();
+        let Some(path) = locate_fingerprints(env)? else {
+            return Ok(db);
+        };
+        let config = Self::load_config(&path).map_err(|e| anyhow!("无法加载指纹文件 {}: {}", path.display(), e))?;
+        db.initialize_from_config(config);
+        db.source = FingerprintSource::File(path);
+        Ok(db)
+    }
+
+    /// 加载指纹库；`strict` 为真时任何正则编译失败都视为错误
+    ///
+    /// 与 `new` 不同，`$RUSTSCAN_FINGERPRINTS` 指向的文件不存在或找到的文件无法解析时报错，
+    /// 而不是悄悄改用内置默认指纹。
+    pub fn load(strict: bool) -> Result<Self> {
+        let db = Self::discover(|name| std::env::var_os(name))?;
+        if strict && !db.pattern_errors.is_empty() {
+            let details: Vec<String> = db.pattern_errors.iter().map(|e| e.to_string()).collect();
+            return Err(anyhow::anyhow!("指纹库包含无效正则:\n{}", details.join("\n")));
+        }
+        Ok(db)
+    }
+
+    /// 加载时编译失败的正则
+    pub fn pattern_errors(&self) -> &[PatternError] {
+        &self.pattern_errors
+    }
+
+    /// 指纹来自哪个文件，或内置默认指纹
+    pub fn source(&self) -> &FingerprintSource {
+        &self.source
+    }
+
+    fn load_config<P: AsRef<Path>>(path: P) -> Result<FingerprintConfig> {
         let content = fs::read_to_string(path)?;
         let config: FingerprintConfig = serde_json::from_str(&content)?;
         Ok(config)
@@ -84,114 +379,330 @@ impl ServiceFingerprintDB {
         }
     }
 
+    /// 添加指纹，已有同端口同名的指纹时替换
     fn add_fingerprint(&mut self, fingerprint: ServiceFingerprint) {
         let port = fingerprint.port;
-        let entry = self.fingerprints.entry(port).or_insert_with(Vec::new);
+        let entry = self.fingerprints.entry(port).or_default();
+        entry.retain(|existing| existing.name != fingerprint.name);
         
-        // 预编译正则表达式
-        if let Some(pattern) = &fingerprint.banner_pattern {
-            if let Ok(re) = Regex::new(pattern) {
-                self.compiled_patterns.insert(pattern.clone(), re);
-            }
-        }
-        if let Some(pattern) = &fingerprint.response_pattern {
-            if let Ok(re) = Regex::new(pattern) {
-                self.compiled_patterns.insert(pattern.clone(), re);
+        // 预编译正则表达式，失败的记录下来供调用方报告
+        let patterns = [
+            &fingerprint.banner_pattern,
+            &fingerprint.response_pattern,
+            &fingerprint.version_pattern,
+        ];
+        for pattern in patterns.into_iter().flatten() {
+            let compiled = if fingerprint.binary {
+                if self.compiled_bytes.contains_key(pattern) {
+                    continue;
+                }
+                regex::bytes::RegexBuilder::new(pattern)
+                    .unicode(false)
+                    .build()
+                    .map(|re| {
+                        self.compiled_bytes.insert(pattern.clone(), re);
+                    })
+            } else {
+                if self.compiled_patterns.contains_key(pattern) {
+                    continue;
+                }
+                Regex::new(pattern).map(|re| {
+                    self.compiled_patterns.insert(pattern.clone(), re);
+                })
+            };
+            match compiled {
+                Ok(()) => {}
+                Err(e) => self.pattern_errors.push(PatternError {
+                    fingerprint: fingerprint.name.clone(),
+                    pattern: pattern.clone(),
+                    error: e.to_string(),
+                }),
             }
         }
         
         entry.push(fingerprint);
     }
 
-    fn initialize_default_fingerprints(&mut self) {
-        // Web 服务
-        self.add_fingerprint(ServiceFingerprint {
-            name: "HTTP".to_string(),
-            protocol: "TCP".to_string(),
-            port: 80,
-            banner_pattern: Some(r"HTTP/\d\.\d".to_string()),
-            response_pattern: Some(r"Server: (.*)".to_string()),
-            weight: 1.0,
-            description: Some("Hypertext Transfer Protocol".to_string()),
-            version_pattern: Some(r"HTTP/(\d\.\d)".to_string()),
-            vendor: None,
-            cpe: Some("cpe:/a:http:http_server".to_string()),
-        });
-
-        // 数据库服务
-        self.add_fingerprint(ServiceFingerprint {
-            name: "MySQL".to_string(),
-            protocol: "TCP".to_string(),
-            port: 3306,
-            banner_pattern: Some(r"mysql_native_password".to_string()),
-            response_pattern: Some(r"(\d+\.\d+\.\d+)-MySQL".to_string()),
-            weight: 0.9,
-            description: Some("MySQL Database Server".to_string()),
-            version_pattern: Some(r"(\d+\.\d+\.\d+)-MySQL".to_string()),
-            vendor: Some("Oracle".to_string()),
-            cpe: Some("cpe:/a:mysql:mysql".to_string()),
-        });
-
-        // 远程管理服务
-        self.add_fingerprint(ServiceFingerprint {
-            name: "SSH".to_string(),
-            protocol: "TCP".to_string(),
-            port: 22,
-            banner_pattern: Some(r"SSH-\d\.\d".to_string()),
-            response_pattern: None,
-            weight: 0.95,
-            description: Some("Secure Shell".to_string()),
-            version_pattern: Some(r"SSH-(\d\.\d)".to_string()),
-            vendor: None,
-            cpe: Some("cpe:/a:openssh:openssh".to_string()),
-        });
-    }
-
     pub async fn identify_service(
         &self,
         target: &str,
         port: u16,
         timeout_duration: Duration,
     ) -> Result<Option<ServiceFingerprint>> {
-        if let Some(fingerprints) = self.fingerprints.get(&port) {
-            let addr = format!("{}:{}", target, port);
-            if let Ok(stream) = timeout(timeout_duration, TcpStream::connect(&addr)).await {
-                if let Ok(mut stream) = stream {
-                    let mut buffer = [0u8; 1024];
-                    if let Ok(len) = stream.read(&mut buffer).await {
-                        let response = String::from_utf8_lossy(&buffer[..len]);
-                        
-                        for fingerprint in fingerprints {
-                            // 使用预编译的正则表达式
-                            if let Some(pattern) = &fingerprint.banner_pattern {
-                                if let Some(re) = self.compiled_patterns.get(pattern) {
-                                    if re.is_match(&response) {
-                                        return Ok(Some(fingerprint.clone()));
-                                    }
-                                }
-                            }
-                            
-                            if let Some(pattern) = &fingerprint.response_pattern {
-                                if let Some(re) = self.compiled_patterns.get(pattern) {
-                                    if re.is_match(&response) {
-                                        return Ok(Some(fingerprint.clone()));
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        let target: ScanTarget = target.parse()?;
+        Ok(self
+            .identify(target.socket_addr(port), timeout_duration)
+            .await?
+            .map(|m| m.fingerprint))
+    }
+
+    /// 连接目标端口读取 banner 并匹配指纹，同时提取版本号
+    pub async fn identify(
+        &self,
+        addr: SocketAddr,
+        timeout_duration: Duration,
+    ) -> Result<Option<ServiceMatch>> {
+        Ok(self
+            .grab_banner(&TcpConnector, addr, timeout_duration, timeout_duration, &[])
+            .await
+            .and_then(|banner| self.classify(addr.port(), &banner)))
+    }
+
+    /// 端口是否有专属指纹
+    pub fn has_fingerprints(&self, port: u16) -> bool {
+        self.fingerprints.contains_key(&port)
+    }
+
+    /// 连接目标端口读取 banner；端口没有任何指纹时不连接
+    pub async fn grab_banner(
+        &self,
+        connector: &dyn Connector,
+        addr: SocketAddr,
+        timeout_duration: Duration,
+        read_timeout: Duration,
+        http_headers: &[HttpHeader],
+    ) -> Option<Vec<u8>> {
+        if !self.fingerprints.contains_key(&addr.port()) {
+            return None;
+        }
+        let mut stream = timeout(timeout_duration, connector.connect(addr)).await.ok()?.ok()?;
+        Some(read_banner(&mut stream, addr, read_timeout, http_headers).await)
+    }
+
+    /// 对已读取的 banner 匹配指纹，Web 端口额外解析标题和重定向
+    ///
+    /// 端口没有专属指纹时（如 [`identify_unknown`] 的各次探测）匹配全部指纹，
+    /// 是否为 HTTP 应答按内容判断。
+    pub fn classify(&self, port: u16, banner: &[u8]) -> Option<ServiceMatch> {
+        let (mut matched, is_http) = match self.fingerprints.get(&port) {
+            Some(fingerprints) => (self.best_match(fingerprints, banner)?, http::is_http_port(port)),
+            None => (
+                self.best_match(self.fingerprints.values().flatten(), banner)?,
+                http::parse_response(banner).is_some(),
+            ),
+        };
+        matched.protocol_version = protocol_version(banner);
+        if is_http {
+            matched.http = http::parse_response(banner);
+            matched.method = ServiceMethod::Probe;
+        }
+        Some(matched)
+    }
+
+    /// 在端口对应的指纹中选出权重最高的匹配项
+    pub fn match_banner(&self, port: u16, banner: &[u8]) -> Option<ServiceMatch> {
+        self.best_match(self.fingerprints.get(&port)?, banner)
+    }
+
+    /// 文本指纹匹配 UTF-8 解码后的字符串（无效字节替换为 U+FFFD），二进制指纹匹配原始字节
+    fn best_match<'a>(
+        &self,
+        fingerprints: impl IntoIterator<Item = &'a ServiceFingerprint>,
+        banner: &[u8],
+    ) -> Option<ServiceMatch> {
+        let response = String::from_utf8_lossy(banner);
+        let mut best: Option<&ServiceFingerprint> = None;
+
+        for fingerprint in fingerprints {
+            // 使用预编译的正则表达式
+            let matched = [&fingerprint.banner_pattern, &fingerprint.response_pattern]
+                .into_iter()
+                .flatten()
+                .any(|pattern| match fingerprint.binary {
+                    true => self.compiled_bytes.get(pattern).is_some_and(|re| re.is_match(banner)),
+                    false => self.compiled_patterns.get(pattern).is_some_and(|re| re.is_match(&response)),
+                });
+            if matched && best.is_none_or(|b| fingerprint.weight > b.weight) {
+                best = Some(fingerprint);
             }
         }
-        Ok(None)
+
+        best.map(|fingerprint| ServiceMatch {
+            version: self.extract_version(fingerprint, banner, &response),
+            fingerprint: fingerprint.clone(),
+            http: None,
+            method: ServiceMethod::Banner,
+            protocol_version: None,
+        })
+    }
+
+    fn extract_version(&self, fingerprint: &ServiceFingerprint, banner: &[u8], response: &str) -> Option<String> {
+        let pattern = fingerprint.version_pattern.as_ref()?;
+        if fingerprint.binary {
+            let caps = self.compiled_bytes.get(pattern)?.captures(banner)?;
+            return caps.get(1).map(|m| String::from_utf8_lossy(m.as_bytes()).trim().to_string());
+        }
+        let caps = self.compiled_patterns.get(pattern)?.captures(response)?;
+        caps.get(1).map(|m| m.as_str().trim().to_string())
     }
 
     pub fn get_fingerprints_by_port(&self, port: u16) -> Option<&Vec<ServiceFingerprint>> {
         self.fingerprints.get(&port)
     }
 
+    /// 全部指纹，按端口排序，同一端口内保持加载顺序
     pub fn get_all_fingerprints(&self) -> Vec<&ServiceFingerprint> {
-        self.fingerprints.values().flatten().collect()
+        let mut fingerprints: Vec<&ServiceFingerprint> = self.fingerprints.values().flatten().collect();
+        fingerprints.sort_by_key(|fingerprint| fingerprint.port);
+        fingerprints
+    }
+
+    /// 导出为 fingerprints.json 的格式
+    pub fn to_config(&self) -> FingerprintConfig {
+        FingerprintConfig {
+            fingerprints: self.get_all_fingerprints().into_iter().cloned().collect(),
+        }
+    }
+
+    /// 列出全部指纹：端口、名称、协议、权重和 CPE，匹配规则缩进列在下方
+    pub fn print_list(&self) {
+        let fingerprints = self.get_all_fingerprints();
+        println!("{} 指纹库: {}（共 {} 条）", "[*]".blue(), self.source, fingerprints.len());
+        println!("{:<6} {:<24} {:<8} {:<6} CPE", "PORT", "NAME", "PROTO", "WEIGHT");
+        for fingerprint in fingerprints {
+            println!(
+                "{:<6} {:<24} {:<8} {:<6.2} {}",
+                fingerprint.port,
+                fingerprint.name,
+                fingerprint.protocol,
+                fingerprint.weight,
+                fingerprint.cpe.as_deref().unwrap_or("-")
+            );
+            let patterns = [
+                ("banner", &fingerprint.banner_pattern),
+                ("response", &fingerprint.response_pattern),
+                ("version", &fingerprint.version_pattern),
+            ];
+            let mode = if fingerprint.binary { " (bytes)" } else { "" };
+            for (kind, pattern) in patterns {
+                if let Some(pattern) = pattern {
+                    println!("{:>15}: {}{}", kind, pattern, mode.dimmed());
+                }
+            }
+        }
+    }
+
+    /// 统计指纹库覆盖的端口、版本提取情况和无效正则
+    pub fn stats(&self) -> FingerprintStats {
+        let mut stats = FingerprintStats::default();
+        for fingerprint in self.get_all_fingerprints() {
+            stats.total += 1;
+            *stats.per_port.entry(fingerprint.port).or_default() += 1;
+            if fingerprint.version_pattern.is_some() {
+                stats.with_version += 1;
+            } else {
+                stats.without_version += 1;
+            }
+        }
+        stats.invalid_patterns = self.pattern_errors.clone();
+        stats.uncovered_ports = (1..=u16::MAX)
+            .filter(|port| guess_service(*port).is_some() && !self.fingerprints.contains_key(port))
+            .collect();
+        stats
+    }
+}
+
+/// 按协议读取服务 banner
+///
+/// FTP/SMTP 的欢迎信息可能是多行（`220-...`），POP3 为单行，一次 `read` 常常只拿到一部分，
+/// 因此读到协议的结束标记为止；SMTP 额外发送 `EHLO` 并读取到最后一行 `250 `。
+/// Web 端口先发送 `GET /`（附带 `http_headers`），读取响应头和正文开头（最多 8KB，读到 `</title>` 为止）。
+/// 其他端口保持单次读取。
+///
+/// 整个读取过程（包括 SMTP 的 `EHLO` 往返）最多持续 `read_timeout`，
+/// 逐字节慢速发送的服务无法借分段到达拖住识别流程。
+pub async fn read_banner<S>(stream: &mut S, addr: SocketAddr, read_timeout: Duration, http_headers: &[HttpHeader]) -> Vec<u8>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut banner = Vec::new();
+    let deadline = Instant::now() + read_timeout;
+    match greeting_protocol(addr.port()) {
+        None => {
+            read_until(stream, &mut banner, 0, MAX_BANNER_LEN, deadline, |_| true).await;
+        }
+        Some(GreetingProtocol::Ftp) => {
+            read_until(stream, &mut banner, 0, MAX_BANNER_LEN, deadline, has_final_reply).await;
+        }
+        Some(GreetingProtocol::Pop3) => {
+            read_until(stream, &mut banner, 0, MAX_BANNER_LEN, deadline, |data| data.contains(&b'\n')).await;
+        }
+        Some(GreetingProtocol::Smtp) => {
+            read_until(stream, &mut banner, 0, MAX_BANNER_LEN, deadline, has_final_reply).await;
+            if banner.starts_with(b"220") && stream.write_all(b"EHLO rustscan\r\n").await.is_ok() {
+                let start = banner.len();
+                read_until(stream, &mut banner, start, MAX_BANNER_LEN, deadline, has_final_reply).await;
+            }
+        }
+        Some(GreetingProtocol::Http) => {
+            if stream.write_all(http::build_request(addr, http_headers).as_bytes()).await.is_ok() {
+                read_until(stream, &mut banner, 0, MAX_HTTP_LEN, deadline, http::has_title_end).await;
+            }
+        }
+        Some(GreetingProtocol::Rdp) => {
+            if stream.write_all(&RDP_NEGOTIATION_REQUEST).await.is_ok() {
+                // TPKT 头中的长度包含头部本身
+                let complete = |data: &[u8]| data.len() >= 4 && data.len() >= usize::from(u16::from_be_bytes([data[2], data[3]]));
+                read_until(stream, &mut banner, 0, MAX_BANNER_LEN, deadline, complete).await;
+            }
+        }
+    }
+    banner
+}
+
+/// 持续读取直到 `done(&buf[start..])` 成立、连接关闭、到达 `deadline` 或达到长度上限
+pub(crate) async fn read_until<S, F>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+    start: usize,
+    max_len: usize,
+    deadline: Instant,
+    done: F,
+) where
+    S: AsyncRead + Unpin,
+    F: Fn(&[u8]) -> bool,
+{
+    let mut chunk = [0u8; 1024];
+    while buf.len() < max_len {
+        match timeout_at(deadline, stream.read(&mut chunk)).await {
+            Ok(Ok(len)) if len > 0 => {
+                let len = len.min(max_len - buf.len());
+                buf.extend_from_slice(&chunk[..len]);
+                if done(&buf[start..]) {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+}
+
+/// 从应答开头识别协议版本：SSH 标识串（`SSH-2.0`）、HTTP 状态行（`HTTP/1.1`）
+/// 或 TLS ServerHello（`TLS 1.3`），与产品版本无关
+pub fn protocol_version(banner: &[u8]) -> Option<String> {
+    if banner.starts_with(b"SSH-") {
+        // SSH-protoversion-softwareversion
+        let end = banner[4..].iter().position(|&b| b == b'-')? + 4;
+        return Some(String::from_utf8_lossy(&banner[..end]).into_owned()).filter(|v| v.len() > 4);
+    }
+    if banner.starts_with(b"HTTP/") {
+        let end = banner.iter().position(|&b| b == b' ')?;
+        return Some(String::from_utf8_lossy(&banner[..end]).into_owned()).filter(|v| v.len() > 5);
+    }
+    tls::parse_server_hello(banner).map(|hello| tls::tls_version_name(hello.version()).to_string())
+}
+
+/// 多行应答以 `xyz ` 开头的完整行结束，`xyz-` 表示后面还有内容
+pub(crate) fn has_final_reply(data: &[u8]) -> bool {
+    data.split(|&b| b == b'\n')
+        .rev()
+        .skip(1) // 最后一段是未以换行结尾的残行
+        .any(|line| line.len() >= 4 && line[..3].iter().all(u8::is_ascii_digit) && line[3] == b' ')
+}
+
+impl Default for ServiceFingerprintDB {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -205,4 +716,261 @@ mod tests {
         let result = db.identify_service("127.0.0.1", 80, Duration::from_secs(1)).await;
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_read_multiline_greeting() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            // 欢迎信息拆成两次写入，模拟被截断的多行应答
+            server.write_all(b"220-mail.example.com ESMTP Exim 4.96 ready\r\n").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            server.write_all(b"220 no UCE\r\n").await.unwrap();
+            let mut ehlo = [0u8; 64];
+            let _ = server.read(&mut ehlo).await.unwrap();
+            server.write_all(b"250-mail.example.com Hello\r\n250 HELP\r\n").await.unwrap();
+        });
+
+        let addr = "192.0.2.1:25".parse().unwrap();
+        let banner = read_banner(&mut client, addr, Duration::from_secs(1), &[]).await;
+        let banner = String::from_utf8_lossy(&banner);
+        assert!(banner.contains("220 no UCE"));
+        assert!(banner.ends_with("250 HELP\r\n"));
+
+        let db = ServiceFingerprintDB::new();
+        let matched = db.match_banner(25, banner.as_bytes()).unwrap();
+        assert_eq!(matched.fingerprint.name, "Exim");
+        assert_eq!(matched.version.as_deref(), Some("4.96"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_order_and_early_exit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use crate::connector::{BoxStream, MockConnector, MockService};
+
+        let db = ServiceFingerprintDB::new();
+        let timeout_duration = Duration::from_millis(300);
+
+        // 主动发送 SSH banner：被动探测即命中高权重指纹，只连接一次
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let _ = stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").await;
+            }
+        });
+        let matched = identify_unknown(&db, &TcpConnector, addr, timeout_duration, timeout_duration, DEFAULT_PROBE_THRESHOLD, &[]).await.unwrap();
+        assert_eq!(matched.name, "SSH");
+        assert_eq!(matched.method, ServiceMethod::Banner);
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+
+        // 只响应请求的 Web 服务：被动探测读不到数据，再尝试 HTTP 探测
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    if let Ok(len) = stream.read(&mut request).await {
+                        if len > 0 {
+                            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n<title>Panel</title>").await;
+                        }
+                    }
+                });
+            }
+        });
+        let matched = identify_unknown(&db, &TcpConnector, addr, timeout_duration, timeout_duration, DEFAULT_PROBE_THRESHOLD, &[]).await.unwrap();
+        assert_eq!(matched.name, "HTTP");
+        assert_eq!(matched.method, ServiceMethod::Probe);
+        assert_eq!(matched.title.as_deref(), Some("Panel"));
+
+        // 只接受一个连接的服务（如 sshd 的 MaxStartups）：HTTP 探测连接失败时保留被动探测的匹配
+        struct OneShot(MockConnector, AtomicUsize);
+        impl Connector for OneShot {
+            fn connect(&self, addr: SocketAddr) -> futures::future::BoxFuture<'_, std::io::Result<BoxStream>> {
+                match self.1.fetch_add(1, Ordering::Relaxed) {
+                    0 => self.0.connect(addr),
+                    _ => Box::pin(async { Err(std::io::ErrorKind::ConnectionRefused.into()) }),
+                }
+            }
+        }
+        let connector = OneShot(
+            MockConnector::new().service(2222, MockService::Banner(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec())),
+            AtomicUsize::new(0),
+        );
+        let addr = SocketAddr::from(([192, 0, 2, 1], 2222));
+        // 阈值高于任何指纹的权重，被动探测命中后仍会继续尝试 HTTP 探测
+        let matched = identify_unknown(&db, &connector, addr, timeout_duration, timeout_duration, 2.0, &[]).await.unwrap();
+        assert_eq!(matched.name, "SSH");
+        assert_eq!(connector.1.load(Ordering::Relaxed), 2);
+    }
+
+    fn fingerprint(name: &str, port: u16, banner_pattern: &str, version_pattern: Option<&str>) -> ServiceFingerprint {
+        ServiceFingerprint {
+            name: name.to_string(),
+            port,
+            protocol: "TCP".to_string(),
+            banner_pattern: Some(banner_pattern.to_string()),
+            response_pattern: None,
+            weight: 1.0,
+            description: None,
+            version_pattern: version_pattern.map(str::to_string),
+            vendor: None,
+            cpe: None,
+            binary: false,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut db = ServiceFingerprintDB::empty(FingerprintSource::Builtin);
+        db.add_fingerprint(fingerprint("HTTP", 80, r"HTTP/\d\.\d", Some(r"HTTP/(\d\.\d)")));
+        db.add_fingerprint(fingerprint("MySQL", 3306, "mysql_native_password", None));
+        db.add_fingerprint(fingerprint("SSH", 22, r"SSH-\d\.\d", Some(r"SSH-(\d\.\d)")));
+        db.add_fingerprint(fingerprint("Broken", 22, "SSH-(", None));
+
+        let stats = db.stats();
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.per_port[&22], 2);
+        assert_eq!(stats.with_version + stats.without_version, stats.total);
+        assert!(stats.uncovered_ports.contains(&5432));
+        assert!(!stats.uncovered_ports.contains(&80));
+        assert_eq!(stats.invalid_patterns.len(), 1);
+        assert_eq!(stats.invalid_patterns[0].fingerprint, "Broken");
+        assert_eq!(db.pattern_errors()[0].pattern, "SSH-(");
+        assert!(db.compiled_patterns.contains_key(r"SSH-\d\.\d"));
+
+        // 导出的列表按端口排序，且能作为指纹文件重新读取
+        let ports: Vec<u16> = db.get_all_fingerprints().iter().map(|f| f.port).collect();
+        assert_eq!(ports, [22, 22, 80, 3306]);
+        let json = serde_json::to_string(&db.to_config()).unwrap();
+        let config: FingerprintConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.fingerprints.len(), 4);
+        assert_eq!(config.fingerprints[2].banner_pattern.as_deref(), Some(r"HTTP/\d\.\d"));
+        assert_eq!("JSON".parse::<ListFormat>().unwrap(), ListFormat::Json);
+        assert!("xml".parse::<ListFormat>().is_err());
+    }
+
+    #[test]
+    fn test_protocol_version() {
+        assert_eq!(protocol_version(b"SSH-2.0-OpenSSH_9.6\r\n").as_deref(), Some("SSH-2.0"));
+        assert_eq!(protocol_version(b"SSH-1.99-Cisco-1.25\r\n").as_deref(), Some("SSH-1.99"));
+        assert_eq!(protocol_version(b"HTTP/1.0 200 OK\r\n\r\n").as_deref(), Some("HTTP/1.0"));
+        assert_eq!(protocol_version(b"220 mail ESMTP Postfix\r\n"), None);
+        assert_eq!(protocol_version(b"SSH-"), None);
+
+        // ServerHello：记录头、握手头、legacy_version 1.2、随机数、空会话 ID、密码套件、压缩方法
+        let server_hello = |extensions: &[u8]| {
+            let mut hello = vec![0x16, 0x03, 0x03, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x03];
+            hello.extend_from_slice(&[0u8; 32]);
+            hello.extend_from_slice(&[0x00, 0x13, 0x01, 0x00]);
+            hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+            hello.extend_from_slice(extensions);
+            hello
+        };
+        assert_eq!(protocol_version(&server_hello(&[0xff, 0x01, 0x00, 0x01, 0x00])).as_deref(), Some("TLS 1.2"));
+        let tls13 = server_hello(&[0x00, 0x33, 0x00, 0x02, 0x00, 0x1d, 0x00, 0x2b, 0x00, 0x02, 0x03, 0x04]);
+        assert_eq!(protocol_version(&tls13).as_deref(), Some("TLS 1.3"));
+        assert_eq!(protocol_version(&tls13[..20]), None);
+    }
+
+    #[tokio::test]
+    async fn test_binary_fingerprint() {
+        // X.224 连接确认 + RDP_NEG_RSP（选择 CredSSP），解码为文本后 0xd0 等字节会变成 U+FFFD
+        let rdp = [
+            0x03, 0x00, 0x00, 0x13, 0x0e, 0xd0, 0x00, 0x00, 0x12, 0x34, 0x00, 0x02, 0x1f, 0x08, 0x00, 0x02, 0x00, 0x00, 0x00,
+        ];
+        assert!(String::from_utf8_lossy(&rdp).contains('\u{fffd}'));
+        let db = ServiceFingerprintDB::builtin();
+        assert_eq!(db.classify(3389, &rdp).unwrap().fingerprint.name, "RDP");
+        assert!(db.classify(3389, b"HTTP/1.1 400 Bad Request\r\n").is_none());
+
+        // 同一正则按文本匹配时，\xd0 表示 U+00D0 而不是字节 0xd0
+        let mut text = fingerprint("RDP text", 3390, r"(?s)^\x03\x00..[\x06\x0e]\xd0", None);
+        let mut db = ServiceFingerprintDB::empty(FingerprintSource::Builtin);
+        db.add_fingerprint(text.clone());
+        assert!(db.classify(3390, &rdp).is_none());
+        text.port = 3391;
+        text.binary = true;
+        text.version_pattern = Some(r"(?s)^\x03\x00..\x0e\xd0\x00\x00(\x124)".to_string());
+        db.add_fingerprint(text);
+        assert_eq!(db.classify(3391, &rdp).unwrap().version.as_deref(), Some("\u{12}4"));
+        assert!(db.pattern_errors().is_empty());
+
+        // 3389 端口先发送 RDP 连接请求再读取应答
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0u8; 19];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, RDP_NEGOTIATION_REQUEST);
+            server.write_all(&rdp).await.unwrap();
+            let _ = server.read(&mut request).await;
+        });
+        let addr = "192.0.2.1:3389".parse().unwrap();
+        assert_eq!(read_banner(&mut client, addr, Duration::from_secs(1), &[]).await, rdp);
+    }
+
+    #[test]
+    fn test_embedded_fingerprints() {
+        let mut db = ServiceFingerprintDB::builtin();
+        let stats = db.stats();
+        assert!(stats.total >= 50, "内置指纹只有 {} 条", stats.total);
+        assert!(db.pattern_errors().is_empty(), "{:?}", db.pattern_errors());
+        assert!(stats.uncovered_ports.is_empty(), "{:?}", stats.uncovered_ports);
+        assert_eq!(db.classify(5900, b"RFB 003.008\n").unwrap().version.as_deref(), Some("003.008"));
+        assert_eq!(db.classify(22, b"SSH-2.0-dropbear_2022.83\r\n").unwrap().fingerprint.name, "Dropbear SSH");
+
+        // 用户文件中同端口同名的指纹替换内置的，其余追加
+        let ssh = db.get_fingerprints_by_port(22).unwrap().len();
+        db.add_fingerprint(fingerprint("SSH", 22, "SSH-2\\.0-Custom", None));
+        db.add_fingerprint(fingerprint("Custom", 4444, "CUSTOM", None));
+        let port_22 = db.get_fingerprints_by_port(22).unwrap();
+        assert_eq!(port_22.len(), ssh);
+        assert_eq!(port_22.iter().filter(|f| f.name == "SSH").count(), 1);
+        assert!(db.has_fingerprints(4444));
+        assert_eq!(db.stats().total, stats.total + 1);
+    }
+
+    #[test]
+    fn test_fingerprint_search_path() {
+        let dir = std::env::temp_dir().join(format!("rustscan-fingerprints-{}", std::process::id()));
+        let xdg = dir.join("xdg");
+        std::fs::create_dir_all(xdg.join("rustscan")).unwrap();
+        let home_config = dir.join("home").join(".config").join("rustscan");
+        std::fs::create_dir_all(&home_config).unwrap();
+        let custom = dir.join("custom.json");
+        let config = r#"{"fingerprints": [{"name": "Custom", "protocol": "TCP", "port": 4444,
+            "banner_pattern": "CUSTOM", "response_pattern": null, "weight": 1.0, "description": null,
+            "version_pattern": null, "vendor": null, "cpe": null}]}"#;
+        std::fs::write(&custom, config).unwrap();
+        std::fs::write(xdg.join("rustscan").join("fingerprints.json"), config).unwrap();
+        std::fs::write(home_config.join("fingerprints.json"), config).unwrap();
+        let env = |vars: Vec<(&'static str, PathBuf)>| {
+            move |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.clone().into_os_string())
+        };
+
+        // 环境变量优先于 XDG 配置目录，XDG 配置目录优先于 ~/.config
+        let both = env(vec![(FINGERPRINTS_ENV, custom.clone()), ("XDG_CONFIG_HOME", xdg.clone())]);
+        assert_eq!(locate_fingerprints(&both).unwrap(), Some(custom.clone()));
+        let db = ServiceFingerprintDB::discover(both).unwrap();
+        assert_eq!(db.source(), &FingerprintSource::File(custom.clone()));
+        assert!(db.has_fingerprints(4444));
+        assert_eq!(
+            locate_fingerprints(env(vec![("XDG_CONFIG_HOME", xdg.clone()), ("HOME", dir.join("home"))])).unwrap(),
+            Some(xdg.join("rustscan").join("fingerprints.json"))
+        );
+        // 没有 XDG_CONFIG_HOME 时使用 ~/.config（先于当前目录查找）
+        assert_eq!(
+            locate_fingerprints(env(vec![("HOME", dir.join("home"))])).unwrap(),
+            Some(home_config.join("fingerprints.json"))
+        );
+        assert!(locate_fingerprints(env(vec![(FINGERPRINTS_ENV, dir.join("missing.json"))])).is_err());
+
+        std::fs::write(&custom, "{").unwrap();
+        assert!(ServiceFingerprintDB::discover(env(vec![(FINGERPRINTS_ENV, custom)])).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
\ No newline at end of file
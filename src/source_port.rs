@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Result};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use socket2::SockRef;
+use tokio::net::{TcpSocket, TcpStream};
+
+/// 单次连接最多尝试的源端口数，范围被占满时放弃而不是无限轮询
+const MAX_BIND_ATTEMPTS: u32 = 16;
+
+/// 连接扫描使用的源端口范围，每次探测轮换到下一个端口
+///
+/// 用于绕过按源端口放行的有状态防火墙规则（例如只放行源端口 53 的流量）。
+#[derive(Debug)]
+pub struct SourcePorts {
+    start: u16,
+    len: u32,
+    next: AtomicU32,
+}
+
+impl SourcePorts {
+    pub fn new(start: u16, end: u16) -> Result<Self> {
+        if start == 0 || start > end {
+            return Err(anyhow!("无效的源端口范围: {}-{}", start, end));
+        }
+        Ok(Self {
+            start,
+            len: u32::from(end - start) + 1,
+            next: AtomicU32::new(0),
+        })
+    }
+
+    /// 范围内的端口数量
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 轮换取下一个源端口
+    pub fn next_port(&self) -> u16 {
+        let offset = self.next.fetch_add(1, Ordering::Relaxed) % self.len;
+        self.start + offset as u16
+    }
+
+    /// 从范围内的源端口发起 TCP 连接，`ttl` 为发出报文的 TTL
+    ///
+    /// 设置 SO_REUSEADDR，使 TIME_WAIT 中的端口可以立即复用；若端口仍被占用
+    /// （EADDRINUSE/EADDRNOTAVAIL），换下一个端口重试。
+    pub async fn connect(&self, addr: SocketAddr, ttl: Option<u8>) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for _ in 0..self.len.min(MAX_BIND_ATTEMPTS) {
+            let socket = tcp_socket(addr, ttl)?;
+            let local_ip = match addr {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+            socket.set_reuseaddr(true)?;
+            let result = match socket.bind(SocketAddr::new(local_ip, self.next_port())) {
+                Ok(()) => socket.connect(addr).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e) if is_port_busy(&e) => last_err = Some(e),
+                result => return result,
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::Error::from(io::ErrorKind::AddrInUse)))
+    }
+}
+
+/// 创建连接 `addr` 用的 TCP 套接字，指定 `ttl` 时在握手前设置，SYN 即带上该 TTL
+pub fn tcp_socket(addr: SocketAddr, ttl: Option<u8>) -> io::Result<TcpSocket> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(ttl) = ttl {
+        set_ttl(SockRef::from(&socket), addr.is_ipv6(), ttl)?;
+    }
+    Ok(socket)
+}
+
+/// 设置发出报文的 TTL（IPv6 为跳数限制）
+pub fn set_ttl(socket: SockRef<'_>, ipv6: bool, ttl: u8) -> io::Result<()> {
+    if ipv6 {
+        socket.set_unicast_hops_v6(u32::from(ttl))
+    } else {
+        socket.set_ttl(u32::from(ttl))
+    }
+}
+
+fn is_port_busy(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable
+    )
+}
+
+impl FromStr for SourcePorts {
+    type Err = anyhow::Error;
+
+    /// 解析 `起始-结束` 或单个端口
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map_err(|_| anyhow!("无效的源端口范围: {}", s))
+        };
+        Self::new(parse(start)?, parse(end)?)
+    }
+}
+
+/// 与 `--source-port-range` 相同的格式，单个端口不写范围
+impl fmt::Display for SourcePorts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = u32::from(self.start) + self.len - 1;
+        if self.len == 1 {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_ports() {
+        let ports: SourcePorts = "40000-40002".parse().unwrap();
+        assert_eq!(ports.len(), 3);
+        let rotated: Vec<u16> = (0..4).map(|_| ports.next_port()).collect();
+        assert_eq!(rotated, vec![40000, 40001, 40002, 40000]);
+
+        let single: SourcePorts = "53".parse().unwrap();
+        assert_eq!((single.next_port(), single.next_port()), (53, 53));
+        assert_eq!((ports.to_string(), single.to_string()), ("40000-40002".to_string(), "53".to_string()));
+
+        assert!("0-10".parse::<SourcePorts>().is_err());
+        assert!("2000-1000".parse::<SourcePorts>().is_err());
+        assert!("1000-70000".parse::<SourcePorts>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_from_source_port() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ports = SourcePorts::new(47000, 47100).unwrap();
+
+        let (stream, accepted) = tokio::join!(ports.connect(addr, Some(7)), listener.accept());
+        let peer = accepted.unwrap().1;
+        let stream = stream.unwrap();
+        assert_eq!(stream.local_addr().unwrap().port(), peer.port());
+        assert!((47000..=47100).contains(&peer.port()));
+        assert_eq!(stream.ttl().unwrap(), 7);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::connector::{BoxStream, Connector};
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use russh::client::{self, Handle};
+use russh::keys::known_hosts::learn_known_hosts_path;
+use russh::keys::{check_known_hosts_path, HashAlg, PrivateKeyWithHashAlg, PublicKey};
+use russh::ChannelOpenFailure;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 密码认证读取的环境变量，避免密码出现在命令行和 shell 历史中
+pub const PASSWORD_ENV: &str = "RUSTSCAN_SSH_PASSWORD";
+/// 加密私钥的口令读取的环境变量
+pub const PASSPHRASE_ENV: &str = "RUSTSCAN_SSH_KEY_PASSPHRASE";
+/// 未指定私钥时依次尝试的默认私钥
+const DEFAULT_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+/// 连接并完成密钥交换的超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 跳板机地址：`user@host[:port]`，IPv6 地址写作 `user@[::1]:22`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshJump {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for SshJump {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (user, address) = spec
+            .split_once('@')
+            .filter(|(user, _)| !user.is_empty())
+            .ok_or_else(|| anyhow!("跳板机格式应为 user@host[:port]: {}", spec))?;
+        let (host, port) = match address.strip_prefix('[') {
+            Some(rest) => {
+                let (host, port) = rest.split_once(']').ok_or_else(|| anyhow!("无效的跳板机地址: {}", spec))?;
+                (host, port.strip_prefix(':'))
+            }
+            // 不带方括号的 IPv6 地址不能再跟端口
+            None if address.matches(':').count() > 1 => (address, None),
+            None => match address.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (address, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(anyhow!("无效的跳板机地址: {}", spec));
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| anyhow!("无效的跳板机端口: {}", spec))?,
+            None => 22,
+        };
+        Ok(Self {
+            user: user.to_string(),
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// 登录跳板机的方式
+pub enum SshAuth {
+    /// 私钥文件，加密的私钥需要口令
+    Key { path: PathBuf, passphrase: Option<String> },
+    Password(String),
+}
+
+impl SshAuth {
+    /// 按命令行和环境变量选择认证方式：指定了私钥时用私钥，否则设置了
+    /// [`PASSWORD_ENV`] 时用密码，都没有时依次尝试 `~/.ssh` 下的默认私钥
+    pub fn from_env(key: Option<&Path>) -> Result<Self> {
+        let passphrase = std::env::var(PASSPHRASE_ENV).ok();
+        if let Some(path) = key {
+            return Ok(SshAuth::Key {
+                path: path.to_path_buf(),
+                passphrase,
+            });
+        }
+        if let Ok(password) = std::env::var(PASSWORD_ENV) {
+            return Ok(SshAuth::Password(password));
+        }
+        let ssh_dir = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh"));
+        ssh_dir
+            .iter()
+            .flat_map(|dir| DEFAULT_KEYS.iter().map(move |name| dir.join(name)))
+            .find(|path| path.is_file())
+            .map(|path| SshAuth::Key { path, passphrase })
+            .ok_or_else(|| anyhow!("未找到登录跳板机的私钥，请用 --ssh-key 指定私钥或设置环境变量 {} 使用密码认证", PASSWORD_ENV))
+    }
+}
+
+/// 跳板机主机密钥的核对结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    /// 与 known_hosts 中的记录一致
+    Known,
+    /// known_hosts 中没有记录，拒绝连接
+    Unknown { fingerprint: String },
+    /// known_hosts 中没有记录，按 `--ssh-accept-new` 接受并写入 known_hosts
+    Added { fingerprint: String },
+    /// 与 known_hosts 中的记录不符，拒绝连接
+    Changed { fingerprint: String },
+}
+
+/// 按 known_hosts 核对跳板机的主机密钥；密钥在认证之前核对，被拒绝的主机收不到密码
+struct HostKeyCheck {
+    host: String,
+    port: u16,
+    known_hosts: PathBuf,
+    /// 接受 known_hosts 中没有记录的主机并写入记录
+    accept_new: bool,
+    status: Arc<Mutex<Option<HostKeyStatus>>>,
+}
+
+impl client::Handler for HostKeyCheck {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, server_public_key: &PublicKey) -> Result<bool, Self::Error> {
+        let fingerprint = server_public_key.fingerprint(HashAlg::Sha256).to_string();
+        let status = match check_known_hosts_path(&self.host, self.port, server_public_key, &self.known_hosts) {
+            Ok(true) => HostKeyStatus::Known,
+            Err(russh::keys::Error::KeyChanged { .. }) => HostKeyStatus::Changed { fingerprint },
+            _ if self.accept_new => {
+                learn_known_hosts_path(&self.host, self.port, server_public_key, &self.known_hosts)?;
+                HostKeyStatus::Added { fingerprint }
+            }
+            _ => HostKeyStatus::Unknown { fingerprint },
+        };
+        let accepted = matches!(status, HostKeyStatus::Known | HostKeyStatus::Added { .. });
+        *self.status.lock().unwrap() = Some(status);
+        Ok(accepted)
+    }
+}
+
+/// 经 SSH 跳板机建立连接：每次连接在同一个 SSH 会话中打开一个 direct-tcpip 通道，
+/// 由跳板机连接目标端口
+///
+/// 跳板机无法连接目标端口时按端口关闭处理（OpenSSH 对连接被拒和连接超时返回同一个
+/// 错误码）；跳板机禁止端口转发时按被过滤处理。只转发 TCP 连接，UDP 和原始套接字
+/// 探测无法经跳板机进行。
+#[derive(Clone)]
+pub struct SshJumpConnector {
+    handle: Arc<Handle<HostKeyCheck>>,
+    host_key: HostKeyStatus,
+}
+
+impl SshJumpConnector {
+    /// 连接并登录跳板机；`accept_new` 时接受 `~/.ssh/known_hosts` 中没有记录的主机并写入记录，
+    /// 否则拒绝连接
+    pub async fn connect(jump: &SshJump, auth: SshAuth, accept_new: bool) -> Result<Self> {
+        let known_hosts = std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".ssh").join("known_hosts"))
+            .ok_or_else(|| anyhow!("未设置 HOME，无法核对跳板机的主机密钥"))?;
+        let config = Arc::new(client::Config {
+            inactivity_timeout: None,
+            keepalive_interval: Some(Duration::from_secs(30)),
+            ..client::Config::default()
+        });
+        let status = Arc::new(Mutex::new(None));
+        let handler = HostKeyCheck {
+            host: jump.host.clone(),
+            port: jump.port,
+            known_hosts,
+            accept_new,
+            status: status.clone(),
+        };
+        let connect = client::connect(config, (jump.host.as_str(), jump.port), handler);
+        let connected = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .map_err(|_| anyhow!("连接跳板机 {}:{} 超时", jump.host, jump.port))?;
+        let host_key = status.lock().unwrap().take();
+        let (mut handle, host_key) = match (connected, host_key) {
+            (Ok(handle), Some(host_key)) => (handle, host_key),
+            (_, Some(HostKeyStatus::Changed { fingerprint })) => {
+                return Err(anyhow!(
+                    "跳板机 {} 的主机密钥（{}）与 known_hosts 中的记录不符，可能遭到中间人攻击，已拒绝连接",
+                    jump.host,
+                    fingerprint
+                ))
+            }
+            (_, Some(HostKeyStatus::Unknown { fingerprint })) => {
+                return Err(anyhow!(
+                    "跳板机 {} 不在 known_hosts 中，主机密钥指纹为 {}，已拒绝连接。核对指纹无误后加上 --ssh-accept-new 连接并记入 known_hosts",
+                    jump.host,
+                    fingerprint
+                ))
+            }
+            (Err(e), _) => return Err(anyhow!("无法连接跳板机 {}:{}: {}", jump.host, jump.port, e)),
+            (Ok(_), None) => return Err(anyhow!("跳板机 {} 未提供主机密钥", jump.host)),
+        };
+
+        let authenticated = match auth {
+            SshAuth::Key { path, passphrase } => {
+                let key = russh::keys::load_secret_key(&path, passphrase.as_deref()).map_err(|e| {
+                    anyhow!("无法读取私钥 {}: {}（加密的私钥需要通过环境变量 {} 提供口令）", path.display(), e, PASSPHRASE_ENV)
+                })?;
+                let hash_alg = handle.best_supported_rsa_hash().await?.flatten();
+                handle
+                    .authenticate_publickey(jump.user.as_str(), PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg))
+                    .await?
+            }
+            SshAuth::Password(password) => handle.authenticate_password(jump.user.as_str(), password).await?,
+        };
+        if !authenticated.success() {
+            return Err(anyhow!("以 {} 登录跳板机 {} 认证失败", jump.user, jump.host));
+        }
+        Ok(Self {
+            handle: Arc::new(handle),
+            host_key,
+        })
+    }
+
+    /// 跳板机主机密钥的核对结果，新记入 known_hosts 的主机需要提示用户核对指纹
+    pub fn host_key(&self) -> &HostKeyStatus {
+        &self.host_key
+    }
+}
+
+impl Connector for SshJumpConnector {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<BoxStream>> {
+        Box::pin(async move {
+            let channel = self
+                .handle
+                .channel_open_direct_tcpip(addr.ip().to_string(), u32::from(addr.port()), "127.0.0.1", 0)
+                .await
+                .map_err(|e| open_error(&e))?;
+            Ok(Box::new(channel.into_stream()) as BoxStream)
+        })
+    }
+}
+
+/// 打开通道失败的原因对应到端口探测使用的错误类型
+fn open_error(err: &russh::Error) -> io::Error {
+    let kind = match err {
+        russh::Error::ChannelOpenFailure(ChannelOpenFailure::ConnectFailed) => io::ErrorKind::ConnectionRefused,
+        russh::Error::ChannelOpenFailure(ChannelOpenFailure::AdministrativelyProhibited) => {
+            io::ErrorKind::PermissionDenied
+        }
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_jump_spec() {
+        let jump: SshJump = "root@bastion.example.com".parse().unwrap();
+        assert_eq!((jump.user.as_str(), jump.host.as_str(), jump.port), ("root", "bastion.example.com", 22));
+        let jump: SshJump = "ops@10.0.0.1:2222".parse().unwrap();
+        assert_eq!((jump.host.as_str(), jump.port), ("10.0.0.1", 2222));
+        let jump: SshJump = "ops@[fe80::1]:2200".parse().unwrap();
+        assert_eq!((jump.host.as_str(), jump.port), ("fe80::1", 2200));
+        assert_eq!("ops@fe80::1".parse::<SshJump>().unwrap().port, 22);
+        assert!("bastion".parse::<SshJump>().is_err());
+        assert!("@bastion".parse::<SshJump>().is_err());
+        assert!("ops@bastion:ssh".parse::<SshJump>().is_err());
+        assert!("ops@[::1".parse::<SshJump>().is_err());
+
+        // 跳板机连不上目标端口为关闭，禁止转发为被过滤
+        let refused = open_error(&russh::Error::ChannelOpenFailure(ChannelOpenFailure::ConnectFailed));
+        assert_eq!(crate::scanner::PortState::from_error(&refused), crate::scanner::PortState::Closed);
+        let prohibited = open_error(&russh::Error::ChannelOpenFailure(ChannelOpenFailure::AdministrativelyProhibited));
+        assert_eq!(crate::scanner::PortState::from_error(&prohibited), crate::scanner::PortState::Filtered);
+    }
+
+    #[tokio::test]
+    async fn test_host_key_check() {
+        use client::Handler;
+
+        let dir = std::env::temp_dir().join(format!("rustscan-known-hosts-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let key = russh::keys::parse_public_key_base64("AAAAC3NzaC1lZDI1NTE5AAAAIGoCxXfGl/QiqvZHQ7Q6dGuI1QIfQ2X23QaArMLx3LPD").unwrap();
+        let other = russh::keys::parse_public_key_base64("AAAAC3NzaC1lZDI1NTE5AAAAIJdD7y3aLq454yWBdwLWbieU1ebz9/cu7/QEXn9OIeZJ").unwrap();
+        let check = |accept_new| HostKeyCheck {
+            host: "bastion.example.com".to_string(),
+            port: 2222,
+            known_hosts: dir.join("known_hosts"),
+            accept_new,
+            status: Arc::new(Mutex::new(None)),
+        };
+        let status = |check: &HostKeyCheck| check.status.lock().unwrap().clone().unwrap();
+
+        // 默认拒绝没有记录的主机，也不写入 known_hosts
+        let mut refuse = check(false);
+        assert!(!refuse.check_server_key(&key).await.unwrap());
+        assert!(matches!(status(&refuse), HostKeyStatus::Unknown { .. }));
+        assert!(!dir.join("known_hosts").exists());
+
+        // --ssh-accept-new 接受并记录，之后按已知主机核对
+        let mut accept = check(true);
+        assert!(accept.check_server_key(&key).await.unwrap());
+        assert!(matches!(status(&accept), HostKeyStatus::Added { .. }));
+        assert!(refuse.check_server_key(&key).await.unwrap());
+        assert_eq!(status(&refuse), HostKeyStatus::Known);
+        // 密钥变化时即使允许新主机也拒绝
+        assert!(!accept.check_server_key(&other).await.unwrap());
+        assert!(matches!(status(&accept), HostKeyStatus::Changed { .. }));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::events::{ScanEvent, ScanObserver};
+use anyhow::{anyhow, Result};
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "audit", "alert", "clock", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// TCP 传输时等待发送的消息数上限，队列满时丢弃新消息
+const TCP_QUEUE_LEN: usize = 1024;
+
+/// TCP 传输单次写入的超时；超时后连接的分帧已不可靠，不再发送后续消息
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// syslog 传输方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogTransport {
+    Udp,
+    /// 按 RFC 6587 八位组计数分帧
+    Tcp,
+}
+
+impl FromStr for SyslogTransport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => Ok(SyslogTransport::Udp),
+            "tcp" => Ok(SyslogTransport::Tcp),
+            _ => Err(anyhow!("无效的 syslog 传输方式: {}（可选 udp/tcp）", s)),
+        }
+    }
+}
+
+/// 解析 facility 名称（如 local0）或 0-23 的数字
+pub fn parse_facility(s: &str) -> Result<u8> {
+    parse_code(s, &FACILITIES).ok_or_else(|| anyhow!("无效的 syslog facility: {}", s))
+}
+
+/// 解析 severity 名称（如 notice）或 0-7 的数字
+pub fn parse_severity(s: &str) -> Result<u8> {
+    parse_code(s, &SEVERITIES).ok_or_else(|| anyhow!("无效的 syslog severity: {}", s))
+}
+
+fn parse_code(s: &str, names: &[&str]) -> Option<u8> {
+    let s = s.to_ascii_lowercase();
+    match s.parse::<u8>() {
+        Ok(code) => (usize::from(code) < names.len()).then_some(code),
+        Err(_) => names.iter().position(|name| *name == s).map(|code| code as u8),
+    }
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    /// 消息经有界队列交给单独的发送线程，收集端停滞时不会阻塞扫描任务
+    Tcp {
+        queue: Option<SyncSender<String>>,
+        writer: Option<JoinHandle<()>>,
+    },
+}
+
+/// 将扫描事件以 RFC 5424 格式实时发送到 syslog 服务器
+pub struct SyslogSink {
+    connection: Connection,
+    facility: u8,
+    severity: u8,
+    hostname: String,
+}
+
+impl SyslogSink {
+    pub fn connect(endpoint: &str, transport: SyslogTransport, facility: u8, severity: u8) -> Result<Self> {
+        let addr = endpoint
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("无法解析 syslog 地址: {}", endpoint))?;
+        let connection = match transport {
+            SyslogTransport::Udp => {
+                let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+                socket.connect(addr)?;
+                Connection::Udp(socket)
+            }
+            SyslogTransport::Tcp => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT))?;
+                let (queue, messages) = mpsc::sync_channel(TCP_QUEUE_LEN);
+                let writer = std::thread::Builder::new()
+                    .name("syslog-tcp".to_string())
+                    .spawn(move || write_messages(stream, messages))?;
+                Connection::Tcp {
+                    queue: Some(queue),
+                    writer: Some(writer),
+                }
+            }
+        };
+        Ok(Self {
+            connection,
+            facility,
+            severity,
+            hostname: local_hostname(),
+        })
+    }
+
+    /// 按 RFC 5424 格式化一条消息，正文为事件的 JSON
+    fn format(&self, event: &ScanEvent, timestamp: SystemTime) -> String {
+        format_message(
+            self.facility,
+            self.severity,
+            &rfc3339_utc(timestamp),
+            &self.hostname,
+            event.kind(),
+            &serde_json::to_string(event).unwrap_or_default(),
+        )
+    }
+}
+
+impl ScanObserver for SyslogSink {
+    fn on_event(&self, event: &ScanEvent) {
+        let message = self.format(event, SystemTime::now());
+        // 发送失败不影响扫描本身
+        match &self.connection {
+            Connection::Udp(socket) => {
+                let _ = socket.send(message.as_bytes());
+            }
+            Connection::Tcp { queue, .. } => {
+                // 队列已满或发送线程已退出时丢弃
+                if let Some(queue) = queue {
+                    let _ = queue.try_send(message);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SyslogSink {
+    /// 关闭队列并等待发送线程写完已排队的消息；收集端停滞时最多等待一次写入超时
+    fn drop(&mut self) {
+        if let Connection::Tcp { queue, writer } = &mut self.connection {
+            queue.take();
+            if let Some(writer) = writer.take() {
+                let _ = writer.join();
+            }
+        }
+    }
+}
+
+/// 发送线程：按 RFC 6587 八位组计数分帧逐条写入，写入失败或超时后停止
+fn write_messages(mut stream: TcpStream, messages: Receiver<String>) {
+    for message in messages {
+        if write!(stream, "{} {}", message.len(), message).is_err() {
+            break;
+        }
+    }
+}
+
+fn format_message(facility: u8, severity: u8, timestamp: &str, hostname: &str, msg_id: &str, msg: &str) -> String {
+    let pri = u16::from(facility) * 8 + u16::from(severity);
+    format!(
+        "<{}>1 {} {} rustscan {} {} - {}",
+        pri,
+        timestamp,
+        hostname,
+        std::process::id(),
+        msg_id,
+        msg
+    )
+}
+
+/// RFC 3339 UTC 时间戳，精确到毫秒
+fn rfc3339_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, day_secs) = (secs / 86400, secs % 86400);
+
+    // 由 Unix 天数换算公历日期（Howard Hinnant 的 civil_from_days）
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_secs / 3600,
+        day_secs % 3600 / 60,
+        day_secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(unix)]
+fn local_hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    if ret != 0 || len == 0 {
+        return "-".to_string();
+    }
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn local_hostname() -> String {
+    "-".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_syslog_message() {
+        assert_eq!(parse_facility("local0").unwrap(), 16);
+        assert_eq!(parse_facility("3").unwrap(), 3);
+        assert!(parse_facility("local8").is_err());
+        assert_eq!(parse_severity("notice").unwrap(), 5);
+        assert!(parse_severity("8").is_err());
+
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_251_200_123);
+        assert_eq!(rfc3339_utc(time), "2024-03-01T00:00:00.123Z");
+
+        let message = format_message(16, 5, "2024-03-01T00:00:00.123Z", "scanner", "port_open", "{}");
+        assert!(message.starts_with("<133>1 2024-03-01T00:00:00.123Z scanner rustscan "));
+        assert!(message.ends_with(" port_open - {}"));
+    }
+
+    #[test]
+    fn test_udp_sink() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let endpoint = server.local_addr().unwrap().to_string();
+
+        let sink = SyslogSink::connect(&endpoint, SyslogTransport::Udp, 1, 6).unwrap();
+        sink.on_event(&ScanEvent::PortOpen {
+            target: "10.0.0.1".to_string(),
+            port: 22,
+            protocol: "TCP".to_string(),
+        });
+
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]);
+        assert!(message.starts_with("<14>1 "));
+        assert!(message.contains(r#""event":"port_open","target":"10.0.0.1","port":22"#));
+    }
+
+    #[test]
+    fn test_tcp_sink_does_not_block() {
+        let event = |target: String| ScanEvent::PortOpen {
+            target,
+            port: 22,
+            protocol: "TCP".to_string(),
+        };
+
+        // 正常的收集端按八位组计数分帧收到消息
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = SyslogSink::connect(&server.local_addr().unwrap().to_string(), SyslogTransport::Tcp, 1, 6).unwrap();
+        sink.on_event(&event("10.0.0.1".to_string()));
+        drop(sink);
+        let mut received = String::new();
+        std::io::Read::read_to_string(&mut server.accept().unwrap().0, &mut received).unwrap();
+        let (len, message) = received.split_once(' ').unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), message.len());
+        assert!(message.starts_with("<14>1 "));
+
+        // 收集端接受连接后从不读取：发送缓冲区写满后 on_event 仍立即返回
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = SyslogSink::connect(&server.local_addr().unwrap().to_string(), SyslogTransport::Tcp, 1, 6).unwrap();
+        let _stalled = server.accept().unwrap();
+        let target = "x".repeat(16 * 1024);
+        for _ in 0..4 * TCP_QUEUE_LEN {
+            sink.on_event(&event(target.clone()));
+        }
+        // 此时套接字缓冲区和队列都已写满，只计时之后的发送，避免把格式化消息的耗时算进去
+        let start = std::time::Instant::now();
+        for _ in 0..100 {
+            sink.on_event(&event(target.clone()));
+        }
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,510 @@
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// 扫描目标地址，IPv6 链路本地地址可带区域标识（`fe80::1%eth0`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScanTarget {
+    pub ip: IpAddr,
+    /// IPv6 scope id（网卡索引），0 表示未指定
+    pub scope_id: u32,
+}
+
+impl ScanTarget {
+    pub fn new(ip: IpAddr) -> Self {
+        Self { ip, scope_id: 0 }
+    }
+
+    /// 构造连接地址，保留 IPv6 scope id
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, self.scope_id)),
+            IpAddr::V4(_) => SocketAddr::new(self.ip, port),
+        }
+    }
+}
+
+impl From<IpAddr> for ScanTarget {
+    fn from(ip: IpAddr) -> Self {
+        Self::new(ip)
+    }
+}
+
+impl fmt::Display for ScanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.scope_id == 0 {
+            return write!(f, "{}", self.ip);
+        }
+        match interface_name(self.scope_id) {
+            Some(name) => write!(f, "{}%{}", self.ip, name),
+            None => write!(f, "{}%{}", self.ip, self.scope_id),
+        }
+    }
+}
+
+impl FromStr for ScanTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((addr, zone)) = s.split_once('%') else {
+            return Ok(Self::new(s.parse()?));
+        };
+        let ip: Ipv6Addr = addr
+            .parse()
+            .map_err(|_| anyhow!("区域标识只适用于 IPv6 地址: {}", s))?;
+        Ok(Self {
+            ip: IpAddr::V6(ip),
+            scope_id: parse_zone(zone)?,
+        })
+    }
+}
+
+/// 区域标识可以是网卡名（eth0）或数字索引
+fn parse_zone(zone: &str) -> Result<u32> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Ok(index);
+    }
+    interface_index(zone).ok_or_else(|| anyhow!("未知的网络接口: {}", zone))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn interface_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let ptr = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if ptr.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn interface_name(_index: u32) -> Option<String> {
+    None
+}
+
+/// PTR 反向解析主机名，阻塞调用
+#[cfg(unix)]
+pub fn reverse_lookup(ip: IpAddr) -> Option<String> {
+    let addr = socket2::SockAddr::from(std::net::SocketAddr::new(ip, 0));
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    let ret = unsafe {
+        libc::getnameinfo(
+            addr.as_ptr(),
+            addr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(host.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+pub fn reverse_lookup(_ip: IpAddr) -> Option<String> {
+    None
+}
+
+pub fn parse_subnet(subnet: &str) -> Result<Vec<ScanTarget>> {
+    parse_subnet_with(subnet, false)
+}
+
+/// 展开目标网段；`include_network_broadcast` 为真时保留 IPv4 网段的网络地址和广播地址
+/// （`--include-network-broadcast`），点对点链路和部分路由器上这两个地址也会应答
+pub fn parse_subnet_with(subnet: &str, include_network_broadcast: bool) -> Result<Vec<ScanTarget>> {
+    if subnet.contains('/') {
+        let (ip_str, mask_str) = subnet.split_once('/').unwrap();
+        let base_ip: Ipv4Addr = ip_str.parse()?;
+        let mask: u8 = mask_str.parse()?;
+
+        if mask > 32 {
+            return Err(anyhow!("无效的子网掩码"));
+        }
+
+        let mut ips = Vec::new();
+        let host_bits = 32 - mask;
+        let num_hosts = 1u32 << host_bits;
+        let base_ip_u32 = u32::from_be_bytes(base_ip.octets());
+        let network_addr = base_ip_u32 & (!0u32 << host_bits);
+
+        // 默认跳过网络地址和广播地址
+        let hosts = if include_network_broadcast { 0..num_hosts } else { 1..num_hosts - 1 };
+        for i in hosts {
+            let ip_u32 = network_addr | i;
+            let ip = Ipv4Addr::from(ip_u32);
+            ips.push(ScanTarget::new(IpAddr::V4(ip)));
+        }
+
+        Ok(ips)
+    } else {
+        Ok(vec![subnet.parse()?])
+    }
+}
+
+/// IPv4 网段（`a.b.c.d/n`，n 不超过 30）的广播地址；单个地址或 IPv6 返回 `None`
+pub fn subnet_broadcast(subnet: &str) -> Option<Ipv4Addr> {
+    let (ip, mask) = subnet.split_once('/')?;
+    let ip: Ipv4Addr = ip.parse().ok()?;
+    let mask: u32 = mask.parse().ok().filter(|mask| *mask <= 30)?;
+    let host_mask = u32::MAX.checked_shr(mask).unwrap_or(0);
+    Some(Ipv4Addr::from(u32::from(ip) | host_mask))
+}
+
+/// 解析单个 `host:port` 或 `[v6]:port` 端点，IPv6 可带区域标识 `[fe80::1%eth0]:22`
+pub fn parse_endpoint(endpoint: &str) -> Result<(ScanTarget, u16)> {
+    let (host, port) = if let Some(rest) = endpoint.strip_prefix('[') {
+        rest.split_once("]:")
+            .ok_or_else(|| anyhow!("无效的端点: {}", endpoint))?
+    } else {
+        endpoint
+            .rsplit_once(':')
+            .filter(|(host, _)| !host.contains(':'))
+            .ok_or_else(|| anyhow!("无效的端点: {}（IPv6 地址需写成 [地址]:端口）", endpoint))?
+    };
+    let ip: ScanTarget = host.parse().map_err(|_| anyhow!("无效的端点地址: {}", endpoint))?;
+    let port: u16 = port.parse().map_err(|_| anyhow!("无效的端点端口: {}", endpoint))?;
+    if port == 0 {
+        return Err(anyhow!("无效的端点端口: {}", endpoint));
+    }
+    Ok((ip, port))
+}
+
+/// 解析端点列表（每行一个，`#` 开头为注释），按主机聚合端口
+pub fn parse_endpoints(content: &str) -> Result<BTreeMap<ScanTarget, Vec<u16>>> {
+    let mut hosts: BTreeMap<ScanTarget, Vec<u16>> = BTreeMap::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (ip, port) = parse_endpoint(line)?;
+        let ports = hosts.entry(ip).or_default();
+        if !ports.contains(&port) {
+            ports.push(port);
+        }
+    }
+    for ports in hosts.values_mut() {
+        ports.sort_unstable();
+    }
+    Ok(hosts)
+}
+
+impl ScanTarget {
+    /// 是否为内网地址：RFC 1918 私有网段、回环、链路本地，以及 IPv6 唯一本地地址（fc00::/7）
+    pub fn is_private(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+            IpAddr::V6(ip) => {
+                let first = ip.segments()[0];
+                ip.is_loopback() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+            }
+        }
+    }
+}
+
+/// 排除列表：逗号分隔的地址或 CIDR 网段，IPv4 和 IPv6 均可（如 `10.0.0.0/8,192.168.1.5,fd00::/8`）
+///
+/// 网段按前缀长度分组保存网络地址，判断一个地址时对每种前缀长度做一次掩码和哈希查找，
+/// 耗时只与不同前缀长度的个数有关，与排除网段的大小无关，不需要展开。
+#[derive(Debug, Clone, Default)]
+pub struct ExcludeList {
+    v4: BTreeMap<u8, HashSet<u32>>,
+    v6: BTreeMap<u8, HashSet<u128>>,
+}
+
+impl ExcludeList {
+    pub fn insert(&mut self, ip: IpAddr, prefix: u8) -> Result<()> {
+        match ip {
+            IpAddr::V4(ip) if prefix <= 32 => {
+                self.v4.entry(prefix).or_default().insert(u32::from(ip) & v4_mask(prefix));
+            }
+            IpAddr::V6(ip) if prefix <= 128 => {
+                self.v6.entry(prefix).or_default().insert(u128::from(ip) & v6_mask(prefix));
+            }
+            _ => return Err(anyhow!("无效的前缀长度: {}/{}", ip, prefix)),
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => {
+                let ip = u32::from(ip);
+                self.v4.iter().any(|(&prefix, networks)| networks.contains(&(ip & v4_mask(prefix))))
+            }
+            IpAddr::V6(ip) => {
+                let ip = u128::from(ip);
+                self.v6.iter().any(|(&prefix, networks)| networks.contains(&(ip & v6_mask(prefix))))
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+}
+
+fn v4_mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn v6_mask(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+impl FromStr for ExcludeList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut list = Self::default();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (addr, prefix) = match item.split_once('/') {
+                Some((addr, prefix)) => (addr, Some(prefix)),
+                None => (item, None),
+            };
+            let ip: IpAddr = addr.parse().map_err(|_| anyhow!("排除列表只支持地址和网段: {}", item))?;
+            let prefix = match prefix {
+                Some(prefix) => prefix.parse().map_err(|_| anyhow!("无效的子网掩码: {}", item))?,
+                None if ip.is_ipv4() => 32,
+                None => 128,
+            };
+            list.insert(ip, prefix)?;
+        }
+        Ok(list)
+    }
+}
+
+/// 最常见的 100 个 TCP 端口（与 nmap `-F` 相同）
+pub const TOP_TCP_PORTS: &str = "7,9,13,21-23,25-26,37,53,79-81,88,106,110-111,113,119,135,139,143-144,179,199,\
+    389,427,443-445,465,513-515,543-544,548,554,587,631,646,873,990,993,995,1025-1029,1110,1433,1720,1723,\
+    1755,1900,2000-2001,2049,2121,2717,3000,3128,3306,3389,3986,4899,5000,5009,5051,5060,5101,5190,5357,\
+    5432,5631,5666,5800,5900,6000-6001,6646,7070,8000,8008-8009,8080-8081,8443,8888,9100,9999-10000,\
+    32768,49152-49157";
+
+/// 最常见的 20 个 UDP 端口（与 nmap `-sU --top-ports 20` 相同）
+pub const TOP_UDP_PORTS: &str = "53,67-69,123,135,137-139,161-162,445,500,514,520,631,1434,1900,4500,49152";
+
+/// 解析端口列表，如 `22,80,8000-8100`
+///
+/// 与 nmap 一致，`-`（或 `all`）表示全部 65535 个端口，区间可省略一端（`-1024`、`60000-`）。
+/// 结果升序且去重。
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>> {
+    let spec = spec.trim();
+    if spec.eq_ignore_ascii_case("all") {
+        return Ok((1..=65535).collect());
+    }
+    let parse = |port: &str, default: u16| -> Result<u16> {
+        let port = port.trim();
+        if port.is_empty() {
+            return Ok(default);
+        }
+        match port.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(port),
+            _ => Err(anyhow!("无效的端口: {}", port)),
+        }
+    };
+
+    let mut ports = Vec::new();
+    for item in spec.split(',') {
+        let (start, end) = match item.split_once('-') {
+            Some((start, end)) => (parse(start, 1)?, parse(end, 65535)?),
+            None if item.trim().is_empty() => return Err(anyhow!("无效的端口列表: {}", spec)),
+            None => {
+                let port = parse(item, 0)?;
+                (port, port)
+            }
+        };
+        if start > end {
+            return Err(anyhow!("无效的端口范围: {}", item.trim()));
+        }
+        ports.extend(start..=end);
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// `-s`/`-e` 指定的端口范围；起始端口大于结束端口时报错，而不是得到空的端口列表
+pub fn port_range(start: u16, end: u16) -> Result<Vec<u16>> {
+    if start > end {
+        return Err(anyhow!("起始端口 {} 大于结束端口 {}", start, end));
+    }
+    Ok((start..=end).collect())
+}
+
+/// 按种子打乱目标顺序，相同种子总是得到相同的顺序
+pub fn shuffle_targets<T>(targets: &mut [T], seed: u64) {
+    targets.shuffle(&mut StdRng::seed_from_u64(seed));
+}
+
+/// 开始扫描前的随机等待时长，在 0 到 `max` 之间均匀分布（毫秒精度），相同种子总是相同
+pub fn start_jitter(max: Duration, seed: u64) -> Duration {
+    let max = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+    Duration::from_millis(StdRng::seed_from_u64(seed).gen_range(0..=max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoints() {
+        let hosts = parse_endpoints(
+            "# 来自其他工具的结果\n10.0.0.5:8443\n10.0.0.6:22\n10.0.0.5:80\n\n[2001:db8::1]:443\n10.0.0.5:80\n",
+        )
+        .unwrap();
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(hosts[&"10.0.0.5".parse::<ScanTarget>().unwrap()], vec![80, 8443]);
+        assert_eq!(hosts[&"2001:db8::1".parse::<ScanTarget>().unwrap()], vec![443]);
+
+        assert!(parse_endpoint("2001:db8::1:443").is_err());
+        assert!(parse_endpoint("10.0.0.5").is_err());
+        assert!(parse_endpoint("10.0.0.5:0").is_err());
+        assert!(parse_endpoint("10.0.0.5:70000").is_err());
+    }
+
+    #[test]
+    fn test_parse_ports() {
+        assert_eq!(parse_ports("-").unwrap().len(), 65535);
+        assert_eq!(parse_ports("all").unwrap(), parse_ports("1-65535").unwrap());
+        assert_eq!(parse_ports("443,22,80-82,22").unwrap(), vec![22, 80, 81, 82, 443]);
+        assert_eq!(parse_ports("-3").unwrap(), vec![1, 2, 3]);
+        assert_eq!(parse_ports("65534-").unwrap(), vec![65534, 65535]);
+        assert!(parse_ports("0").is_err());
+        assert!(parse_ports("100-10").is_err());
+        assert!(parse_ports("22,,80").is_err());
+        assert!(parse_ports("http").is_err());
+        assert!(parse_ports("").is_err());
+
+        assert_eq!(port_range(80, 82).unwrap(), vec![80, 81, 82]);
+        assert_eq!(port_range(443, 443).unwrap(), vec![443]);
+        let reversed = port_range(8000, 80).unwrap_err();
+        assert_eq!(reversed.to_string(), "起始端口 8000 大于结束端口 80");
+    }
+
+    #[test]
+    fn test_top_ports() {
+        assert_eq!(parse_ports(TOP_TCP_PORTS).unwrap().len(), 100);
+        assert_eq!(parse_ports(TOP_UDP_PORTS).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn test_shuffle_targets() {
+        let targets = parse_subnet("10.0.0.0/24").unwrap();
+        let mut first = targets.clone();
+        let mut second = targets.clone();
+        shuffle_targets(&mut first, 42);
+        shuffle_targets(&mut second, 42);
+        assert_eq!(first, second);
+        assert_ne!(first, targets);
+
+        shuffle_targets(&mut second, 7);
+        assert_ne!(first, second);
+        second.sort();
+        assert_eq!(second, targets);
+    }
+
+    #[test]
+    fn test_start_jitter() {
+        let max = Duration::from_secs(600);
+        assert_eq!(start_jitter(max, 42), start_jitter(max, 42));
+        let delays: HashSet<Duration> = (0..20).map(|seed| start_jitter(max, seed)).collect();
+        assert!(delays.len() > 1);
+        assert!(delays.iter().all(|delay| *delay <= max));
+        assert_eq!(start_jitter(Duration::ZERO, 42), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_subnet_broadcast() {
+        assert_eq!(subnet_broadcast("192.168.1.0/24"), Some(Ipv4Addr::new(192, 168, 1, 255)));
+        assert_eq!(subnet_broadcast("10.1.2.3/8"), Some(Ipv4Addr::new(10, 255, 255, 255)));
+        assert_eq!(subnet_broadcast("10.0.0.0/0"), Some(Ipv4Addr::BROADCAST));
+        assert_eq!(subnet_broadcast("10.0.0.1/32"), None);
+        assert_eq!(subnet_broadcast("10.0.0.1"), None);
+
+        let all: Vec<String> = parse_subnet_with("10.0.0.5/30", true).unwrap().iter().map(ToString::to_string).collect();
+        assert_eq!(all, ["10.0.0.4", "10.0.0.5", "10.0.0.6", "10.0.0.7"]);
+        assert_eq!(parse_subnet("10.0.0.5/30").unwrap().len(), 2);
+        assert_eq!(parse_subnet_with("10.0.0.1/32", true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_is_private() {
+        for addr in ["10.1.2.3", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.1.1", "fd00::1", "fe80::1%1", "::1"] {
+            assert!(addr.parse::<ScanTarget>().unwrap().is_private(), "{}", addr);
+        }
+        for addr in ["8.8.8.8", "172.32.0.1", "100.64.0.1", "2001:4860:4860::8888"] {
+            assert!(!addr.parse::<ScanTarget>().unwrap().is_private(), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn test_zone_id() {
+        let target: ScanTarget = "fe80::1%3".parse().unwrap();
+        assert_eq!(target.scope_id, 3);
+        match target.socket_addr(22) {
+            SocketAddr::V6(addr) => assert_eq!(addr.scope_id(), 3),
+            SocketAddr::V4(_) => panic!("应为 IPv6 地址"),
+        }
+
+        let (target, port) = parse_endpoint("[fe80::1%3]:8443").unwrap();
+        assert_eq!((target.scope_id, port), (3, 8443));
+
+        // Linux 上回环接口固定为 lo
+        #[cfg(target_os = "linux")]
+        assert_ne!("fe80::1%lo".parse::<ScanTarget>().unwrap().scope_id, 0);
+
+        assert!("10.0.0.1%eth0".parse::<ScanTarget>().is_err());
+        assert!("fe80::1%no-such-iface0".parse::<ScanTarget>().is_err());
+    }
+
+    #[test]
+    fn test_exclude_list() {
+        let exclude: ExcludeList = "10.0.0.0/8, 192.168.1.5,fd00::/8".parse().unwrap();
+        assert!(exclude.contains("10.255.0.1".parse().unwrap()));
+        assert!(exclude.contains("192.168.1.5".parse().unwrap()));
+        assert!(!exclude.contains("192.168.1.6".parse().unwrap()));
+        assert!(!exclude.contains("11.0.0.1".parse().unwrap()));
+        assert!(exclude.contains("fd12::1".parse().unwrap()));
+        assert!(!exclude.contains("fe80::1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<ExcludeList>().unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!("".parse::<ExcludeList>().unwrap().is_empty());
+        assert!("10.0.0.0/33".parse::<ExcludeList>().is_err());
+        assert!("example.com".parse::<ExcludeList>().is_err());
+
+        // 从 /16 中排除 /8 和 /24：逐个地址做前缀查找，不展开排除网段
+        let start = std::time::Instant::now();
+        let mut targets = parse_subnet("10.1.0.0/16").unwrap();
+        targets.retain(|target| !exclude.contains(target.ip));
+        assert!(targets.is_empty());
+        let exclude: ExcludeList = "172.16.4.0/24".parse().unwrap();
+        let mut targets = parse_subnet("172.16.0.0/16").unwrap();
+        targets.retain(|target| !exclude.contains(target.ip));
+        assert_eq!(targets.len(), 65534 - 256);
+        assert!(start.elapsed() < std::time::Duration::from_secs(2), "{:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,384 @@
+use crate::connector::Connector;
+use crate::service_fingerprints::read_until;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{timeout, Instant};
+
+/// 握手记录的最大读取长度，ServerHello 之后的证书等内容不需要
+const MAX_HANDSHAKE_LEN: usize = 16 * 1024;
+
+/// ClientHello 提供的密码套件：TLS 1.3 套件和常见的 ECDHE/RSA 套件
+const CIPHER_SUITES: [u16; 13] = [
+    0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0x009c, 0x009d, 0x002f, 0x0035,
+];
+/// x25519、secp256r1、secp384r1
+const SUPPORTED_GROUPS: [u16; 3] = [0x001d, 0x0017, 0x0018];
+const SIGNATURE_ALGORITHMS: [u16; 9] = [0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601, 0x0201];
+
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+const EXT_KEY_SHARE: u16 = 0x0033;
+const EXT_RENEGOTIATION_INFO: u16 = 0xff01;
+
+/// TLS 握手得到的指纹
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsFingerprint {
+    /// 本程序发出的 ClientHello 的 JA3，便于用同样的 ClientHello 复现 JA3S
+    pub ja3: String,
+    /// 服务端 ServerHello 的 JA3S（MD5）
+    pub ja3s: String,
+    /// 计算 JA3S 的原始字符串：`版本,密码套件,扩展列表`
+    pub ja3s_string: String,
+    /// 协商出的协议版本，如 `TLS 1.3`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// 解析出的 ServerHello
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello<'a> {
+    pub legacy_version: u16,
+    pub cipher_suite: u16,
+    /// 扩展类型和内容，按出现顺序
+    pub extensions: Vec<(u16, &'a [u8])>,
+}
+
+impl ServerHello<'_> {
+    /// 协商出的版本：TLS 1.3 的 legacy_version 仍为 1.2，实际版本在 supported_versions 扩展中
+    pub fn version(&self) -> u16 {
+        self.extensions
+            .iter()
+            .find(|(kind, data)| *kind == EXT_SUPPORTED_VERSIONS && data.len() == 2)
+            .map(|(_, data)| u16::from_be_bytes([data[0], data[1]]))
+            .unwrap_or(self.legacy_version)
+    }
+
+    /// JA3S 原始字符串：`SSLVersion,Cipher,SSLExtension`，数值为十进制，扩展以 `-` 连接
+    pub fn ja3s_string(&self) -> String {
+        let extensions: Vec<String> = self
+            .extensions
+            .iter()
+            .map(|(kind, _)| *kind)
+            .filter(|&kind| !is_grease(kind))
+            .map(|kind| kind.to_string())
+            .collect();
+        format!("{},{},{}", self.legacy_version, self.cipher_suite, extensions.join("-"))
+    }
+}
+
+/// 解析记录开头的 ServerHello；扩展部分被截断时只保留完整的扩展
+pub fn parse_server_hello(data: &[u8]) -> Option<ServerHello<'_>> {
+    // 记录头：类型 22（握手）、版本、长度；握手头：类型 2（ServerHello）、长度
+    if data.len() < 11 || data[0] != 0x16 || data[1] != 0x03 || data[5] != 0x02 {
+        return None;
+    }
+    let legacy_version = u16::from_be_bytes([data[9], data[10]]);
+    // 跳过 32 字节随机数和会话 ID
+    let session_id_len = usize::from(*data.get(43)?);
+    let pos = 44 + session_id_len;
+    let cipher_suite = u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]);
+    // 压缩方法之后是扩展；没有扩展的 ServerHello 到此结束
+    let extensions = match data.get(pos + 3..pos + 5) {
+        Some(len) => {
+            let end = (pos + 5 + usize::from(u16::from_be_bytes([len[0], len[1]]))).min(data.len());
+            parse_extensions(&data[pos + 5..end])
+        }
+        None => Vec::new(),
+    };
+    Some(ServerHello {
+        legacy_version,
+        cipher_suite,
+        extensions,
+    })
+}
+
+fn parse_extensions(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut extensions = Vec::new();
+    while data.len() >= 4 {
+        let kind = u16::from_be_bytes([data[0], data[1]]);
+        let len = usize::from(u16::from_be_bytes([data[2], data[3]]));
+        let Some(body) = data.get(4..4 + len) else {
+            break;
+        };
+        extensions.push((kind, body));
+        data = &data[4 + len..];
+    }
+    extensions
+}
+
+/// RFC 8701 保留的 GREASE 值（0x0a0a、0x1a1a……），计算 JA3 时忽略
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a
+}
+
+fn u16_list(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_be_bytes()).collect()
+}
+
+fn push_extension(out: &mut Vec<u8>, kind: u16, body: &[u8]) {
+    out.extend_from_slice(&kind.to_be_bytes());
+    out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+/// 构造 ClientHello 记录：同时支持 TLS 1.2 和 1.3，不带 SNI
+///
+/// 随机数、会话 ID 和 x25519 公钥每次随机生成，不影响 JA3；其余内容固定，JA3 始终相同。
+pub fn client_hello() -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    let mut random = [0u8; 96];
+    rng.fill_bytes(&mut random);
+
+    let ciphers = u16_list(&CIPHER_SUITES);
+    let groups = u16_list(&SUPPORTED_GROUPS);
+    let signatures = u16_list(&SIGNATURE_ALGORITHMS);
+    let mut extensions = Vec::new();
+    push_extension(&mut extensions, EXT_SUPPORTED_GROUPS, &[&(groups.len() as u16).to_be_bytes()[..], &groups].concat());
+    push_extension(&mut extensions, EXT_EC_POINT_FORMATS, &[1, 0]);
+    push_extension(
+        &mut extensions,
+        EXT_SIGNATURE_ALGORITHMS,
+        &[&(signatures.len() as u16).to_be_bytes()[..], &signatures].concat(),
+    );
+    push_extension(&mut extensions, EXT_SUPPORTED_VERSIONS, &[4, 0x03, 0x04, 0x03, 0x03]);
+    push_extension(&mut extensions, EXT_KEY_SHARE, &[&[0x00, 0x24, 0x00, 0x1d, 0x00, 0x20][..], &random[64..]].concat());
+    push_extension(&mut extensions, EXT_RENEGOTIATION_INFO, &[0]);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&random[..32]);
+    body.push(32);
+    body.extend_from_slice(&random[32..64]);
+    body.extend_from_slice(&(ciphers.len() as u16).to_be_bytes());
+    body.extend_from_slice(&ciphers);
+    body.extend_from_slice(&[1, 0]);
+    body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    body.extend_from_slice(&extensions);
+
+    let mut handshake = vec![0x01];
+    handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&body);
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+/// 构造只提供 `version` 及以下版本的 ClientHello（不带 supported_versions），
+/// 用于 `--tls-audit` 检查旧版本和指定的密码套件；SSL 3.0 不带任何扩展
+pub fn legacy_client_hello(version: u16, ciphers: &[u16]) -> Vec<u8> {
+    let mut random = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut random);
+
+    let mut extensions = Vec::new();
+    if version > 0x0300 {
+        let groups = u16_list(&SUPPORTED_GROUPS);
+        push_extension(&mut extensions, EXT_SUPPORTED_GROUPS, &[&(groups.len() as u16).to_be_bytes()[..], &groups].concat());
+        push_extension(&mut extensions, EXT_EC_POINT_FORMATS, &[1, 0]);
+        if version >= 0x0303 {
+            let signatures = u16_list(&SIGNATURE_ALGORITHMS);
+            push_extension(
+                &mut extensions,
+                EXT_SIGNATURE_ALGORITHMS,
+                &[&(signatures.len() as u16).to_be_bytes()[..], &signatures].concat(),
+            );
+        }
+        push_extension(&mut extensions, EXT_RENEGOTIATION_INFO, &[0]);
+    }
+
+    let ciphers = u16_list(ciphers);
+    let mut body = version.to_be_bytes().to_vec();
+    body.extend_from_slice(&random);
+    body.push(0);
+    body.extend_from_slice(&(ciphers.len() as u16).to_be_bytes());
+    body.extend_from_slice(&ciphers);
+    body.extend_from_slice(&[1, 0]);
+    if !extensions.is_empty() {
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+    }
+
+    let mut handshake = vec![0x01];
+    handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&body);
+    let mut record = vec![0x16];
+    record.extend_from_slice(&version.min(0x0301).to_be_bytes());
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+/// ClientHello 记录的 JA3 原始字符串：`SSLVersion,Cipher,SSLExtension,EllipticCurve,EllipticCurvePointFormat`
+pub fn ja3_string(record: &[u8]) -> Option<String> {
+    if record.len() < 11 || record[0] != 0x16 || record[5] != 0x01 {
+        return None;
+    }
+    let version = u16::from_be_bytes([record[9], record[10]]);
+    let session_id_len = usize::from(*record.get(43)?);
+    let mut pos = 44 + session_id_len;
+    let ciphers_len = usize::from(u16::from_be_bytes([*record.get(pos)?, *record.get(pos + 1)?]));
+    let ciphers = record.get(pos + 2..pos + 2 + ciphers_len)?;
+    pos += 2 + ciphers_len;
+    pos += 1 + usize::from(*record.get(pos)?);
+    let extensions_len = usize::from(u16::from_be_bytes([*record.get(pos)?, *record.get(pos + 1)?]));
+    let extensions = parse_extensions(record.get(pos + 2..pos + 2 + extensions_len)?);
+
+    let join = |values: &mut dyn Iterator<Item = u16>| {
+        values.filter(|&value| !is_grease(value)).map(|value| value.to_string()).collect::<Vec<_>>().join("-")
+    };
+    let u16s = |data: &[u8]| data.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect::<Vec<_>>();
+    let extension = |kind: u16| extensions.iter().find(|(k, _)| *k == kind).map(|(_, body)| *body);
+    let groups = extension(EXT_SUPPORTED_GROUPS).and_then(|body| body.get(2..)).map(u16s).unwrap_or_default();
+    let point_formats: Vec<String> = extension(EXT_EC_POINT_FORMATS)
+        .and_then(|body| body.get(1..))
+        .unwrap_or_default()
+        .iter()
+        .map(|format| format.to_string())
+        .collect();
+    Some(format!(
+        "{},{},{},{},{}",
+        version,
+        join(&mut u16s(ciphers).into_iter()),
+        join(&mut extensions.iter().map(|(kind, _)| *kind)),
+        join(&mut groups.into_iter()),
+        point_formats.join("-")
+    ))
+}
+
+/// JA3/JA3S 字符串的 MD5
+pub fn ja3_hash(ja3_string: &str) -> String {
+    format!("{:x}", md5::compute(ja3_string))
+}
+
+/// 首个记录已完整读到，或读到的不是 TLS 握手记录
+fn first_record_done(data: &[u8]) -> bool {
+    if data.first() != Some(&0x16) {
+        return true;
+    }
+    data.len() >= 5 && data.len() >= 5 + usize::from(u16::from_be_bytes([data[3], data[4]]))
+}
+
+/// 发送 ClientHello 并从 ServerHello 计算 JA3S；对方不是 TLS 服务时返回 `None`
+pub async fn fingerprint(
+    connector: &dyn Connector,
+    addr: SocketAddr,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+) -> Option<TlsFingerprint> {
+    let mut stream = timeout(connect_timeout, connector.connect(addr)).await.ok()?.ok()?;
+    let hello = client_hello();
+    stream.write_all(&hello).await.ok()?;
+    let mut response = Vec::new();
+    read_until(&mut stream, &mut response, 0, MAX_HANDSHAKE_LEN, Instant::now() + read_timeout, first_record_done).await;
+    let server_hello = parse_server_hello(&response)?;
+    let ja3s_string = server_hello.ja3s_string();
+    Some(TlsFingerprint {
+        ja3: ja3_hash(&ja3_string(&hello)?),
+        ja3s: ja3_hash(&ja3s_string),
+        ja3s_string,
+        version: Some(tls_version_name(server_hello.version()).to_string()),
+    })
+}
+
+pub fn tls_version_name(version: u16) -> &'static str {
+    match version {
+        0x0300 => "SSL 3.0",
+        0x0301 => "TLS 1.0",
+        0x0302 => "TLS 1.1",
+        0x0303 => "TLS 1.2",
+        0x0304 => "TLS 1.3",
+        _ => "TLS",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::{MockConnector, MockService};
+    use crate::service_detector::{ServiceDetector, ServiceMethod};
+    use crate::target::ScanTarget;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_ja3_fingerprint() {
+        // 随机部分不影响 JA3
+        let hello = client_hello();
+        let expected = "771,4865-4866-4867-49195-49199-49196-49200-52393-52392-156-157-47-53,10-11-13-43-51-65281,29-23-24,0";
+        assert_eq!(ja3_string(&hello).as_deref(), Some(expected));
+        assert_eq!(ja3_string(&client_hello()).as_deref(), Some(expected));
+        assert_eq!(ja3_hash(""), "d41d8cd98f00b204e9800998ecf8427e");
+
+        // TLS 1.3 ServerHello：TLS_AES_128_GCM_SHA256，key_share 和 supported_versions 扩展
+        let extensions = [0x00, 0x33, 0x00, 0x02, 0x00, 0x1d, 0x00, 0x2b, 0x00, 0x02, 0x03, 0x04];
+        let mut server_hello = vec![0x16, 0x03, 0x03, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x03];
+        server_hello.extend_from_slice(&[0u8; 32]);
+        server_hello.extend_from_slice(&[0x00, 0x13, 0x01, 0x00, 0x00, extensions.len() as u8]);
+        server_hello.extend_from_slice(&extensions);
+        let len = (server_hello.len() - 5) as u16;
+        server_hello[3..5].copy_from_slice(&len.to_be_bytes());
+        server_hello[8] = (len - 4) as u8;
+
+        let parsed = parse_server_hello(&server_hello).unwrap();
+        assert_eq!(parsed.version(), 0x0304);
+        assert_eq!(parsed.ja3s_string(), "771,4865,51-43");
+        assert!(parse_server_hello(b"HTTP/1.1 400 Bad Request\r\n").is_none());
+
+        let connector = MockConnector::new()
+            .service(443, MockService::Reply(server_hello.clone()))
+            .service(22, MockService::Banner(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec()));
+        let timeout = Duration::from_millis(200);
+        let tls = fingerprint(&connector, "192.0.2.1:443".parse().unwrap(), timeout, timeout).await.unwrap();
+        assert_eq!(tls.ja3, ja3_hash(expected));
+        assert_eq!(tls.ja3s, ja3_hash("771,4865,51-43"));
+        assert_eq!(tls.version.as_deref(), Some("TLS 1.3"));
+        assert!(fingerprint(&connector, "192.0.2.1:22".parse().unwrap(), timeout, timeout).await.is_none());
+
+        // 没有指纹的端口完成握手时按 TLS 报告，已识别的服务附带 JA3S
+        let connector = Arc::new(
+            connector
+                .service(10443, MockService::Reply(server_hello.clone()))
+                // 端口号与协议相反：8080 上是 HTTPS，8443 上是明文 HTTP
+                .service(8080, MockService::Reply(server_hello.clone()))
+                .service(8443, MockService::Reply(b"HTTP/1.1 200 OK\r\nServer: nginx/1.24.0\r\n\r\n<title>Admin</title>".to_vec())),
+        );
+        let detector = ServiceDetector::new()
+            .with_timeout(timeout)
+            .with_connector(connector.clone())
+            .with_tls_fingerprint(true);
+        let target: ScanTarget = "192.0.2.1".parse().unwrap();
+        let service = detector.detect(target, 10443).await.unwrap().unwrap();
+        assert_eq!((service.name.as_str(), service.method), ("TLS", ServiceMethod::Tls));
+        assert_eq!(service.protocol_version.as_deref(), Some("TLS 1.3"));
+        assert_eq!(service.tls.unwrap().ja3s_string, "771,4865,51-43");
+        let ssh = detector.detect(target, 22).await.unwrap().unwrap();
+        assert_eq!((ssh.name.as_str(), ssh.tls), ("SSH", None));
+
+        // Web 端口不开 JA3 也会尝试握手，按握手结果区分 HTTP 和 HTTPS
+        let detector = ServiceDetector::new().with_timeout(timeout).with_connector(connector);
+        let https = detector.detect(target, 8080).await.unwrap().unwrap();
+        assert_eq!((https.name.as_str(), https.method), ("HTTPS", ServiceMethod::Tls));
+        assert_eq!((https.protocol_version.as_deref(), https.tls), (Some("TLS 1.3"), None));
+        let plain = detector.detect(target, 8443).await.unwrap().unwrap();
+        assert_eq!((plain.name.as_str(), plain.title.as_deref()), ("HTTP", Some("Admin")));
+        assert_eq!(plain.protocol_version.as_deref(), Some("HTTP/1.1"));
+
+        // 同时完成握手并应答明文请求的 TLS Web 端口保留明文识别出的指纹和标题
+        let connector = MockConnector::new().service(
+            8443,
+            MockService::Replies(vec![
+                (vec![0x16], server_hello),
+                (b"GET".to_vec(), b"HTTP/1.1 200 OK\r\nServer: nginx/1.24.0\r\n\r\n<title>Admin</title>".to_vec()),
+            ]),
+        );
+        let detector = ServiceDetector::new().with_timeout(timeout).with_connector(Arc::new(connector));
+        let https = detector.detect(target, 8443).await.unwrap().unwrap();
+        assert_eq!((https.name.as_str(), https.method), ("HTTPS", ServiceMethod::Tls));
+        assert_eq!(https.title.as_deref(), Some("Admin"));
+        assert!(https.fingerprint.is_some());
+        assert_eq!(https.protocol_version.as_deref(), Some("TLS 1.3"));
+    }
+}
@@ -0,0 +1,463 @@
+use crate::anomaly::Anomaly;
+use crate::connector::Connector;
+use crate::service_detector::{DetectedService, ServiceMethod};
+use crate::service_fingerprints::read_until;
+use crate::tls::{legacy_client_hello, tls_version_name};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::time::{timeout, Instant};
+
+/// 证书在这么多天内到期时提前报告
+pub const CERT_EXPIRY_WARNING_DAYS: u64 = 30;
+
+/// 读取服务端握手消息的长度上限，足够容纳常见的证书链
+const MAX_FLIGHT_LEN: usize = 64 * 1024;
+
+/// 读取证书时提供的 TLS 1.2 密码套件：ECDHE/RSA 的 GCM、ChaCha20 和 CBC 套件，兼容较旧的服务
+const AUDIT_CIPHER_SUITES: [u16; 16] = [
+    0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc009, 0xc013, 0xc00a, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+    0x003c, 0x000a,
+];
+
+/// RFC 8996 已弃用的协议版本：SSL 3.0、TLS 1.0、TLS 1.1
+const WEAK_VERSIONS: [u16; 3] = [0x0300, 0x0301, 0x0302];
+
+/// 弱密码套件：NULL、出口级、匿名、DES/3DES 和 RC4
+const WEAK_CIPHERS: [(u16, &str); 18] = [
+    (0x0001, "TLS_RSA_WITH_NULL_MD5"),
+    (0x0002, "TLS_RSA_WITH_NULL_SHA"),
+    (0x003b, "TLS_RSA_WITH_NULL_SHA256"),
+    (0x0003, "TLS_RSA_EXPORT_WITH_RC4_40_MD5"),
+    (0x0006, "TLS_RSA_EXPORT_WITH_RC2_CBC_40_MD5"),
+    (0x0008, "TLS_RSA_EXPORT_WITH_DES40_CBC_SHA"),
+    (0x0014, "TLS_DHE_RSA_EXPORT_WITH_DES40_CBC_SHA"),
+    (0x0018, "TLS_DH_anon_WITH_RC4_128_MD5"),
+    (0x001b, "TLS_DH_anon_WITH_3DES_EDE_CBC_SHA"),
+    (0x0034, "TLS_DH_anon_WITH_AES_128_CBC_SHA"),
+    (0x0009, "TLS_RSA_WITH_DES_CBC_SHA"),
+    (0x000a, "TLS_RSA_WITH_3DES_EDE_CBC_SHA"),
+    (0x0016, "TLS_DHE_RSA_WITH_3DES_EDE_CBC_SHA"),
+    (0xc012, "TLS_ECDHE_RSA_WITH_3DES_EDE_CBC_SHA"),
+    (0x0004, "TLS_RSA_WITH_RC4_128_MD5"),
+    (0x0005, "TLS_RSA_WITH_RC4_128_SHA"),
+    (0xc011, "TLS_ECDHE_RSA_WITH_RC4_128_SHA"),
+    (0xc007, "TLS_ECDHE_ECDSA_WITH_RC4_128_SHA"),
+];
+
+/// 服务端证书中用于报告的字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    /// 主体的 CN 和 O，如 `CN=example.com, O=Example`
+    pub subject: String,
+    pub issuer: String,
+    /// 到期时间，RFC 3339 格式（UTC）
+    pub not_after: String,
+    /// 到期时间的 Unix 时间戳
+    pub not_after_unix: i64,
+    /// 颁发者与主体相同
+    pub self_signed: bool,
+}
+
+/// 一个 TLS 端口的检查结果（`--tls-audit`）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsAudit {
+    /// 以 TLS 1.2 及以下版本握手读到的叶子证书；只支持 TLS 1.3 的服务证书是加密传输的，读不到
+    pub certificate: Option<Certificate>,
+    /// 服务端接受的已弃用版本
+    pub weak_versions: Vec<u16>,
+    /// 只提供弱密码套件时服务端选中的套件
+    pub weak_cipher: Option<u16>,
+}
+
+impl TlsAudit {
+    /// 对 TLS 端口额外进行五次握手：一次读取证书，三次分别只提供 SSL 3.0、TLS 1.0、TLS 1.1，
+    /// 一次只提供弱密码套件；都在收到 ServerHello（和证书）后断开，不完成密钥交换
+    pub async fn run(connector: &dyn Connector, addr: SocketAddr, connect_timeout: Duration, read_timeout: Duration) -> Self {
+        let weak_ciphers: Vec<u16> = WEAK_CIPHERS.iter().map(|(id, _)| *id).collect();
+        let handshake = |version: u16, ciphers: &[u16], need_certificate: bool| {
+            let hello = legacy_client_hello(version, ciphers);
+            async move {
+                let mut stream = timeout(connect_timeout, connector.connect(addr)).await.ok()?.ok()?;
+                stream.write_all(&hello).await.ok()?;
+                let mut response = Vec::new();
+                let deadline = Instant::now() + read_timeout;
+                read_until(&mut stream, &mut response, 0, MAX_FLIGHT_LEN, deadline, |data| {
+                    let flight = Flight::parse(data);
+                    flight.finished || (flight.server_hello.is_some() && (!need_certificate || flight.certificate.is_some()))
+                })
+                .await;
+                Some(Flight::parse(&response))
+            }
+        };
+        let (full, versions, cipher) = futures::join!(
+            handshake(0x0303, &AUDIT_CIPHER_SUITES, true),
+            futures::future::join_all(WEAK_VERSIONS.map(|version| async move {
+                let flight = handshake(version, &AUDIT_CIPHER_SUITES, false).await?;
+                flight.server_hello.filter(|(negotiated, _)| *negotiated == version).map(|_| version)
+            })),
+            handshake(0x0303, &weak_ciphers, false),
+        );
+        TlsAudit {
+            certificate: full.and_then(|flight| flight.certificate).and_then(|der| parse_certificate(&der)),
+            weak_versions: versions.into_iter().flatten().collect(),
+            weak_cipher: cipher
+                .and_then(|flight| flight.server_hello)
+                .map(|(_, cipher)| cipher)
+                .filter(|cipher| weak_ciphers.contains(cipher)),
+        }
+    }
+
+    /// 需要报告的问题：证书已过期或即将到期、自签名、已弃用的版本和弱密码套件
+    pub fn findings(&self, port: u16, now: SystemTime) -> Vec<Anomaly> {
+        let mut findings = Vec::new();
+        if let Some(certificate) = &self.certificate {
+            let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+            let remaining = certificate.not_after_unix - now;
+            if remaining < 0 {
+                findings.push(Anomaly::CertExpired {
+                    port,
+                    not_after: certificate.not_after.clone(),
+                });
+            } else if remaining < (CERT_EXPIRY_WARNING_DAYS * 86400) as i64 {
+                findings.push(Anomaly::CertExpiring {
+                    port,
+                    not_after: certificate.not_after.clone(),
+                    days: (remaining as u64).div_ceil(86400),
+                });
+            }
+            if certificate.self_signed {
+                findings.push(Anomaly::SelfSignedCert {
+                    port,
+                    subject: certificate.subject.clone(),
+                });
+            }
+        }
+        for &version in &self.weak_versions {
+            findings.push(Anomaly::WeakTlsVersion {
+                port,
+                version: tls_version_name(version).to_string(),
+            });
+        }
+        if let Some(cipher) = self.weak_cipher {
+            findings.push(Anomaly::WeakCipher {
+                port,
+                cipher: cipher_name(cipher),
+            });
+        }
+        findings
+    }
+
+    /// 证书摘要，写入主机备注；所有证书都报告到期时间，不论是否有问题
+    pub fn summary(&self, port: u16) -> String {
+        match &self.certificate {
+            Some(certificate) => format!(
+                "端口 {} 的证书：主体 {}，颁发者 {}，有效期至 {}",
+                port, certificate.subject, certificate.issuer, certificate.not_after
+            ),
+            None => format!("端口 {} 未能以 TLS 1.2 及以下版本读取证书（可能只支持 TLS 1.3）", port),
+        }
+    }
+}
+
+/// 需要检查的服务：完成了 TLS 握手，或协议版本来自 ServerHello
+pub fn is_tls_service(service: &DetectedService) -> bool {
+    service.method == ServiceMethod::Tls
+        || service.tls.is_some()
+        || service.protocol_version.as_deref().is_some_and(|version| version.starts_with("TLS") || version.starts_with("SSL"))
+}
+
+fn cipher_name(cipher: u16) -> String {
+    match WEAK_CIPHERS.iter().find(|(id, _)| *id == cipher) {
+        Some((_, name)) => name.to_string(),
+        None => format!("0x{:04x}", cipher),
+    }
+}
+
+/// 服务端第一轮握手消息中关心的部分
+#[derive(Debug, Default)]
+struct Flight {
+    /// ServerHello 中的版本和密码套件
+    server_hello: Option<(u16, u16)>,
+    /// 证书链中第一个（叶子）证书的 DER
+    certificate: Option<Vec<u8>>,
+    /// 已收到 ServerHelloDone、告警或非 TLS 的数据，不会再有需要的内容
+    finished: bool,
+}
+
+impl Flight {
+    /// 拼接各握手记录的内容后逐条解析消息，末尾不完整的消息忽略
+    fn parse(mut data: &[u8]) -> Self {
+        let mut flight = Flight::default();
+        let mut handshake = Vec::new();
+        while data.len() >= 5 {
+            let len = usize::from(u16::from_be_bytes([data[3], data[4]]));
+            match data[0] {
+                0x16 => handshake.extend_from_slice(&data[5..(5 + len).min(data.len())]),
+                // 告警（如不支持的版本）或不是 TLS
+                _ => flight.finished = true,
+            }
+            if flight.finished || data.len() < 5 + len {
+                break;
+            }
+            data = &data[5 + len..];
+        }
+
+        let mut messages = handshake.as_slice();
+        while messages.len() >= 4 {
+            let len = usize::from(messages[1]) << 16 | usize::from(messages[2]) << 8 | usize::from(messages[3]);
+            let Some(body) = messages.get(4..4 + len) else {
+                break;
+            };
+            match messages[0] {
+                // ServerHello：版本、32 字节随机数、会话 ID、密码套件
+                2 if body.len() >= 35 => {
+                    let pos = 35 + usize::from(body[34]);
+                    if let Some(cipher) = body.get(pos..pos + 2) {
+                        let version = u16::from_be_bytes([body[0], body[1]]);
+                        flight.server_hello = Some((version, u16::from_be_bytes([cipher[0], cipher[1]])));
+                    }
+                }
+                // Certificate：3 字节的证书链长度，之后每个证书前有 3 字节长度
+                11 if body.len() >= 6 => {
+                    let first = usize::from(body[3]) << 16 | usize::from(body[4]) << 8 | usize::from(body[5]);
+                    flight.certificate = body.get(6..6 + first).map(<[u8]>::to_vec);
+                }
+                14 => flight.finished = true,
+                _ => {}
+            }
+            messages = &messages[4 + len..];
+        }
+        flight
+    }
+}
+
+/// 一个 DER 元素
+struct Der<'a> {
+    tag: u8,
+    content: &'a [u8],
+    /// 包括标签和长度的整个元素
+    raw: &'a [u8],
+    /// 之后的数据
+    rest: &'a [u8],
+}
+
+/// 读取一个 DER 元素，只支持 4 字节以内的长度
+fn der_element(data: &[u8]) -> Option<Der<'_>> {
+    let tag = *data.first()?;
+    let first = *data.get(1)?;
+    let (len, header) = match first {
+        0..=0x7f => (usize::from(first), 2),
+        0x81..=0x84 => {
+            let count = usize::from(first & 0x7f);
+            let len = data.get(2..2 + count)?.iter().fold(0usize, |len, &b| len << 8 | usize::from(b));
+            (len, 2 + count)
+        }
+        _ => return None,
+    };
+    let end = header.checked_add(len)?;
+    Some(Der {
+        tag,
+        content: data.get(header..end)?,
+        raw: &data[..end],
+        rest: &data[end..],
+    })
+}
+
+/// 从证书 DER 中取出主体、颁发者和到期时间
+///
+/// Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signature }，
+/// tbsCertificate 依次为可选的版本、序列号、签名算法、颁发者、有效期、主体……
+fn parse_certificate(der: &[u8]) -> Option<Certificate> {
+    let certificate = der_element(der).filter(|element| element.tag == 0x30)?;
+    let tbs = der_element(certificate.content).filter(|element| element.tag == 0x30)?;
+    let mut fields = tbs.content;
+    if fields.first() == Some(&0xa0) {
+        fields = der_element(fields)?.rest;
+    }
+    let serial = der_element(fields)?;
+    let algorithm = der_element(serial.rest)?;
+    let issuer = der_element(algorithm.rest)?;
+    let validity = der_element(issuer.rest)?;
+    let subject = der_element(validity.rest)?;
+    let not_before = der_element(validity.content)?;
+    let not_after = der_element(not_before.rest)?;
+    let (not_after_text, not_after_unix) = parse_time(not_after.tag, not_after.content)?;
+    Some(Certificate {
+        subject: name_string(subject.raw),
+        issuer: name_string(issuer.raw),
+        not_after: not_after_text,
+        not_after_unix,
+        self_signed: issuer.raw == subject.raw,
+    })
+}
+
+/// 名称中的 CN 和 O，如 `CN=example.com, O=Example`；都没有时为 `-`
+fn name_string(name: &[u8]) -> String {
+    const COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+    const ORGANIZATION: [u8; 3] = [0x55, 0x04, 0x0a];
+    let Some(name) = der_element(name) else {
+        return "-".to_string();
+    };
+    let mut parts = Vec::new();
+    let mut sets = name.content;
+    while let Some(set) = der_element(sets) {
+        // 每个 RDN 是 SET { SEQUENCE { OID, 值 } }
+        let attribute = der_element(set.content).and_then(|attribute| {
+            let oid = der_element(attribute.content)?;
+            let value = der_element(oid.rest)?;
+            Some((oid.content, value.content))
+        });
+        if let Some((oid, value)) = attribute {
+            let label = match oid {
+                oid if oid == COMMON_NAME => Some("CN"),
+                oid if oid == ORGANIZATION => Some("O"),
+                _ => None,
+            };
+            if let Some(label) = label {
+                parts.push(format!("{}={}", label, String::from_utf8_lossy(value)));
+            }
+        }
+        sets = set.rest;
+    }
+    match parts.is_empty() {
+        true => "-".to_string(),
+        false => parts.join(", "),
+    }
+}
+
+/// UTCTime（`YYMMDDHHMMSSZ`，50 及以上为 19xx 年）或 GeneralizedTime（`YYYYMMDDHHMMSSZ`），
+/// 返回 RFC 3339 字符串和 Unix 时间戳
+fn parse_time(tag: u8, value: &[u8]) -> Option<(String, i64)> {
+    let text = std::str::from_utf8(value).ok()?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let year: i64 = text.get(..2)?.parse().ok()?;
+            (if year >= 50 { 1900 + year } else { 2000 + year }, text.get(2..)?)
+        }
+        0x18 => (text.get(..4)?.parse().ok()?, text.get(4..)?),
+        _ => return None,
+    };
+    let field = |range: std::ops::Range<usize>| rest.get(range)?.parse::<i64>().ok();
+    let (month, day, hour, minute, second) = (field(0..2)?, field(2..4)?, field(4..6)?, field(6..8)?, field(8..10)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // 由公历日期换算 Unix 天数（Howard Hinnant 的 days_from_civil）
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let text = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second);
+    Some((text, days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::{MockConnector, MockService};
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut element = vec![tag];
+        match content.len() {
+            len @ 0..=0x7f => element.push(len as u8),
+            len => element.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+        }
+        element.extend_from_slice(content);
+        element
+    }
+
+    fn name(cn: &str) -> Vec<u8> {
+        let attribute = der(0x30, &[der(0x06, &[0x55, 0x04, 0x03]), der(0x0c, cn.as_bytes())].concat());
+        der(0x30, &der(0x31, &attribute))
+    }
+
+    fn certificate(issuer: &str, subject: &str, not_after: &str) -> Vec<u8> {
+        let algorithm = der(0x30, &der(0x06, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b]));
+        let validity = der(0x30, &[der(0x17, b"240101000000Z"), der(0x18, not_after.as_bytes())].concat());
+        let tbs = [
+            der(0xa0, &der(0x02, &[2])),
+            der(0x02, &[0x01, 0x23]),
+            algorithm.clone(),
+            name(issuer),
+            validity,
+            name(subject),
+        ]
+        .concat();
+        der(0x30, &[der(0x30, &tbs), algorithm, der(0x03, &[0, 0xff])].concat())
+    }
+
+    /// 服务端的一轮握手消息：ServerHello、Certificate 和 ServerHelloDone，各占一个记录
+    fn server_flight(version: u16, cipher: u16, certificate: &[u8]) -> Vec<u8> {
+        let message = |kind: u8, body: &[u8]| {
+            let mut message = vec![kind];
+            message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            message.extend_from_slice(body);
+            let mut record = vec![0x16, 0x03, 0x03];
+            record.extend_from_slice(&(message.len() as u16).to_be_bytes());
+            record.extend_from_slice(&message);
+            record
+        };
+        let mut hello = version.to_be_bytes().to_vec();
+        hello.extend_from_slice(&[0u8; 32]);
+        hello.push(0);
+        hello.extend_from_slice(&cipher.to_be_bytes());
+        hello.push(0);
+        let mut chain = ((certificate.len() + 3) as u32).to_be_bytes()[1..].to_vec();
+        chain.extend_from_slice(&(certificate.len() as u32).to_be_bytes()[1..]);
+        chain.extend_from_slice(certificate);
+        [message(2, &hello), message(11, &chain), message(14, &[])].concat()
+    }
+
+    #[tokio::test]
+    async fn test_tls_audit() {
+        let self_signed = certificate("test.local", "test.local", "20250601120000Z");
+        let parsed = parse_certificate(&self_signed).unwrap();
+        assert_eq!((parsed.subject.as_str(), parsed.not_after.as_str()), ("CN=test.local", "2025-06-01T12:00:00Z"));
+        assert_eq!(parsed.not_after_unix, 1_748_779_200);
+        assert!(parsed.self_signed);
+        assert!(!parse_certificate(&certificate("Example CA", "example.com", "20250601120000Z")).unwrap().self_signed);
+        assert!(parse_certificate(b"\x30\x05garbage").is_none());
+
+        // 现代服务：只以 TLS 1.2 和强密码套件应答
+        let modern = server_flight(0x0303, 0xc02f, &certificate("Example CA", "example.com", "20300101000000Z"));
+        // 旧服务：总是以 TLS 1.0 和 3DES 应答，证书自签名且已过期
+        let legacy = server_flight(0x0301, 0x000a, &self_signed);
+        let connector = MockConnector::new()
+            .service(443, MockService::Reply(modern))
+            .service(8443, MockService::Reply(legacy));
+        let timeout = Duration::from_millis(200);
+        let audit = |port| TlsAudit::run(&connector, SocketAddr::from(([192, 0, 2, 1], port)), timeout, timeout);
+
+        let now = UNIX_EPOCH + Duration::from_secs(1_893_456_000 - 10 * 86400); // 2030-01-01 前 10 天
+        let modern = audit(443).await;
+        assert_eq!((modern.weak_versions.len(), modern.weak_cipher), (0, None));
+        assert_eq!(modern.summary(443), "端口 443 的证书：主体 CN=example.com，颁发者 CN=Example CA，有效期至 2030-01-01T00:00:00Z");
+        assert_eq!(
+            modern.findings(443, now),
+            vec![Anomaly::CertExpiring {
+                port: 443,
+                not_after: "2030-01-01T00:00:00Z".to_string(),
+                days: 10
+            }]
+        );
+
+        let legacy = audit(8443).await;
+        assert_eq!((legacy.weak_versions.as_slice(), legacy.weak_cipher), (&[0x0301][..], Some(0x000a)));
+        let findings = legacy.findings(8443, now);
+        assert_eq!(findings.len(), 4);
+        assert!(matches!(&findings[0], Anomaly::CertExpired { not_after, .. } if not_after == "2025-06-01T12:00:00Z"));
+        assert!(matches!(&findings[1], Anomaly::SelfSignedCert { subject, .. } if subject == "CN=test.local"));
+        assert!(matches!(&findings[2], Anomaly::WeakTlsVersion { version, .. } if version == "TLS 1.0"));
+        assert!(matches!(&findings[3], Anomaly::WeakCipher { cipher, .. } if cipher == "TLS_RSA_WITH_3DES_EDE_CBC_SHA"));
+
+        // 不是 TLS 服务
+        let silent = MockConnector::new().service(443, MockService::Banner(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec()));
+        let none = TlsAudit::run(&silent, SocketAddr::from(([192, 0, 2, 1], 443)), timeout, timeout).await;
+        assert_eq!(none, TlsAudit::default());
+    }
+}
@@ -0,0 +1,389 @@
+use crate::events::{ScanEvent, ScanObserver};
+use crate::progress::ScanProgress;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::crossterm::{cursor, execute};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::HashMap;
+use std::io::{self, Stderr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// 界面刷新间隔，也是检查按键的间隔
+const FRAME_INTERVAL: Duration = Duration::from_millis(200);
+/// 计算当前速率的时间窗口
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// 输出区最多显示的行数
+const MAX_LOG_LINES: usize = 6;
+
+/// 全屏界面中一个主机的状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostRow {
+    pub target: String,
+    pub finished: bool,
+    pub open_ports: usize,
+    pub os: Option<String>,
+}
+
+/// 全屏界面中一个开放端口，识别出服务后补上服务名
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRow {
+    pub target: String,
+    pub port: u16,
+    pub protocol: String,
+    pub service: Option<String>,
+}
+
+#[derive(Default)]
+struct TuiState {
+    hosts: Vec<HostRow>,
+    /// 目标 -> hosts 中的下标
+    host_index: HashMap<String, usize>,
+    ports: Vec<PortRow>,
+}
+
+impl TuiState {
+    fn host(&mut self, target: &str) -> &mut HostRow {
+        let index = match self.host_index.get(target) {
+            Some(&index) => index,
+            None => {
+                self.hosts.push(HostRow {
+                    target: target.to_string(),
+                    finished: false,
+                    open_ports: 0,
+                    os: None,
+                });
+                self.host_index.insert(target.to_string(), self.hosts.len() - 1);
+                self.hosts.len() - 1
+            }
+        };
+        &mut self.hosts[index]
+    }
+}
+
+/// 订阅扫描事件，记录全屏界面显示的主机状态和开放端口
+#[derive(Default)]
+pub struct TuiObserver {
+    state: Mutex<TuiState>,
+}
+
+impl TuiObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按开始扫描的顺序排列的主机
+    pub fn hosts(&self) -> Vec<HostRow> {
+        self.state.lock().unwrap().hosts.clone()
+    }
+
+    /// 按发现顺序排列的开放端口
+    pub fn ports(&self) -> Vec<PortRow> {
+        self.state.lock().unwrap().ports.clone()
+    }
+}
+
+impl ScanObserver for TuiObserver {
+    fn on_event(&self, event: &ScanEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            ScanEvent::HostStarted { target } => {
+                state.host(target);
+            }
+            ScanEvent::PortOpen { target, port, protocol } => {
+                state.host(target).open_ports += 1;
+                state.ports.push(PortRow {
+                    target: target.clone(),
+                    port: *port,
+                    protocol: protocol.clone(),
+                    service: None,
+                });
+            }
+            ScanEvent::ServiceDetected { target, port, protocol, service } => {
+                let found = state
+                    .ports
+                    .iter_mut()
+                    .rev()
+                    .find(|row| row.target == *target && row.port == *port && row.protocol == *protocol);
+                match found {
+                    Some(row) => row.service = Some(service.to_string()),
+                    // UDP 端口只在识别出服务后才报告
+                    None => state.ports.push(PortRow {
+                        target: target.clone(),
+                        port: *port,
+                        protocol: protocol.clone(),
+                        service: Some(service.to_string()),
+                    }),
+                }
+            }
+            ScanEvent::OsDetected { target, os } => {
+                state.host(target).os = Some(match &os.version {
+                    Some(version) => format!("{} {}", os.name, version),
+                    None => os.name.clone(),
+                });
+            }
+            ScanEvent::HostFinished { target, open_ports } => {
+                let host = state.host(target);
+                host.finished = true;
+                host.open_ports = *open_ports;
+            }
+        }
+    }
+}
+
+/// 在标准错误上绘制的全屏界面（`--tui`），扫描在后台进行
+///
+/// 界面在独立线程中按 [`FRAME_INTERVAL`] 刷新，主机和端口来自 [`TuiObserver`]，
+/// 进度、速率和暂存的输出来自 [`ScanProgress`]。按 q 或 Ctrl-C 通过 [`Tui::cancel_signal`]
+/// 通知调用方中止扫描，界面继续绘制直到调用方收尾后调用 [`Tui::finish`]。
+pub struct Tui {
+    stop: Arc<AtomicBool>,
+    cancel: watch::Receiver<bool>,
+    progress: Arc<ScanProgress>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Tui {
+    /// 切换到备用屏幕并开始绘制；界面退出前 `progress` 的输出先暂存
+    pub fn start(observer: Arc<TuiObserver>, progress: Arc<ScanProgress>) -> io::Result<Self> {
+        enable_raw_mode()?;
+        let mut stderr = io::stderr();
+        if let Err(e) = execute!(stderr, EnterAlternateScreen, cursor::Hide) {
+            restore_terminal();
+            return Err(e);
+        }
+        let terminal = match Terminal::new(CrosstermBackend::new(stderr)) {
+            Ok(terminal) => terminal,
+            Err(e) => {
+                restore_terminal();
+                return Err(e);
+            }
+        };
+        progress.hold_lines();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (cancel_tx, cancel) = watch::channel(false);
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            let progress = progress.clone();
+            move || render_loop(terminal, &observer, &progress, &stop, &cancel_tx)
+        });
+        Ok(Self {
+            stop,
+            cancel,
+            progress,
+            thread: Some(thread),
+        })
+    }
+
+    /// 用户按 q 或 Ctrl-C 后变为 true
+    pub fn cancel_signal(&self) -> watch::Receiver<bool> {
+        self.cancel.clone()
+    }
+
+    /// 停止绘制并恢复终端，写出暂存的输出，之后的输出照常写到终端
+    pub fn finish(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        self.stop.store(true, Ordering::Relaxed);
+        let result = thread.join().unwrap_or(Ok(()));
+        restore_terminal();
+        self.progress.release_lines();
+        result
+    }
+}
+
+impl Drop for Tui {
+    /// 扫描出错提前返回时也要恢复终端
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stderr(), LeaveAlternateScreen, cursor::Show);
+}
+
+fn render_loop(
+    mut terminal: Terminal<CrosstermBackend<Stderr>>,
+    observer: &TuiObserver,
+    progress: &ScanProgress,
+    stop: &AtomicBool,
+    cancel: &watch::Sender<bool>,
+) -> io::Result<()> {
+    let started = Instant::now();
+    let mut window = (Instant::now(), progress.probes_sent());
+    let mut rate = 0.0;
+    while !stop.load(Ordering::Relaxed) {
+        let elapsed = window.0.elapsed();
+        if elapsed >= RATE_WINDOW {
+            let probes = progress.probes_sent();
+            rate = probes.saturating_sub(window.1) as f64 / elapsed.as_secs_f64();
+            window = (Instant::now(), probes);
+        }
+        let cancelling = *cancel.borrow();
+        terminal.draw(|frame| draw(frame, observer, progress, rate, started.elapsed(), cancelling))?;
+        if event::poll(FRAME_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (key.code == KeyCode::Char('q') || ctrl_c) {
+                    // 原始模式下 Ctrl-C 不产生 SIGINT，由界面通知调用方中止扫描
+                    cancel.send_replace(true);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, observer: &TuiObserver, progress: &ScanProgress, rate: f64, elapsed: Duration, cancelling: bool) {
+    // 有暂存的输出（如 --exec 的输出）时在底部显示最新几行
+    let lines = progress.held_lines(MAX_LOG_LINES);
+    let log_height = if lines.is_empty() { 0 } else { lines.len() as u16 + 2 };
+    let [header, body, log_area, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(3),
+        Constraint::Length(log_height),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [hosts_area, ports_area] =
+        Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(body);
+
+    let (scanned, total) = (progress.scanned_ports(), progress.total_ports());
+    let ratio = if total == 0 { 0.0 } else { (scanned as f64 / total as f64).min(1.0) };
+    let label = format!(
+        "端口 {}/{} · 探测 {} 次 · {:.0} 次/秒 · 用时 {}s",
+        scanned,
+        total,
+        progress.probes_sent(),
+        rate,
+        elapsed.as_secs()
+    );
+    let gauge = Gauge::default()
+        .block(Block::bordered().title(" 端口扫描 "))
+        .gauge_style(Style::default().fg(Color::Cyan))
+        .ratio(ratio)
+        .label(label);
+    frame.render_widget(gauge, header);
+
+    // 主机和端口都只显示最新的、放得下的部分
+    let hosts = observer.hosts();
+    let finished = hosts.iter().filter(|host| host.finished).count();
+    let visible = hosts_area.height.saturating_sub(3) as usize;
+    let rows = hosts.iter().skip(hosts.len().saturating_sub(visible)).map(|host| {
+        let status = if host.finished { "完成".green() } else { "扫描中".yellow() };
+        Row::new(vec![
+            Line::from(host.target.clone()),
+            Line::from(status),
+            Line::from(host.open_ports.to_string()),
+            Line::from(host.os.clone().unwrap_or_default()),
+        ])
+    });
+    let widths = [Constraint::Fill(3), Constraint::Length(6), Constraint::Length(8), Constraint::Fill(2)];
+    let table = Table::new(rows, widths)
+        .header(Row::new(["主机", "状态", "开放端口", "操作系统"]).bold())
+        .block(Block::bordered().title(format!(" 主机 {}/{} 完成 ", finished, hosts.len())));
+    frame.render_widget(table, hosts_area);
+
+    let ports = observer.ports();
+    let visible = ports_area.height.saturating_sub(2) as usize;
+    let items = ports.iter().skip(ports.len().saturating_sub(visible)).map(|row| {
+        let mut line = Line::from(format!("{} {}/{}", row.target, row.port, row.protocol));
+        if let Some(service) = &row.service {
+            line.push_span(" ");
+            line.push_span(service.clone().cyan());
+        }
+        ListItem::new(line)
+    });
+    let list = List::new(items).block(Block::bordered().title(format!(" 开放端口 {} ", ports.len())));
+    frame.render_widget(list, ports_area);
+
+    if !lines.is_empty() {
+        let items = lines.into_iter().map(ListItem::new);
+        frame.render_widget(List::new(items).block(Block::bordered().title(" 输出 ")), log_area);
+    }
+
+    let hint = match cancelling {
+        true => " 正在中止扫描…",
+        false => " 按 q 或 Ctrl-C 中止扫描",
+    };
+    frame.render_widget(Line::from(hint).dark_gray(), footer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service_detector::DetectedService;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    fn test_tui_observer() {
+        let observer = TuiObserver::new();
+        let target = "10.0.0.1".to_string();
+        observer.on_event(&ScanEvent::HostStarted { target: target.clone() });
+        observer.on_event(&ScanEvent::HostStarted { target: "10.0.0.2".into() });
+        observer.on_event(&ScanEvent::PortOpen {
+            target: target.clone(),
+            port: 22,
+            protocol: "TCP".into(),
+        });
+        observer.on_event(&ScanEvent::ServiceDetected {
+            target: target.clone(),
+            port: 22,
+            protocol: "TCP".into(),
+            service: DetectedService::new("SSH"),
+        });
+        observer.on_event(&ScanEvent::ServiceDetected {
+            target: target.clone(),
+            port: 53,
+            protocol: "UDP".into(),
+            service: DetectedService::new("DNS"),
+        });
+        observer.on_event(&ScanEvent::HostFinished {
+            target: target.clone(),
+            open_ports: 2,
+        });
+
+        let hosts = observer.hosts();
+        assert_eq!(hosts.len(), 2);
+        assert_eq!((hosts[0].finished, hosts[0].open_ports), (true, 2));
+        assert!(!hosts[1].finished);
+        let ports = observer.ports();
+        assert_eq!(ports.len(), 2);
+        assert_eq!((ports[0].port, ports[0].service.as_deref()), (22, Some("SSH")));
+        assert_eq!((ports[1].port, ports[1].protocol.as_str()), (53, "UDP"));
+
+        let progress = ScanProgress::hidden(100, 2);
+        for _ in 0..50 {
+            progress.add_probes(1);
+            progress.increment_port_scan();
+        }
+        progress.hold_lines();
+        progress.println("[10.0.0.1] exec output");
+        let mut terminal = Terminal::new(TestBackend::new(100, 15)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, &observer, &progress, 25.0, Duration::from_secs(3), false))
+            .unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("10.0.0.1 22/TCP SSH"));
+        assert!(screen.contains("1/2"));
+        assert!(screen.contains("50/100"));
+        // 暂存的输出显示在界面里，而不是直接写到标准错误
+        assert!(screen.contains("[10.0.0.1] exec output"));
+        assert_eq!(progress.held_lines(MAX_LOG_LINES).len(), 1);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::service_detector::{DetectedService, ServiceMethod, PORT_GUESS_CONFIDENCE};
+
+/// 从协议应答中解析出版本时的置信度
+const PARSED_RESPONSE_CONFIDENCE: f32 = 0.95;
+
+/// NTP 控制消息（mode 6）READVAR 请求：LI=0、VN=2、mode=6，opcode 2，序号 1
+const NTP_READVAR: [u8; 12] = [0x16, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// SNMPv1 GetRequest，community 为 public，查询 sysDescr（1.3.6.1.2.1.1.1.0）
+const SNMP_GET_SYSDESCR: [u8; 40] = [
+    0x30, 0x26, // SEQUENCE
+    0x02, 0x01, 0x00, // version: 1
+    0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', // community
+    0xa0, 0x19, // GetRequest-PDU
+    0x02, 0x01, 0x01, // request-id
+    0x02, 0x01, 0x00, // error-status
+    0x02, 0x01, 0x00, // error-index
+    0x30, 0x0e, 0x30, 0x0c, // varbind 列表
+    0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, // sysDescr.0
+    0x05, 0x00, // NULL
+];
+
+/// DNS 查询 `version.bind` CHAOS TXT 记录
+const DNS_VERSION_BIND: [u8; 30] = [
+    0x52, 0x53, // id
+    0x01, 0x00, // 标准查询，期望递归
+    0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // QDCOUNT=1
+    0x07, b'v', b'e', b'r', b's', b'i', b'o', b'n', 0x04, b'b', b'i', b'n', b'd', 0x00,
+    0x00, 0x10, // TXT
+    0x00, 0x03, // CH
+];
+
+/// 探测包所属的 UDP 服务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UdpService {
+    Dns,
+    Ntp,
+    Snmp,
+}
+
+fn udp_service(port: u16) -> Option<UdpService> {
+    match port {
+        53 => Some(UdpService::Dns),
+        123 => Some(UdpService::Ntp),
+        161 => Some(UdpService::Snmp),
+        _ => None,
+    }
+}
+
+impl UdpService {
+    fn name(&self) -> &'static str {
+        match self {
+            UdpService::Dns => "DNS",
+            UdpService::Ntp => "NTP",
+            UdpService::Snmp => "SNMP",
+        }
+    }
+}
+
+/// DNS 类探测的载荷上限：不带 EDNS 的 DNS over UDP 报文最大 512 字节（RFC 1035）
+pub const DNS_PAYLOAD_LIMIT: usize = 512;
+
+/// 取不到路径 MTU 时的保守估计：IPv4 所有主机都必须能接收的数据报大小（RFC 791）
+pub const FALLBACK_MTU_V4: u16 = 576;
+/// IPv6 链路的最小 MTU（RFC 8200）
+pub const FALLBACK_MTU_V6: u16 = 1280;
+
+pub fn fallback_mtu(ipv6: bool) -> u16 {
+    if ipv6 {
+        FALLBACK_MTU_V6
+    } else {
+        FALLBACK_MTU_V4
+    }
+}
+
+/// 在 `mtu` 下不分片能发送的最大载荷：减去 IP 首部（IPv4 20、IPv6 40 字节）和 UDP 首部 8 字节
+pub fn unfragmented_limit(mtu: u16, ipv6: bool) -> usize {
+    let headers = if ipv6 { 40 + 8 } else { 20 + 8 };
+    usize::from(mtu).saturating_sub(headers)
+}
+
+/// 端口的专属载荷允许的大小：不超过不分片的上限，DNS 类探测另外不超过 [`DNS_PAYLOAD_LIMIT`]
+pub fn payload_limit(port: u16, mtu: u16, ipv6: bool) -> usize {
+    let limit = unfragmented_limit(mtu, ipv6);
+    match udp_service(port) {
+        Some(UdpService::Dns) => limit.min(DNS_PAYLOAD_LIMIT),
+        _ => limit,
+    }
+}
+
+/// 实际发送的载荷：专属载荷超出 `limit` 且不允许分片时改发空数据报
+pub fn sized_payload(port: u16, limit: usize, allow_fragment: bool) -> &'static [u8] {
+    let payload = payload(port);
+    if payload.len() > limit && !allow_fragment {
+        &[]
+    } else {
+        payload
+    }
+}
+
+/// 端口对应的探测载荷；没有专属载荷的端口发送空数据报
+pub fn payload(port: u16) -> &'static [u8] {
+    match udp_service(port) {
+        Some(UdpService::Dns) => &DNS_VERSION_BIND,
+        Some(UdpService::Ntp) => &NTP_READVAR,
+        Some(UdpService::Snmp) => &SNMP_GET_SYSDESCR,
+        None => &[],
+    }
+}
+
+/// 根据端口和应答识别 UDP 服务，能解析出版本信息时填入 `version`
+pub fn identify(port: u16, response: Option<&[u8]>) -> Option<DetectedService> {
+    let service = udp_service(port)?;
+    let version = response.and_then(|response| match service {
+        UdpService::Dns => parse_dns_version(response),
+        UdpService::Ntp => parse_ntp_version(response),
+        UdpService::Snmp => parse_snmp_sysdescr(response),
+    });
+    let (confidence, method) = match version {
+        Some(_) => (PARSED_RESPONSE_CONFIDENCE, ServiceMethod::Probe),
+        None => (PORT_GUESS_CONFIDENCE, ServiceMethod::PortGuess),
+    };
+    Some(DetectedService {
+        version,
+        confidence,
+        method,
+        ..DetectedService::new(service.name())
+    })
+}
+
+/// 从 mode 6 READVAR 应答的变量列表中取出 `version="..."`
+fn parse_ntp_version(response: &[u8]) -> Option<String> {
+    // 第二字节最高位为应答标志，低 5 位为 opcode
+    if response.len() < 12 || response[0] & 0x07 != 6 || response[1] & 0x80 == 0 {
+        return None;
+    }
+    let count = usize::from(u16::from_be_bytes([response[10], response[11]]));
+    let data = &response[12..response.len().min(12 + count)];
+    let text = String::from_utf8_lossy(data);
+    let start = text.find("version=\"")? + "version=\"".len();
+    let end = text[start..].find('"')?;
+    non_empty(&text[start..start + end])
+}
+
+/// 读取一个 BER TLV，返回标签、值和剩余数据
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first & 0x80 == 0 {
+        (usize::from(first), rest)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let len = rest[..octets].iter().fold(0usize, |len, &b| len << 8 | usize::from(b));
+        (len, &rest[octets..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// 从 GetResponse 中取出第一个变量绑定的 OCTET STRING 值
+fn parse_snmp_sysdescr(response: &[u8]) -> Option<String> {
+    let (0x30, message, _) = read_tlv(response)? else {
+        return None;
+    };
+    let (_version, _, rest) = read_tlv(message)?;
+    let (_community, _, rest) = read_tlv(rest)?;
+    let (0xa2, pdu, _) = read_tlv(rest)? else {
+        return None;
+    };
+    let (_request_id, _, rest) = read_tlv(pdu)?;
+    let (_, error_status, rest) = read_tlv(rest)?;
+    if error_status.iter().any(|&b| b != 0) {
+        return None;
+    }
+    let (_error_index, _, rest) = read_tlv(rest)?;
+    let (0x30, varbinds, _) = read_tlv(rest)? else {
+        return None;
+    };
+    let (0x30, varbind, _) = read_tlv(varbinds)? else {
+        return None;
+    };
+    let (_oid, _, rest) = read_tlv(varbind)?;
+    let (0x04, value, _) = read_tlv(rest)? else {
+        return None;
+    };
+    non_empty(&String::from_utf8_lossy(value))
+}
+
+/// 从 `version.bind` 应答的第一条 TXT 记录取出版本字符串
+fn parse_dns_version(response: &[u8]) -> Option<String> {
+    if response.len() < 12 || response[..2] != DNS_VERSION_BIND[..2] || response[2] & 0x80 == 0 {
+        return None;
+    }
+    // RCODE 非 0（如 REFUSED）或没有应答记录
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+    if response[3] & 0x0f != 0 || ancount == 0 {
+        return None;
+    }
+    let mut pos = skip_name(response, 12)? + 4; // 问题：QTYPE + QCLASS
+    pos = skip_name(response, pos)?;
+    let record = response.get(pos..pos + 10)?;
+    let rtype = u16::from_be_bytes([record[0], record[1]]);
+    let rdlen = usize::from(u16::from_be_bytes([record[8], record[9]]));
+    if rtype != 0x0010 {
+        return None;
+    }
+    let rdata = response.get(pos + 10..pos + 10 + rdlen)?;
+    let (&len, text) = rdata.split_first()?;
+    non_empty(&String::from_utf8_lossy(text.get(..usize::from(len))?))
+}
+
+/// 跳过 `pos` 处的域名（标签序列或压缩指针），返回其后的位置
+fn skip_name(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + usize::from(len),
+        }
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_udp_versions() {
+        // ntpd 对 READVAR 的应答（截取）
+        let mut ntp = vec![0x16, 0x82, 0x00, 0x01, 0x06, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let vars = b"version=\"ntpd 4.2.8p15@1.3728-o Wed Feb 16 17:13:02 UTC 2022 (1)\",\r\nprocessor=\"x86_64\", system=\"Linux/5.15.0\"";
+        ntp[11] = vars.len() as u8;
+        ntp.extend_from_slice(vars);
+        assert_eq!(
+            parse_ntp_version(&ntp).as_deref(),
+            Some("ntpd 4.2.8p15@1.3728-o Wed Feb 16 17:13:02 UTC 2022 (1)")
+        );
+        assert_eq!(parse_ntp_version(&NTP_READVAR), None);
+
+        // net-snmp 对 sysDescr.0 的 GetResponse
+        let descr = b"Linux router 5.15.0-91-generic #101-Ubuntu SMP x86_64";
+        let mut snmp = vec![0x30, 0x00, 0x02, 0x01, 0x00, 0x04, 0x06];
+        snmp.extend_from_slice(b"public");
+        snmp.extend_from_slice(&[0xa2, 0x00, 0x02, 0x01, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x00, 0x30, 0x00]);
+        snmp.extend_from_slice(&[0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x04, descr.len() as u8]);
+        snmp.extend_from_slice(descr);
+        let len = snmp.len();
+        snmp[1] = (len - 2) as u8;
+        snmp[14] = (len - 15) as u8;
+        snmp[25] = (len - 26) as u8;
+        snmp[27] = (len - 28) as u8;
+        assert_eq!(parse_snmp_sysdescr(&snmp).as_deref(), Some(std::str::from_utf8(descr).unwrap()));
+        assert_eq!(parse_snmp_sysdescr(&SNMP_GET_SYSDESCR), None);
+
+        // BIND 的 version.bind 应答，应答名使用压缩指针
+        let mut dns = DNS_VERSION_BIND.to_vec();
+        dns[2] = 0x85;
+        dns[3] = 0x80;
+        dns[7] = 0x01;
+        dns.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x10, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x09]);
+        dns.extend_from_slice(b"9.18.18-0");
+        assert_eq!(parse_dns_version(&dns).as_deref(), Some("9.18.18-0"));
+        dns[3] = 0x85; // REFUSED
+        assert_eq!(parse_dns_version(&dns), None);
+
+        let service = identify(161, Some(&snmp)).unwrap();
+        assert_eq!(service.name, "SNMP");
+        assert!(service.version.unwrap().starts_with("Linux router"));
+        assert_eq!(service.method, ServiceMethod::Probe);
+        let guessed = identify(53, None).unwrap();
+        assert_eq!((guessed.name.as_str(), guessed.method), ("DNS", ServiceMethod::PortGuess));
+        assert!(identify(9999, None).is_none());
+
+        // 载荷大小按 MTU 限制，DNS 探测另外不超过 512 字节
+        assert_eq!(unfragmented_limit(1500, false), 1472);
+        assert_eq!(unfragmented_limit(1280, true), 1232);
+        assert_eq!(unfragmented_limit(20, false), 0);
+        assert_eq!(payload_limit(53, 9000, false), DNS_PAYLOAD_LIMIT);
+        assert_eq!(payload_limit(161, 9000, false), 8972);
+        assert_eq!(sized_payload(161, payload_limit(161, 576, false), false), SNMP_GET_SYSDESCR);
+        assert!(sized_payload(161, 32, false).is_empty());
+        assert_eq!(sized_payload(161, 32, true), SNMP_GET_SYSDESCR);
+    }
+}